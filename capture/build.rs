@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/capture.proto");
+
+    // protox compiles the schema without needing protoc installed.
+    let file_descriptors = protox::compile(["proto/capture.proto"], ["proto"])?;
+    prost_build::compile_fds(file_descriptors)?;
+
+    Ok(())
+}
@@ -1,4 +1,7 @@
-use axum::http::StatusCode;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -15,6 +18,13 @@ pub enum CaptureResponseCode {
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct CaptureResponse {
     pub status: CaptureResponseCode,
+    /// The id of the receipt of the batch, when accepted with a 202.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt: Option<Uuid>,
+    /// The id of the team of the token, only when asked for with `include_team_id` and the team
+    /// could be resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<i64>,
 }
 
 #[derive(Error, Debug)]
@@ -23,6 +33,8 @@ pub enum CaptureError {
     RequestDecodingError(String),
     #[error("failed to parse request: {0}")]
     RequestParsingError(#[from] serde_json::Error),
+    #[error("unsupported content type: {0}")]
+    UnsupportedContentType(String),
 
     #[error("request holds no event")]
     EmptyBatch,
@@ -32,6 +44,12 @@ pub enum CaptureError {
     EmptyDistinctId,
     #[error("event submitted without a distinct_id")]
     MissingDistinctId,
+    #[error("$groupidentify event submitted without a valid {0}")]
+    InvalidGroupIdentify(&'static str),
+    #[error("$exception event submitted without a valid {0}")]
+    InvalidException(&'static str),
+    #[error("{0} event does not match its schema: {1}")]
+    SchemaViolation(String, String),
 
     #[error("event submitted without an api_key")]
     NoTokenError,
@@ -50,8 +68,12 @@ pub enum CaptureError {
     #[error("billing limit reached")]
     BillingLimit,
 
+    /// Holds the number of seconds to wait before retrying, sent as the `Retry-After` header.
     #[error("rate limited")]
-    RateLimited,
+    RateLimited(u64),
+
+    #[error("too many batches in flight for this token")]
+    TooManyBatchesInFlight,
 }
 
 impl IntoResponse for CaptureError {
@@ -63,6 +85,9 @@ impl IntoResponse for CaptureError {
             | CaptureError::MissingEventName
             | CaptureError::EmptyDistinctId
             | CaptureError::MissingDistinctId
+            | CaptureError::InvalidGroupIdentify(_)
+            | CaptureError::InvalidException(_)
+            | CaptureError::SchemaViolation(_, _)
             | CaptureError::EventTooBig
             | CaptureError::NonRetryableSinkError => (StatusCode::BAD_REQUEST, self.to_string()),
 
@@ -72,9 +97,22 @@ impl IntoResponse for CaptureError {
 
             CaptureError::RetryableSinkError => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
 
-            CaptureError::BillingLimit | CaptureError::RateLimited => {
+            CaptureError::UnsupportedContentType(_) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, self.to_string())
+            }
+
+            CaptureError::BillingLimit | CaptureError::TooManyBatchesInFlight => {
                 (StatusCode::TOO_MANY_REQUESTS, self.to_string())
             }
+
+            CaptureError::RateLimited(retry_after_secs) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    self.to_string(),
+                )
+                    .into_response()
+            }
         }
         .into_response()
     }
@@ -84,6 +122,24 @@ impl IntoResponse for CaptureError {
 pub enum DataType {
     AnalyticsMain,
     AnalyticsHistorical,
+    GroupIdentify,
+    Exception,
+    ServerIngest,
+}
+
+impl FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "analytics_main" => Ok(DataType::AnalyticsMain),
+            "analytics_historical" => Ok(DataType::AnalyticsHistorical),
+            "group_identify" => Ok(DataType::GroupIdentify),
+            "exception" => Ok(DataType::Exception),
+            "server_ingest" => Ok(DataType::ServerIngest),
+            _ => Err(format!("unknown data type: {}", s)),
+        }
+    }
 }
 #[derive(Clone, Debug, Serialize, Eq, PartialEq)]
 pub struct ProcessedEvent {
@@ -91,7 +147,7 @@ pub struct ProcessedEvent {
     pub data_type: DataType,
     pub uuid: Uuid,
     pub distinct_id: String,
-    pub ip: String,
+    pub ip: Option<String>, // None if the event opted out of IP collection
     pub data: String,
     pub now: String,
     #[serde(
@@ -99,7 +155,23 @@ pub struct ProcessedEvent {
         skip_serializing_if = "Option::is_none"
     )]
     pub sent_at: Option<OffsetDateTime>,
+    // How far `now` is ahead of the client's sent_at in milliseconds, so downstream can correct
+    // for skewed client clocks or trust either time. Null without a sent_at.
+    pub sent_at_offset_ms: Option<i64>,
+    // When the event happened: from its timestamp property if EVENT_TIMESTAMP_PROPERTY is set and
+    // it has a valid one, from sent_at or the time it was received otherwise
+    #[serde(skip_serializing)]
+    pub timestamp: Option<OffsetDateTime>,
     pub token: String,
+    // Sent as a Kafka header for downstream deduplication, generated if the event has none
+    #[serde(skip_serializing)]
+    pub insert_id: String,
+    // Request details such as the SDK version, also sent as Kafka headers. Omitted when empty.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    // The route of the request the event was captured in, see `ProcessingContext::route`
+    #[serde(skip_serializing)]
+    pub route: Option<String>,
 }
 
 impl ProcessedEvent {
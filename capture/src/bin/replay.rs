@@ -0,0 +1,82 @@
+//! Replays an NDJSON file of events into the configured sink, bypassing the HTTP server.
+//! Useful for load testing and backfills.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use envconfig::Envconfig;
+use health::HealthRegistry;
+use time::Duration;
+use tracing_subscriber::EnvFilter;
+
+use capture::config::ReplayConfig;
+use capture::replay::replay_events;
+use capture::server::{event_processor, route_token_topics};
+use capture::sinks::kafka::KafkaSink;
+use capture::sinks::print::PrintSink;
+use capture::sinks::routing::SinkRouter;
+use capture::time::SystemTime;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let config = ReplayConfig::init_from_env().expect("Invalid configuration:");
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let file = File::open(&config.replay_file).expect("failed to open replay file");
+    let reader = BufReader::new(file);
+
+    // Events are processed and routed like capture does with the same settings
+    let processor = event_processor(config.event);
+    let (sinks, kafka) = if config.print_sink {
+        (SinkRouter::new(Arc::new(PrintSink::default())), None)
+    } else {
+        let liveness = HealthRegistry::new("liveness");
+        let sink_liveness = liveness
+            .register("rdkafka".to_string(), Duration::seconds(30))
+            .await;
+        let sink =
+            KafkaSink::new(config.kafka, sink_liveness, None).expect("failed to start Kafka sink");
+        let sinks = route_token_topics(
+            SinkRouter::new(Arc::new(sink.clone())),
+            &sink,
+            config.kafka_token_topics.as_deref(),
+        );
+        (sinks, Some(sink))
+    };
+
+    let stats = replay_events(
+        sinks,
+        &processor,
+        &SystemTime {},
+        reader,
+        &config.replay_token,
+        config.replay_batch_size.get(),
+        config.replay_historical_migration,
+    )
+    .await
+    .expect("failed to read replay file");
+
+    if let Some(kafka) = kafka {
+        kafka.flush().expect("failed to flush Kafka producer");
+    }
+
+    println!(
+        "processed={} invalid={} failed={}",
+        stats.processed, stats.invalid, stats.failed
+    );
+
+    if stats.failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
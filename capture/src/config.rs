@@ -1,12 +1,28 @@
-use std::{net::SocketAddr, num::NonZeroU32};
+use std::{
+    net::SocketAddr,
+    num::{NonZeroU32, NonZeroUsize},
+};
 
 use envconfig::Envconfig;
+use redis::IntoConnectionInfo;
+use thiserror::Error;
+
+use crate::limiters::team::TeamRateLimitOverrides;
+use crate::router::parse_content_types;
+use crate::sinks::kafka::KafkaTimestampSource;
+use crate::sinks::routing::parse_routes;
+use crate::v0_endpoint::{DataTypeRules, FutureDatedMode, OversizedPropertiesMode};
+use crate::v0_request::DistinctIdFields;
 
 #[derive(Envconfig, Clone)]
 pub struct Config {
     #[envconfig(default = "false")]
     pub print_sink: bool,
 
+    // Print BEGIN BATCH and END BATCH lines around the events of each batch with the print sink
+    #[envconfig(default = "false")]
+    pub print_sink_batch_framing: bool,
+
     #[envconfig(default = "127.0.0.1:3000")]
     pub address: SocketAddr,
 
@@ -27,6 +43,16 @@ pub struct Config {
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 
+    // Comma-delimited token=topic pairs, to send the events of some teams to a dedicated topic
+    pub kafka_token_topics: Option<String>,
+
+    // Brokers of a second Kafka cluster, to send KAFKA_SPLIT_PERCENT of the distinct_ids to while
+    // validating it. Teams routed with KAFKA_TOKEN_TOPICS stay on KAFKA_HOSTS.
+    pub kafka_split_hosts: Option<String>,
+
+    #[envconfig(default = "0")]
+    pub kafka_split_percent: f64,
+
     #[envconfig(default = "1.0")]
     pub otel_sampling_rate: f64,
 
@@ -35,6 +61,260 @@ pub struct Config {
 
     #[envconfig(default = "true")]
     pub export_prometheus: bool,
+
+    // Keep serving for this long after the shutdown signal when exporting metrics, for a final
+    // scrape to collect the metrics recorded until then
+    #[envconfig(default = "0")]
+    pub metrics_drain_secs: u64,
+
+    #[envconfig(nested = true)]
+    pub event: EventConfig,
+
+    // Answer accepted batches with a 202 and the id of a receipt, stored in redis for this many
+    // seconds and served on /capture/receipt/<id>. Batches are answered with a 200 if unset.
+    pub receipt_ttl_secs: Option<u64>,
+
+    // Requests per second each team, by token, can send before being answered with a 429 and a
+    // Retry-After header, unlimited if unset. Unlike billing limits, this protects capture itself.
+    pub team_rate_limit_per_second: Option<NonZeroU32>,
+
+    // Comma-delimited token=limit pairs overriding TEAM_RATE_LIMIT_PER_SECOND for some teams
+    pub team_rate_limit_overrides: Option<TeamRateLimitOverrides>,
+
+    // Batches of the same token processed at the same time, before its next batches are answered
+    // with a 429 until one is done. High enough not to affect well-behaved clients.
+    #[envconfig(default = "100")]
+    pub max_in_flight_batches_per_token: NonZeroUsize,
+
+    // Maximum number of requests handled at the same time, unlimited if unset. Requests over
+    // the limit are rejected with a 503 instead of waiting.
+    pub max_concurrent_requests: Option<usize>,
+
+    // Compress responses of at least this many bytes with gzip or brotli, for clients accepting
+    // either in their Accept-Encoding. Smaller responses aren't worth the overhead. Responses are
+    // never compressed if unset.
+    pub response_compression_min_bytes: Option<u16>,
+
+    // Comma-delimited content-types accepted by capture, other requests are rejected with a 415.
+    // text/plain is sent by posthog-js when using sendBeacon.
+    #[envconfig(
+        default = "application/json,application/x-www-form-urlencoded,text/plain,application/x-protobuf"
+    )]
+    pub allowed_content_types: String,
+
+    // Decode text/plain bodies as base64-encoded JSON, sent by some SDKs behind strict proxies,
+    // before falling back to reading them as JSON. Other content-types are read as usual.
+    #[envconfig(default = "false")]
+    pub plain_text_base64: bool,
+
+    // Serve over HTTPS with these PEM files if both are set, plain HTTP otherwise
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+}
+
+/// Every problem found while validating a configuration, reported together so that they can all
+/// be fixed before the next start.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid configuration:{}", list_problems(.0))]
+pub struct ConfigError(pub Vec<String>);
+
+fn list_problems(problems: &[String]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n  - {}", problem))
+        .collect()
+}
+
+impl Config {
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if let Err(e) = self.redis_url.as_str().into_connection_info() {
+            problems.push(format!("REDIS_URL is not a valid redis URL: {}", e));
+        }
+        if !(0.0..=1.0).contains(&self.otel_sampling_rate) {
+            problems.push(format!(
+                "OTEL_SAMPLING_RATE must be between 0 and 1, got {}",
+                self.otel_sampling_rate
+            ));
+        }
+        if let Some(token_topics) = &self.kafka_token_topics {
+            if let Err(e) = parse_routes(token_topics) {
+                problems.push(format!("KAFKA_TOKEN_TOPICS is invalid: {}", e));
+            }
+        }
+        if !(0.0..=100.0).contains(&self.kafka_split_percent) {
+            problems.push(format!(
+                "KAFKA_SPLIT_PERCENT must be between 0 and 100, got {}",
+                self.kafka_split_percent
+            ));
+        }
+        if self
+            .kafka_split_hosts
+            .as_deref()
+            .is_some_and(|hosts| hosts.trim().is_empty())
+        {
+            problems.push(
+                "KAFKA_SPLIT_HOSTS must not be empty, unset it to disable the split".to_string(),
+            );
+        }
+        self.event.validate(&mut problems);
+        if self.receipt_ttl_secs == Some(0) {
+            problems.push("RECEIPT_TTL_SECS must be greater than zero".to_string());
+        }
+        if self.max_concurrent_requests == Some(0) {
+            problems.push("MAX_CONCURRENT_REQUESTS must be greater than zero".to_string());
+        }
+        if parse_content_types(&self.allowed_content_types).is_empty() {
+            problems.push("ALLOWED_CONTENT_TYPES must not be empty".to_string());
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            problems.push("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string());
+        }
+        // The Kafka settings are unused by the print sink
+        if !self.print_sink {
+            self.kafka.validate(&mut problems);
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError(problems)),
+        }
+    }
+}
+
+/// Configuration of the `replay` binary, that feeds an NDJSON file of events into the sink.
+#[derive(Envconfig, Clone)]
+pub struct ReplayConfig {
+    pub replay_file: String,
+    pub replay_token: String,
+
+    #[envconfig(default = "100")]
+    pub replay_batch_size: NonZeroUsize,
+
+    #[envconfig(default = "false")]
+    pub replay_historical_migration: bool,
+
+    #[envconfig(default = "false")]
+    pub print_sink: bool,
+
+    #[envconfig(nested = true)]
+    pub kafka: KafkaConfig,
+
+    // Comma-delimited token=topic pairs, routed like capture does with KAFKA_TOKEN_TOPICS
+    pub kafka_token_topics: Option<String>,
+
+    #[envconfig(nested = true)]
+    pub event: EventConfig,
+}
+
+impl ReplayConfig {
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.replay_token.is_empty() {
+            problems.push("REPLAY_TOKEN must not be empty".to_string());
+        }
+        if !self.print_sink {
+            self.kafka.validate(&mut problems);
+            if let Some(token_topics) = &self.kafka_token_topics {
+                if let Err(e) = parse_routes(token_topics) {
+                    problems.push(format!("KAFKA_TOKEN_TOPICS is invalid: {}", e));
+                }
+            }
+        }
+        self.event.validate(&mut problems);
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError(problems)),
+        }
+    }
+}
+
+/// How events are processed before being sent to the sink, shared by capture and `replay`.
+#[derive(Envconfig, Clone)]
+pub struct EventConfig {
+    // Number of threads used to process the events of large requests in parallel, 0 to
+    // process them serially on the request's task
+    #[envconfig(default = "0")]
+    pub event_processing_threads: usize,
+
+    // Requests with fewer events than this are always processed serially
+    #[envconfig(default = "100")]
+    pub event_processing_parallel_threshold: usize,
+
+    // Maximum size of the serialized properties of an event, unlimited if unset
+    pub event_properties_max_bytes: Option<usize>,
+
+    // What to do with events over the properties size limit: drop or truncate
+    #[envconfig(default = "drop")]
+    pub event_properties_oversized_mode: OversizedPropertiesMode,
+
+    // Maximum number of seconds event timestamps can be ahead of the time they are received,
+    // unlimited if unset
+    pub event_max_future_skew_secs: Option<u32>,
+
+    // What to do with events timestamped beyond the future skew: clamp to now or drop
+    #[envconfig(default = "clamp")]
+    pub event_future_dated_mode: FutureDatedMode,
+
+    // Property holding the time of events, for SDKs sending it there rather than in the timestamp
+    // field. Events without it, or with a value that isn't an ISO 8601 timestamp, keep their own.
+    pub event_timestamp_property: Option<String>,
+
+    // Comma-separated fields to take the distinct_id of events from, the first one present wins,
+    // like distinct_id,properties.$user_id,properties.$anon_distinct_id. Properties can be nested
+    // with more dots. The distinct_id field, then the distinct_id property, are used if unset.
+    pub event_distinct_id_fields: Option<DistinctIdFields>,
+
+    // Path of a JSON file of schemas, keyed by token then event name, that event properties
+    // must conform to. Events without a schema are not validated.
+    pub event_schemas_path: Option<String>,
+
+    // Semicolon-separated property=value:data_type rules choosing the data type, and topic, of
+    // events like $lib=server:server_ingest, sent to KAFKA_SERVER_INGEST_TOPIC. The first matching
+    // rule wins, events matching none go to the main topic. Historical, group identify and
+    // exception events keep theirs.
+    pub event_data_type_rules: Option<DataTypeRules>,
+
+    // Path of a MaxMind City database to set the $geoip_* properties of events from, looking up
+    // their $ip property or the client IP. Events aren't enriched with their location if unset.
+    pub event_geoip_database_path: Option<String>,
+
+    // Comma-delimited properties whose values are replaced with $redacted, including in $set and
+    // $set_once. Runs after the geoip enrichment, so $geoip_* properties can be redacted too.
+    pub event_redacted_properties: Option<String>,
+
+    // Reject requests with $groupidentify events missing their $group_type or $group_key with a
+    // 400. Off by default, as a single invalid event fails its whole batch.
+    #[envconfig(default = "false")]
+    pub event_validate_group_identify: bool,
+
+    // Reject requests with $exception events without a well-formed $exception_list with a 400.
+    // Off by default, as older SDKs send exceptions without one.
+    #[envconfig(default = "false")]
+    pub event_validate_exceptions: bool,
+}
+
+impl EventConfig {
+    fn validate(&self, problems: &mut Vec<String>) {
+        if self.event_properties_max_bytes == Some(0) {
+            problems.push("EVENT_PROPERTIES_MAX_BYTES must be greater than zero".to_string());
+        }
+        if self
+            .event_timestamp_property
+            .as_deref()
+            .is_some_and(|property| property.trim().is_empty())
+        {
+            problems.push(
+                "EVENT_TIMESTAMP_PROPERTY must not be empty, unset it to keep event timestamps"
+                    .to_string(),
+            );
+        }
+    }
 }
 
 #[derive(Envconfig, Clone)]
@@ -43,15 +323,154 @@ pub struct KafkaConfig {
     pub kafka_producer_linger_ms: u32, // Maximum time between producer batches during low traffic
     #[envconfig(default = "400")]
     pub kafka_producer_queue_mib: u32, // Size of the in-memory producer queue in mebibytes
+    pub kafka_producer_batch_size: Option<u32>, // Maximum size of a producer batch in bytes, larger batches compress better
+    pub kafka_producer_max_in_flight: Option<usize>, // Produces waiting for an ACK, sends wait for one to complete past it
     #[envconfig(default = "20000")]
     pub kafka_message_timeout_ms: u32, // Time before we stop retrying producing a message: 20 seconds
     #[envconfig(default = "none")]
     pub kafka_compression_codec: String, // none, gzip, snappy, lz4, zstd
+    pub kafka_compression_level: Option<i32>, // Codec-dependent, defaults to the codec's default level
     pub kafka_hosts: String,
     #[envconfig(default = "events_plugin_ingestion")]
     pub kafka_topic: String,
     #[envconfig(default = "events_plugin_ingestion_historical")]
     pub kafka_historical_topic: String,
+    pub kafka_group_identify_topic: Option<String>, // Defaults to the main topic if unset
+    pub kafka_exceptions_topic: Option<String>,     // Defaults to the main topic if unset
+    pub kafka_server_ingest_topic: Option<String>,  // Defaults to the main topic if unset
+    pub kafka_route_topics: Option<String>, // Comma-delimited route=topic pairs, for the X-PostHog-Route header
+    #[envconfig(default = "60")]
+    pub kafka_overflow_partitions_refresh_secs: u64, // Overflowed events are spread across the main topic's partitions, as of this often
+    #[envconfig(default = "produce_time")]
+    pub kafka_timestamp_source: KafkaTimestampSource, // produce_time, now, sent_at, event_time
     #[envconfig(default = "false")]
     pub kafka_tls: bool,
 }
+
+impl KafkaConfig {
+    fn validate(&self, problems: &mut Vec<String>) {
+        let valid_hosts = !self.kafka_hosts.trim().is_empty()
+            && self
+                .kafka_hosts
+                .split(',')
+                .all(|broker| match broker.trim().rsplit_once(':') {
+                    Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+                    None => false,
+                });
+        if !valid_hosts {
+            problems.push(format!(
+                "KAFKA_HOSTS must be a comma-separated list of host:port brokers, got {:?}",
+                self.kafka_hosts
+            ));
+        }
+        if !["none", "gzip", "snappy", "lz4", "zstd"]
+            .contains(&self.kafka_compression_codec.as_str())
+        {
+            problems.push(format!(
+                "KAFKA_COMPRESSION_CODEC must be one of none, gzip, snappy, lz4 or zstd, got {:?}",
+                self.kafka_compression_codec
+            ));
+        }
+        if self.kafka_message_timeout_ms == 0 {
+            problems.push("KAFKA_MESSAGE_TIMEOUT_MS must be greater than zero".to_string());
+        }
+        if self.kafka_producer_queue_mib == 0 {
+            problems.push("KAFKA_PRODUCER_QUEUE_MIB must be greater than zero".to_string());
+        }
+        if let Some(route_topics) = &self.kafka_route_topics {
+            if let Err(e) = parse_routes(route_topics) {
+                problems.push(format!("KAFKA_ROUTE_TOPICS is invalid: {}", e));
+            }
+        }
+        if self.kafka_producer_max_in_flight == Some(0) {
+            problems.push("KAFKA_PRODUCER_MAX_IN_FLIGHT must be greater than zero".to_string());
+        }
+        if self.kafka_overflow_partitions_refresh_secs == 0 {
+            problems.push(
+                "KAFKA_OVERFLOW_PARTITIONS_REFRESH_SECS must be greater than zero".to_string(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let mut env: HashMap<String, String> = HashMap::from([
+            (
+                "REDIS_URL".to_string(),
+                "redis://localhost:6379/".to_string(),
+            ),
+            ("KAFKA_HOSTS".to_string(), "kafka:9092".to_string()),
+        ]);
+        for (key, value) in vars {
+            env.insert(key.to_string(), value.to_string());
+        }
+        Config::init_from_hashmap(&env).expect("failed to parse config")
+    }
+
+    #[test]
+    fn it_accepts_a_valid_config() {
+        assert_eq!(config(&[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn it_allows_every_supported_content_type_by_default() {
+        let allowed = parse_content_types(&config(&[]).allowed_content_types);
+        for content_type in [
+            "application/json",
+            "application/x-www-form-urlencoded",
+            "text/plain",
+            "application/x-protobuf",
+        ] {
+            assert!(
+                allowed.contains(content_type),
+                "{} is not allowed",
+                content_type
+            );
+        }
+    }
+
+    #[test]
+    fn it_reports_every_problem() {
+        let error = config(&[
+            ("REDIS_URL", "localhost:6379"),
+            ("OTEL_SAMPLING_RATE", "1.5"),
+            ("KAFKA_TOKEN_TOPICS", "phc_a"),
+            ("KAFKA_SPLIT_PERCENT", "150"),
+            ("ALLOWED_CONTENT_TYPES", ","),
+            ("TLS_CERT_PATH", "/etc/capture/cert.pem"),
+            ("KAFKA_HOSTS", "kafka:9092,kafka"),
+            ("KAFKA_COMPRESSION_CODEC", "brotli"),
+            ("KAFKA_MESSAGE_TIMEOUT_MS", "0"),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+
+        let message = error.to_string();
+        for setting in [
+            "REDIS_URL",
+            "OTEL_SAMPLING_RATE",
+            "KAFKA_TOKEN_TOPICS",
+            "KAFKA_SPLIT_PERCENT",
+            "ALLOWED_CONTENT_TYPES",
+            "TLS_CERT_PATH",
+            "KAFKA_HOSTS",
+            "KAFKA_COMPRESSION_CODEC",
+            "KAFKA_MESSAGE_TIMEOUT_MS",
+        ] {
+            assert!(message.contains(setting), "{} is not reported", setting);
+        }
+        assert_eq!(error.0.len(), 9);
+    }
+
+    #[test]
+    fn it_skips_kafka_settings_with_the_print_sink() {
+        let config = config(&[("PRINT_SINK", "true"), ("KAFKA_HOSTS", "")]);
+        assert_eq!(config.validate(), Ok(()));
+    }
+}
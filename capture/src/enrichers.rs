@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, MaxMindDBError};
+use metrics::counter;
+use serde_json::Value;
+
+use crate::api::CaptureError;
+use crate::v0_request::{ProcessingContext, RawEvent};
+
+/// Value that redacted properties are replaced with.
+pub const REDACTED_PROPERTY_VALUE: &str = "$redacted";
+
+/// A step run on every event before it's serialized, to add or transform properties. Returning an
+/// error rejects the whole request, like any other processing error.
+pub trait EventEnricher: Send + Sync {
+    fn enrich(&self, event: &mut RawEvent, context: &ProcessingContext)
+        -> Result<(), CaptureError>;
+}
+
+/// Enrichers run on every event, in order.
+pub type EventEnrichers = Vec<Box<dyn EventEnricher>>;
+
+/// Sets the `$geoip_*` properties of events from the location of their IP in a MaxMind City
+/// database. The `$ip` property is looked up if it's set, otherwise the IP of the client.
+/// Events opting out of having their IP recorded, or setting `$geoip_disable`, are left as is,
+/// as are the properties the event already has.
+pub struct GeoIpEnricher {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIpEnricher {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            reader: maxminddb::Reader::open_readfile(path)?,
+        })
+    }
+
+    fn lookup(&self, ip: IpAddr) -> HashMap<&'static str, Value> {
+        let mut properties = HashMap::new();
+
+        let city: geoip2::City = match self.reader.lookup(ip) {
+            Ok(city) => city,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return properties,
+            Err(e) => {
+                counter!("capture_geoip_lookup_errors_total").increment(1);
+                tracing::warn!("failed to look up the location of {}: {}", ip, e);
+                return properties;
+            }
+        };
+
+        let name = |names: Option<BTreeMap<&str, &str>>| {
+            names.and_then(|names| names.get("en").map(|name| Value::from(*name)))
+        };
+
+        if let Some(city) = city.city {
+            properties.extend(name(city.names).map(|name| ("$geoip_city_name", name)));
+        }
+        if let Some(country) = city.country {
+            properties.extend(name(country.names).map(|name| ("$geoip_country_name", name)));
+            properties.extend(
+                country
+                    .iso_code
+                    .map(|code| ("$geoip_country_code", Value::from(code))),
+            );
+        }
+        if let Some(continent) = city.continent {
+            properties.extend(name(continent.names).map(|name| ("$geoip_continent_name", name)));
+            properties.extend(
+                continent
+                    .code
+                    .map(|code| ("$geoip_continent_code", Value::from(code))),
+            );
+        }
+        if let Some(subdivision) = city.subdivisions.and_then(|s| s.into_iter().next()) {
+            properties
+                .extend(name(subdivision.names).map(|name| ("$geoip_subdivision_1_name", name)));
+            properties.extend(
+                subdivision
+                    .iso_code
+                    .map(|code| ("$geoip_subdivision_1_code", Value::from(code))),
+            );
+        }
+        if let Some(postal) = city.postal {
+            properties.extend(
+                postal
+                    .code
+                    .map(|code| ("$geoip_postal_code", Value::from(code))),
+            );
+        }
+        if let Some(location) = city.location {
+            properties.extend(
+                location
+                    .latitude
+                    .map(|latitude| ("$geoip_latitude", Value::from(latitude))),
+            );
+            properties.extend(
+                location
+                    .longitude
+                    .map(|longitude| ("$geoip_longitude", Value::from(longitude))),
+            );
+            properties.extend(
+                location
+                    .time_zone
+                    .map(|time_zone| ("$geoip_time_zone", Value::from(time_zone))),
+            );
+        }
+
+        properties
+    }
+}
+
+impl EventEnricher for GeoIpEnricher {
+    fn enrich(
+        &self,
+        event: &mut RawEvent,
+        context: &ProcessingContext,
+    ) -> Result<(), CaptureError> {
+        if event.opts_out_of_ip()
+            || matches!(
+                event.properties.get("$geoip_disable"),
+                Some(Value::Bool(true))
+            )
+        {
+            return Ok(());
+        }
+
+        let ip = match event.properties.get("$ip") {
+            Some(Value::String(ip)) => ip.parse(),
+            _ => context.client_ip.parse(),
+        };
+        let Ok(ip) = ip else {
+            return Ok(());
+        };
+
+        for (key, value) in self.lookup(ip) {
+            event.properties.entry(key.to_string()).or_insert(value);
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the value of some properties, in `properties`, `$set` and `$set_once`, with
+/// `REDACTED_PROPERTY_VALUE`, for properties that must not be stored.
+pub struct PropertyRedactionEnricher {
+    properties: HashSet<String>,
+}
+
+impl PropertyRedactionEnricher {
+    pub fn new(properties: HashSet<String>) -> Self {
+        Self { properties }
+    }
+
+    fn redact<'a>(&self, properties: impl IntoIterator<Item = (&'a String, &'a mut Value)>) {
+        for (key, value) in properties {
+            if self.properties.contains(key) {
+                *value = Value::from(REDACTED_PROPERTY_VALUE);
+            }
+        }
+    }
+}
+
+impl EventEnricher for PropertyRedactionEnricher {
+    fn enrich(&self, event: &mut RawEvent, _: &ProcessingContext) -> Result<(), CaptureError> {
+        self.redact(event.properties.iter_mut());
+        for properties in [&mut event.set, &mut event.set_once].into_iter().flatten() {
+            self.redact(properties.iter_mut());
+        }
+        for key in ["$set", "$set_once"] {
+            if let Some(Value::Object(properties)) = event.properties.get_mut(key) {
+                self.redact(properties.iter_mut());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn context() -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            sent_at: None,
+            token: "token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            user_agent: None,
+            route: None,
+        }
+    }
+
+    fn event(properties: Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "user",
+            "properties": properties,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_redacts_properties_everywhere() {
+        let enricher = PropertyRedactionEnricher::new(HashSet::from(["email".to_string()]));
+        let mut event = event(json!({
+            "email": "user@example.com",
+            "plan": "free",
+            "$set": {"email": "user@example.com", "name": "User"},
+        }));
+        event.set_once = Some(HashMap::from([(
+            "email".to_string(),
+            json!("user@example.com"),
+        )]));
+
+        enricher
+            .enrich(&mut event, &context())
+            .expect("failed to enrich event");
+
+        assert_eq!(event.properties["email"], json!(REDACTED_PROPERTY_VALUE));
+        assert_eq!(event.properties["plan"], json!("free"));
+        assert_eq!(
+            event.properties["$set"],
+            json!({"email": REDACTED_PROPERTY_VALUE, "name": "User"})
+        );
+        assert_eq!(
+            event.set_once.unwrap()["email"],
+            json!(REDACTED_PROPERTY_VALUE)
+        );
+    }
+}
@@ -1,11 +1,17 @@
 pub mod api;
 pub mod config;
+pub mod enrichers;
 pub mod limiters;
 pub mod prometheus;
+pub mod proto;
+pub mod receipts;
 pub mod redis;
+pub mod replay;
 pub mod router;
+pub mod schemas;
 pub mod server;
 pub mod sinks;
+pub mod teams;
 pub mod time;
 pub mod token;
 pub mod utils;
@@ -0,0 +1,93 @@
+/// Caps how many batches of the same team, by token, are processed at the same time, so that a
+/// team sending large batches in parallel can't monopolize capture even within its rate limit.
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct InFlightLimiter {
+    max_in_flight: usize,
+    // Tokens are removed once they have no batch in flight, to keep the map small.
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl InFlightLimiter {
+    pub fn new(max_in_flight: NonZeroUsize) -> Self {
+        InFlightLimiter {
+            max_in_flight: max_in_flight.get(),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns a permit to process a batch of the team with this token, released when dropped,
+    /// or `None` if the team already has the maximum number of batches in flight.
+    pub fn try_acquire(&self, token: &str) -> Option<InFlightPermit> {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("poisoned InFlightLimiter mutex");
+        let count = in_flight.entry(token.to_string()).or_default();
+        if *count >= self.max_in_flight {
+            return None;
+        }
+        *count += 1;
+
+        Some(InFlightPermit {
+            in_flight: self.in_flight.clone(),
+            token: token.to_string(),
+        })
+    }
+}
+
+/// A batch in flight for `token`, counted against its limit until dropped.
+pub struct InFlightPermit {
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+    token: String,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("poisoned InFlightLimiter mutex");
+        if let Some(count) = in_flight.get_mut(&self.token) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_limits_batches_in_flight_per_token() {
+        let limiter = InFlightLimiter::new(NonZeroUsize::new(2).unwrap());
+
+        let first = limiter.try_acquire("one").expect("one is under its limit");
+        let _second = limiter.try_acquire("one").expect("one is under its limit");
+        assert!(limiter.try_acquire("one").is_none());
+
+        // Other tokens have their own limit
+        let _other = limiter.try_acquire("two").expect("two is under its limit");
+
+        // Finishing a batch frees a slot
+        drop(first);
+        assert!(limiter.try_acquire("one").is_some());
+    }
+
+    #[test]
+    fn it_forgets_tokens_without_batches_in_flight() {
+        let limiter = InFlightLimiter::new(NonZeroUsize::new(1).unwrap());
+
+        let permit = limiter.try_acquire("one").expect("one is under its limit");
+        assert_eq!(limiter.in_flight.lock().unwrap().len(), 1);
+
+        drop(permit);
+        assert!(limiter.in_flight.lock().unwrap().is_empty());
+    }
+}
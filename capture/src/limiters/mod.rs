@@ -1,2 +1,4 @@
 pub mod billing;
+pub mod in_flight;
 pub mod overflow;
+pub mod team;
@@ -0,0 +1,162 @@
+/// Protects capture from a single team sending more requests than it can handle, like a
+/// misconfigured client retrying in a loop. Unlike billing limits, which drop events silently
+/// once a team is over its quota, requests over these limits are rejected with a 429 so that
+/// clients back off.
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use governor::clock::{Clock, DefaultClock};
+use governor::state::keyed::DefaultKeyedStateStore;
+use governor::{Quota, RateLimiter};
+
+/// Requests per second allowed for some teams, keyed by token, instead of the default limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TeamRateLimitOverrides(pub HashMap<String, NonZeroU32>);
+
+impl FromStr for TeamRateLimitOverrides {
+    type Err = String;
+
+    /// Parses comma-separated token=limit pairs, like `phc_one=10,phc_two=1000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = HashMap::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (token, limit) = pair.split_once('=').ok_or_else(|| {
+                format!("invalid rate limit override {}, expected token=limit", pair)
+            })?;
+            let limit = limit
+                .trim()
+                .parse::<NonZeroU32>()
+                .map_err(|e| format!("invalid rate limit for {}: {}", token, e))?;
+            overrides.insert(token.trim().to_string(), limit);
+        }
+        Ok(TeamRateLimitOverrides(overrides))
+    }
+}
+
+type KeyedRateLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>;
+
+/// Rate limits the requests of each team, by token, allowing bursts of up to one second of
+/// requests.
+#[derive(Clone)]
+pub struct TeamRateLimiter {
+    /// Teams without an override are unlimited when unset.
+    default: Option<Arc<KeyedRateLimiter>>,
+    overrides: Arc<HashMap<String, KeyedRateLimiter>>,
+    clock: DefaultClock,
+}
+
+impl TeamRateLimiter {
+    pub fn new(per_second: Option<NonZeroU32>, overrides: TeamRateLimitOverrides) -> Self {
+        let default = per_second
+            .map(|per_second| Arc::new(RateLimiter::dashmap(Quota::per_second(per_second))));
+        let overrides = overrides
+            .0
+            .into_iter()
+            .map(|(token, per_second)| (token, RateLimiter::dashmap(Quota::per_second(per_second))))
+            .collect();
+
+        TeamRateLimiter {
+            default,
+            overrides: Arc::new(overrides),
+            clock: DefaultClock::default(),
+        }
+    }
+
+    /// Counts a request of the team with this token, returning how long to wait before retrying
+    /// if it is over its limit.
+    pub fn check(&self, token: &str) -> Result<(), Duration> {
+        let limiter = match self.overrides.get(token) {
+            Some(limiter) => limiter,
+            None => match &self.default {
+                Some(limiter) => limiter,
+                None => return Ok(()),
+            },
+        };
+
+        limiter
+            .check_key(&token.to_string())
+            .map_err(|not_until| not_until.wait_time_from(self.clock.now()))
+    }
+
+    /// Clean up the rate limiter state, once per minute. Ensure we don't use more memory than
+    /// necessary.
+    pub async fn clean_state(&self) {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+
+            if let Some(limiter) = &self.default {
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(per_second: u32) -> NonZeroU32 {
+        NonZeroU32::new(per_second).unwrap()
+    }
+
+    #[test]
+    fn it_limits_each_team_separately() {
+        let limiter = TeamRateLimiter::new(Some(limit(2)), TeamRateLimitOverrides::default());
+
+        assert!(limiter.check("one").is_ok());
+        assert!(limiter.check("one").is_ok());
+        let retry_after = limiter.check("one").expect_err("one should be limited");
+        assert!(retry_after <= Duration::from_secs(1));
+
+        assert!(limiter.check("two").is_ok());
+    }
+
+    #[test]
+    fn it_applies_overrides() {
+        let overrides: TeamRateLimitOverrides = "big=3, small=1".parse().unwrap();
+        let limiter = TeamRateLimiter::new(Some(limit(2)), overrides);
+
+        assert!(limiter.check("small").is_ok());
+        assert!(limiter.check("small").is_err());
+
+        for _ in 0..3 {
+            assert!(limiter.check("big").is_ok());
+        }
+        assert!(limiter.check("big").is_err());
+    }
+
+    #[test]
+    fn it_only_limits_overrides_without_a_default() {
+        let overrides: TeamRateLimitOverrides = "small=1".parse().unwrap();
+        let limiter = TeamRateLimiter::new(None, overrides);
+
+        assert!(limiter.check("small").is_ok());
+        assert!(limiter.check("small").is_err());
+        for _ in 0..100 {
+            assert!(limiter.check("other").is_ok());
+        }
+    }
+
+    #[test]
+    fn it_parses_overrides() {
+        assert_eq!(
+            "".parse::<TeamRateLimitOverrides>(),
+            Ok(TeamRateLimitOverrides::default())
+        );
+        assert_eq!(
+            "one=1,two=20".parse::<TeamRateLimitOverrides>(),
+            Ok(TeamRateLimitOverrides(HashMap::from([
+                ("one".to_string(), limit(1)),
+                ("two".to_string(), limit(20)),
+            ])))
+        );
+        assert!("one".parse::<TeamRateLimitOverrides>().is_err());
+        assert!("one=0".parse::<TeamRateLimitOverrides>().is_err());
+        assert!("one=many".parse::<TeamRateLimitOverrides>().is_err());
+    }
+}
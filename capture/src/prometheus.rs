@@ -20,6 +20,10 @@ pub fn setup_metrics_recorder() -> PrometheusHandle {
     const EXPONENTIAL_SECONDS: &[f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
     ];
+    // Clients' clocks are as often behind as ahead of ours, by up to a day
+    const SKEW_SECONDS: &[f64] = &[
+        -86400.0, -3600.0, -600.0, -60.0, -10.0, -1.0, 0.0, 1.0, 10.0, 60.0, 600.0, 3600.0, 86400.0,
+    ];
     const BATCH_SIZES: &[f64] = &[
         1.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0,
     ];
@@ -32,6 +36,11 @@ pub fn setup_metrics_recorder() -> PrometheusHandle {
         .unwrap()
         .set_buckets_for_metric(Matcher::Suffix("_batch_size".to_string()), BATCH_SIZES)
         .unwrap()
+        .set_buckets_for_metric(
+            Matcher::Full("capture_clock_skew_seconds".to_string()),
+            SKEW_SECONDS,
+        )
+        .unwrap()
         .install_recorder()
         .unwrap()
 }
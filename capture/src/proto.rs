@@ -0,0 +1,88 @@
+/// Protobuf-encoded batches, generated from `proto/capture.proto`, and their conversion into
+/// the same structures as JSON batches, so that both are processed the same way.
+use prost_types::value::Kind;
+use serde_json::{Map, Number, Value};
+use uuid::Uuid;
+
+use crate::api::CaptureError;
+use crate::v0_request::{BatchedRequest, RawEvent};
+
+include!(concat!(env!("OUT_DIR"), "/capture.rs"));
+
+/// Integers are sent as doubles in protobuf values, only those beyond this bound lose precision.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+impl TryFrom<Batch> for BatchedRequest {
+    type Error = CaptureError;
+
+    fn try_from(batch: Batch) -> Result<Self, Self::Error> {
+        Ok(BatchedRequest {
+            token: batch.api_key,
+            historical_migration: batch.historical_migration,
+            sent_at: batch.sent_at,
+            batch: batch
+                .batch
+                .into_iter()
+                .map(RawEvent::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl TryFrom<Event> for RawEvent {
+    type Error = CaptureError;
+
+    fn try_from(event: Event) -> Result<Self, Self::Error> {
+        let uuid = event
+            .uuid
+            .map(|uuid| Uuid::parse_str(&uuid))
+            .transpose()
+            .map_err(|e| {
+                tracing::error!("failed to parse event uuid: {}", e);
+                CaptureError::RequestDecodingError(String::from("invalid event uuid"))
+            })?;
+
+        Ok(RawEvent {
+            token: event.token,
+            distinct_id: event.distinct_id.map(to_json),
+            uuid,
+            event: event.event,
+            properties: to_json_map(event.properties),
+            timestamp: event.timestamp,
+            offset: event.offset,
+            set: event.set.map(|set| to_json_map(set.fields)),
+            set_once: event.set_once.map(|set_once| to_json_map(set_once.fields)),
+        })
+    }
+}
+
+fn to_json_map<M: FromIterator<(String, Value)>>(
+    fields: impl IntoIterator<Item = (String, prost_types::Value)>,
+) -> M {
+    fields
+        .into_iter()
+        .map(|(key, value)| (key, to_json(value)))
+        .collect()
+}
+
+/// Converts a protobuf value into the JSON value the same data would have been sent as.
+fn to_json(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(value)) => Value::Bool(value),
+        // Whole numbers are read back as integers, like `1` and not `1.0` in JSON
+        Some(Kind::NumberValue(value))
+            if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER =>
+        {
+            Value::Number(Number::from(value as i64))
+        }
+        Some(Kind::NumberValue(value)) => {
+            Number::from_f64(value).map_or(Value::Null, Value::Number)
+        }
+        Some(Kind::StringValue(value)) => Value::String(value),
+        Some(Kind::ListValue(list)) => Value::Array(list.values.into_iter().map(to_json).collect()),
+        Some(Kind::StructValue(object)) => {
+            Value::Object(to_json_map::<Map<String, Value>>(object.fields))
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::redis::Client;
+use crate::router;
+use crate::utils::uuid_v7;
+
+const RECEIPT_KEY_PREFIX: &str = "@posthog/capture/receipt/";
+
+/// The summary of an accepted batch, that clients can query with its id.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Receipt {
+    pub events: usize,
+    pub received_at: String,
+}
+
+/// Stores a receipt in redis for each accepted batch, for `ttl_secs`.
+#[derive(Clone)]
+pub struct Receipts {
+    redis: Arc<dyn Client + Send + Sync>,
+    ttl_secs: u64,
+}
+
+impl Receipts {
+    pub fn new(redis: Arc<dyn Client + Send + Sync>, ttl_secs: u64) -> Self {
+        Self { redis, ttl_secs }
+    }
+
+    /// Store `receipt` under a new id, returned unless redis fails to store it.
+    pub async fn issue(&self, receipt: &Receipt) -> Option<Uuid> {
+        let id = uuid_v7();
+        let value = serde_json::to_string(receipt).expect("receipts serialize to JSON");
+
+        match self
+            .redis
+            .set_ex(
+                format!("{}{}", RECEIPT_KEY_PREFIX, id),
+                value,
+                self.ttl_secs,
+            )
+            .await
+        {
+            Ok(()) => {
+                counter!("capture_receipts_issued_total").increment(1);
+                Some(id)
+            }
+            Err(e) => {
+                counter!("capture_receipts_failed_total").increment(1);
+                tracing::error!("failed to store receipt: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The receipt of `id`, unless it doesn't exist or expired.
+    pub async fn get(&self, id: Uuid) -> anyhow::Result<Option<Receipt>> {
+        let value = self
+            .redis
+            .get(format!("{}{}", RECEIPT_KEY_PREFIX, id))
+            .await?;
+        Ok(match value {
+            None => None,
+            Some(value) => Some(serde_json::from_str(&value)?),
+        })
+    }
+}
+
+/// Returns the receipt of a batch accepted with a 202, 404 once it expired.
+pub async fn receipt(
+    state: State<router::State>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Receipt>, StatusCode> {
+    let Some(receipts) = &state.receipts else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    match receipts.get(id).await {
+        Ok(Some(receipt)) => Ok(Json(receipt)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("failed to get receipt: {}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
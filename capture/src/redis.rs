@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::Result;
@@ -19,6 +21,10 @@ const REDIS_TIMEOUT_MILLISECS: u64 = 10;
 pub trait Client {
     // A very simplified wrapper, but works for our usage
     async fn zrangebyscore(&self, k: String, min: String, max: String) -> Result<Vec<String>>;
+    async fn set_ex(&self, k: String, v: String, seconds: u64) -> Result<()>;
+    async fn get(&self, k: String) -> Result<Option<String>>;
+    // For values django pickled, like its cache
+    async fn get_pickled(&self, k: String) -> Result<Option<String>>;
 }
 
 pub struct RedisClient {
@@ -43,18 +49,53 @@ impl Client for RedisClient {
 
         Ok(fut?)
     }
+
+    async fn set_ex(&self, k: String, v: String, seconds: u64) -> Result<()> {
+        let mut conn = self.client.get_async_connection().await?;
+
+        let results = conn.set_ex(k, v, seconds as usize);
+        let fut = timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
+
+        Ok(fut?)
+    }
+
+    async fn get(&self, k: String) -> Result<Option<String>> {
+        let mut conn = self.client.get_async_connection().await?;
+
+        let results = conn.get(k);
+        let fut = timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
+
+        Ok(fut?)
+    }
+
+    async fn get_pickled(&self, k: String) -> Result<Option<String>> {
+        let mut conn = self.client.get_async_connection().await?;
+
+        let results = conn.get(k);
+        let fut: Option<Vec<u8>> =
+            timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await??;
+
+        // TRICKY: django serializes data to json, then pickles it
+        Ok(match fut {
+            None => None,
+            Some(bytes) => Some(serde_pickle::from_slice(&bytes, Default::default())?),
+        })
+    }
 }
 
 // mockall got really annoying with async and results so I'm just gonna do my own
 #[derive(Clone)]
 pub struct MockRedisClient {
     zrangebyscore_ret: Vec<String>,
+    // Values set with set_ex, which never expire
+    values: Arc<Mutex<HashMap<String, String>>>,
 }
 
 impl MockRedisClient {
     pub fn new() -> MockRedisClient {
         MockRedisClient {
             zrangebyscore_ret: Vec::new(),
+            values: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -77,4 +118,18 @@ impl Client for MockRedisClient {
     async fn zrangebyscore(&self, _k: String, _min: String, _max: String) -> Result<Vec<String>> {
         Ok(self.zrangebyscore_ret.clone())
     }
+
+    async fn set_ex(&self, k: String, v: String, _seconds: u64) -> Result<()> {
+        self.values.lock().unwrap().insert(k, v);
+        Ok(())
+    }
+
+    async fn get(&self, k: String) -> Result<Option<String>> {
+        Ok(self.values.lock().unwrap().get(&k).cloned())
+    }
+
+    // Values are stored as is, so set_ex already holds what would be unpickled
+    async fn get_pickled(&self, k: String) -> Result<Option<String>> {
+        self.get(k).await
+    }
 }
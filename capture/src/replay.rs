@@ -0,0 +1,200 @@
+use std::io::BufRead;
+
+use tracing::{error, info, warn};
+
+use crate::sinks::routing::SinkRouter;
+use crate::time::TimeSource;
+use crate::v0_endpoint::{process_events, EventProcessor};
+use crate::v0_request::{ProcessingContext, RawEvent};
+
+/// Client IP reported for replayed events, as they did not arrive over the network.
+const REPLAY_CLIENT_IP: &str = "127.0.0.1";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Events accepted by the sink.
+    pub processed: usize,
+    /// Lines that could not be parsed as a `RawEvent`.
+    pub invalid: usize,
+    /// Events that were parsed but rejected by the pipeline or the sink.
+    pub failed: usize,
+}
+
+/// Replays an NDJSON stream of `RawEvent`s through `process_events` with `processor`, as if they
+/// had been received by the capture endpoint for the given token. Events are sent to `sinks` in
+/// batches of `batch_size`. Blank lines are skipped, malformed lines are counted as invalid.
+pub async fn replay_events<R: BufRead>(
+    sinks: SinkRouter,
+    processor: &EventProcessor,
+    timesource: &dyn TimeSource,
+    reader: R,
+    token: &str,
+    batch_size: usize,
+    historical_migration: bool,
+) -> std::io::Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+    let mut batch: Vec<RawEvent> = Vec::with_capacity(batch_size);
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<RawEvent>(&line) {
+            Ok(event) => batch.push(event),
+            Err(e) => {
+                warn!("skipping invalid event on line {}: {}", line_number + 1, e);
+                stats.invalid += 1;
+                continue;
+            }
+        }
+
+        if batch.len() >= batch_size {
+            flush_batch(
+                &sinks,
+                processor,
+                timesource,
+                &mut batch,
+                token,
+                historical_migration,
+                &mut stats,
+            )
+            .await;
+        }
+    }
+
+    if !batch.is_empty() {
+        flush_batch(
+            &sinks,
+            processor,
+            timesource,
+            &mut batch,
+            token,
+            historical_migration,
+            &mut stats,
+        )
+        .await;
+    }
+
+    info!(
+        "replay done: {} processed, {} invalid, {} failed",
+        stats.processed, stats.invalid, stats.failed
+    );
+    Ok(stats)
+}
+
+async fn flush_batch(
+    sinks: &SinkRouter,
+    processor: &EventProcessor,
+    timesource: &dyn TimeSource,
+    batch: &mut Vec<RawEvent>,
+    token: &str,
+    historical_migration: bool,
+    stats: &mut ReplayStats,
+) {
+    let context = ProcessingContext {
+        lib_version: None,
+        sent_at: None,
+        token: token.to_string(),
+        now: timesource.current_time(),
+        client_ip: REPLAY_CLIENT_IP.to_string(),
+        historical_migration,
+        user_agent: None,
+        route: None,
+    };
+
+    // process_events is all-or-nothing, so a failure rejects the whole batch
+    match process_events(sinks, processor, batch, &context).await {
+        Ok(()) => stats.processed += batch.len(),
+        Err(e) => {
+            error!("failed to replay batch of {} events: {}", batch.len(), e);
+            stats.failed += batch.len();
+        }
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use crate::replay::{replay_events, ReplayStats};
+    use crate::sinks::print::PrintSink;
+    use crate::sinks::routing::SinkRouter;
+    use crate::time::SystemTime;
+    use crate::v0_endpoint::EventProcessor;
+
+    #[tokio::test]
+    async fn it_replays_ndjson_events_into_sink() {
+        let input = r#"{"event": "event1", "distinct_id": "id1"}
+{"event": "event2", "distinct_id": "id2", "properties": {"foo": "bar"}}
+
+{"event": "event3", "distinct_id": "id3"}
+not json
+{"event": "event4", "distinct_id": "id4"}
+"#;
+
+        let stats = replay_events(
+            SinkRouter::new(Arc::new(PrintSink::default())),
+            &EventProcessor::default(),
+            &SystemTime {},
+            Cursor::new(input),
+            "token",
+            3,
+            false,
+        )
+        .await
+        .expect("failed to read input");
+
+        assert_eq!(
+            stats,
+            ReplayStats {
+                processed: 4,
+                invalid: 1,
+                failed: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_replays_events_with_the_given_processor() {
+        let input = r#"{"event": "event1", "distinct_id": "id1"}
+{"event": "$exception", "distinct_id": "id2"}
+"#;
+
+        for (processor, expected) in [
+            (
+                EventProcessor::default(),
+                ReplayStats {
+                    processed: 2,
+                    invalid: 0,
+                    failed: 0,
+                },
+            ),
+            (
+                EventProcessor::default().with_exception_validation(),
+                ReplayStats {
+                    processed: 1,
+                    invalid: 0,
+                    failed: 1,
+                },
+            ),
+        ] {
+            let stats = replay_events(
+                SinkRouter::new(Arc::new(PrintSink::default())),
+                &processor,
+                &SystemTime {},
+                Cursor::new(input),
+                "token",
+                1,
+                false,
+            )
+            .await
+            .expect("failed to read input");
+
+            assert_eq!(stats, expected);
+        }
+    }
+}
@@ -1,50 +1,93 @@
+use std::collections::HashSet;
 use std::future::ready;
 use std::sync::Arc;
 
-use axum::http::Method;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::Request;
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use axum::{
     routing::{get, post},
-    Router,
+    BoxError, Router,
 };
 use health::HealthRegistry;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::LoadShedLayer;
+use tower::ServiceBuilder;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use crate::{
-    limiters::billing::BillingLimiter, redis::Client, sinks, time::TimeSource, v0_endpoint,
+    api::CaptureError,
+    limiters::{billing::BillingLimiter, in_flight::InFlightLimiter, team::TeamRateLimiter},
+    receipts::{self, Receipts},
+    redis::Client,
+    sinks::routing::SinkRouter,
+    time::TimeSource,
+    v0_endpoint::{self, EventProcessor},
 };
 
 use crate::prometheus::{setup_metrics_recorder, track_metrics};
 
 #[derive(Clone)]
 pub struct State {
-    pub sink: Arc<dyn sinks::Event + Send + Sync>,
+    pub sinks: SinkRouter,
     pub timesource: Arc<dyn TimeSource + Send + Sync>,
     pub redis: Arc<dyn Client + Send + Sync>,
     pub billing: BillingLimiter,
+    pub rate_limiter: Option<TeamRateLimiter>,
+    pub in_flight: Option<InFlightLimiter>,
+    pub processor: EventProcessor,
+    pub receipts: Option<Receipts>,
+    pub plain_text_base64: bool,
+}
+
+/// The optional parts of the router, all disabled by default.
+#[derive(Default)]
+pub struct RouterOptions {
+    pub rate_limiter: Option<TeamRateLimiter>,
+    pub in_flight: Option<InFlightLimiter>,
+    pub processor: EventProcessor,
+    pub receipts: Option<Receipts>,
+    // Decode text/plain bodies as base64-encoded JSON before falling back to JSON
+    pub plain_text_base64: bool,
+    // Serve prometheus metrics on /metrics
+    pub metrics: bool,
 }
 
 async fn index() -> &'static str {
     "capture"
 }
 
-pub fn router<
-    TZ: TimeSource + Send + Sync + 'static,
-    S: sinks::Event + Send + Sync + 'static,
-    R: Client + Send + Sync + 'static,
->(
+pub fn router<TZ: TimeSource + Send + Sync + 'static, R: Client + Send + Sync + 'static>(
     timesource: TZ,
     liveness: HealthRegistry,
-    sink: S,
+    sinks: SinkRouter,
     redis: Arc<R>,
     billing: BillingLimiter,
-    metrics: bool,
+    options: RouterOptions,
 ) -> Router {
+    let RouterOptions {
+        rate_limiter,
+        in_flight,
+        processor,
+        receipts,
+        plain_text_base64,
+        metrics,
+    } = options;
     let state = State {
-        sink: Arc::new(sink),
+        sinks,
         timesource: Arc::new(timesource),
         redis,
         billing,
+        rate_limiter,
+        in_flight,
+        processor,
+        receipts,
+        plain_text_base64,
     };
 
     // Very permissive CORS policy, as old SDK versions
@@ -96,6 +139,7 @@ pub fn router<
                 .get(v0_endpoint::event)
                 .options(v0_endpoint::options),
         )
+        .route("/capture/receipt/:id", get(receipts::receipt))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .layer(axum::middleware::from_fn(track_metrics))
@@ -111,3 +155,230 @@ pub fn router<
         router
     }
 }
+
+/// Limit `router` to handling `max_concurrent_requests` at the same time, across all routes.
+/// Requests over the limit are shed with a 503 right away, instead of piling up in memory
+/// while we are overloaded.
+pub fn with_concurrency_limit(router: Router, max_concurrent_requests: usize) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(shed_load))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_requests)),
+    )
+}
+
+/// Compress the responses of `router` of at least `min_bytes` with gzip or brotli, whichever the
+/// client prefers in its `Accept-Encoding`. Responses are left as is for clients accepting
+/// neither, and small ones aren't worth compressing.
+pub fn with_response_compression(router: Router, min_bytes: u16) -> Router {
+    let predicate = DefaultPredicate::new().and(SizeAbove::new(min_bytes));
+    router.layer(CompressionLayer::new().compress_when(predicate))
+}
+
+async fn shed_load(_: BoxError) -> StatusCode {
+    metrics::counter!("capture_load_shed_total").increment(1);
+    StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// Parses a comma-delimited list of media types, such as `application/json,text/plain`.
+pub fn parse_content_types(content_types: &str) -> HashSet<String> {
+    content_types
+        .split(',')
+        .map(|content_type| content_type.trim().to_lowercase())
+        .filter(|content_type| !content_type.is_empty())
+        .collect()
+}
+
+/// Reject requests to `router` whose content-type is not one of `allowed` with a 415, before
+/// their body is parsed. Parameters such as `charset` are ignored, and requests without a
+/// content-type are let through, as older SDKs don't set it.
+pub fn with_content_type_allowlist(router: Router, allowed: HashSet<String>) -> Router {
+    let allowed = Arc::new(allowed);
+    router.layer(axum::middleware::from_fn(
+        move |req: Request, next: Next| {
+            let allowed = allowed.clone();
+            async move { check_content_type(&allowed, req, next).await }
+        },
+    ))
+}
+
+async fn check_content_type(allowed: &HashSet<String>, req: Request, next: Next) -> Response {
+    if let Some(content_type) = req.headers().get(header::CONTENT_TYPE) {
+        let media_type = content_type
+            .to_str()
+            .unwrap_or_default()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if !allowed.contains(&media_type) {
+            metrics::counter!("capture_unsupported_content_type_total").increment(1);
+            return CaptureError::UnsupportedContentType(media_type).into_response();
+        }
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_sheds_requests_over_the_concurrency_limit() {
+        let started = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let (handler_started, handler_release) = (started.clone(), release.clone());
+        let router = Router::new().route(
+            "/",
+            get(move || async move {
+                handler_started.notify_one();
+                handler_release.notified().await;
+                "done"
+            }),
+        );
+        let router = with_concurrency_limit(router, 1);
+
+        let request = || Request::builder().uri("/").body(Body::empty()).unwrap();
+
+        // Hold the only slot until released
+        let in_flight = tokio::spawn(router.clone().oneshot(request()));
+        started.notified().await;
+
+        let response = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        release.notify_one();
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The slot is free again, let the next request through right away
+        release.notify_one();
+        let response = router.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    async fn get_with_encoding(router: Router, uri: &str, accept: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT_ENCODING, accept);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_compresses_large_responses_for_clients_accepting_it() {
+        use std::io::Read;
+
+        // Validation results listing every schema violation of an event
+        let violations = "\"$current_url\" is a required property; ".repeat(100);
+        let expected =
+            CaptureError::SchemaViolation("$pageview".to_string(), violations.clone()).to_string();
+        let router = Router::new()
+            .route(
+                "/large",
+                get(move || {
+                    let violations = violations.clone();
+                    async move { CaptureError::SchemaViolation("$pageview".to_string(), violations) }
+                }),
+            )
+            .route("/small", get(|| async { "ok" }));
+        let router = with_response_compression(router, 1024);
+
+        let response = get_with_encoding(router.clone(), "/large", Some("gzip")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.len() < expected.len());
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decompressed)
+            .expect("failed to decompress the response");
+        assert_eq!(decompressed, expected);
+
+        // Clients that don't accept compressed responses get them as is
+        let response = get_with_encoding(router.clone(), "/large", None).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, expected.as_bytes());
+
+        let response = get_with_encoding(router.clone(), "/large", Some("identity")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        // Small responses are never compressed
+        let response = get_with_encoding(router, "/small", Some("gzip, br")).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "ok");
+    }
+
+    async fn post_with_content_type(router: Router, content_type: Option<&str>) -> StatusCode {
+        let mut request = Request::builder().method(Method::POST).uri("/");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        router
+            .oneshot(request.body(Body::from("{}")).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn it_rejects_content_types_not_in_the_allowlist() {
+        let router = Router::new().route("/", post(|| async { "done" }));
+        let router = with_content_type_allowlist(
+            router,
+            parse_content_types("application/json, application/x-www-form-urlencoded"),
+        );
+
+        assert_eq!(
+            post_with_content_type(router.clone(), Some("application/json")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_with_content_type(router.clone(), Some("application/xml")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        // Requests without a content-type are parsed as JSON
+        assert_eq!(post_with_content_type(router, None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_allows_form_content_type_with_parameters() {
+        let router = Router::new().route("/", post(|| async { "done" }));
+        let router = with_content_type_allowlist(
+            router,
+            parse_content_types("application/json,application/x-www-form-urlencoded"),
+        );
+
+        assert_eq!(
+            post_with_content_type(
+                router.clone(),
+                Some("application/x-www-form-urlencoded; charset=UTF-8")
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_with_content_type(router, Some("Application/X-WWW-Form-Urlencoded")).await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use jsonschema::JSONSchema;
+use metrics::counter;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::api::CaptureError;
+use crate::v0_request::RawEvent;
+
+/// Number of schema errors listed in the error returned for a non-conforming event.
+const MAX_REPORTED_ERRORS: usize = 5;
+
+#[derive(Error, Debug)]
+pub enum EventSchemasError {
+    #[error("failed to read event schemas: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse event schemas: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("event schemas must map tokens to objects of event schemas, got {0}")]
+    InvalidLayout(String),
+    #[error("invalid schema for event {event} of token {token}: {reason}")]
+    InvalidSchema {
+        token: String,
+        event: String,
+        reason: String,
+    },
+}
+
+/// JSON Schemas that the properties of events must conform to, keyed by project token and event
+/// name. Events without a schema are accepted as is.
+#[derive(Default)]
+pub struct EventSchemas {
+    schemas: HashMap<(String, String), JSONSchema>,
+}
+
+impl EventSchemas {
+    /// Load schemas from a JSON file shaped like `{"<token>": {"<event name>": <schema>}}`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, EventSchemasError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_value(serde_json::from_str(&contents)?)
+    }
+
+    /// Compile the schemas of `value`, shaped like the file of `from_file`.
+    pub fn from_value(value: Value) -> Result<Self, EventSchemasError> {
+        let tokens = match value {
+            Value::Object(tokens) => tokens,
+            other => return Err(EventSchemasError::InvalidLayout(other.to_string())),
+        };
+
+        let mut schemas = HashMap::new();
+        for (token, events) in tokens {
+            let events = match events {
+                Value::Object(events) => events,
+                other => return Err(EventSchemasError::InvalidLayout(other.to_string())),
+            };
+            for (event, schema) in events {
+                let compiled =
+                    JSONSchema::compile(&schema).map_err(|e| EventSchemasError::InvalidSchema {
+                        token: token.clone(),
+                        event: event.clone(),
+                        reason: e.to_string(),
+                    })?;
+                schemas.insert((token.clone(), event), compiled);
+            }
+        }
+
+        Ok(Self { schemas })
+    }
+
+    /// Check the properties of `event` against the schema of its token and name, if there is one.
+    pub fn validate(&self, token: &str, event: &RawEvent) -> Result<(), CaptureError> {
+        let Some(schema) = self.schemas.get(&(token.to_string(), event.event.clone())) else {
+            return Ok(());
+        };
+
+        let properties = Value::Object(event.properties.clone().into_iter().collect());
+        if let Err(errors) = schema.validate(&properties) {
+            counter!("capture_schema_violations_total").increment(1);
+            let errors: Vec<String> = errors
+                .take(MAX_REPORTED_ERRORS)
+                .map(|e| match e.instance_path.to_string().as_str() {
+                    "" => e.to_string(),
+                    path => format!("{}: {}", path, e),
+                })
+                .collect();
+            return Err(CaptureError::SchemaViolation(
+                event.event.clone(),
+                errors.join("; "),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schemas() -> EventSchemas {
+        EventSchemas::from_value(json!({
+            "token": {
+                "purchase": {
+                    "type": "object",
+                    "properties": {
+                        "amount": {"type": "number"},
+                        "currency": {"type": "string", "enum": ["EUR", "USD"]},
+                    },
+                    "required": ["amount", "currency"],
+                }
+            }
+        }))
+        .expect("failed to compile schemas")
+    }
+
+    fn event(name: &str, properties: Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": name,
+            "distinct_id": "id1",
+            "properties": properties,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_accepts_conforming_events() {
+        let event = event("purchase", json!({"amount": 12.5, "currency": "EUR"}));
+        assert!(schemas().validate("token", &event).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_non_conforming_events() {
+        let event = event("purchase", json!({"amount": "12.5"}));
+        match schemas().validate("token", &event) {
+            Err(CaptureError::SchemaViolation(name, details)) => {
+                assert_eq!(name, "purchase");
+                assert!(details.contains("/amount"), "{}", details);
+                assert!(details.contains("currency"), "{}", details);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_accepts_events_without_a_schema() {
+        let schemas = schemas();
+        let pageview = event("$pageview", json!({"amount": "not a number"}));
+        assert!(schemas.validate("token", &pageview).is_ok());
+
+        // Schemas only apply to the events of their token
+        let purchase = event("purchase", json!({}));
+        assert!(schemas.validate("other_token", &purchase).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_invalid_schemas() {
+        let result = EventSchemas::from_value(json!({"token": {"purchase": {"type": 12}}}));
+        assert!(matches!(
+            result,
+            Err(EventSchemasError::InvalidSchema { .. })
+        ));
+
+        let result = EventSchemas::from_value(json!({"token": []}));
+        assert!(matches!(result, Err(EventSchemasError::InvalidLayout(_))));
+    }
+}
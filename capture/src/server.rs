@@ -1,19 +1,116 @@
 use std::future::Future;
 use std::net::SocketAddr;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use health::{ComponentStatus, HealthRegistry};
+use metrics_drain::MetricsDrain;
 use time::Duration;
 use tokio::net::TcpListener;
 
-use crate::config::Config;
+use crate::config::{Config, EventConfig, KafkaConfig};
+use crate::enrichers::{EventEnrichers, GeoIpEnricher, PropertyRedactionEnricher};
 
 use crate::limiters::billing::BillingLimiter;
+use crate::limiters::in_flight::InFlightLimiter;
 use crate::limiters::overflow::OverflowLimiter;
+use crate::limiters::team::TeamRateLimiter;
+use crate::receipts::Receipts;
 use crate::redis::RedisClient;
-use crate::router;
+use crate::router::{self, RouterOptions};
+use crate::schemas::EventSchemas;
 use crate::sinks::kafka::KafkaSink;
 use crate::sinks::print::PrintSink;
+use crate::sinks::routing::{parse_routes, SinkRouter};
+use crate::sinks::split::WeightedSink;
+use crate::sinks::Event;
+use crate::v0_endpoint::{EventProcessor, FutureSkewLimit, PropertiesLimit, TimestampProperty};
+
+/// Builds the processor applying the event settings, with their enrichers.
+pub fn event_processor(config: EventConfig) -> EventProcessor {
+    let processor = match NonZeroUsize::new(config.event_processing_threads) {
+        None => EventProcessor::default(),
+        Some(threads) => {
+            EventProcessor::parallel(threads, config.event_processing_parallel_threshold)
+                .expect("failed to create event processing thread pool")
+        }
+    };
+    let processor = match config.event_properties_max_bytes {
+        None => processor,
+        Some(max_bytes) => processor.with_properties_limit(PropertiesLimit {
+            max_bytes,
+            mode: config.event_properties_oversized_mode,
+        }),
+    };
+    let processor = match config.event_max_future_skew_secs {
+        None => processor,
+        Some(max_skew_secs) => processor.with_future_skew_limit(FutureSkewLimit {
+            max_skew: Duration::seconds(i64::from(max_skew_secs)),
+            mode: config.event_future_dated_mode,
+        }),
+    };
+    let processor = match config.event_timestamp_property {
+        None => processor,
+        Some(name) => processor.with_timestamp_property(TimestampProperty { name }),
+    };
+    let processor = match config.event_distinct_id_fields {
+        None => processor,
+        Some(fields) => processor.with_distinct_id_fields(fields),
+    };
+    let processor = match config.event_data_type_rules {
+        None => processor,
+        Some(rules) => processor.with_data_type_rules(rules),
+    };
+    let processor = match config.event_validate_group_identify {
+        false => processor,
+        true => processor.with_group_identify_validation(),
+    };
+    let processor = match config.event_validate_exceptions {
+        false => processor,
+        true => processor.with_exception_validation(),
+    };
+    let processor = match config.event_schemas_path {
+        None => processor,
+        Some(path) => processor.with_event_schemas(Arc::new(
+            EventSchemas::from_file(path).expect("failed to load event schemas"),
+        )),
+    };
+
+    let mut enrichers: EventEnrichers = Vec::new();
+    if let Some(path) = config.event_geoip_database_path {
+        enrichers.push(Box::new(
+            GeoIpEnricher::from_file(path).expect("failed to load geoip database"),
+        ));
+    }
+    if let Some(properties) = config.event_redacted_properties {
+        enrichers.push(Box::new(PropertyRedactionEnricher::new(
+            properties
+                .split(',')
+                .map(str::trim)
+                .filter(|property| !property.is_empty())
+                .map(str::to_string)
+                .collect(),
+        )));
+    }
+    match enrichers.is_empty() {
+        true => processor,
+        false => processor.with_enrichers(enrichers),
+    }
+}
+
+/// Routes the tokens of `token_topics`, comma-delimited token=topic pairs, to their topic on `sink`.
+pub fn route_token_topics(
+    mut sinks: SinkRouter,
+    sink: &KafkaSink,
+    token_topics: Option<&str>,
+) -> SinkRouter {
+    if let Some(token_topics) = token_topics {
+        for (token, topic) in parse_routes(token_topics).expect("invalid KAFKA_TOKEN_TOPICS") {
+            sinks = sinks.route(&token, Arc::new(sink.with_topic(&topic)));
+        }
+    }
+    sinks
+}
 
 pub async fn serve<F>(config: Config, listener: TcpListener, shutdown: F)
 where
@@ -24,8 +121,40 @@ where
     let redis_client =
         Arc::new(RedisClient::new(config.redis_url).expect("failed to create redis client"));
 
+    let receipts = config
+        .receipt_ttl_secs
+        .map(|ttl_secs| Receipts::new(redis_client.clone(), ttl_secs));
     let billing = BillingLimiter::new(Duration::seconds(5), redis_client.clone())
         .expect("failed to create billing limiter");
+    let in_flight = InFlightLimiter::new(config.max_in_flight_batches_per_token);
+    let rate_limiter = match (
+        config.team_rate_limit_per_second,
+        config.team_rate_limit_overrides,
+    ) {
+        (None, None) => None,
+        (per_second, overrides) => {
+            let rate_limiter = TeamRateLimiter::new(per_second, overrides.unwrap_or_default());
+            {
+                // Ensure that the rate limiter state does not grow unbounded
+                let rate_limiter = rate_limiter.clone();
+                tokio::spawn(async move {
+                    rate_limiter.clean_state().await;
+                });
+            }
+            Some(rate_limiter)
+        }
+    };
+
+    let processor = event_processor(config.event);
+
+    let options = RouterOptions {
+        rate_limiter,
+        in_flight: Some(in_flight),
+        processor,
+        receipts,
+        plain_text_base64: config.plain_text_base64,
+        metrics: config.export_prometheus,
+    };
 
     let app = if config.print_sink {
         // Print sink is only used for local debug, don't allow a container with it to run on prod
@@ -35,13 +164,18 @@ where
             .report_status(ComponentStatus::Unhealthy)
             .await;
 
+        let print_sink = match config.print_sink_batch_framing {
+            false => PrintSink::default(),
+            true => PrintSink::default().with_batch_framing(),
+        };
+
         router::router(
             crate::time::SystemTime {},
             liveness,
-            PrintSink {},
+            SinkRouter::new(Arc::new(print_sink)),
             redis_client,
             billing,
-            config.export_prometheus,
+            options,
         )
     } else {
         let sink_liveness = liveness
@@ -72,27 +206,102 @@ where
                 Some(partition)
             }
         };
-        let sink = KafkaSink::new(config.kafka, sink_liveness, partition)
+        let sink = KafkaSink::new(config.kafka.clone(), sink_liveness, partition.clone())
             .expect("failed to start Kafka sink");
+        let overflow_refresh =
+            std::time::Duration::from_secs(config.kafka.kafka_overflow_partitions_refresh_secs);
+        if partition.is_some() {
+            let sink = sink.clone();
+            tokio::spawn(async move {
+                sink.refresh_overflow_partitions_every(overflow_refresh)
+                    .await;
+            });
+        }
+
+        let default_sink: Arc<dyn Event + Send + Sync> = match &config.kafka_split_hosts {
+            None => Arc::new(sink.clone()),
+            Some(split_hosts) => {
+                let split_liveness = liveness
+                    .register("rdkafka_split".to_string(), Duration::seconds(30))
+                    .await;
+                let split_config = KafkaConfig {
+                    kafka_hosts: split_hosts.clone(),
+                    ..config.kafka.clone()
+                };
+                let split_sink = KafkaSink::new(split_config, split_liveness, partition.clone())
+                    .expect("failed to start split Kafka sink");
+                if partition.is_some() {
+                    let split_sink = split_sink.clone();
+                    tokio::spawn(async move {
+                        split_sink
+                            .refresh_overflow_partitions_every(overflow_refresh)
+                            .await;
+                    });
+                }
+                Arc::new(WeightedSink::new(
+                    Arc::new(sink.clone()),
+                    Arc::new(split_sink),
+                    config.kafka_split_percent,
+                ))
+            }
+        };
+
+        let sinks = route_token_topics(
+            SinkRouter::new(default_sink),
+            &sink,
+            config.kafka_token_topics.as_deref(),
+        );
 
         router::router(
             crate::time::SystemTime {},
             liveness,
-            sink,
+            sinks,
             redis_client,
             billing,
-            config.export_prometheus,
+            options,
         )
     };
 
-    // run our app with hyper
-    // `axum::Server` is a re-export of `hyper::Server`
+    let app = router::with_content_type_allowlist(
+        app,
+        router::parse_content_types(&config.allowed_content_types),
+    );
+
+    let app = match config.max_concurrent_requests {
+        None => app,
+        Some(max) => router::with_concurrency_limit(app, max),
+    };
+
+    let app = match config.response_compression_min_bytes {
+        None => app,
+        Some(min_bytes) => router::with_response_compression(app, min_bytes),
+    };
+
+    let metrics_drain = match config.export_prometheus {
+        true => MetricsDrain::new(std::time::Duration::from_secs(config.metrics_drain_secs)),
+        false => MetricsDrain::default(),
+    };
+    let shutdown = async move {
+        shutdown.await;
+        metrics_drain.run().await;
+    };
+
     tracing::info!("listening on {:?}", listener.local_addr().unwrap());
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown)
-    .await
-    .unwrap()
+    match (config.tls_cert_path, config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            tls::serve_tls(app, listener, cert_path, key_path, shutdown).await
+        }
+        // Config::validate rejects having only one of TLS_CERT_PATH and TLS_KEY_PATH set
+        _ => {
+            // run our app with hyper
+            // `axum::Server` is a re-export of `hyper::Server`
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown)
+            .await
+            .unwrap()
+        }
+    }
 }
@@ -1,20 +1,29 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use health::HealthHandle;
 use metrics::{counter, gauge, histogram};
 use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord, Producer};
 use rdkafka::util::Timeout;
 use rdkafka::ClientConfig;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinSet;
-use tracing::log::{debug, error, info};
+use tracing::log::{debug, error, info, warn};
 use tracing::{info_span, instrument, Instrument};
 
 use crate::api::{CaptureError, DataType, ProcessedEvent};
 use crate::config::KafkaConfig;
 use crate::limiters::overflow::OverflowLimiter;
 use crate::prometheus::report_dropped_events;
+use crate::sinks::routing::parse_routes;
 use crate::sinks::Event;
 
 struct KafkaContext {
@@ -104,12 +113,72 @@ impl rdkafka::ClientContext for KafkaContext {
     }
 }
 
+/// Where the timestamp of produced records comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KafkaTimestampSource {
+    /// Let the producer set the time the record was produced.
+    ProduceTime,
+    /// The time capture received the event.
+    Now,
+    /// The time the client sent the event, falling back to produce time if it didn't say.
+    SentAt,
+    /// The time the event happened, see `ProcessedEvent::timestamp`.
+    EventTime,
+}
+
+impl FromStr for KafkaTimestampSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "produce_time" => Ok(KafkaTimestampSource::ProduceTime),
+            "now" => Ok(KafkaTimestampSource::Now),
+            "sent_at" => Ok(KafkaTimestampSource::SentAt),
+            "event_time" => Ok(KafkaTimestampSource::EventTime),
+            _ => Err(format!("unknown kafka timestamp source: {}", s)),
+        }
+    }
+}
+
+/// Spreads overflowed events round-robin across the partitions of the main topic, as looked up
+/// by `KafkaSink::refresh_overflow_partitions`.
+#[derive(Default)]
+struct OverflowPartitions {
+    /// Zero until known, leaving the partitioner to pick a random partition.
+    count: AtomicUsize,
+    next: AtomicUsize,
+}
+
+impl OverflowPartitions {
+    fn set_count(&self, count: usize) {
+        self.count.store(count, Ordering::Relaxed);
+    }
+
+    fn next(&self) -> Option<i32> {
+        match self.count.load(Ordering::Relaxed) {
+            0 => None,
+            count => i32::try_from(self.next.fetch_add(1, Ordering::Relaxed) % count).ok(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct KafkaSink {
     producer: FutureProducer<KafkaContext>,
     partition: Option<OverflowLimiter>,
     main_topic: String,
     historical_topic: String,
+    group_identify_topic: Option<String>,
+    exceptions_topic: Option<String>,
+    server_ingest_topic: Option<String>,
+    /// Topics of the values of the X-PostHog-Route header, events with another or no route go
+    /// to the topic of their data type.
+    route_topics: HashMap<String, String>,
+    timestamp_source: KafkaTimestampSource,
+    /// Bounds the produces waiting for an ACK, shared by the sinks of `with_topic` as they share
+    /// the producer's queue. Unbounded if unset.
+    in_flight: Option<Arc<Semaphore>>,
+    overflow_partitions: Arc<OverflowPartitions>,
 }
 
 impl KafkaSink {
@@ -120,6 +189,100 @@ impl KafkaSink {
     ) -> anyhow::Result<KafkaSink> {
         info!("connecting to Kafka brokers at {}...", config.kafka_hosts);
 
+        let route_topics = match &config.kafka_route_topics {
+            None => HashMap::new(),
+            Some(routes) => parse_routes(routes)
+                .map_err(anyhow::Error::msg)?
+                .into_iter()
+                .collect(),
+        };
+
+        let client_config = Self::client_config(&config);
+        debug!("rdkafka configuration: {:?}", client_config);
+        let producer: FutureProducer<KafkaContext> =
+            client_config.create_with_context(KafkaContext { liveness })?;
+
+        // Ping the cluster to make sure we can reach brokers, fail after 10 seconds
+        drop(producer.client().fetch_metadata(
+            Some("__consumer_offsets"),
+            Timeout::After(Duration::new(10, 0)),
+        )?);
+        info!("connected to Kafka brokers");
+
+        let sink = KafkaSink {
+            producer,
+            partition,
+            main_topic: config.kafka_topic,
+            historical_topic: config.kafka_historical_topic,
+            group_identify_topic: config.kafka_group_identify_topic,
+            exceptions_topic: config.kafka_exceptions_topic,
+            server_ingest_topic: config.kafka_server_ingest_topic,
+            route_topics,
+            timestamp_source: config.kafka_timestamp_source,
+            in_flight: config
+                .kafka_producer_max_in_flight
+                .map(|max| Arc::new(Semaphore::new(max))),
+            overflow_partitions: Arc::new(OverflowPartitions::default()),
+        };
+        if sink.partition.is_some() {
+            sink.refresh_overflow_partitions();
+        }
+        Ok(sink)
+    }
+
+    /// Look up the number of partitions of the main topic, that overflowed events are spread
+    /// across. Overflowed events are partitioned randomly until the topic exists, and the last
+    /// known count is kept if the lookup fails.
+    pub fn refresh_overflow_partitions(&self) {
+        let metadata = match self
+            .producer
+            .client()
+            .fetch_metadata(Some(&self.main_topic), Timeout::After(Duration::new(10, 0)))
+        {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                error!(
+                    "failed to fetch the partitions of {}: {}",
+                    self.main_topic, e
+                );
+                return;
+            }
+        };
+
+        let count = metadata
+            .topics()
+            .iter()
+            .find(|topic| topic.name() == self.main_topic && topic.error().is_none())
+            .map_or(0, |topic| topic.partitions().len());
+        if count == 0 {
+            warn!(
+                "topic {} has no partitions yet, overflowed events are partitioned randomly",
+                self.main_topic
+            );
+        }
+        gauge!("capture_kafka_overflow_partitions").set(count as f64);
+        self.overflow_partitions.set_count(count);
+    }
+
+    /// Refresh the partitions of the main topic every `interval`, for overflowed events to be
+    /// spread across partitions added later. Needs to be spawned in a separate task.
+    pub async fn refresh_overflow_partitions_every(&self, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await; // Refreshed once already when created
+        loop {
+            interval.tick().await;
+
+            let sink = self.clone();
+            if let Err(e) =
+                tokio::task::spawn_blocking(move || sink.refresh_overflow_partitions()).await
+            {
+                error!("failed to refresh overflow partitions: {}", e);
+            }
+        }
+    }
+
+    /// Builds the rdkafka producer configuration for `config`.
+    fn client_config(config: &KafkaConfig) -> ClientConfig {
         let mut client_config = ClientConfig::new();
         client_config
             .set("bootstrap.servers", &config.kafka_hosts)
@@ -130,35 +293,45 @@ impl KafkaSink {
                 "message.timeout.ms",
                 config.kafka_message_timeout_ms.to_string(),
             )
-            .set("compression.codec", config.kafka_compression_codec)
+            .set("compression.codec", &config.kafka_compression_codec)
             .set(
                 "queue.buffering.max.kbytes",
                 (config.kafka_producer_queue_mib * 1024).to_string(),
             );
 
+        // Left to librdkafka's defaults unless set
+        if let Some(level) = config.kafka_compression_level {
+            client_config.set("compression.level", level.to_string());
+        }
+        if let Some(batch_size) = config.kafka_producer_batch_size {
+            client_config.set("batch.size", batch_size.to_string());
+        }
+
         if config.kafka_tls {
             client_config
                 .set("security.protocol", "ssl")
                 .set("enable.ssl.certificate.verification", "false");
         };
 
-        debug!("rdkafka configuration: {:?}", client_config);
-        let producer: FutureProducer<KafkaContext> =
-            client_config.create_with_context(KafkaContext { liveness })?;
-
-        // Ping the cluster to make sure we can reach brokers, fail after 10 seconds
-        drop(producer.client().fetch_metadata(
-            Some("__consumer_offsets"),
-            Timeout::After(Duration::new(10, 0)),
-        )?);
-        info!("connected to Kafka brokers");
+        client_config
+    }
 
-        Ok(KafkaSink {
-            producer,
-            partition,
-            main_topic: config.kafka_topic,
-            historical_topic: config.kafka_historical_topic,
-        })
+    /// Returns a sink sharing this sink's producer, that sends all events to `topic`.
+    pub fn with_topic(&self, topic: &str) -> KafkaSink {
+        KafkaSink {
+            producer: self.producer.clone(),
+            partition: self.partition.clone(),
+            main_topic: topic.to_owned(),
+            historical_topic: topic.to_owned(),
+            group_identify_topic: Some(topic.to_owned()),
+            exceptions_topic: Some(topic.to_owned()),
+            server_ingest_topic: Some(topic.to_owned()),
+            route_topics: HashMap::new(),
+            timestamp_source: self.timestamp_source,
+            in_flight: self.in_flight.clone(),
+            // Unknown, and never refreshed, as the partitions are the main topic's
+            overflow_partitions: Arc::new(OverflowPartitions::default()),
+        }
     }
 
     pub fn flush(&self) -> Result<(), KafkaError> {
@@ -166,38 +339,121 @@ impl KafkaSink {
         self.producer.flush(Duration::new(30, 0))
     }
 
-    async fn kafka_send(&self, event: ProcessedEvent) -> Result<DeliveryFuture, CaptureError> {
+    /// Returns the timestamp to set on the record of `event`, in milliseconds since the epoch, or
+    /// `None` to use the produce time.
+    fn record_timestamp(&self, event: &ProcessedEvent) -> Option<i64> {
+        let timestamp = match self.timestamp_source {
+            KafkaTimestampSource::ProduceTime => None,
+            KafkaTimestampSource::Now => OffsetDateTime::parse(&event.now, &Rfc3339).ok(),
+            KafkaTimestampSource::SentAt => event.sent_at,
+            KafkaTimestampSource::EventTime => event.timestamp,
+        }?;
+
+        i64::try_from(timestamp.unix_timestamp_nanos() / 1_000_000).ok()
+    }
+
+    /// Returns the topic of the route of `event`, if it has a known one.
+    fn routed_topic(&self, event: &ProcessedEvent) -> Option<&str> {
+        event
+            .route
+            .as_ref()
+            .and_then(|route| self.route_topics.get(route))
+            .map(String::as_str)
+    }
+
+    /// Returns the headers to set on the record of `event`: its insert_id and metadata.
+    fn record_headers(event: &ProcessedEvent) -> OwnedHeaders {
+        let mut headers =
+            OwnedHeaders::new_with_capacity(1 + event.metadata.len()).insert(Header {
+                key: "insert_id",
+                value: Some(&event.insert_id),
+            });
+        for (key, value) in &event.metadata {
+            headers = headers.insert(Header {
+                key: key.as_str(),
+                value: Some(value),
+            });
+        }
+        headers
+    }
+
+    /// Wait for a produce to complete if `in_flight` are already waiting for an ACK, so that a
+    /// flood of events backpressures callers instead of filling the producer's queue.
+    async fn acquire_in_flight(&self) -> Option<OwnedSemaphorePermit> {
+        let in_flight = self.in_flight.clone()?;
+        let start = std::time::Instant::now();
+        let permit = in_flight
+            .acquire_owned()
+            .await
+            .expect("in-flight semaphore is never closed");
+        histogram!("capture_kafka_produce_permit_wait_seconds")
+            .record(start.elapsed().as_secs_f64());
+        Some(permit)
+    }
+
+    /// Enqueue `event` in the producer, returning its ACK along with the permit to release once
+    /// it has been received.
+    async fn kafka_send(
+        &self,
+        event: ProcessedEvent,
+    ) -> Result<(DeliveryFuture, Option<OwnedSemaphorePermit>), CaptureError> {
         let payload = serde_json::to_string(&event).map_err(|e| {
             error!("failed to serialize event: {}", e);
             CaptureError::NonRetryableSinkError
         })?;
 
         let event_key = event.key();
-        let (topic, partition_key): (&str, Option<&str>) = match &event.data_type {
-            DataType::AnalyticsHistorical => (&self.historical_topic, Some(event_key.as_str())), // We never trigger overflow on historical events
-            DataType::AnalyticsMain => {
-                // TODO: deprecate capture-led overflow or move logic in handler
-                let is_limited = match &self.partition {
-                    None => false,
-                    Some(partition) => partition.is_limited(&event_key),
-                };
-                if is_limited {
-                    (&self.main_topic, None) // Analytics overflow goes to the main topic without locality
-                } else {
-                    (&self.main_topic, Some(event_key.as_str()))
-                }
-            }
+        let dedicated_topic = match event.data_type {
+            DataType::GroupIdentify => self.group_identify_topic.as_ref(),
+            DataType::Exception => self.exceptions_topic.as_ref(),
+            DataType::ServerIngest => self.server_ingest_topic.as_ref(),
+            DataType::AnalyticsMain | DataType::AnalyticsHistorical => None,
         };
+        let mut partition = None;
+        let (topic, partition_key): (&str, Option<&str>) =
+            match (&event.data_type, dedicated_topic, self.routed_topic(&event)) {
+                // Routed events go to the topic of their route, whatever their data type
+                (_, _, Some(routed_topic)) => (routed_topic, Some(event_key.as_str())),
+                // We never trigger overflow on historical events
+                (DataType::AnalyticsHistorical, _, None) => {
+                    (&self.historical_topic, Some(event_key.as_str()))
+                }
+                (_, Some(dedicated_topic), None) => (dedicated_topic, Some(event_key.as_str())),
+                // Group identify, exception and server ingest events go through the main topic if
+                // no dedicated topic is configured
+                (
+                    DataType::AnalyticsMain
+                    | DataType::GroupIdentify
+                    | DataType::Exception
+                    | DataType::ServerIngest,
+                    None,
+                    None,
+                ) => {
+                    // TODO: deprecate capture-led overflow or move logic in handler
+                    let is_limited = match &self.partition {
+                        None => false,
+                        Some(partition) => partition.is_limited(&event_key),
+                    };
+                    if is_limited {
+                        // Analytics overflow goes to the main topic without locality
+                        partition = self.overflow_partitions.next();
+                        (&self.main_topic, None)
+                    } else {
+                        (&self.main_topic, Some(event_key.as_str()))
+                    }
+                }
+            };
 
+        let permit = self.acquire_in_flight().await;
         match self.producer.send_result(FutureRecord {
             topic,
             payload: Some(&payload),
-            partition: None,
+            partition,
             key: partition_key,
-            timestamp: None,
-            headers: None,
+            timestamp: self.record_timestamp(&event),
+            headers: Some(Self::record_headers(&event)),
         }) {
-            Ok(ack) => Ok(ack),
+            Ok(ack) => Ok((ack, permit)),
             Err((e, _)) => match e.rdkafka_error_code() {
                 Some(RDKafkaErrorCode::MessageSizeTooLarge) => {
                     report_dropped_events("kafka_message_size", 1);
@@ -213,7 +469,11 @@ impl KafkaSink {
         }
     }
 
-    async fn process_ack(delivery: DeliveryFuture) -> Result<(), CaptureError> {
+    /// Wait for the ACK of a produce, releasing its in-flight `_permit` once done.
+    async fn process_ack(
+        delivery: DeliveryFuture,
+        _permit: Option<OwnedSemaphorePermit>,
+    ) -> Result<(), CaptureError> {
         match delivery.await {
             Err(_) => {
                 // Cancelled due to timeout while retrying
@@ -244,9 +504,9 @@ impl KafkaSink {
 impl Event for KafkaSink {
     #[instrument(skip_all)]
     async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
-        let ack = self.kafka_send(event).await?;
+        let (ack, permit) = self.kafka_send(event).await?;
         histogram!("capture_event_batch_size").record(1.0);
-        Self::process_ack(ack)
+        Self::process_ack(ack, permit)
             .instrument(info_span!("ack_wait_one"))
             .await
     }
@@ -257,10 +517,10 @@ impl Event for KafkaSink {
         let batch_size = events.len();
         for event in events {
             // We await kafka_send to get events in the producer queue sequentially
-            let ack = self.kafka_send(event).await?;
+            let (ack, permit) = self.kafka_send(event).await?;
 
             // Then stash the returned DeliveryFuture, waiting concurrently for the write ACKs from brokers.
-            set.spawn(Self::process_ack(ack));
+            set.spawn(Self::process_ack(ack, permit));
         }
 
         // Await on all the produce promises, fail batch on first failure
@@ -294,18 +554,63 @@ mod tests {
     use crate::api::{CaptureError, DataType, ProcessedEvent};
     use crate::config;
     use crate::limiters::overflow::OverflowLimiter;
-    use crate::sinks::kafka::KafkaSink;
+    use crate::sinks::kafka::{KafkaSink, KafkaTimestampSource};
     use crate::sinks::Event;
     use crate::utils::uuid_v7;
     use health::HealthRegistry;
     use rand::distributions::Alphanumeric;
     use rand::Rng;
+    use rdkafka::message::Headers;
     use rdkafka::mocking::MockCluster;
     use rdkafka::producer::DefaultProducerContext;
     use rdkafka::types::{RDKafkaApiKey, RDKafkaRespErr};
+    use std::collections::HashMap;
     use std::num::NonZeroU32;
     use time::Duration;
 
+    fn mocked_config(
+        cluster: &MockCluster<'static, DefaultProducerContext>,
+    ) -> config::KafkaConfig {
+        config::KafkaConfig {
+            kafka_producer_linger_ms: 0,
+            kafka_producer_queue_mib: 50,
+            kafka_producer_batch_size: None,
+            kafka_producer_max_in_flight: None,
+            kafka_message_timeout_ms: 500,
+            kafka_compression_codec: "none".to_string(),
+            kafka_compression_level: None,
+            kafka_hosts: cluster.bootstrap_servers(),
+            kafka_topic: "events_plugin_ingestion".to_string(),
+            kafka_historical_topic: "events_plugin_ingestion_historical".to_string(),
+            kafka_group_identify_topic: None,
+            kafka_exceptions_topic: None,
+            kafka_server_ingest_topic: None,
+            kafka_route_topics: None,
+            kafka_overflow_partitions_refresh_secs: 60,
+            kafka_timestamp_source: KafkaTimestampSource::ProduceTime,
+            kafka_tls: false,
+        }
+    }
+
+    /// An analytics event of `token1` and `id1`, carrying `data`.
+    fn processed_event(data: String) -> ProcessedEvent {
+        ProcessedEvent {
+            data_type: DataType::AnalyticsMain,
+            uuid: uuid_v7(),
+            distinct_id: "id1".to_string(),
+            ip: None,
+            data,
+            now: "".to_string(),
+            sent_at: None,
+            sent_at_offset_ms: None,
+            timestamp: None,
+            token: "token1".to_string(),
+            insert_id: uuid_v7().to_string(),
+            metadata: HashMap::new(),
+            route: None,
+        }
+    }
+
     async fn start_on_mocked_sink() -> (MockCluster<'static, DefaultProducerContext>, KafkaSink) {
         let registry = HealthRegistry::new("liveness");
         let handle = registry
@@ -317,36 +622,178 @@ mod tests {
             None,
         ));
         let cluster = MockCluster::new(1).expect("failed to create mock brokers");
-        let config = config::KafkaConfig {
-            kafka_producer_linger_ms: 0,
-            kafka_producer_queue_mib: 50,
-            kafka_message_timeout_ms: 500,
-            kafka_compression_codec: "none".to_string(),
-            kafka_hosts: cluster.bootstrap_servers(),
-            kafka_topic: "events_plugin_ingestion".to_string(),
-            kafka_historical_topic: "events_plugin_ingestion_historical".to_string(),
-            kafka_tls: false,
-        };
+        let config = mocked_config(&cluster);
         let sink = KafkaSink::new(config, handle, limiter).expect("failed to create sink");
         (cluster, sink)
     }
 
+    #[tokio::test]
+    async fn kafka_sink_with_compression() {
+        let registry = HealthRegistry::new("liveness");
+        let handle = registry
+            .register("one".to_string(), Duration::seconds(30))
+            .await;
+        let cluster = MockCluster::new(1).expect("failed to create mock brokers");
+        let config = config::KafkaConfig {
+            kafka_compression_codec: "zstd".to_string(),
+            kafka_compression_level: Some(3),
+            kafka_producer_linger_ms: 50,
+            kafka_producer_batch_size: Some(262144),
+            ..mocked_config(&cluster)
+        };
+
+        let client_config = KafkaSink::client_config(&config);
+        assert_eq!(client_config.get("compression.codec"), Some("zstd"));
+        assert_eq!(client_config.get("compression.level"), Some("3"));
+        assert_eq!(client_config.get("linger.ms"), Some("50"));
+        assert_eq!(client_config.get("batch.size"), Some("262144"));
+
+        // Unset settings are left to librdkafka's defaults
+        let client_config = KafkaSink::client_config(&mocked_config(&cluster));
+        assert_eq!(client_config.get("compression.level"), None);
+        assert_eq!(client_config.get("batch.size"), None);
+
+        // librdkafka accepts the settings, and the compressed sink can produce
+        let sink = KafkaSink::new(config, handle, None).expect("failed to create sink");
+        let event = processed_event(String::new());
+        sink.send(event).await.expect("failed to send event");
+    }
+
+    #[tokio::test]
+    async fn kafka_sink_backpressures_past_max_in_flight() {
+        let registry = HealthRegistry::new("liveness");
+        let handle = registry
+            .register("one".to_string(), Duration::seconds(30))
+            .await;
+        let cluster = MockCluster::new(1).expect("failed to create mock brokers");
+
+        // 30 events of 200kB overflow a 1MiB producer queue, unless only 3 are in flight at once
+        let config = config::KafkaConfig {
+            kafka_producer_queue_mib: 1,
+            kafka_producer_max_in_flight: Some(3),
+            kafka_message_timeout_ms: 10000,
+            ..mocked_config(&cluster)
+        };
+        let sink = KafkaSink::new(config, handle, None).expect("failed to create sink");
+        let event = processed_event("a".repeat(200_000));
+
+        sink.send_batch(vec![event; 30])
+            .await
+            .expect("flooded sink didn't backpressure");
+        let in_flight = sink.in_flight.as_ref().expect("in-flight limit is set");
+        assert_eq!(in_flight.available_permits(), 3);
+    }
+
+    #[tokio::test]
+    async fn kafka_sink_routes_events_by_header() {
+        let registry = HealthRegistry::new("liveness");
+        let handle = registry
+            .register("one".to_string(), Duration::seconds(30))
+            .await;
+        let cluster = MockCluster::new(1).expect("failed to create mock brokers");
+        let config = config::KafkaConfig {
+            kafka_route_topics: Some("experimental=events_experimental".to_string()),
+            ..mocked_config(&cluster)
+        };
+        let sink = KafkaSink::new(config, handle, None).expect("failed to create sink");
+        let mut event = processed_event(String::new());
+
+        // Events without a route, or with an unknown one, go to the topic of their data type
+        assert_eq!(sink.routed_topic(&event), None);
+        event.route = Some("unknown".to_string());
+        assert_eq!(sink.routed_topic(&event), None);
+
+        event.route = Some("experimental".to_string());
+        assert_eq!(sink.routed_topic(&event), Some("events_experimental"));
+        sink.send(event).await.expect("failed to send routed event");
+    }
+
+    #[tokio::test]
+    async fn kafka_sink_spreads_overflow_across_partitions() {
+        let registry = HealthRegistry::new("liveness");
+        let handle = registry
+            .register("one".to_string(), Duration::seconds(30))
+            .await;
+        let cluster = MockCluster::new(1).expect("failed to create mock brokers");
+        cluster
+            .create_topic("events_plugin_ingestion", 5, 1)
+            .expect("failed to create topic");
+        let limiter = Some(OverflowLimiter::new(
+            NonZeroU32::new(10).unwrap(),
+            NonZeroU32::new(10).unwrap(),
+            Some("token1:id1".to_string()),
+        ));
+        let sink = KafkaSink::new(mocked_config(&cluster), handle, limiter)
+            .expect("failed to create sink");
+
+        // The partitions were looked up when the sink was created
+        let partitions: Vec<Option<i32>> =
+            (0..7).map(|_| sink.overflow_partitions.next()).collect();
+        assert_eq!(
+            partitions,
+            vec![
+                Some(0),
+                Some(1),
+                Some(2),
+                Some(3),
+                Some(4),
+                Some(0),
+                Some(1)
+            ]
+        );
+
+        // Overflowed events are produced to the partitions of the topic
+        let event = processed_event(String::new());
+        sink.send_batch(vec![event; 10])
+            .await
+            .expect("failed to send overflowed events");
+
+        // Sinks of other topics don't know their partitions, leaving it to the partitioner
+        assert_eq!(sink.with_topic("other").overflow_partitions.next(), None);
+    }
+
+    #[test]
+    fn kafka_record_carries_event_metadata() {
+        let mut event = processed_event(String::new());
+        event.insert_id = "abc123".to_string();
+
+        // Without metadata, the payload and headers are unchanged
+        let payload: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert!(payload.get("metadata").is_none());
+        let headers = KafkaSink::record_headers(&event);
+        assert_eq!(headers.count(), 1);
+
+        event.metadata = HashMap::from([
+            ("lib_version".to_string(), "1.2.3".to_string()),
+            ("user_agent".to_string(), "posthog-test".to_string()),
+        ]);
+        let payload: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            payload["metadata"],
+            serde_json::json!({"lib_version": "1.2.3", "user_agent": "posthog-test"})
+        );
+
+        let headers: HashMap<&str, &[u8]> = KafkaSink::record_headers(&event)
+            .iter()
+            .map(|header| (header.key, header.value.unwrap()))
+            .collect();
+        assert_eq!(
+            headers,
+            HashMap::from([
+                ("insert_id", b"abc123".as_slice()),
+                ("lib_version", b"1.2.3".as_slice()),
+                ("user_agent", b"posthog-test".as_slice()),
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn kafka_sink_error_handling() {
         // Uses a mocked Kafka broker that allows injecting write errors, to check error handling.
         // We test different cases in a single test to amortize the startup cost of the producer.
 
         let (cluster, sink) = start_on_mocked_sink().await;
-        let event: ProcessedEvent = ProcessedEvent {
-            data_type: DataType::AnalyticsMain,
-            uuid: uuid_v7(),
-            distinct_id: "id1".to_string(),
-            ip: "".to_string(),
-            data: "".to_string(),
-            now: "".to_string(),
-            sent_at: None,
-            token: "token1".to_string(),
-        };
+        let event = processed_event(String::new());
 
         // Wait for producer to be healthy, to keep kafka_message_timeout_ms short and tests faster
         for _ in 0..20 {
@@ -369,16 +816,7 @@ mod tests {
             .take(2_000_000)
             .map(char::from)
             .collect();
-        let big_event: ProcessedEvent = ProcessedEvent {
-            data_type: DataType::AnalyticsMain,
-            uuid: uuid_v7(),
-            distinct_id: "id1".to_string(),
-            ip: "".to_string(),
-            data: big_data,
-            now: "".to_string(),
-            sent_at: None,
-            token: "token1".to_string(),
-        };
+        let big_event = processed_event(big_data);
         match sink.send(big_event).await {
             Err(CaptureError::EventTooBig) => {} // Expected
             Err(err) => panic!("wrong error code {}", err),
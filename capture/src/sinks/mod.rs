@@ -4,6 +4,8 @@ use crate::api::{CaptureError, ProcessedEvent};
 
 pub mod kafka;
 pub mod print;
+pub mod routing;
+pub mod split;
 
 #[async_trait]
 pub trait Event {
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use async_trait::async_trait;
 use metrics::{counter, histogram};
 use tracing::log::info;
@@ -5,7 +7,47 @@ use tracing::log::info;
 use crate::api::{CaptureError, ProcessedEvent};
 use crate::sinks::Event;
 
-pub struct PrintSink {}
+#[derive(Default)]
+pub struct PrintSink {
+    batch_framing: bool,
+}
+
+impl PrintSink {
+    /// Wrap the events of each batch between `BEGIN BATCH` and `END BATCH` lines, the latter
+    /// summarizing the batch, to tell batches apart when capturing test fixtures.
+    pub fn with_batch_framing(mut self) -> Self {
+        self.batch_framing = true;
+        self
+    }
+
+    fn batch_lines(&self, events: &[ProcessedEvent]) -> Vec<String> {
+        let mut lines = Vec::with_capacity(events.len() + 2);
+        if self.batch_framing {
+            lines.push("BEGIN BATCH".to_string());
+        }
+        for event in events {
+            lines.push(format!("event: {:?}", event));
+        }
+        if self.batch_framing {
+            let mut data_types: BTreeMap<String, usize> = BTreeMap::new();
+            for event in events {
+                *data_types
+                    .entry(format!("{:?}", event.data_type))
+                    .or_default() += 1;
+            }
+            let data_types: Vec<String> = data_types
+                .iter()
+                .map(|(data_type, count)| format!("{}: {}", data_type, count))
+                .collect();
+            lines.push(format!(
+                "END BATCH {} events ({})",
+                events.len(),
+                data_types.join(", ")
+            ));
+        }
+        lines
+    }
+}
 
 #[async_trait]
 impl Event for PrintSink {
@@ -21,10 +63,69 @@ impl Event for PrintSink {
 
         histogram!("capture_event_batch_size").record(events.len() as f64);
         counter!("capture_events_ingested_total").increment(events.len() as u64);
-        for event in events {
-            info!("event: {:?}", event);
+        for line in self.batch_lines(&events) {
+            info!("{}", line);
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::api::DataType;
+    use crate::utils::uuid_v7;
+
+    fn event(data_type: DataType) -> ProcessedEvent {
+        ProcessedEvent {
+            data_type,
+            uuid: uuid_v7(),
+            distinct_id: "id1".to_string(),
+            ip: None,
+            data: "".to_string(),
+            now: "".to_string(),
+            sent_at: None,
+            sent_at_offset_ms: None,
+            timestamp: None,
+            token: "token1".to_string(),
+            insert_id: uuid_v7().to_string(),
+            metadata: HashMap::new(),
+            route: None,
+        }
+    }
+
+    #[test]
+    fn it_frames_batches() {
+        let events = vec![
+            event(DataType::AnalyticsMain),
+            event(DataType::GroupIdentify),
+            event(DataType::AnalyticsMain),
+        ];
+
+        let lines = PrintSink::default()
+            .with_batch_framing()
+            .batch_lines(&events);
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "BEGIN BATCH");
+        for (line, event) in lines[1..4].iter().zip(&events) {
+            assert_eq!(line, &format!("event: {:?}", event));
+        }
+        assert_eq!(
+            lines[4],
+            "END BATCH 3 events (AnalyticsMain: 2, GroupIdentify: 1)"
+        );
+    }
+
+    #[test]
+    fn it_only_prints_events_without_framing() {
+        let events = vec![event(DataType::AnalyticsMain)];
+
+        let lines = PrintSink::default().batch_lines(&events);
+
+        assert_eq!(lines, vec![format!("event: {:?}", events[0])]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::sinks::Event;
+
+/// Picks the sink to send events to based on the token they were captured with.
+///
+/// Some teams need their events to land in a dedicated topic or cluster, for data-residency
+/// reasons. Their tokens are routed to a dedicated sink, while every other token goes to the
+/// shared default sink.
+#[derive(Clone)]
+pub struct SinkRouter {
+    default: Arc<dyn Event + Send + Sync>,
+    routes: HashMap<String, Arc<dyn Event + Send + Sync>>,
+}
+
+impl SinkRouter {
+    pub fn new(default: Arc<dyn Event + Send + Sync>) -> Self {
+        Self {
+            default,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Send the events of `token` to `sink` instead of the default sink.
+    pub fn route(mut self, token: &str, sink: Arc<dyn Event + Send + Sync>) -> Self {
+        self.routes.insert(token.to_owned(), sink);
+        self
+    }
+
+    /// Return the sink to send the events of `token` to.
+    pub fn sink_for(&self, token: &str) -> Arc<dyn Event + Send + Sync> {
+        self.routes.get(token).unwrap_or(&self.default).clone()
+    }
+}
+
+/// Parse comma-delimited `token=destination` pairs, as used to configure routes.
+pub fn parse_routes(routes: &str) -> Result<Vec<(String, String)>, String> {
+    routes
+        .split(',')
+        .map(str::trim)
+        .filter(|route| !route.is_empty())
+        .map(|route| match route.split_once('=') {
+            Some((token, destination)) if !token.is_empty() && !destination.is_empty() => {
+                Ok((token.trim().to_owned(), destination.trim().to_owned()))
+            }
+            _ => Err(format!("invalid sink route: {}", route)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::api::{CaptureError, ProcessedEvent};
+    use crate::v0_endpoint::{process_events, EventProcessor};
+    use crate::v0_request::{ProcessingContext, RawEvent};
+
+    #[derive(Default)]
+    struct StubSink {
+        events: Mutex<Vec<ProcessedEvent>>,
+    }
+
+    impl StubSink {
+        fn tokens(&self) -> Vec<String> {
+            let events = self.events.lock().unwrap();
+            events.iter().map(|e| e.token.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl Event for StubSink {
+        async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().extend(events);
+            Ok(())
+        }
+    }
+
+    fn context(token: &str) -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            sent_at: None,
+            token: token.to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            user_agent: None,
+            route: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_routes_events_by_token() {
+        let default = Arc::new(StubSink::default());
+        let dedicated = Arc::new(StubSink::default());
+        let sinks = SinkRouter::new(default.clone()).route("phc_dedicated", dedicated.clone());
+
+        let events: Vec<RawEvent> = serde_json::from_str(
+            r#"[{"event": "e1", "distinct_id": "id1"}, {"event": "e2", "distinct_id": "id2"}]"#,
+        )
+        .unwrap();
+        let processor = EventProcessor::default();
+
+        process_events(&sinks, &processor, &events, &context("phc_dedicated"))
+            .await
+            .expect("failed to process events");
+        process_events(&sinks, &processor, &events[..1], &context("phc_shared"))
+            .await
+            .expect("failed to process events");
+
+        assert_eq!(dedicated.tokens(), vec!["phc_dedicated", "phc_dedicated"]);
+        assert_eq!(default.tokens(), vec!["phc_shared"]);
+    }
+
+    #[test]
+    fn it_parses_routes() {
+        assert_eq!(
+            parse_routes("phc_a=topic_a, phc_b=topic_b,").unwrap(),
+            vec![
+                ("phc_a".to_string(), "topic_a".to_string()),
+                ("phc_b".to_string(), "topic_b".to_string()),
+            ]
+        );
+        assert!(parse_routes("").unwrap().is_empty());
+        assert!(parse_routes("phc_a").is_err());
+        assert!(parse_routes("phc_a=").is_err());
+    }
+}
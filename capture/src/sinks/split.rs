@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use metrics::counter;
+
+use crate::api::{CaptureError, ProcessedEvent};
+use crate::sinks::Event;
+
+/// Number of buckets distinct_ids are hashed into, giving the split a 0.01% resolution.
+const BUCKETS: u64 = 10_000;
+
+/// Sends a share of events to a candidate sink and the rest to the primary one, to validate a
+/// new ingestion path, like a new Kafka cluster, under real load.
+///
+/// Events are split by distinct_id, so that the events of a given user all take the same path.
+pub struct WeightedSink {
+    primary: Arc<dyn Event + Send + Sync>,
+    candidate: Arc<dyn Event + Send + Sync>,
+    candidate_buckets: u64,
+}
+
+impl WeightedSink {
+    /// `candidate_percent` of the distinct_ids go to `candidate`, clamped between 0 and 100.
+    pub fn new(
+        primary: Arc<dyn Event + Send + Sync>,
+        candidate: Arc<dyn Event + Send + Sync>,
+        candidate_percent: f64,
+    ) -> Self {
+        let candidate_buckets =
+            (candidate_percent.clamp(0.0, 100.0) / 100.0 * BUCKETS as f64).round() as u64;
+        Self {
+            primary,
+            candidate,
+            candidate_buckets,
+        }
+    }
+
+    fn is_candidate(&self, event: &ProcessedEvent) -> bool {
+        bucket(&event.distinct_id) < self.candidate_buckets
+    }
+
+    async fn send_to(
+        sink: &Arc<dyn Event + Send + Sync>,
+        path: &'static str,
+        mut events: Vec<ProcessedEvent>,
+    ) -> Result<(), CaptureError> {
+        counter!("capture_events_split_total", "path" => path).increment(events.len() as u64);
+        match events.len() {
+            0 => Ok(()),
+            1 => sink.send(events.pop().expect("one event")).await,
+            _ => sink.send_batch(events).await,
+        }
+    }
+}
+
+/// Hash a distinct_id into one of the buckets, with FNV-1a so that the bucket of a distinct_id
+/// stays the same across releases.
+fn bucket(distinct_id: &str) -> u64 {
+    let hash = distinct_id
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    hash % BUCKETS
+}
+
+#[async_trait]
+impl Event for WeightedSink {
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        match self.is_candidate(&event) {
+            true => Self::send_to(&self.candidate, "candidate", vec![event]).await,
+            false => Self::send_to(&self.primary, "primary", vec![event]).await,
+        }
+    }
+
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        let (candidate, primary): (Vec<_>, Vec<_>) = events
+            .into_iter()
+            .partition(|event| self.is_candidate(event));
+
+        let (primary, candidate) = tokio::join!(
+            Self::send_to(&self.primary, "primary", primary),
+            Self::send_to(&self.candidate, "candidate", candidate),
+        );
+        primary.and(candidate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::api::DataType;
+    use crate::utils::uuid_v7;
+
+    #[derive(Default)]
+    struct StubSink {
+        events: Mutex<Vec<ProcessedEvent>>,
+    }
+
+    impl StubSink {
+        fn distinct_ids(&self) -> Vec<String> {
+            let events = self.events.lock().unwrap();
+            events.iter().map(|e| e.distinct_id.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl Event for StubSink {
+        async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().extend(events);
+            Ok(())
+        }
+    }
+
+    fn event(distinct_id: &str) -> ProcessedEvent {
+        ProcessedEvent {
+            data_type: DataType::AnalyticsMain,
+            uuid: uuid_v7(),
+            distinct_id: distinct_id.to_string(),
+            ip: None,
+            data: "".to_string(),
+            now: "".to_string(),
+            sent_at: None,
+            sent_at_offset_ms: None,
+            timestamp: None,
+            token: "token1".to_string(),
+            insert_id: uuid_v7().to_string(),
+            metadata: HashMap::new(),
+            route: None,
+        }
+    }
+
+    fn sinks(candidate_percent: f64) -> (Arc<StubSink>, Arc<StubSink>, WeightedSink) {
+        let primary = Arc::new(StubSink::default());
+        let candidate = Arc::new(StubSink::default());
+        let sink = WeightedSink::new(primary.clone(), candidate.clone(), candidate_percent);
+        (primary, candidate, sink)
+    }
+
+    #[tokio::test]
+    async fn it_honors_the_split_ratio() {
+        let (primary, candidate, sink) = sinks(20.0);
+
+        let events = (0..10_000).map(|i| event(&format!("user-{}", i))).collect();
+        sink.send_batch(events).await.unwrap();
+
+        let candidate_count = candidate.distinct_ids().len();
+        assert_eq!(primary.distinct_ids().len() + candidate_count, 10_000);
+        assert!(
+            (1_800..=2_200).contains(&candidate_count),
+            "{} events went to the candidate",
+            candidate_count
+        );
+    }
+
+    #[tokio::test]
+    async fn it_keeps_distinct_ids_on_one_path() {
+        let (primary, candidate, sink) = sinks(50.0);
+
+        for _ in 0..3 {
+            let events = (0..100).map(|i| event(&format!("user-{}", i))).collect();
+            sink.send_batch(events).await.unwrap();
+            for i in 0..100 {
+                sink.send(event(&format!("user-{}", i))).await.unwrap();
+            }
+        }
+
+        let primary_ids = primary.distinct_ids();
+        let candidate_ids = candidate.distinct_ids();
+        assert!(!primary_ids.is_empty());
+        assert!(!candidate_ids.is_empty());
+        for id in &candidate_ids {
+            assert!(!primary_ids.contains(id), "{} took both paths", id);
+        }
+        // Every distinct_id got all of its 6 events on its path
+        assert_eq!(primary_ids.len() + candidate_ids.len(), 600);
+        for id in candidate_ids.iter().chain(&primary_ids) {
+            let count = candidate_ids
+                .iter()
+                .chain(&primary_ids)
+                .filter(|other| *other == id)
+                .count();
+            assert_eq!(count, 6);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_sends_everything_to_one_path_at_the_bounds() {
+        let (primary, candidate, sink) = sinks(0.0);
+        let events = (0..100).map(|i| event(&format!("user-{}", i))).collect();
+        sink.send_batch(events).await.unwrap();
+        assert_eq!(primary.distinct_ids().len(), 100);
+        assert!(candidate.distinct_ids().is_empty());
+
+        let (primary, candidate, sink) = sinks(100.0);
+        let events = (0..100).map(|i| event(&format!("user-{}", i))).collect();
+        sink.send_batch(events).await.unwrap();
+        assert!(primary.distinct_ids().is_empty());
+        assert_eq!(candidate.distinct_ids().len(), 100);
+    }
+
+    #[test]
+    fn it_hashes_distinct_ids_stably() {
+        // Pinned, so that changing the hash, and moving users across paths, is deliberate
+        assert_eq!(bucket("user-1"), 708);
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::redis::Client;
+
+// TRICKY: This cache data is coming from django-redis. If it ever goes out of sync, we'll bork.
+pub const TEAM_TOKEN_CACHE_PREFIX: &str = "posthog:1:team_token:";
+
+#[derive(Error, Debug)]
+pub enum TeamError {
+    #[error("no team found for the token")]
+    NotFound,
+    #[error("failed to fetch team: {0}")]
+    Redis(#[from] anyhow::Error),
+    #[error("failed to parse team: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// The team of a token, as cached by django. Only the fields capture needs are parsed.
+#[derive(Debug, Deserialize)]
+pub struct Team {
+    pub id: i64,
+}
+
+impl Team {
+    /// Returns the team of `token` from the cache django keeps in redis.
+    pub async fn from_redis(
+        client: &(dyn Client + Send + Sync),
+        token: &str,
+    ) -> Result<Team, TeamError> {
+        let serialized_team = client
+            .get_pickled(format!("{TEAM_TOKEN_CACHE_PREFIX}{}", token))
+            .await?
+            .ok_or(TeamError::NotFound)?;
+
+        Ok(serde_json::from_str(&serialized_team)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::MockRedisClient;
+
+    #[tokio::test]
+    async fn it_fetches_the_team_of_a_token() {
+        let client = MockRedisClient::new();
+        client
+            .set_ex(
+                format!("{TEAM_TOKEN_CACHE_PREFIX}token"),
+                r#"{"id": 42, "name": "team", "api_token": "token"}"#.to_string(),
+                60,
+            )
+            .await
+            .expect("failed to store team");
+
+        let team = Team::from_redis(&client, "token")
+            .await
+            .expect("failed to fetch team");
+        assert_eq!(team.id, 42);
+
+        assert!(matches!(
+            Team::from_redis(&client, "other").await,
+            Err(TeamError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_fails_on_malformed_teams() {
+        let client = MockRedisClient::new();
+        client
+            .set_ex(
+                format!("{TEAM_TOKEN_CACHE_PREFIX}token"),
+                r#"{"name": "team"}"#.to_string(),
+                60,
+            )
+            .await
+            .expect("failed to store team");
+
+        assert!(matches!(
+            Team::from_redis(&client, "token").await,
+            Err(TeamError::Parse(_))
+        ));
+    }
+}
@@ -1,22 +1,34 @@
+use std::borrow::Cow;
+use std::num::NonZeroUsize;
 use std::ops::Deref;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use axum::{debug_handler, Json};
 use bytes::Bytes;
 // TODO: stream this instead
 use axum::extract::{MatchedPath, Query, State};
-use axum::http::{HeaderMap, Method};
+use axum::http::{HeaderMap, Method, StatusCode};
 use axum_client_ip::InsecureClientIp;
 use base64::Engine;
 use metrics::counter;
+use rayon::prelude::*;
+use serde_json::Value;
+use time::format_description::well_known::Iso8601;
+use time::{Duration, OffsetDateTime};
 use tracing::instrument;
 
+use crate::enrichers::EventEnrichers;
 use crate::limiters::billing::QuotaResource;
 use crate::prometheus::report_dropped_events;
-use crate::v0_request::{Compression, ProcessingContext, RawRequest};
+use crate::receipts::Receipt;
+use crate::schemas::EventSchemas;
+use crate::teams::{Team, TeamError};
+use crate::v0_request::{is_truthy, Compression, DistinctIdFields, ProcessingContext, RawRequest};
 use crate::{
     api::{CaptureError, CaptureResponse, CaptureResponseCode, DataType, ProcessedEvent},
-    router, sinks,
+    router,
+    sinks::routing::SinkRouter,
     utils::uuid_v7,
     v0_request::{EventFormData, EventQuery, RawEvent},
 };
@@ -51,7 +63,7 @@ pub async fn event(
     method: Method,
     path: MatchedPath,
     body: Bytes,
-) -> Result<Json<CaptureResponse>, CaptureError> {
+) -> Result<(StatusCode, Json<CaptureResponse>), CaptureError> {
     let user_agent = headers
         .get("user-agent")
         .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
@@ -91,6 +103,16 @@ pub async fn event(
                 })?;
             RawRequest::from_bytes(payload.into())
         }
+        "application/x-protobuf" => {
+            tracing::Span::current().record("content_type", "application/x-protobuf");
+
+            RawRequest::from_protobuf(body)
+        }
+        ct if state.plain_text_base64 && is_plain_text(ct) => {
+            tracing::Span::current().record("content_type", ct);
+
+            RawRequest::from_plain_text(body)
+        }
         ct => {
             tracing::Span::current().record("content_type", ct);
 
@@ -108,11 +130,25 @@ pub async fn event(
     };
     let historical_migration = request.historical_migration();
     let events = request.events(); // Takes ownership of request
+    let include_team_id = meta.include_team_id()
+        || headers
+            .get(INCLUDE_TEAM_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(is_truthy);
 
     tracing::Span::current().record("token", &token);
     tracing::Span::current().record("historical_migration", historical_migration);
     tracing::Span::current().record("batch_size", events.len());
 
+    if let Some(rate_limiter) = &state.rate_limiter {
+        if let Err(retry_after) = rate_limiter.check(&token) {
+            report_dropped_events("rate_limited", events.len() as u64);
+            return Err(CaptureError::RateLimited(
+                retry_after.as_secs_f64().ceil().max(1.0) as u64,
+            ));
+        }
+    }
+
     if events.is_empty() {
         return Err(CaptureError::EmptyBatch);
     }
@@ -126,7 +162,16 @@ pub async fn event(
         now: state.timesource.current_time(),
         client_ip: ip.to_string(),
         historical_migration,
+        user_agent: headers
+            .get("user-agent")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
+        route: headers
+            .get(ROUTE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from),
     };
+    report_clock_skew(&context);
 
     let billing_limited = state
         .billing
@@ -142,14 +187,35 @@ pub async fn event(
         //
         // for v1, we'll return a meaningful error code and error, so that the clients can do
         // something meaningful with that error
-        return Ok(Json(CaptureResponse {
-            status: CaptureResponseCode::Ok,
-        }));
+        let team_id = match include_team_id {
+            false => None,
+            true => resolve_team_id(&state, &context.token).await,
+        };
+        return Ok((
+            StatusCode::OK,
+            Json(CaptureResponse {
+                status: CaptureResponseCode::Ok,
+                receipt: None,
+                team_id,
+            }),
+        ));
     }
 
     tracing::debug!(context=?context, events=?events, "decoded request");
 
-    if let Err(err) = process_events(state.sink.clone(), &events, &context).await {
+    // Held until the events are sent, counting the batch against the token's limit
+    let _in_flight = match &state.in_flight {
+        None => None,
+        Some(in_flight) => match in_flight.try_acquire(&context.token) {
+            Some(permit) => Some(permit),
+            None => {
+                report_dropped_events("too_many_in_flight", events.len() as u64);
+                return Err(CaptureError::TooManyBatchesInFlight);
+            }
+        },
+    };
+
+    if let Err(err) = process_events(&state.sinks, &state.processor, &events, &context).await {
         let cause = match err {
             // TODO: automate this with a macro
             CaptureError::EmptyDistinctId => "empty_distinct_id",
@@ -162,64 +228,1541 @@ pub async fn event(
         return Err(err);
     }
 
-    Ok(Json(CaptureResponse {
-        status: CaptureResponseCode::Ok,
-    }))
+    // Batches are still accepted if their receipt can't be stored, as their events were sent
+    let receipt = match &state.receipts {
+        None => None,
+        Some(receipts) => {
+            receipts
+                .issue(&Receipt {
+                    events: events.len(),
+                    received_at: context.now.clone(),
+                })
+                .await
+        }
+    };
+    let status = match receipt {
+        None => StatusCode::OK,
+        Some(_) => StatusCode::ACCEPTED,
+    };
+    let team_id = match include_team_id {
+        false => None,
+        true => resolve_team_id(&state, &context.token).await,
+    };
+
+    Ok((
+        status,
+        Json(CaptureResponse {
+            status: CaptureResponseCode::Ok,
+            receipt,
+            team_id,
+        }),
+    ))
 }
 
 pub async fn options() -> Result<Json<CaptureResponse>, CaptureError> {
     Ok(Json(CaptureResponse {
         status: CaptureResponseCode::Ok,
+        receipt: None,
+        team_id: None,
     }))
 }
 
-#[instrument(skip_all)]
-pub fn process_single_event(
-    event: &RawEvent,
-    context: &ProcessingContext,
-) -> Result<ProcessedEvent, CaptureError> {
-    if event.event.is_empty() {
-        return Err(CaptureError::MissingEventName);
+/// The id of the team of `token`, for responses that asked for it. The events were accepted
+/// already, so the id is left out of the response rather than failing it when it's unknown.
+async fn resolve_team_id(state: &router::State, token: &str) -> Option<i64> {
+    match Team::from_redis(state.redis.as_ref(), token).await {
+        Ok(team) => Some(team.id),
+        Err(TeamError::NotFound) => {
+            counter!("capture_team_id_unresolved_total", "cause" => "not_found").increment(1);
+            None
+        }
+        Err(e) => {
+            counter!("capture_team_id_unresolved_total", "cause" => "error").increment(1);
+            tracing::warn!("failed to resolve the team of a token: {}", e);
+            None
+        }
     }
+}
+
+/// Whether `content_type` is `text/plain`, whatever its parameters, like sendBeacon's
+/// `text/plain;charset=UTF-8`.
+fn is_plain_text(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("text/plain"))
+}
 
-    let data_type = match context.historical_migration {
-        true => DataType::AnalyticsHistorical,
-        false => DataType::AnalyticsMain,
+/// Set by our ingestion proxy to send the events of a request to another topic, see
+/// `KafkaConfig::kafka_route_topics`.
+const ROUTE_HEADER: &str = "x-posthog-route";
+
+/// Header asking for the id of the team in the response, like the `include_team_id` query param.
+const INCLUDE_TEAM_ID_HEADER: &str = "x-posthog-include-team-id";
+
+/// Record how far ahead of our clock the client's was when it sent the request, which is
+/// negative for clocks running behind. Requests without a `sent_at` aren't recorded.
+fn report_clock_skew(context: &ProcessingContext) {
+    let Some(offset) = context.sent_at_offset() else {
+        return;
     };
 
-    let data = serde_json::to_string(&event).map_err(|e| {
-        tracing::error!("failed to encode data field: {}", e);
-        CaptureError::NonRetryableSinkError
-    })?;
+    metrics::histogram!("capture_clock_skew_seconds").record(-offset.as_seconds_f64());
+}
+
+const GROUP_IDENTIFY_EVENT: &str = "$groupidentify";
+
+/// `$groupidentify` events update a group's properties, they must tell which group they target
+/// with non-empty `$group_type` and `$group_key` properties. Only checked when the processor is
+/// built `with_group_identify_validation`, as one invalid event rejects its whole request.
+fn validate_group_identify(event: &RawEvent) -> Result<(), CaptureError> {
+    match event.properties.get("$group_type") {
+        Some(Value::String(group_type)) if !group_type.is_empty() => {}
+        _ => return Err(CaptureError::InvalidGroupIdentify("$group_type")),
+    }
+    match event.properties.get("$group_key") {
+        None | Some(Value::Null) => Err(CaptureError::InvalidGroupIdentify("$group_key")),
+        Some(Value::String(group_key)) if group_key.is_empty() => {
+            Err(CaptureError::InvalidGroupIdentify("$group_key"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+const EXCEPTION_EVENT: &str = "$exception";
+
+/// `$exception` events are ingested by error tracking, which expects a non-empty
+/// `$exception_list` of exceptions, each with a `type` and a `value`. Stack traces are optional,
+/// but must hold a list of frames when present. Only checked when the processor is built
+/// `with_exception_validation`, as older SDKs send `$exception` events without a list.
+fn validate_exception(event: &RawEvent) -> Result<(), CaptureError> {
+    let exceptions = match event.properties.get("$exception_list") {
+        Some(Value::Array(exceptions)) if !exceptions.is_empty() => exceptions,
+        _ => return Err(CaptureError::InvalidException("$exception_list")),
+    };
+
+    for exception in exceptions {
+        match exception.get("type") {
+            Some(Value::String(exception_type)) if !exception_type.is_empty() => {}
+            _ => return Err(CaptureError::InvalidException("exception type")),
+        }
+        match exception.get("value") {
+            Some(Value::String(_)) => {}
+            _ => return Err(CaptureError::InvalidException("exception value")),
+        }
+        match exception.get("stacktrace") {
+            None | Some(Value::Null) => {}
+            Some(stacktrace) if stacktrace.get("frames").is_some_and(Value::is_array) => {}
+            Some(_) => return Err(CaptureError::InvalidException("exception stacktrace")),
+        }
+    }
+
+    Ok(())
+}
+
+/// How to handle events whose serialized properties are larger than `PropertiesLimit::max_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OversizedPropertiesMode {
+    /// Drop the event.
+    Drop,
+    /// Replace the largest property values with a placeholder until the properties fit.
+    Truncate,
+}
+
+impl FromStr for OversizedPropertiesMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "drop" => Ok(OversizedPropertiesMode::Drop),
+            "truncate" => Ok(OversizedPropertiesMode::Truncate),
+            _ => Err(format!("unknown oversized properties mode: {}", s)),
+        }
+    }
+}
+
+/// Replaces the property values elided by `OversizedPropertiesMode::Truncate`.
+pub const TRUNCATED_PROPERTY_VALUE: &str = "$truncated";
+
+/// Properties needed to route and validate events, which are never truncated.
+const CORE_PROPERTIES: [&str; 3] = ["distinct_id", "$group_type", "$group_key"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct PropertiesLimit {
+    pub max_bytes: usize,
+    pub mode: OversizedPropertiesMode,
+}
+
+impl PropertiesLimit {
+    /// Returns the event to send with its properties under the limit, or `None` if it
+    /// must be dropped. Events already under the limit are returned as is.
+    fn apply<'a>(&self, event: &'a RawEvent) -> Option<Cow<'a, RawEvent>> {
+        let mut size = serialized_size(&event.properties);
+        if size <= self.max_bytes {
+            return Some(Cow::Borrowed(event));
+        }
+        if self.mode == OversizedPropertiesMode::Drop {
+            return None;
+        }
+
+        let mut candidates: Vec<(&String, usize)> = event
+            .properties
+            .iter()
+            .filter(|(key, _)| !CORE_PROPERTIES.contains(&key.as_str()))
+            .map(|(key, value)| (key, serialized_size(value)))
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+
+        // Swapping a value only changes the size of the value, so we can keep track of the
+        // size of the properties without serializing them again.
+        let placeholder_size = serialized_size(&TRUNCATED_PROPERTY_VALUE);
+        let mut truncated = event.clone();
+        for (key, value_size) in candidates {
+            if size <= self.max_bytes || value_size <= placeholder_size {
+                break;
+            }
+            truncated.properties.insert(
+                key.clone(),
+                Value::String(TRUNCATED_PROPERTY_VALUE.to_string()),
+            );
+            size -= value_size - placeholder_size;
+        }
+
+        if size <= self.max_bytes {
+            counter!("capture_events_properties_truncated_total").increment(1);
+            Some(Cow::Owned(truncated))
+        } else {
+            // Only keys and core properties are left, there is nothing more we can remove
+            None
+        }
+    }
+}
+
+fn serialized_size<T: serde::Serialize + ?Sized>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+}
 
-    Ok(ProcessedEvent {
-        data_type,
-        uuid: event.uuid.unwrap_or_else(uuid_v7),
-        distinct_id: event.extract_distinct_id()?,
-        ip: context.client_ip.clone(),
-        data,
-        now: context.now.clone(),
-        sent_at: context.sent_at,
-        token: context.token.clone(),
-    })
+/// How to handle events timestamped further in the future than `FutureSkewLimit::max_skew`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FutureDatedMode {
+    /// Replace the timestamp of the event with the time it was received.
+    Clamp,
+    /// Drop the event.
+    Drop,
+}
+
+impl FromStr for FutureDatedMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "clamp" => Ok(FutureDatedMode::Clamp),
+            "drop" => Ok(FutureDatedMode::Drop),
+            _ => Err(format!("unknown future dated mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FutureSkewLimit {
+    pub max_skew: Duration,
+    pub mode: FutureDatedMode,
+}
+
+impl FutureSkewLimit {
+    /// Returns the event to send with its timestamp at most `max_skew` after `now`, or `None`
+    /// if it must be dropped. Events without a timestamp ingestion can parse are returned as is.
+    fn apply<'a>(&self, event: Cow<'a, RawEvent>, now: &str) -> Option<Cow<'a, RawEvent>> {
+        let (Some(timestamp), Ok(received_at)) = (
+            event.timestamp.as_deref(),
+            OffsetDateTime::parse(now, &Iso8601::DEFAULT),
+        ) else {
+            return Some(event);
+        };
+        match OffsetDateTime::parse(timestamp, &Iso8601::DEFAULT) {
+            Ok(timestamp) if timestamp - received_at > self.max_skew => {}
+            _ => return Some(event),
+        }
+
+        counter!("capture_events_future_dated_total").increment(1);
+        match self.mode {
+            FutureDatedMode::Drop => None,
+            FutureDatedMode::Clamp => {
+                let mut clamped = event.into_owned();
+                clamped.timestamp = Some(now.to_string());
+                Some(Cow::Owned(clamped))
+            }
+        }
+    }
+}
+
+/// Takes the timestamp of events from one of their properties, for SDKs that send the time of
+/// events there rather than in the `timestamp` field.
+#[derive(Clone, Debug)]
+pub struct TimestampProperty {
+    pub name: String,
+}
+
+impl TimestampProperty {
+    /// Returns the event with its timestamp replaced by the value of the property, and whether
+    /// it was. Events without the property, or whose property isn't an ISO 8601 timestamp, are
+    /// returned as is.
+    fn apply<'a>(&self, event: Cow<'a, RawEvent>) -> (Cow<'a, RawEvent>, bool) {
+        let Some(value) = event.properties.get(&self.name) else {
+            return (event, false);
+        };
+        let timestamp = match value.as_str() {
+            Some(timestamp) if OffsetDateTime::parse(timestamp, &Iso8601::DEFAULT).is_ok() => {
+                timestamp.to_string()
+            }
+            _ => {
+                counter!("capture_events_invalid_timestamp_property_total").increment(1);
+                return (event, false);
+            }
+        };
+
+        let mut event = event.into_owned();
+        event.timestamp = Some(timestamp);
+        (Cow::Owned(event), true)
+    }
+}
+
+/// Sends events whose `property` is `value` to `data_type`, and so to its topic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataTypeRule {
+    pub property: String,
+    pub value: String,
+    pub data_type: DataType,
+}
+
+impl DataTypeRule {
+    /// String properties are compared as is, other values by their JSON, like `true` or `42`.
+    fn matches(&self, event: &RawEvent) -> bool {
+        match event.properties.get(&self.property) {
+            Some(Value::String(value)) => *value == self.value,
+            Some(value) => value.to_string() == self.value,
+            None => false,
+        }
+    }
+}
+
+/// Ordered rules choosing the data type of events by their properties, the first matching rule
+/// wins. They only apply to events that would go to `DataType::AnalyticsMain`, which they keep
+/// without a match: historical migrations, group identify and exception events keep their own.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataTypeRules(pub Vec<DataTypeRule>);
+
+impl DataTypeRules {
+    fn route(&self, event: &RawEvent, mut processed: ProcessedEvent) -> ProcessedEvent {
+        if processed.data_type == DataType::AnalyticsMain {
+            if let Some(rule) = self.0.iter().find(|rule| rule.matches(event)) {
+                processed.data_type = rule.data_type;
+            }
+        }
+        processed
+    }
+}
+
+impl FromStr for DataTypeRules {
+    type Err = String;
+
+    /// Parse semicolon-separated `property=value:data_type` rules, like
+    /// `$lib=server:server_ingest`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = Vec::new();
+        for rule in s.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            let Some((property, value, data_type)) =
+                rule.rsplit_once(':').and_then(|(matcher, data_type)| {
+                    let (property, value) = matcher.split_once('=')?;
+                    Some((property, value, data_type))
+                })
+            else {
+                return Err(format!(
+                    "data type rule must be property=value:data_type, got: {}",
+                    rule
+                ));
+            };
+            if property.trim().is_empty() {
+                return Err(format!("data type rule without a property: {}", rule));
+            }
+            rules.push(DataTypeRule {
+                property: property.trim().to_string(),
+                value: value.trim().to_string(),
+                data_type: data_type.parse()?,
+            });
+        }
+        Ok(DataTypeRules(rules))
+    }
+}
+
+/// Runs `process_single_event` on the events of a request. By default, events are processed
+/// serially on the calling task. When built with `parallel`, batches of at least
+/// `parallel_threshold` events are processed on a dedicated thread pool instead.
+/// Either way, the output keeps the order of the input events, minus the dropped ones.
+#[derive(Clone, Default)]
+pub struct EventProcessor {
+    pool: Option<Arc<rayon::ThreadPool>>,
+    parallel_threshold: usize,
+    properties_limit: Option<PropertiesLimit>,
+    future_skew_limit: Option<FutureSkewLimit>,
+    schemas: Option<Arc<EventSchemas>>,
+    timestamp_property: Option<TimestampProperty>,
+    distinct_id_fields: Option<DistinctIdFields>,
+    data_type_rules: Option<DataTypeRules>,
+    enrichers: Option<Arc<EventEnrichers>>,
+    validate_group_identify: bool,
+    validate_exceptions: bool,
+}
+
+impl EventProcessor {
+    pub fn parallel(
+        threads: NonZeroUsize,
+        parallel_threshold: usize,
+    ) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.get())
+            .thread_name(|i| format!("event-processing-{}", i))
+            .build()?;
+
+        Ok(Self {
+            pool: Some(Arc::new(pool)),
+            parallel_threshold,
+            properties_limit: None,
+            future_skew_limit: None,
+            schemas: None,
+            timestamp_property: None,
+            distinct_id_fields: None,
+            data_type_rules: None,
+            enrichers: None,
+            validate_group_identify: false,
+            validate_exceptions: false,
+        })
+    }
+
+    /// Apply `limit` to the serialized size of event properties.
+    pub fn with_properties_limit(mut self, limit: PropertiesLimit) -> Self {
+        self.properties_limit = Some(limit);
+        self
+    }
+
+    /// Apply `limit` to how far in the future event timestamps can be.
+    pub fn with_future_skew_limit(mut self, limit: FutureSkewLimit) -> Self {
+        self.future_skew_limit = Some(limit);
+        self
+    }
+
+    /// Take the timestamp of events from `property` when they have it.
+    pub fn with_timestamp_property(mut self, property: TimestampProperty) -> Self {
+        self.timestamp_property = Some(property);
+        self
+    }
+
+    /// Take the distinct_id of events from the first of `fields` they have.
+    pub fn with_distinct_id_fields(mut self, fields: DistinctIdFields) -> Self {
+        self.distinct_id_fields = Some(fields);
+        self
+    }
+
+    /// Choose the data type of events with the first of `rules` they match.
+    pub fn with_data_type_rules(mut self, rules: DataTypeRules) -> Self {
+        self.data_type_rules = Some(rules);
+        self
+    }
+
+    /// Reject events that don't match the schema of their token and name in `schemas`.
+    pub fn with_event_schemas(mut self, schemas: Arc<EventSchemas>) -> Self {
+        self.schemas = Some(schemas);
+        self
+    }
+
+    /// Run `enrichers` on events, in order, before they're validated and serialized.
+    pub fn with_enrichers(mut self, enrichers: EventEnrichers) -> Self {
+        self.enrichers = Some(Arc::new(enrichers));
+        self
+    }
+
+    /// Reject `$groupidentify` events without a `$group_type` or a `$group_key`.
+    pub fn with_group_identify_validation(mut self) -> Self {
+        self.validate_group_identify = true;
+        self
+    }
+
+    /// Reject `$exception` events without a well-formed `$exception_list`.
+    pub fn with_exception_validation(mut self) -> Self {
+        self.validate_exceptions = true;
+        self
+    }
+
+    fn enrich<'a>(
+        &self,
+        event: &'a RawEvent,
+        context: &ProcessingContext,
+    ) -> Result<Cow<'a, RawEvent>, CaptureError> {
+        let Some(enrichers) = &self.enrichers else {
+            return Ok(Cow::Borrowed(event));
+        };
+
+        let mut event = event.clone();
+        for enricher in enrichers.iter() {
+            enricher.enrich(&mut event, context)?;
+        }
+        Ok(Cow::Owned(event))
+    }
+
+    /// Validates and serializes an event. Returns `None` if the event was dropped because its
+    /// properties are over the properties limit, or its timestamp is over the future skew limit.
+    #[instrument(skip_all)]
+    pub fn process_single_event(
+        &self,
+        event: &RawEvent,
+        context: &ProcessingContext,
+    ) -> Result<Option<ProcessedEvent>, CaptureError> {
+        if event.event.is_empty() {
+            return Err(CaptureError::MissingEventName);
+        }
+
+        let is_group_identify = event.event == GROUP_IDENTIFY_EVENT;
+        if is_group_identify && self.validate_group_identify {
+            validate_group_identify(event)?;
+        }
+        let is_exception = event.event == EXCEPTION_EVENT;
+        if is_exception && self.validate_exceptions {
+            validate_exception(event)?;
+        }
+        if let Some(schemas) = &self.schemas {
+            schemas.validate(&context.token, event)?;
+        }
+
+        let event = match &self.properties_limit {
+            None => Cow::Borrowed(event),
+            Some(limit) => match limit.apply(event) {
+                Some(event) => event,
+                None => {
+                    report_dropped_events("properties_too_large", 1);
+                    return Ok(None);
+                }
+            },
+        };
+
+        // Before the future skew limit, which also applies to timestamps taken from a property
+        let (event, timestamp_from_property) = match &self.timestamp_property {
+            None => (event, false),
+            Some(property) => property.apply(event),
+        };
+
+        let event = match &self.future_skew_limit {
+            None => event,
+            Some(limit) => match limit.apply(event, &context.now) {
+                Some(event) => event,
+                None => {
+                    report_dropped_events("future_dated", 1);
+                    return Ok(None);
+                }
+            },
+        };
+
+        let data_type = match (
+            context.historical_migration,
+            is_group_identify,
+            is_exception,
+        ) {
+            (true, _, _) => DataType::AnalyticsHistorical,
+            (false, true, _) => DataType::GroupIdentify,
+            (false, false, true) => DataType::Exception,
+            (false, false, false) => DataType::AnalyticsMain,
+        };
+
+        let data = serde_json::to_string(&event).map_err(|e| {
+            tracing::error!("failed to encode data field: {}", e);
+            CaptureError::NonRetryableSinkError
+        })?;
+
+        let timestamp = event
+            .timestamp
+            .as_deref()
+            .filter(|_| timestamp_from_property)
+            .and_then(|timestamp| OffsetDateTime::parse(timestamp, &Iso8601::DEFAULT).ok())
+            .or(context.sent_at)
+            .or_else(|| OffsetDateTime::parse(&context.now, &Iso8601::DEFAULT).ok());
+
+        Ok(Some(ProcessedEvent {
+            data_type,
+            uuid: event.uuid.unwrap_or_else(uuid_v7),
+            distinct_id: match &self.distinct_id_fields {
+                None => event.extract_distinct_id()?,
+                Some(fields) => event.extract_distinct_id_from(fields)?,
+            },
+            ip: (!event.opts_out_of_ip()).then(|| context.client_ip.clone()),
+            data,
+            now: context.now.clone(),
+            sent_at: context.sent_at,
+            sent_at_offset_ms: context
+                .sent_at_offset()
+                .map(|offset| offset.whole_milliseconds() as i64),
+            timestamp,
+            token: context.token.clone(),
+            insert_id: event
+                .extract_insert_id()
+                .unwrap_or_else(|| uuid_v7().to_string()),
+            metadata: context.metadata(),
+            route: context.route.clone(),
+        }))
+    }
+
+    pub fn process(
+        &self,
+        events: &[RawEvent],
+        context: &ProcessingContext,
+    ) -> Result<Vec<ProcessedEvent>, CaptureError> {
+        let process = |e: &RawEvent| -> Result<Option<ProcessedEvent>, CaptureError> {
+            let e = self.enrich(e, context)?;
+            let processed = self.process_single_event(&e, context)?;
+            Ok(match &self.data_type_rules {
+                None => processed,
+                Some(rules) => processed.map(|processed| rules.route(&e, processed)),
+            })
+        };
+        let processed: Vec<Option<ProcessedEvent>> = match &self.pool {
+            Some(pool) if events.len() >= self.parallel_threshold => {
+                pool.install(|| events.par_iter().map(process).collect())
+            }
+            _ => events.iter().map(process).collect(),
+        }?;
+
+        Ok(processed.into_iter().flatten().collect())
+    }
 }
 
 #[instrument(skip_all, fields(events = events.len()))]
 pub async fn process_events<'a>(
-    sink: Arc<dyn sinks::Event + Send + Sync>,
+    sinks: &SinkRouter,
+    processor: &EventProcessor,
     events: &'a [RawEvent],
     context: &'a ProcessingContext,
 ) -> Result<(), CaptureError> {
-    let events: Vec<ProcessedEvent> = events
-        .iter()
-        .map(|e| process_single_event(e, context))
-        .collect::<Result<Vec<ProcessedEvent>, CaptureError>>()?;
+    let events = processor.process(events, context)?;
 
     tracing::debug!(events=?events, "processed {} events", events.len());
 
+    if events.is_empty() {
+        // All the events were dropped
+        return Ok(());
+    }
+    let sink = sinks.sink_for(&context.token);
     if events.len() == 1 {
         sink.send(events[0].clone()).await
     } else {
         sink.send_batch(events).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use serde_json::{json, Value};
+
+    use std::collections::{HashMap, HashSet};
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+
+    use uuid::Uuid;
+
+    use crate::api::{CaptureError, DataType, ProcessedEvent};
+    use crate::enrichers::{
+        EventEnricher, EventEnrichers, PropertyRedactionEnricher, REDACTED_PROPERTY_VALUE,
+    };
+    use crate::schemas::EventSchemas;
+    use crate::v0_endpoint::{
+        is_plain_text, report_clock_skew, DataTypeRule, DataTypeRules, EventProcessor,
+        FutureDatedMode, FutureSkewLimit, OversizedPropertiesMode, PropertiesLimit,
+        TimestampProperty, TRUNCATED_PROPERTY_VALUE,
+    };
+    use crate::v0_request::{DistinctIdFields, ProcessingContext, RawEvent};
+
+    fn context(historical_migration: bool) -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            sent_at: None,
+            token: "token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration,
+            user_agent: None,
+            route: None,
+        }
+    }
+
+    /// Processes `event` with the default options.
+    fn process_single_event(
+        event: &RawEvent,
+        context: &ProcessingContext,
+    ) -> Result<Option<ProcessedEvent>, CaptureError> {
+        EventProcessor::default().process_single_event(event, context)
+    }
+
+    fn group_identify(properties: serde_json::Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$groupidentify",
+            "distinct_id": "id1",
+            "properties": properties,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_routes_valid_group_identify_events() {
+        let event = group_identify(json!({
+            "$group_type": "company",
+            "$group_key": "posthog",
+            "$group_set": {"name": "PostHog"}
+        }));
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::GroupIdentify);
+
+        // Historical migrations keep going to the historical topic
+        let processed = process_single_event(&event, &context(true))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::AnalyticsHistorical);
+    }
+
+    #[test]
+    fn it_omits_the_ip_of_events_opting_out() {
+        for properties in [
+            json!({"$ip": null}),
+            json!({"$ip": false}),
+            json!({"$process_person_profile": false}),
+        ] {
+            let event: RawEvent = serde_json::from_value(json!({
+                "event": "$pageview",
+                "distinct_id": "id1",
+                "properties": properties,
+            }))
+            .expect("failed to parse event");
+
+            let processed = process_single_event(&event, &context(false))
+                .expect("failed to process event")
+                .expect("event was dropped");
+            assert_eq!(processed.ip, None);
+        }
+    }
+
+    #[test]
+    fn it_sets_the_ip_of_other_events() {
+        for properties in [
+            json!({}),
+            json!({"$ip": "10.0.0.1"}),
+            json!({"$process_person_profile": true}),
+        ] {
+            let event: RawEvent = serde_json::from_value(json!({
+                "event": "$pageview",
+                "distinct_id": "id1",
+                "properties": properties,
+            }))
+            .expect("failed to parse event");
+
+            let processed = process_single_event(&event, &context(false))
+                .expect("failed to process event")
+                .expect("event was dropped");
+            assert_eq!(processed.ip, Some("127.0.0.1".to_string()));
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_insert_id_of_events() {
+        let event: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "properties": {"$insert_id": "abc123"},
+        }))
+        .expect("failed to parse event");
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.insert_id, "abc123");
+    }
+
+    #[test]
+    fn it_generates_an_insert_id_for_events_without_one() {
+        for properties in [
+            json!({}),
+            json!({"$insert_id": ""}),
+            json!({"$insert_id": 1}),
+        ] {
+            let event: RawEvent = serde_json::from_value(json!({
+                "event": "$pageview",
+                "distinct_id": "id1",
+                "properties": properties,
+            }))
+            .expect("failed to parse event");
+
+            let processed = process_single_event(&event, &context(false))
+                .expect("failed to process event")
+                .expect("event was dropped");
+            let insert_id = Uuid::parse_str(&processed.insert_id).expect("insert_id is not a uuid");
+            assert_eq!(insert_id.get_version_num(), 7);
+        }
+    }
+
+    #[test]
+    fn it_attaches_request_metadata_to_events() {
+        let event: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+        }))
+        .expect("failed to parse event");
+
+        // Nothing is attached if the request details are unknown
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert!(processed.metadata.is_empty());
+
+        let context = ProcessingContext {
+            lib_version: Some("1.2.3".to_string()),
+            user_agent: Some("posthog-test".to_string()),
+            ..context(false)
+        };
+        let processed = process_single_event(&event, &context)
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(
+            processed.metadata,
+            HashMap::from([
+                ("lib_version".to_string(), "1.2.3".to_string()),
+                ("user_agent".to_string(), "posthog-test".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_rejects_group_identify_events_without_group_fields() {
+        let processor = EventProcessor::default().with_group_identify_validation();
+
+        let missing_key = group_identify(json!({"$group_type": "company"}));
+        assert!(matches!(
+            processor.process_single_event(&missing_key, &context(false)),
+            Err(CaptureError::InvalidGroupIdentify("$group_key"))
+        ));
+
+        let empty_key = group_identify(json!({"$group_type": "company", "$group_key": ""}));
+        assert!(matches!(
+            processor.process_single_event(&empty_key, &context(false)),
+            Err(CaptureError::InvalidGroupIdentify("$group_key"))
+        ));
+
+        let missing_type = group_identify(json!({"$group_key": "posthog"}));
+        assert!(matches!(
+            processor.process_single_event(&missing_type, &context(false)),
+            Err(CaptureError::InvalidGroupIdentify("$group_type"))
+        ));
+    }
+
+    #[test]
+    fn it_accepts_group_identify_events_without_group_fields_by_default() {
+        let event = group_identify(json!({"$group_type": "company"}));
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::GroupIdentify);
+    }
+
+    fn exception(properties: serde_json::Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$exception",
+            "distinct_id": "id1",
+            "properties": properties,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_routes_valid_exception_events() {
+        let event = exception(json!({
+            "$exception_list": [{
+                "type": "TypeError",
+                "value": "undefined is not a function",
+                "stacktrace": {"frames": [{"filename": "app.js", "lineno": 12}]},
+            }, {
+                "type": "Error",
+                "value": "",
+            }]
+        }));
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::Exception);
+
+        let processed = process_single_event(&event, &context(true))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::AnalyticsHistorical);
+    }
+
+    #[test]
+    fn it_rejects_malformed_exception_events() {
+        let processor = EventProcessor::default().with_exception_validation();
+        for (properties, field) in [
+            (json!({}), "$exception_list"),
+            (json!({"$exception_list": []}), "$exception_list"),
+            (
+                json!({"$exception_list": [{"value": "boom"}]}),
+                "exception type",
+            ),
+            (
+                json!({"$exception_list": [{"type": "Error"}]}),
+                "exception value",
+            ),
+            (
+                json!({"$exception_list": [{"type": "Error", "value": "boom", "stacktrace": {}}]}),
+                "exception stacktrace",
+            ),
+        ] {
+            match processor.process_single_event(&exception(properties), &context(false)) {
+                Err(CaptureError::InvalidException(invalid)) => assert_eq!(invalid, field),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn it_accepts_legacy_exception_events_by_default() {
+        let event = exception(json!({
+            "$exception_type": "TypeError",
+            "$exception_message": "undefined is not a function",
+        }));
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::Exception);
+    }
+
+    #[test]
+    fn it_processes_events_in_parallel_preserving_order() {
+        let events: Vec<RawEvent> = (0..1000)
+            .map(|i| {
+                serde_json::from_value(json!({
+                    "event": format!("event{}", i),
+                    "distinct_id": format!("id{}", i),
+                    "uuid": Uuid::now_v7(),
+                    "properties": {"index": i},
+                }))
+                .expect("failed to parse event")
+            })
+            .collect();
+        let context = context(false);
+
+        let serial = EventProcessor::default()
+            .process(&events, &context)
+            .expect("failed to process events serially");
+        let parallel = EventProcessor::parallel(NonZeroUsize::new(4).unwrap(), 10)
+            .expect("failed to create processor")
+            .process(&events, &context)
+            .expect("failed to process events in parallel");
+
+        assert_eq!(parallel, serial);
+        for (i, event) in parallel.iter().enumerate() {
+            assert_eq!(event.distinct_id, format!("id{}", i));
+        }
+
+        // Errors are still surfaced when processing in parallel
+        let mut invalid = events;
+        invalid[500].event = String::new();
+        assert!(matches!(
+            EventProcessor::parallel(NonZeroUsize::new(4).unwrap(), 10)
+                .expect("failed to create processor")
+                .process(&invalid, &context),
+            Err(CaptureError::MissingEventName)
+        ));
+    }
+
+    #[test]
+    fn it_keeps_other_events_on_the_main_data_type() {
+        let event: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+        }))
+        .expect("failed to parse event");
+
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.data_type, DataType::AnalyticsMain);
+    }
+
+    struct SetProperty(&'static str, &'static str);
+
+    impl EventEnricher for SetProperty {
+        fn enrich(&self, event: &mut RawEvent, _: &ProcessingContext) -> Result<(), CaptureError> {
+            event
+                .properties
+                .insert(self.0.to_string(), Value::from(self.1));
+            Ok(())
+        }
+    }
+
+    fn enriched_properties(enrichers: EventEnrichers) -> Value {
+        let event: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "properties": {"plan": "free"},
+        }))
+        .expect("failed to parse event");
+
+        let processed = EventProcessor::default()
+            .with_enrichers(enrichers)
+            .process(&[event], &context(false))
+            .expect("failed to process event");
+        let data: Value =
+            serde_json::from_str(&processed[0].data).expect("failed to parse event data");
+        data["properties"].clone()
+    }
+
+    #[test]
+    fn it_runs_enrichers_in_order() {
+        let redact_email = || {
+            Box::new(PropertyRedactionEnricher::new(HashSet::from([
+                "email".to_string()
+            ])))
+        };
+
+        // The email set by the first enricher is redacted by the second one
+        let properties = enriched_properties(vec![
+            Box::new(SetProperty("email", "user@example.com")),
+            redact_email(),
+        ]);
+        assert_eq!(
+            properties,
+            json!({"plan": "free", "email": REDACTED_PROPERTY_VALUE})
+        );
+
+        // The other way around, it's set after the redaction ran
+        let properties = enriched_properties(vec![
+            redact_email(),
+            Box::new(SetProperty("email", "user@example.com")),
+        ]);
+        assert_eq!(
+            properties,
+            json!({"plan": "free", "email": "user@example.com"})
+        );
+
+        // Later enrichers see, and can overwrite, the properties set by earlier ones
+        let properties = enriched_properties(vec![
+            Box::new(SetProperty("plan", "paid")),
+            Box::new(SetProperty("plan", "enterprise")),
+        ]);
+        assert_eq!(properties, json!({"plan": "enterprise"}));
+    }
+
+    fn event_with_large_property() -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$autocapture",
+            "distinct_id": "id1",
+            "properties": {
+                "$elements_chain": "a".repeat(10_000),
+                "$current_url": "https://example.com",
+                "distinct_id": "id1",
+            },
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_drops_events_with_large_properties() {
+        let limit = PropertiesLimit {
+            max_bytes: 1_000,
+            mode: OversizedPropertiesMode::Drop,
+        };
+        let small: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id2",
+            "properties": {"$current_url": "https://example.com"},
+        }))
+        .expect("failed to parse event");
+
+        let processed = EventProcessor::default()
+            .with_properties_limit(limit.clone())
+            .process_single_event(&event_with_large_property(), &context(false))
+            .expect("failed to process event");
+        assert!(processed.is_none());
+
+        let processed = EventProcessor::default()
+            .with_properties_limit(limit)
+            .process(&[event_with_large_property(), small], &context(false))
+            .expect("failed to process events");
+        assert_eq!(processed.len(), 1);
+        assert_eq!(processed[0].distinct_id, "id2");
+    }
+
+    #[test]
+    fn it_takes_the_distinct_id_from_the_configured_fields() {
+        let fields: DistinctIdFields = "properties.$user_id,distinct_id".parse().unwrap();
+        let event: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "anonymous",
+            "properties": {"$user_id": "user1"},
+        }))
+        .expect("failed to parse event");
+
+        let processed = EventProcessor::default()
+            .with_distinct_id_fields(fields.clone())
+            .process(&[event.clone()], &context(false))
+            .expect("failed to process event");
+        assert_eq!(processed[0].distinct_id, "user1");
+
+        // Without the configured fields, events are missing their distinct_id
+        let mut without = event;
+        without.distinct_id = None;
+        without.properties.clear();
+        assert!(matches!(
+            EventProcessor::default()
+                .with_distinct_id_fields(fields)
+                .process_single_event(&without, &context(false)),
+            Err(CaptureError::MissingDistinctId)
+        ));
+    }
+
+    #[test]
+    fn it_truncates_large_property_values() {
+        let limit = PropertiesLimit {
+            max_bytes: 1_000,
+            mode: OversizedPropertiesMode::Truncate,
+        };
+
+        let processed = EventProcessor::default()
+            .with_properties_limit(limit)
+            .process_single_event(&event_with_large_property(), &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+
+        assert_eq!(processed.distinct_id, "id1");
+        let data: RawEvent = serde_json::from_str(&processed.data).expect("failed to parse data");
+        assert_eq!(data.event, "$autocapture");
+        assert_eq!(data.distinct_id, Some(json!("id1")));
+        assert_eq!(
+            data.properties.get("$elements_chain"),
+            Some(&json!(TRUNCATED_PROPERTY_VALUE))
+        );
+        assert_eq!(
+            data.properties.get("$current_url"),
+            Some(&json!("https://example.com"))
+        );
+        assert!(processed.data.len() < 1_000);
+
+        // Events under the limit are left untouched
+        let unlimited = process_single_event(&event_with_large_property(), &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert!(unlimited.data.contains(&"a".repeat(10_000)));
+    }
+
+    #[test]
+    fn it_drops_events_that_cannot_be_truncated_enough() {
+        let limit = PropertiesLimit {
+            max_bytes: 10,
+            mode: OversizedPropertiesMode::Truncate,
+        };
+
+        let processed = EventProcessor::default()
+            .with_properties_limit(limit)
+            .process_single_event(&event_with_large_property(), &context(false))
+            .expect("failed to process event");
+        assert!(processed.is_none());
+    }
+
+    fn event_at(timestamp: &str) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "timestamp": timestamp,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_keeps_events_within_the_future_skew() {
+        let limit = FutureSkewLimit {
+            max_skew: time::Duration::hours(1),
+            mode: FutureDatedMode::Drop,
+        };
+
+        // The context is received at 2024-01-01T00:00:00Z
+        let processed = EventProcessor::default()
+            .with_future_skew_limit(limit)
+            .process_single_event(&event_at("2024-01-01T00:59:00.000Z"), &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        let data: RawEvent = serde_json::from_str(&processed.data).expect("failed to parse data");
+        assert_eq!(data.timestamp.as_deref(), Some("2024-01-01T00:59:00.000Z"));
+    }
+
+    #[test]
+    fn it_clamps_events_beyond_the_future_skew() {
+        let limit = FutureSkewLimit {
+            max_skew: time::Duration::hours(1),
+            mode: FutureDatedMode::Clamp,
+        };
+
+        let processed = EventProcessor::default()
+            .with_future_skew_limit(limit)
+            .process_single_event(&event_at("2024-01-02T00:00:00.000Z"), &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        let data: RawEvent = serde_json::from_str(&processed.data).expect("failed to parse data");
+        assert_eq!(data.timestamp.as_deref(), Some("2024-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn it_drops_events_beyond_the_future_skew() {
+        let limit = FutureSkewLimit {
+            max_skew: time::Duration::hours(1),
+            mode: FutureDatedMode::Drop,
+        };
+
+        let processed = EventProcessor::default()
+            .with_future_skew_limit(limit.clone())
+            .process_single_event(&event_at("2024-01-02T00:00:00.000Z"), &context(false))
+            .expect("failed to process event");
+        assert!(processed.is_none());
+
+        let processed = EventProcessor::default()
+            .with_future_skew_limit(limit)
+            .process(
+                &[
+                    event_at("2024-01-02T00:00:00.000Z"),
+                    event_at("2023-12-31T00:00:00.000Z"),
+                ],
+                &context(false),
+            )
+            .expect("failed to process events");
+        assert_eq!(processed.len(), 1);
+    }
+
+    fn event_with_time_property(time: serde_json::Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "timestamp": "2023-12-31T12:00:00.000Z",
+            "properties": {"event_time": time},
+        }))
+        .expect("failed to parse event")
+    }
+
+    fn time_property() -> TimestampProperty {
+        TimestampProperty {
+            name: "event_time".to_string(),
+        }
+    }
+
+    #[test]
+    fn it_takes_the_timestamp_from_a_property() {
+        let processed = EventProcessor::default()
+            .with_timestamp_property(time_property())
+            .process_single_event(
+                &event_with_time_property(json!("2023-12-31T23:00:00.000Z")),
+                &context(false),
+            )
+            .expect("failed to process event")
+            .expect("event was dropped");
+
+        let data: RawEvent = serde_json::from_str(&processed.data).expect("failed to parse data");
+        assert_eq!(data.timestamp.as_deref(), Some("2023-12-31T23:00:00.000Z"));
+        assert_eq!(
+            processed.timestamp,
+            Some(time::macros::datetime!(2023-12-31 23:00 UTC))
+        );
+
+        // Property timestamps are subject to the future skew limit too
+        let limit = FutureSkewLimit {
+            max_skew: time::Duration::hours(1),
+            mode: FutureDatedMode::Clamp,
+        };
+        let processed = EventProcessor::default()
+            .with_future_skew_limit(limit)
+            .with_timestamp_property(time_property())
+            .process_single_event(
+                &event_with_time_property(json!("2024-01-02T00:00:00.000Z")),
+                &context(false),
+            )
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(
+            processed.timestamp,
+            Some(time::macros::datetime!(2024-01-01 00:00 UTC))
+        );
+    }
+
+    #[test]
+    fn it_recognizes_plain_text_with_parameters() {
+        assert!(is_plain_text("text/plain"));
+        assert!(is_plain_text("text/plain;charset=UTF-8"));
+        assert!(is_plain_text("Text/Plain; charset=utf-8"));
+        assert!(!is_plain_text("application/json"));
+        assert!(!is_plain_text(""));
+    }
+
+    #[test]
+    fn it_reports_the_clock_skew_of_sent_at() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            // Sent 90s ahead of and 30s behind the time the request was received
+            for sent_at in [
+                time::macros::datetime!(2024-01-01 00:01:30 UTC),
+                time::macros::datetime!(2023-12-31 23:59:30 UTC),
+            ] {
+                report_clock_skew(&ProcessingContext {
+                    sent_at: Some(sent_at),
+                    ..context(false)
+                });
+            }
+            // Without sent_at there's no skew to record
+            report_clock_skew(&context(false));
+        });
+
+        let skews = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find(|(key, ..)| key.key().name() == "capture_clock_skew_seconds")
+            .map(|(_, _, _, value)| match value {
+                DebugValue::Histogram(values) => values,
+                _ => panic!("capture_clock_skew_seconds is not a histogram"),
+            })
+            .expect("missing capture_clock_skew_seconds");
+
+        let skews: Vec<f64> = skews.into_iter().map(|skew| skew.into_inner()).collect();
+        assert_eq!(skews, vec![90.0, -30.0]);
+    }
+
+    #[test]
+    fn it_stores_the_offset_between_now_and_sent_at() {
+        // The context is received at 2024-01-01T00:00:00Z, the client's clock is 1.5s behind
+        let context = ProcessingContext {
+            sent_at: Some(time::macros::datetime!(2023-12-31 23:59:58.5 UTC)),
+            ..context(false)
+        };
+        let processed = process_single_event(&event_at("2023-12-31T12:00:00.000Z"), &context)
+            .expect("failed to process event")
+            .expect("event was dropped");
+
+        assert_eq!(processed.now, "2024-01-01T00:00:00Z");
+        assert_eq!(processed.sent_at_offset_ms, Some(1500));
+        assert_eq!(json!(processed)["sent_at_offset_ms"], json!(1500));
+
+        // Clocks running ahead have a negative offset
+        let context = ProcessingContext {
+            sent_at: Some(time::macros::datetime!(2024-01-01 00:00:30 UTC)),
+            ..context
+        };
+        let processed = process_single_event(&event_at("2023-12-31T12:00:00.000Z"), &context)
+            .expect("failed to process event")
+            .expect("event was dropped");
+
+        assert_eq!(processed.sent_at_offset_ms, Some(-30000));
+    }
+
+    #[test]
+    fn it_stores_a_null_offset_without_sent_at() {
+        let processed =
+            process_single_event(&event_at("2023-12-31T12:00:00.000Z"), &context(false))
+                .expect("failed to process event")
+                .expect("event was dropped");
+
+        assert_eq!(processed.sent_at_offset_ms, None);
+        // Serialized as null rather than omitted
+        assert_eq!(
+            json!(processed).get("sent_at_offset_ms"),
+            Some(&serde_json::Value::Null)
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_sent_at_or_now_without_a_timestamp_property() {
+        let event = event_at("2023-12-31T12:00:00.000Z");
+
+        // Without sent_at, the time the event was received
+        let processed = EventProcessor::default()
+            .with_timestamp_property(time_property())
+            .process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        let data: RawEvent = serde_json::from_str(&processed.data).expect("failed to parse data");
+        assert_eq!(data.timestamp.as_deref(), Some("2023-12-31T12:00:00.000Z"));
+        assert_eq!(
+            processed.timestamp,
+            Some(time::macros::datetime!(2024-01-01 00:00 UTC))
+        );
+
+        let sent_at = time::macros::datetime!(2023-12-31 23:59 UTC);
+        let context = ProcessingContext {
+            sent_at: Some(sent_at),
+            ..context(false)
+        };
+        let processed = EventProcessor::default()
+            .with_timestamp_property(time_property())
+            .process_single_event(&event, &context)
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.timestamp, Some(sent_at));
+    }
+
+    #[test]
+    fn it_ignores_unparseable_timestamp_properties() {
+        for value in [json!("yesterday"), json!(1704067200000i64), json!(null)] {
+            let processed = EventProcessor::default()
+                .with_timestamp_property(time_property())
+                .process_single_event(&event_with_time_property(value.clone()), &context(false))
+                .expect("failed to process event")
+                .expect("event was dropped");
+
+            let data: RawEvent =
+                serde_json::from_str(&processed.data).expect("failed to parse data");
+            assert_eq!(
+                data.timestamp.as_deref(),
+                Some("2023-12-31T12:00:00.000Z"),
+                "{}",
+                value
+            );
+            assert_eq!(
+                processed.timestamp,
+                Some(time::macros::datetime!(2024-01-01 00:00 UTC))
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_events_that_do_not_match_their_schema() {
+        let schemas = EventSchemas::from_value(json!({
+            "token": {"$pageview": {"required": ["$current_url"]}}
+        }))
+        .expect("failed to compile schemas");
+        let processor = EventProcessor::default().with_event_schemas(Arc::new(schemas));
+
+        let processed = processor
+            .process(&[event_at("2024-01-01T00:00:00.000Z")], &context(false))
+            .expect_err("event should not match its schema");
+        assert!(matches!(processed, CaptureError::SchemaViolation(_, _)));
+
+        let conforming: RawEvent = serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "properties": {"$current_url": "https://example.com"},
+        }))
+        .expect("failed to parse event");
+        let processed = processor
+            .process(&[conforming], &context(false))
+            .expect("failed to process events");
+        assert_eq!(processed.len(), 1);
+    }
+
+    fn event_with(properties: serde_json::Value) -> RawEvent {
+        serde_json::from_value(json!({
+            "event": "$pageview",
+            "distinct_id": "id1",
+            "properties": properties,
+        }))
+        .expect("failed to parse event")
+    }
+
+    #[test]
+    fn it_routes_events_with_the_first_matching_rule() {
+        let rules: DataTypeRules =
+            "$lib=server:server_ingest; $lib=server:exception; beta=true:exception"
+                .parse()
+                .expect("failed to parse rules");
+        let processor = EventProcessor::default().with_data_type_rules(rules);
+
+        let events = [
+            event_with(json!({"$lib": "server", "beta": true})),
+            event_with(json!({"beta": true})),
+            event_with(json!({"$lib": "web"})),
+        ];
+        let data_types: Vec<DataType> = processor
+            .process(&events, &context(false))
+            .expect("failed to process events")
+            .into_iter()
+            .map(|processed| processed.data_type)
+            .collect();
+        assert_eq!(
+            data_types,
+            vec![
+                // The first matching rule wins over the later ones
+                DataType::ServerIngest,
+                DataType::Exception,
+                // Events matching no rule keep the default
+                DataType::AnalyticsMain,
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_data_type_of_special_events() {
+        let processor =
+            EventProcessor::default().with_data_type_rules(DataTypeRules(vec![DataTypeRule {
+                property: "$group_type".to_string(),
+                value: "company".to_string(),
+                data_type: DataType::Exception,
+            }]));
+        let event = group_identify(json!({"$group_type": "company", "$group_key": "posthog"}));
+
+        let processed = processor
+            .process(&[event], &context(false))
+            .expect("failed to process events");
+        assert_eq!(processed[0].data_type, DataType::GroupIdentify);
+    }
+
+    #[test]
+    fn it_parses_data_type_rules() {
+        let rules: DataTypeRules = "$lib=server:analytics_historical;url=http://a:b:exception"
+            .parse()
+            .expect("failed to parse rules");
+        assert_eq!(
+            rules.0[1],
+            DataTypeRule {
+                property: "url".to_string(),
+                value: "http://a:b".to_string(),
+                data_type: DataType::Exception,
+            }
+        );
+
+        assert_eq!("".parse(), Ok(DataTypeRules::default()));
+        assert!("$lib:exception".parse::<DataTypeRules>().is_err());
+        assert!("$lib=server".parse::<DataTypeRules>().is_err());
+        assert!("=server:exception".parse::<DataTypeRules>().is_err());
+        assert!("$lib:server=x".parse::<DataTypeRules>().is_err());
+        assert!("$lib=server:unknown".parse::<DataTypeRules>().is_err());
+        assert_eq!(
+            "$lib=server:server_ingest"
+                .parse::<DataTypeRules>()
+                .map(|rules| rules.0[0].data_type),
+            Ok(DataType::ServerIngest)
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_route_of_the_request() {
+        let event = event_with(json!({}));
+        let processed = process_single_event(&event, &context(false))
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.route, None);
+
+        let context = ProcessingContext {
+            route: Some("experimental".to_string()),
+            ..context(false)
+        };
+        let processed = process_single_event(&event, &context)
+            .expect("failed to process event")
+            .expect("event was dropped");
+        assert_eq!(processed.route.as_deref(), Some("experimental"));
+    }
+}
@@ -1,8 +1,11 @@
 use std::collections::{HashMap, HashSet};
 use std::io::prelude::*;
+use std::str::FromStr;
 
+use base64::Engine;
 use bytes::{Buf, Bytes};
 use flate2::read::GzDecoder;
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use time::format_description::well_known::Iso8601;
@@ -11,6 +14,7 @@ use tracing::instrument;
 use uuid::Uuid;
 
 use crate::api::CaptureError;
+use crate::proto;
 use crate::token::validate_token;
 
 #[derive(Deserialize, Default)]
@@ -31,6 +35,8 @@ pub struct EventQuery {
 
     #[serde(alias = "_")]
     sent_at: Option<i64>,
+
+    include_team_id: Option<String>,
 }
 
 impl EventQuery {
@@ -49,6 +55,17 @@ impl EventQuery {
         }
         None
     }
+
+    /// Whether the client asked for the id of its team in the response, with `include_team_id=1`
+    /// or `include_team_id=true`.
+    pub fn include_team_id(&self) -> bool {
+        self.include_team_id.as_deref().is_some_and(is_truthy)
+    }
+}
+
+/// Whether a query param or header value enables an option.
+pub fn is_truthy(value: &str) -> bool {
+    value == "1" || value.eq_ignore_ascii_case("true")
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,7 +73,7 @@ pub struct EventFormData {
     pub data: String,
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
 pub struct RawEvent {
     #[serde(
         alias = "$token",
@@ -82,6 +99,9 @@ pub struct RawEvent {
 
 static GZIP_MAGIC_NUMBERS: [u8; 3] = [0x1f, 0x8b, 8];
 
+/// Number of bytes of the payload to show on each side of a JSON syntax error.
+const SYNTAX_ERROR_CONTEXT_BYTES: usize = 20;
+
 #[derive(Deserialize)]
 #[serde(untagged)]
 pub enum RawRequest {
@@ -128,7 +148,45 @@ impl RawRequest {
         };
 
         tracing::debug!(json = payload, "decoded event data");
-        Ok(serde_json::from_str::<RawRequest>(&payload)?)
+        serde_json::from_str::<RawRequest>(&payload).map_err(|e| {
+            if e.is_syntax() || e.is_eof() {
+                CaptureError::RequestDecodingError(describe_syntax_error(&payload, &e))
+            } else {
+                CaptureError::RequestParsingError(e)
+            }
+        })
+    }
+
+    /// Reads a `text/plain` body, that some SDKs behind strict proxies send as base64-encoded
+    /// JSON. Bodies that aren't base64, or don't decode to a request, are read as is.
+    #[instrument(skip_all)]
+    pub fn from_plain_text(bytes: Bytes) -> Result<RawRequest, CaptureError> {
+        let decoded = std::str::from_utf8(&bytes).ok().and_then(|text| {
+            base64::engine::general_purpose::STANDARD
+                .decode(text.trim())
+                .ok()
+        });
+        if let Some(decoded) = decoded {
+            if let Ok(request) = RawRequest::from_bytes(decoded.into()) {
+                return Ok(request);
+            }
+        }
+
+        RawRequest::from_bytes(bytes)
+    }
+
+    /// Reads a protobuf-encoded batch, as sent by native SDKs with a `Content-Type:
+    /// application/x-protobuf` header, into the same request as its JSON counterpart.
+    #[instrument(skip_all)]
+    pub fn from_protobuf(bytes: Bytes) -> Result<RawRequest, CaptureError> {
+        tracing::debug!(len = bytes.len(), "decoding new protobuf batch");
+
+        let batch = proto::Batch::decode(bytes).map_err(|e| {
+            tracing::error!("failed to decode protobuf: {}", e);
+            CaptureError::RequestDecodingError(String::from("invalid protobuf data"))
+        })?;
+
+        Ok(RawRequest::Batch(batch.try_into()?))
     }
 
     pub fn events(self) -> Vec<RawEvent> {
@@ -168,6 +226,28 @@ impl RawRequest {
     }
 }
 
+/// Describes a JSON syntax error with its position and a few bytes of the payload around it,
+/// to help fixing the payload without echoing all of it back.
+fn describe_syntax_error(payload: &str, error: &serde_json::Error) -> String {
+    let line_start: usize = payload
+        .split('\n')
+        .take(error.line().saturating_sub(1))
+        .map(|line| line.len() + 1)
+        .sum();
+    let position = (line_start + error.column().saturating_sub(1)).min(payload.len());
+
+    let mut start = position.saturating_sub(SYNTAX_ERROR_CONTEXT_BYTES);
+    while !payload.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (position + SYNTAX_ERROR_CONTEXT_BYTES).min(payload.len());
+    while !payload.is_char_boundary(end) {
+        end += 1;
+    }
+
+    format!("{}, near {:?}", error, &payload[start..end])
+}
+
 #[instrument(skip_all, fields(events = events.len()))]
 pub fn extract_token(events: &[RawEvent]) -> Result<String, CaptureError> {
     let distinct_tokens: HashSet<Option<String>> = HashSet::from_iter(
@@ -199,6 +279,27 @@ impl RawEvent {
         }
     }
 
+    /// Returns whether the SDK asked us not to record the client IP for this event, by setting
+    /// `$ip` to null or false, or by disabling person processing.
+    pub fn opts_out_of_ip(&self) -> bool {
+        matches!(
+            self.properties.get("$ip"),
+            Some(Value::Null) | Some(Value::Bool(false))
+        ) || matches!(
+            self.properties.get("$process_person_profile"),
+            Some(Value::Bool(false))
+        )
+    }
+
+    /// Returns the `$insert_id` property used to deduplicate events downstream, if it's a
+    /// non-empty string.
+    pub fn extract_insert_id(&self) -> Option<String> {
+        match self.properties.get("$insert_id") {
+            Some(Value::String(insert_id)) if !insert_id.is_empty() => Some(insert_id.clone()),
+            _ => None,
+        }
+    }
+
     /// Extracts, stringifies and trims the distinct_id to a 200 chars String.
     /// SDKs send the distinct_id either in the root field or as a property,
     /// and can send string, number, array, or map values. We try to best-effort
@@ -213,15 +314,96 @@ impl RawEvent {
             Some(id) => id,
         };
 
-        let distinct_id = value
-            .as_str()
-            .map(|s| s.to_owned())
-            .unwrap_or_else(|| value.to_string());
-        match distinct_id.len() {
-            0 => Err(CaptureError::EmptyDistinctId),
-            1..=200 => Ok(distinct_id),
-            _ => Ok(distinct_id.chars().take(200).collect()),
+        stringify_distinct_id(value)
+    }
+
+    /// Like `extract_distinct_id`, taking the distinct_id from the first of `fields` the event
+    /// has instead, for SDKs sending it elsewhere.
+    pub fn extract_distinct_id_from(
+        &self,
+        fields: &DistinctIdFields,
+    ) -> Result<String, CaptureError> {
+        let value = fields
+            .0
+            .iter()
+            .find_map(|field| field.get(self))
+            .ok_or(CaptureError::MissingDistinctId)?;
+
+        stringify_distinct_id(value)
+    }
+}
+
+fn stringify_distinct_id(value: &Value) -> Result<String, CaptureError> {
+    let distinct_id = value
+        .as_str()
+        .map(|s| s.to_owned())
+        .unwrap_or_else(|| value.to_string());
+    match distinct_id.len() {
+        0 => Err(CaptureError::EmptyDistinctId),
+        1..=200 => Ok(distinct_id),
+        _ => Ok(distinct_id.chars().take(200).collect()),
+    }
+}
+
+/// A field of events that may hold their distinct_id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DistinctIdField {
+    /// The top-level `distinct_id`, or `$distinct_id`, field.
+    DistinctId,
+    /// A property, by its path for nested ones, like `["user", "id"]`.
+    Property(Vec<String>),
+}
+
+impl DistinctIdField {
+    /// The value of this field in `event`, None if it's missing or null.
+    fn get<'a>(&self, event: &'a RawEvent) -> Option<&'a Value> {
+        let value = match self {
+            DistinctIdField::DistinctId => event.distinct_id.as_ref(),
+            DistinctIdField::Property(path) => {
+                let (name, nested) = path.split_first()?;
+                nested
+                    .iter()
+                    .try_fold(event.properties.get(name)?, |value, key| value.get(key))
+            }
+        };
+        value.filter(|value| !value.is_null())
+    }
+}
+
+/// Ordered fields events take their distinct_id from, the first one present and not null wins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DistinctIdFields(pub Vec<DistinctIdField>);
+
+impl FromStr for DistinctIdFields {
+    type Err = String;
+
+    /// Parse comma-separated field paths, like `distinct_id,properties.$user_id`. Only the
+    /// `distinct_id` field and properties can be read, nested properties with more dots.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::new();
+        for path in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let field = match path.split_once('.') {
+                None if path == "distinct_id" || path == "$distinct_id" => {
+                    DistinctIdField::DistinctId
+                }
+                Some(("properties", property))
+                    if property.split('.').all(|key| !key.is_empty()) =>
+                {
+                    DistinctIdField::Property(property.split('.').map(String::from).collect())
+                }
+                _ => {
+                    return Err(format!(
+                        "distinct_id field must be distinct_id or properties.<name>, got: {}",
+                        path
+                    ))
+                }
+            };
+            fields.push(field);
+        }
+        if fields.is_empty() {
+            return Err("at least one distinct_id field is needed".to_string());
         }
+        Ok(DistinctIdFields(fields))
     }
 }
 
@@ -233,6 +415,30 @@ pub struct ProcessingContext {
     pub now: String,
     pub client_ip: String,
     pub historical_migration: bool,
+    pub user_agent: Option<String>,
+    // Value of the X-PostHog-Route header, selecting the Kafka topic of the events
+    pub route: Option<String>,
+}
+
+impl ProcessingContext {
+    /// Request details attached to every event of the request, skipping the unknown ones.
+    pub fn metadata(&self) -> HashMap<String, String> {
+        [
+            ("lib_version", &self.lib_version),
+            ("user_agent", &self.user_agent),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_ref().map(|value| (key.to_string(), value.clone())))
+        .collect()
+    }
+
+    /// How far the time the request was received is ahead of the client's `sent_at`, which is
+    /// negative for client clocks running ahead of ours. None without a `sent_at`.
+    pub fn sent_at_offset(&self) -> Option<time::Duration> {
+        let sent_at = self.sent_at?;
+        let now = OffsetDateTime::parse(&self.now, &Iso8601::DEFAULT).ok()?;
+        Some(now - sent_at)
+    }
 }
 
 #[cfg(test)]
@@ -246,6 +452,7 @@ mod tests {
 
     use super::CaptureError;
     use super::RawRequest;
+    use super::{DistinctIdField, DistinctIdFields};
 
     #[test]
     fn decode_uncompressed_raw_event() {
@@ -269,6 +476,38 @@ mod tests {
                 .expect("cannot find distinct_id")
         );
     }
+    #[test]
+    fn decode_base64_plain_text_event() {
+        let payload =
+            json!({"event": "my_event1", "distinct_id": "my_id1", "api_key": "my_token1"});
+        let body = base64::engine::general_purpose::STANDARD.encode(payload.to_string());
+
+        let events = RawRequest::from_plain_text(Bytes::from(body))
+            .expect("failed to parse")
+            .events();
+        assert_eq!(1, events.len());
+        assert_eq!(Some("my_token1".to_string()), events[0].extract_token());
+        assert_eq!("my_event1".to_string(), events[0].event);
+    }
+
+    #[test]
+    fn decode_raw_json_plain_text_event() {
+        let payload =
+            json!({"event": "my_event1", "distinct_id": "my_id1", "api_key": "my_token1"});
+
+        let events = RawRequest::from_plain_text(Bytes::from(payload.to_string()))
+            .expect("failed to parse")
+            .events();
+        assert_eq!(1, events.len());
+        assert_eq!("my_event1".to_string(), events[0].event);
+
+        // Neither base64 nor JSON
+        assert!(matches!(
+            RawRequest::from_plain_text(Bytes::from("not an event")),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+    }
+
     #[test]
     fn decode_gzipped_raw_event() {
         let base64_payload = "H4sIADQSbmUCAz2MsQqAMAxE936FBEcnR2f/o4i9IRTb0AahiP9urcVMx3t3ucxQjxxn5bCrZUfLQEepYabpkzgRtOOWfyMpCpIyctVXY42PDifvsFoE73BF9hqFWuPu403YepT+WKNHmMnc5gENoFu2kwAAAA==";
@@ -349,6 +588,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn extract_distinct_id_from_fields() {
+        let fields: DistinctIdFields =
+            "distinct_id, properties.$user_id, properties.$anon_distinct_id, properties.user.id"
+                .parse()
+                .expect("failed to parse fields");
+        let extract = |input: &'static str| -> Result<String, CaptureError> {
+            let parsed = RawRequest::from_bytes(input.into())
+                .expect("failed to parse")
+                .events();
+            parsed[0].extract_distinct_id_from(&fields)
+        };
+
+        // Each candidate is used when it's the only one present
+        assert_eq!(
+            extract(r#"{"event": "e", "distinct_id": "root"}"#).unwrap(),
+            "root"
+        );
+        assert_eq!(
+            extract(r#"{"event": "e", "$distinct_id": "root"}"#).unwrap(),
+            "root"
+        );
+        assert_eq!(
+            extract(r#"{"event": "e", "properties": {"$user_id": "user"}}"#).unwrap(),
+            "user"
+        );
+        assert_eq!(
+            extract(r#"{"event": "e", "properties": {"$anon_distinct_id": "anon"}}"#).unwrap(),
+            "anon"
+        );
+        assert_eq!(
+            extract(r#"{"event": "e", "properties": {"user": {"id": 42}}}"#).unwrap(),
+            "42"
+        );
+
+        // The first candidate present wins, null ones are skipped
+        assert_eq!(
+            extract(
+                r#"{"event": "e", "distinct_id": null, "properties": {"$user_id": "user", "$anon_distinct_id": "anon"}}"#
+            )
+            .unwrap(),
+            "user"
+        );
+
+        // Only the configured candidates are used
+        assert!(matches!(
+            extract(r#"{"event": "e", "properties": {"distinct_id": "property"}}"#),
+            Err(CaptureError::MissingDistinctId)
+        ));
+        assert!(matches!(
+            extract(r#"{"event": "e", "properties": {"$user_id": null, "user": "id"}}"#),
+            Err(CaptureError::MissingDistinctId)
+        ));
+        assert!(matches!(
+            extract(r#"{"event": "e", "properties": {"$user_id": ""}}"#),
+            Err(CaptureError::EmptyDistinctId)
+        ));
+    }
+
+    #[test]
+    fn parse_distinct_id_fields() {
+        assert_eq!(
+            "distinct_id,properties.$user_id,properties.user.id"
+                .parse::<DistinctIdFields>()
+                .unwrap(),
+            DistinctIdFields(vec![
+                DistinctIdField::DistinctId,
+                DistinctIdField::Property(vec!["$user_id".to_string()]),
+                DistinctIdField::Property(vec!["user".to_string(), "id".to_string()]),
+            ])
+        );
+
+        assert!("".parse::<DistinctIdFields>().is_err());
+        assert!("$user_id".parse::<DistinctIdFields>().is_err());
+        assert!("properties".parse::<DistinctIdFields>().is_err());
+        assert!("properties.".parse::<DistinctIdFields>().is_err());
+        assert!("properties.user..id".parse::<DistinctIdFields>().is_err());
+    }
+
     #[test]
     fn extract_distinct_id_trims_to_200_chars() {
         let distinct_id: String = rand::thread_rng()
@@ -431,4 +749,177 @@ mod tests {
         assert_extracted_token(r#"{"event":"e","$token":"single_token"}"#, "single_token");
         assert_extracted_token(r#"{"event":"e","api_key":"single_token"}"#, "single_token");
     }
+
+    #[test]
+    fn syntax_errors_report_their_position() {
+        let payload = r#"[{"event": "e1", "distinct_id": "id1"},
+{"event": "e2" "distinct_id": "id2", "properties": {"secret": "do not echo this back"}}]"#;
+
+        let err = RawRequest::from_bytes(payload.into())
+            .err()
+            .expect("payload should be rejected");
+        let message = err.to_string();
+        assert!(matches!(err, CaptureError::RequestDecodingError(_)));
+        assert!(message.contains("line 2 column 16"), "{}", message);
+        assert!(
+            message.contains(r#"{\"event\": \"e2\" \"distinct_id\""#),
+            "{}",
+            message
+        );
+        assert!(!message.contains("do not echo this back"), "{}", message);
+
+        let truncated = r#"{"event": "e1", "distinct_id": "id1""#;
+        let err = RawRequest::from_bytes(truncated.into())
+            .err()
+            .expect("payload should be rejected");
+        assert!(err.to_string().contains("line 1 column 36"), "{}", err);
+    }
+
+    #[test]
+    fn syntax_error_snippets_respect_char_boundaries() {
+        let payload = format!(r#"{{"event": "{}" "#, "é".repeat(30));
+
+        let err = RawRequest::from_bytes(payload.into())
+            .err()
+            .expect("payload should be rejected");
+        assert!(matches!(err, CaptureError::RequestDecodingError(_)));
+    }
+
+    #[test]
+    fn shape_errors_are_parsing_errors() {
+        let err = RawRequest::from_bytes(r#"{"distinct_id": "id1"}"#.into())
+            .err()
+            .expect("payload should be rejected");
+        assert!(matches!(err, CaptureError::RequestParsingError(_)));
+    }
+
+    /// Converts a JSON value into the protobuf value an SDK would send for it.
+    fn to_proto_value(value: serde_json::Value) -> prost_types::Value {
+        use prost_types::value::Kind;
+
+        let kind = match value {
+            serde_json::Value::Null => Kind::NullValue(0),
+            serde_json::Value::Bool(value) => Kind::BoolValue(value),
+            serde_json::Value::Number(value) => Kind::NumberValue(value.as_f64().unwrap()),
+            serde_json::Value::String(value) => Kind::StringValue(value),
+            serde_json::Value::Array(values) => Kind::ListValue(prost_types::ListValue {
+                values: values.into_iter().map(to_proto_value).collect(),
+            }),
+            serde_json::Value::Object(fields) => Kind::StructValue(prost_types::Struct {
+                fields: fields
+                    .into_iter()
+                    .map(|(key, value)| (key, to_proto_value(value)))
+                    .collect(),
+            }),
+        };
+        prost_types::Value { kind: Some(kind) }
+    }
+
+    #[test]
+    fn protobuf_batch_matches_json_batch() {
+        use prost::Message;
+
+        let properties = json!({
+            "$current_url": "https://example.com",
+            "count": 3,
+            "ratio": 0.5,
+            "enabled": true,
+            "missing": null,
+            "tags": ["a", "b"],
+            "nested": {"key": "value"},
+        });
+        let json_payload = json!({
+            "api_key": "my_token",
+            "historical_migration": true,
+            "sent_at": "2024-06-01T12:00:00Z",
+            "batch": [
+                {
+                    "event": "my_event",
+                    "distinct_id": "my_id",
+                    "uuid": "018e3a2b-9c8d-7e6f-a5b4-c3d2e1f0a9b8",
+                    "properties": properties,
+                    "timestamp": "2024-06-01T11:59:59Z",
+                    "$set": {"email": "user@example.com"},
+                },
+                {
+                    "event": "other_event",
+                    "distinct_id": 42,
+                    "offset": 100,
+                },
+            ],
+        });
+
+        let proto_payload = super::proto::Batch {
+            api_key: "my_token".to_string(),
+            historical_migration: Some(true),
+            sent_at: Some("2024-06-01T12:00:00Z".to_string()),
+            batch: vec![
+                super::proto::Event {
+                    event: "my_event".to_string(),
+                    distinct_id: Some(to_proto_value(json!("my_id"))),
+                    uuid: Some("018e3a2b-9c8d-7e6f-a5b4-c3d2e1f0a9b8".to_string()),
+                    properties: properties
+                        .as_object()
+                        .unwrap()
+                        .clone()
+                        .into_iter()
+                        .map(|(key, value)| (key, to_proto_value(value)))
+                        .collect(),
+                    timestamp: Some("2024-06-01T11:59:59Z".to_string()),
+                    set: Some(prost_types::Struct {
+                        fields: [(
+                            "email".to_string(),
+                            to_proto_value(json!("user@example.com")),
+                        )]
+                        .into(),
+                    }),
+                    ..Default::default()
+                },
+                super::proto::Event {
+                    event: "other_event".to_string(),
+                    distinct_id: Some(to_proto_value(json!(42))),
+                    offset: Some(100),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let from_json =
+            RawRequest::from_bytes(json_payload.to_string().into()).expect("failed to parse json");
+        let from_proto = RawRequest::from_protobuf(proto_payload.encode_to_vec().into())
+            .expect("failed to parse protobuf");
+
+        assert_eq!(
+            from_proto.extract_and_verify_token().unwrap(),
+            from_json.extract_and_verify_token().unwrap()
+        );
+        assert!(from_proto.historical_migration());
+        assert_eq!(from_proto.sent_at(), from_json.sent_at());
+        assert_eq!(
+            serde_json::to_value(from_proto.events()).unwrap(),
+            serde_json::to_value(from_json.events()).unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_protobuf_is_a_decoding_error() {
+        let err = RawRequest::from_protobuf(Bytes::from_static(&[0xff, 0xff, 0xff]))
+            .err()
+            .expect("payload should be rejected");
+        assert!(matches!(err, CaptureError::RequestDecodingError(_)));
+
+        let invalid_uuid = super::proto::Batch {
+            api_key: "my_token".to_string(),
+            batch: vec![super::proto::Event {
+                event: "my_event".to_string(),
+                uuid: Some("not-a-uuid".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let err = RawRequest::from_protobuf(prost::Message::encode_to_vec(&invalid_uuid).into())
+            .err()
+            .expect("payload should be rejected");
+        assert!(matches!(err, CaptureError::RequestDecodingError(_)));
+    }
 }
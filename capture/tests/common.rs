@@ -2,13 +2,14 @@
 
 use std::default::Default;
 use std::net::SocketAddr;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::str::FromStr;
 use std::string::ToString;
-use std::sync::{Arc, Once};
+use std::sync::{Arc, Mutex, Once};
 use std::time::Duration;
 
 use anyhow::bail;
+use async_trait::async_trait;
 use once_cell::sync::Lazy;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
@@ -22,11 +23,24 @@ use tokio::sync::Notify;
 use tokio::time::timeout;
 use tracing::{debug, warn};
 
-use capture::config::{Config, KafkaConfig};
+use axum::Router;
+use capture::api::{CaptureError, ProcessedEvent};
+use capture::config::{Config, EventConfig, KafkaConfig};
+use capture::limiters::billing::BillingLimiter;
+use capture::redis::MockRedisClient;
+use capture::router::{router, RouterOptions};
 use capture::server::serve;
+use capture::sinks::kafka::KafkaTimestampSource;
+use capture::sinks::print::PrintSink;
+use capture::sinks::routing::SinkRouter;
+use capture::sinks::Event;
+use capture::time::SystemTime;
+use capture::v0_endpoint::{FutureDatedMode, OversizedPropertiesMode};
+use health::HealthRegistry;
 
 pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     print_sink: false,
+    print_sink_batch_framing: false,
     address: SocketAddr::from_str("127.0.0.1:0").unwrap(),
     redis_url: "redis://localhost:6379/".to_string(),
     overflow_enabled: false,
@@ -36,20 +50,115 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     kafka: KafkaConfig {
         kafka_producer_linger_ms: 0, // Send messages as soon as possible
         kafka_producer_queue_mib: 10,
+        kafka_producer_batch_size: None,
+        kafka_producer_max_in_flight: None,
         kafka_message_timeout_ms: 10000, // 10s, ACKs can be slow on low volumes, should be tuned
         kafka_compression_codec: "none".to_string(),
+        kafka_compression_level: None,
         kafka_hosts: "kafka:9092".to_string(),
         kafka_topic: "events_plugin_ingestion".to_string(),
         kafka_historical_topic: "events_plugin_ingestion_historical".to_string(),
+        kafka_group_identify_topic: None,
+        kafka_exceptions_topic: None,
+        kafka_server_ingest_topic: None,
+        kafka_route_topics: None,
+        kafka_overflow_partitions_refresh_secs: 60,
+        kafka_timestamp_source: KafkaTimestampSource::ProduceTime,
         kafka_tls: false,
     },
+    kafka_token_topics: None,
+    kafka_split_hosts: None,
+    kafka_split_percent: 0.0,
     otel_url: None,
     otel_sampling_rate: 0.0,
     otel_service_name: "capture-testing".to_string(),
     export_prometheus: false,
+    metrics_drain_secs: 0,
+    event: EventConfig {
+        event_processing_threads: 0,
+        event_processing_parallel_threshold: 100,
+        event_properties_max_bytes: None,
+        event_properties_oversized_mode: OversizedPropertiesMode::Drop,
+        event_max_future_skew_secs: None,
+        event_future_dated_mode: FutureDatedMode::Clamp,
+        event_timestamp_property: None,
+        event_distinct_id_fields: None,
+        event_schemas_path: None,
+        event_data_type_rules: None,
+        event_geoip_database_path: None,
+        event_redacted_properties: None,
+        event_validate_group_identify: false,
+        event_validate_exceptions: false,
+    },
+    receipt_ttl_secs: None,
+    team_rate_limit_per_second: None,
+    team_rate_limit_overrides: None,
+    max_in_flight_batches_per_token: NonZeroUsize::new(100).unwrap(),
+    plain_text_base64: false,
+    max_concurrent_requests: None,
+    response_compression_min_bytes: None,
+    allowed_content_types:
+        "application/json,application/x-www-form-urlencoded,text/plain,application/x-protobuf"
+            .to_string(),
+    tls_cert_path: None,
+    tls_key_path: None,
 });
 
 static TRACING_INIT: Once = Once::new();
+/// A router sending events to `sinks`, with `redis` behind its billing limiter, for tests that
+/// don't need a Kafka cluster.
+pub fn test_router(
+    redis: Arc<MockRedisClient>,
+    sinks: SinkRouter,
+    options: RouterOptions,
+) -> Router {
+    let billing = BillingLimiter::new(time::Duration::weeks(1), redis.clone())
+        .expect("failed to create billing limiter");
+
+    router(
+        SystemTime {},
+        HealthRegistry::new("dummy"),
+        sinks,
+        redis,
+        billing,
+        options,
+    )
+}
+
+/// Keeps the events sent to it, for tests to look at.
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    events: Arc<Mutex<Vec<ProcessedEvent>>>,
+}
+
+impl MemorySink {
+    pub fn len(&self) -> usize {
+        self.events.lock().unwrap().len()
+    }
+
+    pub fn events(&self) -> Vec<ProcessedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Event for MemorySink {
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        self.events.lock().unwrap().extend_from_slice(&events);
+        Ok(())
+    }
+}
+
+/// Sinks printing events, for tests that don't look at them.
+pub fn print_sinks() -> SinkRouter {
+    SinkRouter::new(Arc::new(PrintSink::default()))
+}
+
 pub fn setup_tracing() {
     TRACING_INIT.call_once(|| {
         tracing_subscriber::fmt()
@@ -174,6 +283,15 @@ impl EphemeralTopic {
         }
     }
 
+    /// Returns the timestamp of the next message, in milliseconds since the epoch.
+    pub fn next_message_timestamp(&self) -> anyhow::Result<Option<i64>> {
+        match self.consumer.poll(self.read_timeout) {
+            Some(Ok(message)) => Ok(message.timestamp().to_millis()),
+            Some(Err(err)) => bail!("kafka read error: {}", err),
+            None => bail!("kafka read timeout"),
+        }
+    }
+
     pub fn topic_name(&self) -> &str {
         &self.topic_name
     }
@@ -1,24 +1,26 @@
 use assert_json_diff::assert_json_matches_no_panic;
-use async_trait::async_trait;
 use axum::http::StatusCode;
 use axum_test_helper::TestClient;
 use base64::engine::general_purpose;
 use base64::Engine;
-use capture::api::{CaptureError, CaptureResponse, CaptureResponseCode, DataType, ProcessedEvent};
+use capture::api::{CaptureResponse, CaptureResponseCode, DataType};
 use capture::limiters::billing::BillingLimiter;
 use capture::redis::MockRedisClient;
-use capture::router::router;
-use capture::sinks::Event;
+use capture::router::{router, RouterOptions};
+use capture::sinks::routing::SinkRouter;
 use capture::time::TimeSource;
 use health::HealthRegistry;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use time::format_description::well_known::{Iso8601, Rfc3339};
 use time::{Duration, OffsetDateTime};
 
+use crate::common::*;
+mod common;
+
 #[derive(Debug, Deserialize)]
 struct RequestDump {
     path: String,
@@ -46,34 +48,6 @@ impl TimeSource for FixedTime {
     }
 }
 
-#[derive(Clone, Default)]
-struct MemorySink {
-    events: Arc<Mutex<Vec<ProcessedEvent>>>,
-}
-
-impl MemorySink {
-    fn len(&self) -> usize {
-        self.events.lock().unwrap().len()
-    }
-
-    fn events(&self) -> Vec<ProcessedEvent> {
-        self.events.lock().unwrap().clone()
-    }
-}
-
-#[async_trait]
-impl Event for MemorySink {
-    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
-        self.events.lock().unwrap().push(event);
-        Ok(())
-    }
-
-    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
-        self.events.lock().unwrap().extend_from_slice(&events);
-        Ok(())
-    }
-}
-
 #[tokio::test]
 async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
     let file = File::open(REQUESTS_DUMP_FILE_NAME)?;
@@ -106,10 +80,10 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
         let app = router(
             timesource,
             liveness.clone(),
-            sink.clone(),
+            SinkRouter::new(Arc::new(sink.clone())),
             redis,
             billing,
-            false,
+            RouterOptions::default(),
         );
 
         let client = TestClient::new(app);
@@ -134,7 +108,9 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
         );
         assert_eq!(
             Some(CaptureResponse {
-                status: CaptureResponseCode::Ok
+                status: CaptureResponseCode::Ok,
+                receipt: None,
+                team_id: None,
             }),
             res.json().await
         );
@@ -149,8 +125,13 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
             sink.events().iter().zip(case.output.iter()).enumerate()
         {
             // Ensure the data type matches
+            let event_name = serde_json::from_str::<Value>(&message.data)?["event"].clone();
             if case.historical_migration {
                 assert_eq!(DataType::AnalyticsHistorical, message.data_type);
+            } else if event_name == "$groupidentify" {
+                assert_eq!(DataType::GroupIdentify, message.data_type);
+            } else if event_name == "$exception" {
+                assert_eq!(DataType::Exception, message.data_type);
             } else {
                 assert_eq!(DataType::AnalyticsMain, message.data_type);
             }
@@ -208,10 +189,15 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
                 }
             }
 
+            // Request metadata and the sent_at offset are not sent by django, ignore them
+            let mut found = json!(message);
+            if let Some(object) = found.as_object_mut() {
+                object.remove("metadata");
+                object.remove("sent_at_offset_ms");
+            }
+
             let match_config = assert_json_diff::Config::new(assert_json_diff::CompareMode::Strict);
-            if let Err(e) =
-                assert_json_matches_no_panic(&json!(expected), &json!(message), match_config)
-            {
+            if let Err(e) = assert_json_matches_no_panic(&json!(expected), &found, match_config) {
                 println!(
                     "record mismatch at line {}, event {}: {}",
                     line_number + 1,
@@ -4,6 +4,9 @@ use anyhow::Result;
 use assert_json_diff::assert_json_include;
 use reqwest::StatusCode;
 use serde_json::json;
+use time::OffsetDateTime;
+
+use capture::sinks::kafka::KafkaTimestampSource;
 
 use crate::common::*;
 mod common;
@@ -349,3 +352,46 @@ async fn it_trims_distinct_id() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_sets_the_record_timestamp_from_sent_at() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.kafka.kafka_timestamp_source = KafkaTimestampSource::SentAt;
+    let server = ServerHandle::for_config(config).await;
+
+    let event = json!({
+        "token": token,
+        "sent_at": "2023-05-01T12:00:00.123Z",
+        "batch": [{"event": "event1", "distinct_id": distinct_id}]
+    });
+    let res = server.capture_events(event.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        main_topic.next_message_timestamp()?,
+        Some(1_682_942_400_123)
+    );
+
+    // Events without a sent_at fall back to the produce time
+    let before = OffsetDateTime::now_utc().unix_timestamp() * 1000;
+    let event = json!({
+        "token": token,
+        "event": "event2",
+        "distinct_id": distinct_id
+    });
+    let res = server.capture_events(event.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    let timestamp = main_topic
+        .next_message_timestamp()?
+        .expect("record has no timestamp");
+    assert!(timestamp >= before);
+
+    Ok(())
+}
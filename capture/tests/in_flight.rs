@@ -0,0 +1,105 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use axum::Router;
+use axum_test_helper::TestClient;
+use capture::api::{CaptureError, ProcessedEvent};
+use capture::limiters::in_flight::InFlightLimiter;
+use capture::redis::MockRedisClient;
+use capture::router::RouterOptions;
+use capture::sinks::routing::SinkRouter;
+use capture::sinks::Event;
+use serde_json::json;
+use tokio::sync::{Notify, Semaphore};
+
+use crate::common::*;
+mod common;
+
+/// Holds the events of the `busy` token until released, sending the others right away.
+struct HoldingSink {
+    held: Notify,
+    release: Semaphore,
+}
+
+impl HoldingSink {
+    fn new() -> Self {
+        HoldingSink {
+            held: Notify::new(),
+            release: Semaphore::new(0),
+        }
+    }
+
+    async fn hold(&self, events: &[ProcessedEvent]) {
+        if events.iter().any(|event| event.token == "busy") {
+            self.held.notify_one();
+            self.release
+                .acquire()
+                .await
+                .expect("release semaphore closed")
+                .forget();
+        }
+    }
+}
+
+#[async_trait]
+impl Event for HoldingSink {
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        self.hold(&[event]).await;
+        Ok(())
+    }
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        self.hold(&events).await;
+        Ok(())
+    }
+}
+
+fn app(sink: Arc<HoldingSink>, max_in_flight: usize) -> Router {
+    test_router(
+        Arc::new(MockRedisClient::new()),
+        SinkRouter::new(sink),
+        RouterOptions {
+            in_flight: Some(InFlightLimiter::new(
+                NonZeroUsize::new(max_in_flight).unwrap(),
+            )),
+            ..Default::default()
+        },
+    )
+}
+
+fn batch(token: &str) -> String {
+    json!({
+        "api_key": token,
+        "batch": [{"event": "one", "distinct_id": "id1"}]
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn it_limits_batches_in_flight_per_token() {
+    let sink = Arc::new(HoldingSink::new());
+    let client = TestClient::new(app(sink.clone(), 1));
+
+    // The first batch of the busy token is held in the sink, saturating its concurrency
+    let held = client.post("/batch").body(batch("busy")).send();
+    tokio::pin!(held);
+    tokio::select! {
+        _ = &mut held => panic!("the busy batch wasn't held"),
+        _ = sink.held.notified() => {}
+    }
+
+    let res = client.post("/batch").body(batch("busy")).send().await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Other tokens are unaffected
+    let res = client.post("/batch").body(batch("other")).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // The busy token gets its slot back once its batch is sent
+    sink.release.add_permits(1);
+    assert_eq!(held.await.status(), StatusCode::OK);
+    sink.release.add_permits(1);
+    let res = client.post("/batch").body(batch("busy")).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
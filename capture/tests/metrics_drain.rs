@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::common::*;
+mod common;
+
+#[tokio::test]
+async fn it_serves_metrics_until_the_drain_is_over() {
+    setup_tracing();
+    let mut config = DEFAULT_CONFIG.clone();
+    config.print_sink = true;
+    config.export_prometheus = true;
+    config.metrics_drain_secs = 2;
+
+    let server = ServerHandle::for_config(config).await;
+    let metrics_url = format!("http://{:?}/metrics", server.addr);
+    let response = reqwest::get(&metrics_url)
+        .await
+        .expect("failed to get metrics");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Dropping the handle sends the shutdown signal
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // The final scrape still gets the metrics while draining
+    let response = reqwest::get(&metrics_url)
+        .await
+        .expect("failed to get metrics while draining");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Then the server stops
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(reqwest::get(&metrics_url).await.is_err());
+}
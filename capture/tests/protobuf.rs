@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum_test_helper::TestClient;
+use capture::proto::{Batch, Event};
+use capture::redis::MockRedisClient;
+use capture::router::{parse_content_types, with_content_type_allowlist, RouterOptions};
+use capture::sinks::routing::SinkRouter;
+use prost::Message;
+use prost_types::value::Kind;
+
+use crate::common::*;
+mod common;
+
+fn app(sink: &MemorySink) -> TestClient {
+    let router = test_router(
+        Arc::new(MockRedisClient::new()),
+        SinkRouter::new(Arc::new(sink.clone())),
+        RouterOptions::default(),
+    );
+    // Like the server, only accept the content types allowed by default
+    TestClient::new(with_content_type_allowlist(
+        router,
+        parse_content_types(&DEFAULT_CONFIG.allowed_content_types),
+    ))
+}
+
+fn string_value(value: &str) -> prost_types::Value {
+    prost_types::Value {
+        kind: Some(Kind::StringValue(value.to_string())),
+    }
+}
+
+#[tokio::test]
+async fn it_captures_protobuf_batches() {
+    let sink = MemorySink::default();
+    let client = app(&sink);
+
+    let batch = Batch {
+        api_key: "token".to_string(),
+        batch: vec![
+            Event {
+                event: "one".to_string(),
+                distinct_id: Some(string_value("id1")),
+                ..Default::default()
+            },
+            Event {
+                event: "two".to_string(),
+                distinct_id: Some(string_value("id2")),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+
+    let res = client
+        .post("/batch")
+        .header("Content-Type", "application/x-protobuf")
+        .body(batch.encode_to_vec())
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text().await);
+
+    let events = sink.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].token, "token");
+    assert_eq!(events[0].distinct_id, "id1");
+    assert_eq!(events[1].distinct_id, "id2");
+}
+
+#[tokio::test]
+async fn it_rejects_malformed_protobuf_batches() {
+    let sink = MemorySink::default();
+    let client = app(&sink);
+
+    let res = client
+        .post("/batch")
+        .header("Content-Type", "application/x-protobuf")
+        .body(vec![0xff, 0xff, 0xff])
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(sink.len(), 0);
+}
@@ -0,0 +1,79 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use axum::http::{header, StatusCode};
+use axum::Router;
+use axum_test_helper::TestClient;
+use capture::limiters::team::{TeamRateLimitOverrides, TeamRateLimiter};
+use capture::redis::MockRedisClient;
+use capture::router::RouterOptions;
+use serde_json::json;
+
+use crate::common::*;
+mod common;
+
+fn app(rate_limiter: Option<TeamRateLimiter>) -> Router {
+    test_router(
+        Arc::new(MockRedisClient::new()),
+        print_sinks(),
+        RouterOptions {
+            rate_limiter,
+            ..Default::default()
+        },
+    )
+}
+
+fn batch(token: &str) -> String {
+    json!({
+        "api_key": token,
+        "batch": [{"event": "one", "distinct_id": "id1"}]
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn it_processes_requests_under_the_limit() {
+    let rate_limiter = TeamRateLimiter::new(NonZeroU32::new(3), TeamRateLimitOverrides::default());
+    let client = TestClient::new(app(Some(rate_limiter)));
+
+    for _ in 0..3 {
+        let res = client.post("/batch").body(batch("token")).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn it_rejects_requests_over_the_limit_with_retry_after() {
+    let overrides: TeamRateLimitOverrides = "limited=1".parse().unwrap();
+    let rate_limiter = TeamRateLimiter::new(NonZeroU32::new(100), overrides);
+    let client = TestClient::new(app(Some(rate_limiter)));
+
+    let res = client.post("/batch").body(batch("limited")).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = client.post("/batch").body(batch("limited")).send().await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = res
+        .headers()
+        .get(header::RETRY_AFTER)
+        .expect("no Retry-After header")
+        .to_str()
+        .unwrap()
+        .parse()
+        .expect("Retry-After is not a number of seconds");
+    assert_eq!(retry_after, 1);
+
+    // Other teams aren't limited by the override
+    let res = client.post("/batch").body(batch("token")).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn it_does_not_limit_without_a_rate_limiter() {
+    let client = TestClient::new(app(None));
+
+    for _ in 0..10 {
+        let res = client.post("/batch").body(batch("token")).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::Router;
+use axum_test_helper::TestClient;
+use capture::api::CaptureResponse;
+use capture::receipts::{Receipt, Receipts};
+use capture::redis::MockRedisClient;
+use capture::router::RouterOptions;
+use serde_json::json;
+
+use crate::common::*;
+mod common;
+
+fn app(receipts_ttl_secs: Option<u64>) -> Router {
+    let redis = Arc::new(MockRedisClient::new());
+    let receipts = receipts_ttl_secs.map(|ttl_secs| Receipts::new(redis.clone(), ttl_secs));
+
+    test_router(
+        redis,
+        print_sinks(),
+        RouterOptions {
+            receipts,
+            ..Default::default()
+        },
+    )
+}
+
+fn batch() -> String {
+    json!({
+        "api_key": "token",
+        "batch": [
+            {"event": "one", "distinct_id": "id1"},
+            {"event": "two", "distinct_id": "id1"},
+        ]
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn it_returns_a_queryable_receipt() {
+    let client = TestClient::new(app(Some(60)));
+
+    let res = client.post("/batch").body(batch()).send().await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let response: CaptureResponse = res.json().await;
+    let id = response.receipt.expect("no receipt id in the response");
+
+    let res = client.get(&format!("/capture/receipt/{}", id)).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let receipt: Receipt = res.json().await;
+    assert_eq!(receipt.events, 2);
+    assert!(!receipt.received_at.is_empty());
+
+    let res = client
+        .get("/capture/receipt/0190a6e0-0000-7000-8000-000000000000")
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn it_returns_ok_without_receipts() {
+    let client = TestClient::new(app(None));
+
+    let res = client.post("/batch").body(batch()).send().await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let response: CaptureResponse = res.json().await;
+    assert_eq!(response.receipt, None);
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::Router;
+use axum_test_helper::TestClient;
+use capture::redis::{Client, MockRedisClient};
+use capture::router::RouterOptions;
+use capture::teams::TEAM_TOKEN_CACHE_PREFIX;
+use serde_json::{json, Value};
+
+use crate::common::*;
+mod common;
+
+async fn app() -> Router {
+    let redis = Arc::new(MockRedisClient::new());
+    redis
+        .set_ex(
+            format!("{}token", TEAM_TOKEN_CACHE_PREFIX),
+            json!({"id": 42, "name": "team", "api_token": "token"}).to_string(),
+            60,
+        )
+        .await
+        .expect("failed to store team");
+
+    test_router(redis, print_sinks(), RouterOptions::default())
+}
+
+fn batch(token: &str) -> String {
+    json!({
+        "api_key": token,
+        "batch": [{"event": "one", "distinct_id": "id1"}]
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn it_returns_the_team_id_when_asked_for() {
+    let client = TestClient::new(app().await);
+
+    let res = client
+        .post("/batch?include_team_id=1")
+        .body(batch("token"))
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let response: Value = res.json().await;
+    assert_eq!(response, json!({"status": "Ok", "team_id": 42}));
+
+    let res = client
+        .post("/batch")
+        .header("x-posthog-include-team-id", "true")
+        .body(batch("token"))
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let response: Value = res.json().await;
+    assert_eq!(response, json!({"status": "Ok", "team_id": 42}));
+}
+
+#[tokio::test]
+async fn it_omits_the_team_id_by_default() {
+    let client = TestClient::new(app().await);
+
+    for path in ["/batch", "/batch?include_team_id=0"] {
+        let res = client.post(path).body(batch("token")).send().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let response: Value = res.json().await;
+        assert_eq!(response, json!({"status": "Ok"}));
+    }
+}
+
+#[tokio::test]
+async fn it_omits_the_team_id_of_unknown_tokens() {
+    let client = TestClient::new(app().await);
+
+    // The events are still accepted
+    let res = client
+        .post("/batch?include_team_id=true")
+        .body(batch("unknown"))
+        .send()
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let response: Value = res.json().await;
+    assert_eq!(response, json!({"status": "Ok"}));
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+/// Gets the metrics recorded until a binary shuts down scraped before it exits, so that
+/// dashboards don't miss the last increments during rollouts. As Prometheus pulls metrics, the
+/// metrics endpoint is kept up for `drain` after the shutdown signal, for a final scrape.
+#[derive(Default)]
+pub struct MetricsDrain {
+    drain: Duration,
+}
+
+impl MetricsDrain {
+    pub fn new(drain: Duration) -> Self {
+        Self { drain }
+    }
+
+    /// Wait for the drain period while the metrics endpoint, which must still be served, gets
+    /// its final scrape.
+    pub async fn run(self) {
+        if !self.drain.is_zero() {
+            tracing::info!(
+                "waiting {:?} for a final metrics scrape before exiting",
+                self.drain
+            );
+            tokio::time::sleep(self.drain).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_waits_for_the_drain_period() {
+        let start = Instant::now();
+        MetricsDrain::new(Duration::from_millis(50)).run().await;
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn it_returns_right_away_without_drain_period() {
+        let start = Instant::now();
+        MetricsDrain::default().run().await;
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
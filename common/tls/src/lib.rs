@@ -0,0 +1,52 @@
+use std::future::Future;
+use std::net::SocketAddr;
+
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Serve `app` over HTTPS with the PEM certificate and key found at the given paths, until
+/// `shutdown` resolves and the requests in flight are done.
+/// The certificate is reloaded from the same paths on SIGHUP, to pick up rotations without a restart.
+pub async fn serve_tls<F>(
+    app: Router,
+    listener: TcpListener,
+    cert_path: String,
+    key_path: String,
+    shutdown: F,
+) where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let tls_config = RustlsConfig::from_pem_file(&cert_path, &key_path)
+        .await
+        .expect("failed to load TLS certificate");
+
+    let reload_config = tls_config.clone();
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).expect("failed to register SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            match reload_config
+                .reload_from_pem_file(&cert_path, &key_path)
+                .await
+            {
+                Ok(()) => tracing::info!("reloaded TLS certificate"),
+                Err(e) => tracing::error!("failed to reload TLS certificate: {}", e),
+            }
+        }
+    });
+
+    let handle = axum_server::Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_handle.graceful_shutdown(None);
+    });
+
+    let listener = listener.into_std().expect("failed to convert TLS listener");
+    axum_server::from_tcp_rustls(listener, tls_config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap()
+}
@@ -3,8 +3,11 @@ use std::collections::HashMap;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error;
 
+use crate::flag_matching::FeatureFlagEvaluationReason;
+
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum FlagsResponseCode {
     Ok = 1,
@@ -16,6 +19,49 @@ pub struct FlagsResponse {
     pub error_while_computing_flags: bool,
     // TODO: better typing here, support bool responses
     pub feature_flags: HashMap<String, String>,
+    /// Flags the client knew that don't exist anymore, only set when the request sent the flags
+    /// it knew, in which case `feature_flags` only holds the flags whose value changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed_flags: Option<Vec<String>>,
+    /// The quota resources the team went over, only set when flags weren't evaluated because of
+    /// it, in which case `feature_flags` is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_limited: Option<Vec<String>>,
+}
+
+/// The evaluation of the flags of one distinct_id, as returned by the `/bulk_flags` endpoint.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkFlagsResult {
+    pub distinct_id: String,
+    pub error_while_computing_flags: bool,
+    pub feature_flags: HashMap<String, String>,
+    /// The quota resources the team went over, only set when flags weren't evaluated because of
+    /// it, in which case `feature_flags` is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_limited: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BulkFlagsResponse {
+    pub results: Vec<BulkFlagsResult>,
+}
+
+/// The evaluation of a single flag, as returned by the `/flags/:key` endpoint.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagResponse {
+    pub key: String,
+    pub enabled: bool,
+    pub variant: Option<String>,
+    pub payload: Option<Value>,
+    /// Only set when the request asked for an explanation with `?explain=1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<FeatureFlagEvaluationReason>,
+    /// The quota resources the team went over, only set when the flag wasn't evaluated because
+    /// of it, in which case it's disabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_limited: Option<Vec<String>>,
 }
 
 #[derive(Error, Debug)]
@@ -32,8 +78,15 @@ pub enum FlagError {
 
     #[error("No api_key in request")]
     NoTokenError,
+    #[error("Request submitted with inconsistent api_key values")]
+    MultipleTokensError,
     #[error("API key is not valid")]
     TokenValidationError,
+    #[error("Origin is not allowed for this API key")]
+    OriginNotAllowed,
+
+    #[error("Flag not found")]
+    FlagNotFound,
 
     #[error("rate limited")]
     RateLimited,
@@ -52,9 +105,13 @@ impl IntoResponse for FlagError {
             | FlagError::EmptyDistinctId
             | FlagError::MissingDistinctId => (StatusCode::BAD_REQUEST, self.to_string()),
 
-            FlagError::NoTokenError | FlagError::TokenValidationError => {
-                (StatusCode::UNAUTHORIZED, self.to_string())
-            }
+            FlagError::NoTokenError
+            | FlagError::MultipleTokensError
+            | FlagError::TokenValidationError => (StatusCode::UNAUTHORIZED, self.to_string()),
+
+            FlagError::OriginNotAllowed => (StatusCode::FORBIDDEN, self.to_string()),
+
+            FlagError::FlagNotFound => (StatusCode::NOT_FOUND, self.to_string()),
 
             FlagError::RateLimited => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
 
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::redis::Client;
+
+pub const QUOTA_LIMITER_CACHE_KEY: &str = "@posthog/quota-limits/";
+
+/// The quota resource of flag requests, also returned to limited clients so that they know which
+/// of their quotas is exceeded.
+pub const FEATURE_FLAGS_RESOURCE: &str = "feature_flag_requests";
+
+/// Limit teams, by token, that went over their feature flag requests quota.
+///
+/// Like capture's billing limiter: a worker regularly checks on the usage of teams and adds the
+/// tokens of those over their quota to a sorted set in redis, scored by when the limit ends.
+/// The set is cached for `interval` so that we don't hit redis for every request, and we fail
+/// open if redis can't be reached, as evaluating flags is better than wrongly limiting a team.
+#[derive(Clone)]
+pub struct BillingLimiter {
+    limited: Arc<RwLock<HashSet<String>>>,
+    redis: Arc<dyn Client + Send + Sync>,
+    interval: Duration,
+    // None until the first update, to force one on the first request
+    updated: Arc<RwLock<Option<Instant>>>,
+}
+
+impl BillingLimiter {
+    pub fn new(interval: Duration, redis: Arc<dyn Client + Send + Sync>) -> BillingLimiter {
+        BillingLimiter {
+            limited: Arc::new(RwLock::new(HashSet::new())),
+            redis,
+            interval,
+            updated: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn fetch_limited(&self) -> anyhow::Result<Vec<String>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        self.redis
+            .zrangebyscore(
+                format!("{QUOTA_LIMITER_CACHE_KEY}{FEATURE_FLAGS_RESOURCE}"),
+                now.to_string(),
+                String::from("+Inf"),
+            )
+            .await
+    }
+
+    /// Whether the team with this token is over its feature flag requests quota.
+    #[instrument(skip_all, fields(token = token))]
+    pub async fn is_limited(&self, token: &str) -> bool {
+        let update_due = {
+            let updated = self.updated.read().await;
+            match *updated {
+                None => true,
+                Some(updated) => updated.elapsed() > self.interval,
+            }
+        };
+
+        if !update_due {
+            return self.limited.read().await.contains(token);
+        }
+
+        // Take the update lock so that concurrent requests don't all refresh the set
+        let mut updated = self.updated.write().await;
+        *updated = Some(Instant::now());
+
+        match self.fetch_limited().await {
+            Ok(set) => {
+                let mut limited = self.limited.write().await;
+                *limited = HashSet::from_iter(set);
+                limited.contains(token)
+            }
+            Err(e) => {
+                tracing::error!("failed to fetch quota limited teams, failing open: {}", e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        insert_quota_limited_token_in_redis, random_string, setup_redis_client,
+    };
+
+    #[tokio::test]
+    async fn test_limits_teams_over_quota() {
+        let client = setup_redis_client(None);
+        let limited = random_string("phc_", 12);
+        insert_quota_limited_token_in_redis(client.clone(), &limited)
+            .await
+            .expect("failed to limit token");
+
+        let limiter = BillingLimiter::new(Duration::from_secs(5), client);
+
+        assert!(limiter.is_limited(&limited).await);
+        assert!(!limiter.is_limited(&random_string("phc_", 12)).await);
+    }
+
+    #[tokio::test]
+    async fn test_caches_limited_teams_until_the_interval() {
+        let client = setup_redis_client(None);
+        let limiter = BillingLimiter::new(Duration::from_secs(60), client.clone());
+        let token = random_string("phc_", 12);
+
+        assert!(!limiter.is_limited(&token).await);
+
+        // The limit isn't seen until the next update
+        insert_quota_limited_token_in_redis(client, &token)
+            .await
+            .expect("failed to limit token");
+        assert!(!limiter.is_limited(&token).await);
+    }
+}
@@ -1,6 +1,10 @@
 use std::net::SocketAddr;
 
 use envconfig::Envconfig;
+use redis::IntoConnectionInfo;
+use thiserror::Error;
+
+use crate::property_matching::ValueCoercion;
 
 #[derive(Envconfig, Clone)]
 pub struct Config {
@@ -21,4 +25,159 @@ pub struct Config {
 
     #[envconfig(default = "redis://localhost:6379/")]
     pub redis_url: String,
+
+    // Separate redis databases for teams, flags and cohorts, each defaulting to REDIS_URL
+    pub teams_redis_url: Option<String>,
+    pub flags_redis_url: Option<String>,
+    pub cohorts_redis_url: Option<String>,
+
+    // Serve teams and flags from this JSON file instead of redis, for offline evaluation.
+    // The file is checked for changes every FLAG_DEFINITIONS_RELOAD_INTERVAL_SECS.
+    pub flag_definitions_path: Option<String>,
+
+    #[envconfig(default = "5")]
+    pub flag_definitions_reload_interval_secs: u64,
+
+    // Use the `token` of requests sending a different `api_key` instead of rejecting them, for
+    // proxies multiplexing several projects' requests
+    #[envconfig(default = "false")]
+    pub allow_mismatched_tokens: bool,
+
+    // Report a $feature_flag_called event for every flag returned by the flags endpoints to the
+    // capture service at this URL, disabled if unset
+    pub capture_url: Option<String>,
+
+    // How the exact and is_not operators compare property values with flag values: legacy
+    // compares their string representations, type_aware compares bools, numbers and strings
+    // by their type first
+    #[envconfig(default = "legacy")]
+    pub flag_value_coercion: ValueCoercion,
+
+    // Serve over HTTPS with these PEM files if both are set, plain HTTP otherwise
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+}
+
+/// Every problem found while validating a configuration, reported together so that they can all
+/// be fixed before the next start.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid configuration:{}", list_problems(.0))]
+pub struct ConfigError(pub Vec<String>);
+
+fn list_problems(problems: &[String]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("\n  - {}", problem))
+        .collect()
+}
+
+impl Config {
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        for (name, url) in [
+            ("REDIS_URL", Some(&self.redis_url)),
+            ("TEAMS_REDIS_URL", self.teams_redis_url.as_ref()),
+            ("FLAGS_REDIS_URL", self.flags_redis_url.as_ref()),
+            ("COHORTS_REDIS_URL", self.cohorts_redis_url.as_ref()),
+        ] {
+            if let Some(Err(e)) = url.map(|url| url.as_str().into_connection_info()) {
+                problems.push(format!("{} is not a valid redis URL: {}", name, e));
+            }
+        }
+        for (name, url) in [
+            ("WRITE_DATABASE_URL", &self.write_database_url),
+            ("READ_DATABASE_URL", &self.read_database_url),
+        ] {
+            if !(url.starts_with("postgres://") || url.starts_with("postgresql://")) {
+                problems.push(format!(
+                    "{} must be a postgres:// or postgresql:// URL",
+                    name
+                ));
+            }
+        }
+        if self.max_pg_connections == 0 {
+            problems.push("MAX_PG_CONNECTIONS must be greater than zero".to_string());
+        }
+        if self.flag_definitions_path.is_some() && self.flag_definitions_reload_interval_secs == 0 {
+            problems.push(
+                "FLAG_DEFINITIONS_RELOAD_INTERVAL_SECS must be greater than zero".to_string(),
+            );
+        }
+        if let Some(url) = &self.capture_url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                problems.push("CAPTURE_URL must be an http:// or https:// URL".to_string());
+            }
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            problems.push("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError(problems)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::init_from_hashmap(&vars).expect("failed to parse config")
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(config(&[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_config_reports_every_problem() {
+        let error = config(&[
+            ("REDIS_URL", "localhost:6379"),
+            ("FLAGS_REDIS_URL", "http://localhost:6379"),
+            ("READ_DATABASE_URL", "localhost:15432"),
+            ("MAX_PG_CONNECTIONS", "0"),
+            ("FLAG_DEFINITIONS_PATH", "flags.json"),
+            ("FLAG_DEFINITIONS_RELOAD_INTERVAL_SECS", "0"),
+            ("TLS_KEY_PATH", "/etc/flags/key.pem"),
+            ("CAPTURE_URL", "localhost:3000"),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+
+        let message = error.to_string();
+        for setting in [
+            "REDIS_URL",
+            "FLAGS_REDIS_URL",
+            "READ_DATABASE_URL",
+            "MAX_PG_CONNECTIONS",
+            "FLAG_DEFINITIONS_RELOAD_INTERVAL_SECS",
+            "TLS_KEY_PATH",
+            "CAPTURE_URL",
+        ] {
+            assert!(message.contains(setting), "{} is not reported", setting);
+        }
+        assert_eq!(error.0.len(), 7);
+    }
+
+    #[test]
+    fn test_value_coercion_is_parsed() {
+        assert_eq!(config(&[]).flag_value_coercion, ValueCoercion::Legacy);
+        assert_eq!(
+            config(&[("FLAG_VALUE_COERCION", " Type_Aware")]).flag_value_coercion,
+            ValueCoercion::TypeAware
+        );
+        let vars = HashMap::from([("FLAG_VALUE_COERCION".to_string(), "strict".to_string())]);
+        assert!(Config::init_from_hashmap(&vars).is_err());
+    }
 }
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+
+use crate::flag_definitions::{FeatureFlag, TEAM_FLAGS_CACHE_PREFIX};
+use crate::redis::{Client, CustomRedisError};
+use crate::team::{Team, TEAM_TOKEN_CACHE_PREFIX};
+
+#[derive(Error, Debug)]
+pub enum FileStoreError {
+    #[error("failed to read definitions file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to parse definitions file: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// The shape of a definitions file: the teams to serve flags for, and the flags of those teams.
+#[derive(Deserialize)]
+struct DefinitionsFile {
+    teams: Vec<serde_json::Value>,
+    #[serde(default)]
+    flags: Vec<serde_json::Value>,
+}
+
+struct Definitions {
+    modified: SystemTime,
+    /// Serialized definitions, keyed like they are in redis.
+    entries: HashMap<String, String>,
+}
+
+/// Serves team and flag definitions from a local JSON file instead of redis, for deployments
+/// that evaluate flags offline. The file is only read again when its modification time changes.
+///
+/// The store answers reads with the same keys and payloads as redis, so it can be used wherever
+/// a redis `Client` is expected. It is read-only.
+pub struct FileDefinitionStore {
+    path: PathBuf,
+    definitions: RwLock<Definitions>,
+}
+
+impl FileDefinitionStore {
+    pub fn load(path: impl AsRef<Path>) -> Result<FileDefinitionStore, FileStoreError> {
+        let path = path.as_ref().to_owned();
+        let definitions = read_definitions(&path)?;
+
+        Ok(FileDefinitionStore {
+            path,
+            definitions: RwLock::new(definitions),
+        })
+    }
+
+    /// Reads the file again if it was modified since it was last loaded, returning whether the
+    /// definitions changed. The current definitions are kept if the file can't be loaded.
+    pub fn reload(&self) -> Result<bool, FileStoreError> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+        if modified == self.definitions.read().unwrap().modified {
+            return Ok(false);
+        }
+
+        let definitions = read_definitions(&self.path)?;
+        *self.definitions.write().unwrap() = definitions;
+
+        Ok(true)
+    }
+
+    /// Spawns a task checking the file for changes every `interval`.
+    pub fn watch(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                match self.reload() {
+                    Ok(true) => tracing::info!("reloaded definitions from {:?}", self.path),
+                    Ok(false) => {}
+                    Err(e) => tracing::error!("failed to reload definitions: {}", e),
+                }
+            }
+        })
+    }
+}
+
+fn read_definitions(path: &Path) -> Result<Definitions, FileStoreError> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let file: DefinitionsFile = serde_json::from_slice(&std::fs::read(path)?)?;
+
+    let mut entries = HashMap::new();
+    for team in file.teams {
+        // Fail on invalid definitions now, rather than on every request using them
+        let parsed = Team::deserialize(&team)?;
+        entries.insert(
+            format!("{TEAM_TOKEN_CACHE_PREFIX}{}", parsed.api_token),
+            team.to_string(),
+        );
+    }
+
+    let mut flags_by_team: HashMap<i64, Vec<serde_json::Value>> = HashMap::new();
+    for flag in file.flags {
+        let parsed = FeatureFlag::deserialize(&flag)?;
+        flags_by_team.entry(parsed.team_id).or_default().push(flag);
+    }
+    for (team_id, flags) in flags_by_team {
+        entries.insert(
+            format!("{TEAM_FLAGS_CACHE_PREFIX}{}", team_id),
+            serde_json::Value::Array(flags).to_string(),
+        );
+    }
+
+    Ok(Definitions { modified, entries })
+}
+
+#[async_trait]
+impl Client for FileDefinitionStore {
+    async fn zrangebyscore(&self, _k: String, _min: String, _max: String) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    async fn get(&self, k: String) -> Result<String, CustomRedisError> {
+        self.definitions
+            .read()
+            .unwrap()
+            .entries
+            .get(&k)
+            .cloned()
+            .ok_or(CustomRedisError::NotFound)
+    }
+
+    async fn set(&self, _k: String, _v: String) -> Result<()> {
+        Err(anyhow!("file definition store is read-only"))
+    }
+
+    async fn setex(&self, _k: String, _v: String, _seconds: usize) -> Result<()> {
+        Err(anyhow!("file definition store is read-only"))
+    }
+
+    async fn zadd(&self, _k: String, _v: String, _score: f64) -> Result<()> {
+        Err(anyhow!("file definition store is read-only"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::flag_definitions::FeatureFlagList;
+    use crate::flag_matching::FeatureFlagMatcher;
+    use crate::test_utils::random_string;
+
+    fn write_definitions(path: &Path, rollout_percentage: f64, modified: SystemTime) {
+        let definitions = json!({
+            "teams": [{"id": 1, "name": "team", "api_token": "phc_offline"}],
+            "flags": [{
+                "id": 1,
+                "key": "flag1",
+                "active": true,
+                "team_id": 1,
+                "filters": {"groups": [{"rollout_percentage": rollout_percentage}]},
+            }],
+        });
+        fs::write(path, definitions.to_string()).expect("failed to write definitions");
+
+        // Set the modification time explicitly, as writes within the same tick look unchanged
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(modified))
+            .expect("failed to set modification time");
+    }
+
+    async fn is_enabled(store: Arc<FileDefinitionStore>) -> bool {
+        let team = Team::from_redis(store.clone(), "phc_offline".to_string())
+            .await
+            .expect("failed to fetch team");
+        let flags = FeatureFlagList::from_redis(store, team.id)
+            .await
+            .expect("failed to fetch flags");
+
+        FeatureFlagMatcher::new("user".to_string())
+            .get_match(&flags.flags[0])
+            .matches
+    }
+
+    #[tokio::test]
+    async fn it_reloads_modified_definitions() {
+        let path = std::env::temp_dir().join(random_string("flag_definitions_", 12));
+        let loaded_at = SystemTime::now() - Duration::from_secs(60);
+        write_definitions(&path, 100.0, loaded_at);
+
+        let store = Arc::new(FileDefinitionStore::load(&path).expect("failed to load"));
+        assert!(is_enabled(store.clone()).await);
+        assert!(!store.reload().expect("failed to reload"));
+
+        write_definitions(&path, 0.0, SystemTime::now());
+        assert!(store.reload().expect("failed to reload"));
+        assert!(!is_enabled(store.clone()).await);
+
+        fs::remove_file(&path).expect("failed to remove definitions");
+    }
+
+    #[tokio::test]
+    async fn it_keeps_definitions_when_reload_fails() {
+        let path = std::env::temp_dir().join(random_string("flag_definitions_", 12));
+        write_definitions(&path, 100.0, SystemTime::now() - Duration::from_secs(60));
+        let store = Arc::new(FileDefinitionStore::load(&path).expect("failed to load"));
+
+        fs::write(&path, "{\"teams\": [{\"id\": 1}]}").expect("failed to write definitions");
+        assert!(matches!(store.reload(), Err(FileStoreError::Parse(_))));
+        assert!(is_enabled(store.clone()).await);
+
+        let err = Team::from_redis(store, "phc_unknown".to_string())
+            .await
+            .expect_err("unknown token was accepted");
+        assert!(matches!(err, crate::api::FlagError::TokenValidationError));
+
+        fs::remove_file(&path).expect("failed to remove definitions");
+    }
+}
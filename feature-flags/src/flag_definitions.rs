@@ -103,6 +103,16 @@ impl FeatureFlag {
         &self.filters.groups
     }
 
+    /// Returns the payload for a flag evaluated to `match_key`, i.e. the matched variant or
+    /// `"true"` for flags without variants.
+    pub fn get_payload(&self, match_key: &str) -> Option<serde_json::Value> {
+        self.filters
+            .payloads
+            .as_ref()
+            .and_then(|payloads| payloads.get(match_key))
+            .cloned()
+    }
+
     pub fn get_variants(&self) -> Vec<MultivariateFlagVariant> {
         self.filters
             .multivariate
@@ -111,14 +121,20 @@ impl FeatureFlag {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 
 pub struct FeatureFlagList {
     pub flags: Vec<FeatureFlag>,
+    /// How many definitions couldn't be parsed and were left out of `flags`.
+    #[serde(skip)]
+    pub invalid_definitions: usize,
 }
 
 impl FeatureFlagList {
     /// Returns feature flags from redis given a team_id
+    ///
+    /// Definitions that can't be parsed are logged and skipped, so that one corrupt flag doesn't
+    /// prevent evaluating the others. Only failing to parse the list itself is an error.
     #[instrument(skip_all)]
     pub async fn from_redis(
         client: Arc<dyn Client + Send + Sync>,
@@ -143,7 +159,7 @@ impl FeatureFlagList {
                 }
             })?;
 
-        let flags_list: Vec<FeatureFlag> =
+        let definitions: Vec<serde_json::Value> =
             serde_json::from_str(&serialized_flags).map_err(|e| {
                 tracing::error!("failed to parse data to flags list: {}", e);
                 println!("failed to parse data: {}", e);
@@ -151,7 +167,28 @@ impl FeatureFlagList {
                 FlagError::DataParsingError
             })?;
 
-        Ok(FeatureFlagList { flags: flags_list })
+        let mut flags = Vec::with_capacity(definitions.len());
+        let mut invalid_definitions = 0;
+        for definition in definitions {
+            match FeatureFlag::deserialize(&definition) {
+                Ok(flag) => flags.push(flag),
+                Err(e) => {
+                    invalid_definitions += 1;
+                    tracing::error!(
+                        team_id,
+                        id = %definition.get("id").unwrap_or(&serde_json::Value::Null),
+                        key = %definition.get("key").unwrap_or(&serde_json::Value::Null),
+                        "skipping invalid flag definition: {}",
+                        e
+                    );
+                }
+            }
+        }
+
+        Ok(FeatureFlagList {
+            flags,
+            invalid_definitions,
+        })
     }
 }
 
@@ -192,6 +229,40 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_fetch_flags_skips_invalid_definitions() {
+        let client = setup_redis_client(None);
+
+        let team = insert_new_team_in_redis(client.clone())
+            .await
+            .expect("Failed to insert team");
+
+        let flags = serde_json::json!([
+            {
+                "id": 1,
+                "key": "good-flag",
+                "team_id": team.id,
+                "filters": {"groups": [{"rollout_percentage": 100}]},
+            },
+            {
+                "id": 2,
+                "key": "corrupt-flag",
+                "team_id": team.id,
+                "filters": {"groups": "not a list"},
+            },
+        ]);
+        insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string()))
+            .await
+            .expect("Failed to insert flags");
+
+        let flags_from_redis = FeatureFlagList::from_redis(client.clone(), team.id)
+            .await
+            .expect("Failed to fetch flags from redis");
+        assert_eq!(flags_from_redis.flags.len(), 1);
+        assert_eq!(flags_from_redis.flags[0].key, "good-flag");
+        assert_eq!(flags_from_redis.invalid_definitions, 1);
+    }
+
     #[tokio::test]
     async fn test_fetch_invalid_team_from_redis() {
         let client = setup_redis_client(None);
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+pub const FEATURE_FLAG_CALLED_EVENT: &str = "$feature_flag_called";
+
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum FlagEventsError {
+    #[error("failed to send events to capture: {0}")]
+    RequestError(#[from] reqwest::Error),
+}
+
+/// A `$feature_flag_called` event, recording the value a distinct_id got for a flag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagCalledEvent {
+    pub event: String,
+    pub distinct_id: String,
+    pub properties: HashMap<String, Value>,
+}
+
+impl FlagCalledEvent {
+    /// `response` is "true", "false" or the matched variant, as returned by the flags endpoint.
+    pub fn new(distinct_id: &str, key: &str, response: &str) -> Self {
+        let response = match response {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            variant => Value::String(variant.to_string()),
+        };
+
+        FlagCalledEvent {
+            event: FEATURE_FLAG_CALLED_EVENT.to_string(),
+            distinct_id: distinct_id.to_string(),
+            properties: HashMap::from([
+                ("$feature_flag".to_string(), Value::String(key.to_string())),
+                ("$feature_flag_response".to_string(), response),
+            ]),
+        }
+    }
+}
+
+/// One event per flag of a flags response.
+pub fn flag_called_events(
+    distinct_id: &str,
+    feature_flags: &HashMap<String, String>,
+) -> Vec<FlagCalledEvent> {
+    feature_flags
+        .iter()
+        .map(|(key, response)| FlagCalledEvent::new(distinct_id, key, response))
+        .collect()
+}
+
+#[async_trait]
+pub trait FlagCalledSink {
+    async fn send(&self, token: &str, events: Vec<FlagCalledEvent>) -> Result<(), FlagEventsError>;
+}
+
+/// Sends events to the `/batch` endpoint of a capture service.
+pub struct CaptureSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl CaptureSink {
+    pub fn new(capture_url: &str) -> Result<Self, FlagEventsError> {
+        let client = reqwest::Client::builder()
+            .timeout(CAPTURE_TIMEOUT)
+            .build()?;
+
+        Ok(CaptureSink {
+            client,
+            url: format!("{}/batch", capture_url.trim_end_matches('/')),
+        })
+    }
+}
+
+#[async_trait]
+impl FlagCalledSink for CaptureSink {
+    async fn send(&self, token: &str, events: Vec<FlagCalledEvent>) -> Result<(), FlagEventsError> {
+        self.client
+            .post(&self.url)
+            .json(&json!({"api_key": token, "batch": events}))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Sends events in the background, so that flag requests don't wait on capture. Failing to send
+/// them is only logged, as flags were already evaluated.
+pub fn report_flags_called(
+    sink: Arc<dyn FlagCalledSink + Send + Sync>,
+    token: String,
+    events: Vec<FlagCalledEvent>,
+) {
+    if events.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = sink.send(&token, events).await {
+            tracing::warn!(
+                "failed to report {} events: {}",
+                FEATURE_FLAG_CALLED_EVENT,
+                e
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    struct MemorySink {
+        sent: mpsc::UnboundedSender<(String, Vec<FlagCalledEvent>)>,
+    }
+
+    #[async_trait]
+    impl FlagCalledSink for MemorySink {
+        async fn send(
+            &self,
+            token: &str,
+            events: Vec<FlagCalledEvent>,
+        ) -> Result<(), FlagEventsError> {
+            self.sent.send((token.to_string(), events)).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_one_event_per_flag() {
+        let feature_flags = HashMap::from([
+            ("rollout-flag".to_string(), "true".to_string()),
+            ("disabled-flag".to_string(), "false".to_string()),
+            ("beta-feature".to_string(), "variant-1".to_string()),
+        ]);
+
+        let mut events = flag_called_events("user_distinct_id", &feature_flags);
+        events.sort_by_key(|event| event.properties["$feature_flag"].to_string());
+
+        assert_eq!(events.len(), 3);
+        for event in &events {
+            assert_eq!(event.event, "$feature_flag_called");
+            assert_eq!(event.distinct_id, "user_distinct_id");
+        }
+        let flags: Vec<(&Value, &Value)> = events
+            .iter()
+            .map(|event| {
+                (
+                    &event.properties["$feature_flag"],
+                    &event.properties["$feature_flag_response"],
+                )
+            })
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (&json!("beta-feature"), &json!("variant-1")),
+                (&json!("disabled-flag"), &json!(false)),
+                (&json!("rollout-flag"), &json!(true)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_are_reported_to_the_sink() {
+        let (sent, mut received) = mpsc::unbounded_channel();
+        let sink = Arc::new(MemorySink { sent });
+
+        report_flags_called(
+            sink.clone(),
+            "phc_token".to_string(),
+            vec![FlagCalledEvent::new(
+                "user_distinct_id",
+                "rollout-flag",
+                "true",
+            )],
+        );
+        // Nothing is sent for requests without flags
+        report_flags_called(sink, "phc_token".to_string(), vec![]);
+
+        let (token, events) = received.recv().await.expect("events were not reported");
+        assert_eq!(token, "phc_token");
+        assert_eq!(
+            serde_json::to_value(&events).unwrap(),
+            json!([{
+                "event": "$feature_flag_called",
+                "distinct_id": "user_distinct_id",
+                "properties": {"$feature_flag": "rollout-flag", "$feature_flag_response": true},
+            }])
+        );
+        assert!(received.recv().await.is_none());
+    }
+}
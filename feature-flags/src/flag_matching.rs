@@ -1,16 +1,50 @@
-use crate::flag_definitions::{FeatureFlag, FlagGroupType};
+use crate::flag_definitions::{FeatureFlag, FlagGroupType, PropertyFilter};
+use crate::group_properties::GroupState;
+use crate::property_matching::{
+    match_property_lenient_with_coercion, match_property_with_coercion, ValueCoercion,
+};
+use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use std::collections::HashMap;
 use std::fmt::Write;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct FeatureFlagMatch {
     pub matches: bool,
     pub variant: Option<String>,
-    //reason
-    //condition_index
     //payload
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureFlagMatchType {
+    Override,
+    Disabled,
+    SuperConditionValue,
+    ConditionMatch,
+    NoConditionMatch,
+    OutOfRolloutBound,
+}
+
+/// Explains how a flag was evaluated: which condition decided the outcome, and the rollout hash
+/// it was compared against, if any.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FeatureFlagEvaluationReason {
+    pub match_type: FeatureFlagMatchType,
+    pub condition_index: Option<usize>,
+    pub rollout_hash: Option<f64>,
+}
+
+impl FeatureFlagEvaluationReason {
+    fn new(match_type: FeatureFlagMatchType, condition_index: usize) -> Self {
+        FeatureFlagEvaluationReason {
+            match_type,
+            condition_index: Some(condition_index),
+            rollout_hash: None,
+        }
+    }
+}
+
 // TODO: Rework FeatureFlagMatcher - python has a pretty awkward interface, where we pass in all flags, and then again
 // the flag to match. I don't think there's any reason anymore to store the flags in the matcher, since we can just
 // pass the flag to match directly to the get_match method. This will also make the matcher more stateless.
@@ -25,6 +59,12 @@ pub struct FeatureFlagMatch {
 pub struct FeatureFlagMatcher {
     // pub flags: Vec<FeatureFlag>,
     pub distinct_id: String,
+    /// The groups of the request, keyed by group type index.
+    pub groups: HashMap<u8, GroupState>,
+    /// How property values are compared with the values of exact and is_not filters.
+    pub value_coercion: ValueCoercion,
+    /// Whether properties missing from the request are plain non-matches, instead of errors.
+    pub lenient_properties: bool,
 }
 
 const LONG_SCALE: u64 = 0xfffffffffffffff;
@@ -34,51 +74,121 @@ impl FeatureFlagMatcher {
         FeatureFlagMatcher {
             // flags,
             distinct_id,
+            groups: HashMap::new(),
+            value_coercion: ValueCoercion::default(),
+            lenient_properties: false,
         }
     }
 
+    /// Evaluates group flags for these groups, keyed by group type index.
+    pub fn with_groups(mut self, groups: HashMap<u8, GroupState>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Compares property values with the values of exact and is_not filters with `coercion`.
+    pub fn with_value_coercion(mut self, coercion: ValueCoercion) -> Self {
+        self.value_coercion = coercion;
+        self
+    }
+
+    /// Matches properties with `match_property_lenient` if `lenient` is set, for previews with
+    /// partial property overrides. The live decide path keeps the strict `match_property`.
+    pub fn with_lenient_properties(mut self, lenient: bool) -> Self {
+        self.lenient_properties = lenient;
+        self
+    }
+
     pub fn get_match(&self, feature_flag: &FeatureFlag) -> FeatureFlagMatch {
+        self.get_match_with_reason(feature_flag).0
+    }
+
+    /// Evaluates the flag like `get_match`, also returning why it evaluated that way.
+    pub fn get_match_with_reason(
+        &self,
+        feature_flag: &FeatureFlag,
+    ) -> (FeatureFlagMatch, FeatureFlagEvaluationReason) {
+        let no_match = FeatureFlagMatch {
+            matches: false,
+            variant: None,
+        };
+
         if self.hashed_identifier(feature_flag).is_none() {
-            return FeatureFlagMatch {
-                matches: false,
-                variant: None,
-            };
+            return (
+                no_match,
+                FeatureFlagEvaluationReason {
+                    match_type: FeatureFlagMatchType::NoConditionMatch,
+                    condition_index: None,
+                    rollout_hash: None,
+                },
+            );
+        }
+
+        // Super conditions take precedence over the regular ones, e.g. for early access opt-ins
+        let super_conditions = feature_flag.filters.super_groups.as_deref().unwrap_or(&[]);
+        for (index, condition) in super_conditions.iter().enumerate() {
+            let (is_match, mut reason) = self.is_condition_match(feature_flag, condition, index);
+
+            if is_match {
+                reason.match_type = FeatureFlagMatchType::SuperConditionValue;
+                return (self.get_condition_match(feature_flag, condition), reason);
+            }
         }
 
-        // TODO: super groups for early access
         // TODO: Variant overrides condition sort
 
+        let mut last_reason = None;
         for (index, condition) in feature_flag.get_conditions().iter().enumerate() {
-            let (is_match, _evaluation_reason) =
-                self.is_condition_match(feature_flag, condition, index);
+            let (is_match, reason) = self.is_condition_match(feature_flag, condition, index);
 
             if is_match {
-                // TODO: This is a bit awkward, we should handle overrides only when variants exist.
-                let variant = match condition.variant.clone() {
-                    Some(variant_override) => {
-                        if feature_flag
-                            .get_variants()
-                            .iter()
-                            .any(|v| v.key == variant_override)
-                        {
-                            Some(variant_override)
-                        } else {
-                            self.get_matching_variant(feature_flag)
-                        }
-                    }
-                    None => self.get_matching_variant(feature_flag),
-                };
-
-                // let payload = self.get_matching_payload(is_match, variant, feature_flag);
-                return FeatureFlagMatch {
-                    matches: true,
-                    variant,
-                };
+                return (self.get_condition_match(feature_flag, condition), reason);
+            }
+            // Being out of a rollout explains a miss better than not matching a condition
+            if !matches!(
+                last_reason,
+                Some(FeatureFlagEvaluationReason {
+                    match_type: FeatureFlagMatchType::OutOfRolloutBound,
+                    ..
+                })
+            ) {
+                last_reason = Some(reason);
             }
         }
+
+        let reason = last_reason.unwrap_or(FeatureFlagEvaluationReason {
+            match_type: FeatureFlagMatchType::NoConditionMatch,
+            condition_index: None,
+            rollout_hash: None,
+        });
+        (no_match, reason)
+    }
+
+    fn get_condition_match(
+        &self,
+        feature_flag: &FeatureFlag,
+        condition: &FlagGroupType,
+    ) -> FeatureFlagMatch {
+        // TODO: This is a bit awkward, we should handle overrides only when variants exist.
+        let variant = match condition.variant.clone() {
+            Some(variant_override) => {
+                if feature_flag
+                    .get_variants()
+                    .iter()
+                    .any(|v| v.key == variant_override)
+                {
+                    Some(variant_override)
+                } else {
+                    self.get_matching_variant(feature_flag)
+                }
+            }
+            None => self.get_matching_variant(feature_flag),
+        };
+
+        // let payload = self.get_matching_payload(is_match, variant, feature_flag);
         FeatureFlagMatch {
-            matches: false,
-            variant: None,
+            matches: true,
+            variant,
         }
     }
 
@@ -86,38 +196,82 @@ impl FeatureFlagMatcher {
         &self,
         feature_flag: &FeatureFlag,
         condition: &FlagGroupType,
-        _index: usize,
-    ) -> (bool, String) {
+        index: usize,
+    ) -> (bool, FeatureFlagEvaluationReason) {
         let rollout_percentage = condition.rollout_percentage.unwrap_or(100.0);
         let mut condition_match = true;
-        if condition.properties.is_some() {
-            // TODO: Handle matching conditions
-            if !condition.properties.as_ref().unwrap().is_empty() {
-                condition_match = false;
+        if let Some(properties) = &condition.properties {
+            if !properties.is_empty() {
+                condition_match = match feature_flag.get_group_type_index() {
+                    Some(group_type_index) => {
+                        self.match_group_properties(group_type_index, properties)
+                    }
+                    // TODO: Handle matching person conditions
+                    None => false,
+                };
             }
         }
 
         if !condition_match {
-            return (false, "NO_CONDITION_MATCH".to_string());
+            return (
+                false,
+                FeatureFlagEvaluationReason::new(FeatureFlagMatchType::NoConditionMatch, index),
+            );
         } else if rollout_percentage == 100.0 {
             // TODO: Check floating point schenanigans if any
-            return (true, "CONDITION_MATCH".to_string());
+            return (
+                true,
+                FeatureFlagEvaluationReason::new(FeatureFlagMatchType::ConditionMatch, index),
+            );
         }
 
-        if self.get_hash(feature_flag, "") > (rollout_percentage / 100.0) {
-            return (false, "OUT_OF_ROLLOUT_BOUND".to_string());
-        }
+        let hash = self.get_hash(feature_flag, "");
+        let match_type = if hash > (rollout_percentage / 100.0) {
+            FeatureFlagMatchType::OutOfRolloutBound
+        } else {
+            FeatureFlagMatchType::ConditionMatch
+        };
+
+        (
+            match_type == FeatureFlagMatchType::ConditionMatch,
+            FeatureFlagEvaluationReason {
+                match_type,
+                condition_index: Some(index),
+                rollout_hash: Some(hash),
+            },
+        )
+    }
 
-        (true, "CONDITION_MATCH".to_string())
+    /// Matches the properties of a group condition against the properties of the request's
+    /// group. Properties the group doesn't have never match, as with partial person properties.
+    fn match_group_properties(&self, group_type_index: u8, properties: &[PropertyFilter]) -> bool {
+        let Some(group) = self.groups.get(&group_type_index) else {
+            return false;
+        };
+
+        properties.iter().all(|property| {
+            let result = if self.lenient_properties {
+                match_property_lenient_with_coercion(
+                    property,
+                    &group.properties,
+                    self.value_coercion,
+                )
+            } else {
+                match_property_with_coercion(property, &group.properties, true, self.value_coercion)
+            };
+            result.unwrap_or(false)
+        })
     }
 
     pub fn hashed_identifier(&self, feature_flag: &FeatureFlag) -> Option<String> {
-        if feature_flag.get_group_type_index().is_none() {
+        match feature_flag.get_group_type_index() {
             // TODO: Use hash key overrides for experience continuity
-            Some(self.distinct_id.clone())
-        } else {
-            // TODO: Handle getting group key
-            Some("".to_string())
+            None => Some(self.distinct_id.clone()),
+            // Group flags don't match requests without the group
+            Some(group_type_index) => self
+                .groups
+                .get(&group_type_index)
+                .map(|group| group.key.clone()),
         }
     }
 
@@ -158,3 +312,225 @@ impl FeatureFlagMatcher {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_utils::create_flag_from_json;
+
+    fn rollout_flag() -> FeatureFlag {
+        create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "rollout-flag",
+                "active": true,
+                "team_id": 1,
+                "filters": {"groups": [{"properties": [], "rollout_percentage": 50}]},
+            }])
+            .to_string(),
+        ))
+        .remove(0)
+    }
+
+    #[test]
+    fn test_reason_for_rollout_match() {
+        let flag = rollout_flag();
+
+        let matcher = FeatureFlagMatcher::new("user_1".to_string());
+        let (flag_match, reason) = matcher.get_match_with_reason(&flag);
+        assert!(flag_match.matches);
+        assert_eq!(
+            reason,
+            FeatureFlagEvaluationReason {
+                match_type: FeatureFlagMatchType::ConditionMatch,
+                condition_index: Some(0),
+                rollout_hash: Some(matcher.get_hash(&flag, "")),
+            }
+        );
+
+        let matcher = FeatureFlagMatcher::new("user_3".to_string());
+        let (flag_match, reason) = matcher.get_match_with_reason(&flag);
+        assert!(!flag_match.matches);
+        assert_eq!(reason.match_type, FeatureFlagMatchType::OutOfRolloutBound);
+        assert!(reason.rollout_hash.unwrap() > 0.5);
+    }
+
+    #[test]
+    fn test_reason_for_super_condition_match() {
+        let flag = create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "early-access-flag",
+                "active": true,
+                "team_id": 1,
+                "filters": {
+                    "groups": [{"properties": [], "rollout_percentage": 0}],
+                    "super_groups": [{"properties": [], "rollout_percentage": 100}],
+                },
+            }])
+            .to_string(),
+        ))
+        .remove(0);
+
+        let (flag_match, reason) =
+            FeatureFlagMatcher::new("user_3".to_string()).get_match_with_reason(&flag);
+        assert!(flag_match.matches);
+        assert_eq!(
+            reason,
+            FeatureFlagEvaluationReason {
+                match_type: FeatureFlagMatchType::SuperConditionValue,
+                condition_index: Some(0),
+                rollout_hash: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_group_flag_matches_group_properties() {
+        let flag = create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "group-flag",
+                "active": true,
+                "team_id": 1,
+                "filters": {
+                    "aggregation_group_type_index": 0,
+                    "groups": [{
+                        "properties": [{
+                            "key": "plan",
+                            "value": "enterprise",
+                            "type": "group",
+                            "group_type_index": 0,
+                        }],
+                    }],
+                },
+            }])
+            .to_string(),
+        ))
+        .remove(0);
+        let group = |properties: Value| {
+            HashMap::from([(
+                0,
+                GroupState {
+                    key: "org_1".to_string(),
+                    properties: serde_json::from_value(properties).unwrap(),
+                },
+            )])
+        };
+
+        let matcher = FeatureFlagMatcher::new("user_1".to_string())
+            .with_groups(group(json!({"plan": "enterprise"})));
+        assert!(matcher.get_match(&flag).matches);
+        assert_eq!(matcher.hashed_identifier(&flag), Some("org_1".to_string()));
+
+        let matcher = FeatureFlagMatcher::new("user_1".to_string())
+            .with_groups(group(json!({"plan": "free"})));
+        assert!(!matcher.get_match(&flag).matches);
+
+        // Missing properties don't match
+        let matcher = FeatureFlagMatcher::new("user_1".to_string()).with_groups(group(json!({})));
+        assert!(!matcher.get_match(&flag).matches);
+
+        // Neither do requests without the group
+        let matcher = FeatureFlagMatcher::new("user_1".to_string());
+        assert!(!matcher.get_match(&flag).matches);
+        assert_eq!(matcher.hashed_identifier(&flag), None);
+    }
+
+    #[test]
+    fn test_group_properties_are_compared_with_the_value_coercion() {
+        let flag = create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "group-flag",
+                "active": true,
+                "team_id": 1,
+                "filters": {
+                    "aggregation_group_type_index": 0,
+                    "groups": [{
+                        "properties": [{
+                            "key": "seats",
+                            "value": 42,
+                            "type": "group",
+                            "group_type_index": 0,
+                        }],
+                    }],
+                },
+            }])
+            .to_string(),
+        ))
+        .remove(0);
+        let groups = HashMap::from([(
+            0,
+            GroupState {
+                key: "org_1".to_string(),
+                properties: HashMap::from([("seats".to_string(), json!("42.0"))]),
+            },
+        )]);
+
+        let matcher = FeatureFlagMatcher::new("user_1".to_string()).with_groups(groups.clone());
+        assert!(!matcher.get_match(&flag).matches);
+
+        let matcher = FeatureFlagMatcher::new("user_1".to_string())
+            .with_groups(groups)
+            .with_value_coercion(ValueCoercion::TypeAware);
+        assert!(matcher.get_match(&flag).matches);
+    }
+
+    #[test]
+    fn test_group_properties_are_matched_leniently() {
+        let flag = create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "group-flag",
+                "active": true,
+                "team_id": 1,
+                "filters": {
+                    "aggregation_group_type_index": 0,
+                    "groups": [{
+                        "properties": [
+                            {
+                                "key": "plan",
+                                "value": "enterprise",
+                                "type": "group",
+                                "group_type_index": 0,
+                            },
+                            {
+                                "key": "seats",
+                                "value": 42,
+                                "type": "group",
+                                "group_type_index": 0,
+                            },
+                        ],
+                    }],
+                },
+            }])
+            .to_string(),
+        ))
+        .remove(0);
+        let group = |properties: Value| {
+            HashMap::from([(
+                0,
+                GroupState {
+                    key: "org_1".to_string(),
+                    properties: serde_json::from_value(properties).unwrap(),
+                },
+            )])
+        };
+
+        // A partial override of the properties is a plain non-match, as it is when strict
+        for lenient in [false, true] {
+            let matcher = FeatureFlagMatcher::new("user_1".to_string())
+                .with_groups(group(json!({"plan": "enterprise"})))
+                .with_lenient_properties(lenient);
+            assert!(!matcher.get_match(&flag).matches);
+
+            let matcher = FeatureFlagMatcher::new("user_1".to_string())
+                .with_groups(group(json!({"plan": "enterprise", "seats": 42})))
+                .with_lenient_properties(lenient);
+            assert!(matcher.get_match(&flag).matches);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+    api::FlagError,
+    flag_matching::FeatureFlagMatch,
+    redis::{Client, CustomRedisError},
+};
+
+// Overrides are set per team and distinct_id, for QA to force flag values for a user.
+pub const FLAG_OVERRIDES_CACHE_PREFIX: &str = "posthog:1:flag_overrides:";
+
+/// The value a flag is forced to: enabled or disabled, or enabled with a variant.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum FlagOverride {
+    Enabled(bool),
+    Variant(String),
+}
+
+impl From<&FlagOverride> for FeatureFlagMatch {
+    fn from(flag_override: &FlagOverride) -> Self {
+        match flag_override {
+            FlagOverride::Enabled(matches) => FeatureFlagMatch {
+                matches: *matches,
+                variant: None,
+            },
+            FlagOverride::Variant(variant) => FeatureFlagMatch {
+                matches: true,
+                variant: Some(variant.clone()),
+            },
+        }
+    }
+}
+
+/// Reads and writes the flag overrides of a distinct_id, which take precedence over evaluating
+/// the flags until they expire.
+pub struct FlagOverrideStore {
+    client: Arc<dyn Client + Send + Sync>,
+}
+
+impl FlagOverrideStore {
+    pub fn new(client: Arc<dyn Client + Send + Sync>) -> Self {
+        FlagOverrideStore { client }
+    }
+
+    /// Returns the overrides of a distinct_id, keyed by flag key.
+    #[instrument(skip_all)]
+    pub async fn get(
+        &self,
+        team_id: i64,
+        distinct_id: &str,
+    ) -> Result<HashMap<String, FlagOverride>, FlagError> {
+        let serialized_overrides = match self
+            .client
+            .get(format!(
+                "{FLAG_OVERRIDES_CACHE_PREFIX}{}:{}",
+                team_id, distinct_id
+            ))
+            .await
+        {
+            Ok(serialized_overrides) => serialized_overrides,
+            // Most distinct_ids have no overrides
+            Err(CustomRedisError::NotFound) => return Ok(HashMap::new()),
+            Err(e) => {
+                tracing::error!("failed to fetch flag overrides: {}", e);
+                return Err(FlagError::RedisUnavailable);
+            }
+        };
+
+        serde_json::from_str(&serialized_overrides).map_err(|e| {
+            tracing::error!("failed to parse data to flag overrides: {}", e);
+            FlagError::DataParsingError
+        })
+    }
+
+    /// Replaces the overrides of a distinct_id, which expire after `ttl`.
+    #[instrument(skip_all)]
+    pub async fn set(
+        &self,
+        team_id: i64,
+        distinct_id: &str,
+        overrides: &HashMap<String, FlagOverride>,
+        ttl: Duration,
+    ) -> Result<(), FlagError> {
+        let serialized_overrides =
+            serde_json::to_string(overrides).map_err(FlagError::RequestParsingError)?;
+
+        self.client
+            .setex(
+                format!("{FLAG_OVERRIDES_CACHE_PREFIX}{}:{}", team_id, distinct_id),
+                serialized_overrides,
+                // Redis expires keys with a TTL of 0 right away
+                usize::try_from(ttl.as_secs().max(1)).unwrap_or(usize::MAX),
+            )
+            .await
+            .map_err(|e| {
+                tracing::error!("failed to write flag overrides: {}", e);
+                FlagError::RedisUnavailable
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{random_string, setup_redis_client};
+
+    #[tokio::test]
+    async fn test_get_and_set_overrides() {
+        let store = FlagOverrideStore::new(setup_redis_client(None));
+        let team_id = i64::from(rand::random::<u32>());
+        let distinct_id = random_string("user_", 8);
+
+        assert!(store.get(team_id, &distinct_id).await.unwrap().is_empty());
+
+        let overrides = HashMap::from([
+            ("enabled-flag".to_string(), FlagOverride::Enabled(false)),
+            (
+                "variant-flag".to_string(),
+                FlagOverride::Variant("control".to_string()),
+            ),
+        ]);
+        store
+            .set(team_id, &distinct_id, &overrides, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        assert_eq!(store.get(team_id, &distinct_id).await.unwrap(), overrides);
+        // Overrides are only for the distinct_id they were set for
+        assert!(store.get(team_id, "other_user").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_overrides_expire() {
+        let store = FlagOverrideStore::new(setup_redis_client(None));
+        let team_id = i64::from(rand::random::<u32>());
+        let distinct_id = random_string("user_", 8);
+
+        let overrides = HashMap::from([("flag".to_string(), FlagOverride::Enabled(true))]);
+        store
+            .set(team_id, &distinct_id, &overrides, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(store.get(team_id, &distinct_id).await.unwrap(), overrides);
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(store.get(team_id, &distinct_id).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_override_to_match() {
+        assert_eq!(
+            FeatureFlagMatch::from(&FlagOverride::Enabled(true)),
+            FeatureFlagMatch {
+                matches: true,
+                variant: None,
+            }
+        );
+        assert_eq!(
+            FeatureFlagMatch::from(&FlagOverride::Variant("test".to_string())),
+            FeatureFlagMatch {
+                matches: true,
+                variant: Some("test".to_string()),
+            }
+        );
+    }
+}
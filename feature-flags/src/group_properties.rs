@@ -0,0 +1,325 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::{
+    api::FlagError,
+    flag_definitions::FeatureFlag,
+    redis::{Client, CustomRedisError},
+    v0_request::FlagRequest,
+};
+
+// Group properties are cached per team, group type and group key.
+pub const GROUP_PROPERTIES_CACHE_PREFIX: &str = "posthog:1:group_properties:";
+// The group types of a team, mapping the group type indexes flags use to the names requests use.
+pub const GROUP_TYPES_CACHE_PREFIX: &str = "posthog:1:group_types:";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GroupTypeMapping {
+    pub group_type: String,
+    pub group_type_index: u8,
+}
+
+/// A group a request is evaluated for, with the properties to match group conditions against.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupState {
+    pub key: String,
+    pub properties: HashMap<String, Value>,
+}
+
+/// Reads the stored properties of groups, to evaluate group conditions when the request
+/// doesn't send all the properties they need.
+pub struct GroupPropertyStore {
+    client: Arc<dyn Client + Send + Sync>,
+}
+
+impl GroupPropertyStore {
+    pub fn new(client: Arc<dyn Client + Send + Sync>) -> Self {
+        GroupPropertyStore { client }
+    }
+
+    /// Returns the group types of a team, keyed by their index.
+    #[instrument(skip_all)]
+    pub async fn group_types(&self, team_id: i64) -> Result<HashMap<u8, String>, FlagError> {
+        let serialized_mappings = match self
+            .client
+            .get(format!("{GROUP_TYPES_CACHE_PREFIX}{}", team_id))
+            .await
+        {
+            Ok(serialized_mappings) => serialized_mappings,
+            // Teams without group types have nothing cached
+            Err(CustomRedisError::NotFound) => return Ok(HashMap::new()),
+            Err(e) => return Err(redis_error(e)),
+        };
+
+        let mappings: Vec<GroupTypeMapping> =
+            serde_json::from_str(&serialized_mappings).map_err(|e| {
+                tracing::error!("failed to parse data to group types: {}", e);
+                FlagError::DataParsingError
+            })?;
+
+        Ok(mappings
+            .into_iter()
+            .map(|mapping| (mapping.group_type_index, mapping.group_type))
+            .collect())
+    }
+
+    /// Returns the stored properties of a group, or None if the group isn't stored.
+    #[instrument(skip_all)]
+    pub async fn get(
+        &self,
+        team_id: i64,
+        group_type: &str,
+        group_key: &str,
+    ) -> Result<Option<HashMap<String, Value>>, FlagError> {
+        let serialized_properties = match self
+            .client
+            .get(format!(
+                "{GROUP_PROPERTIES_CACHE_PREFIX}{}:{}:{}",
+                team_id, group_type, group_key
+            ))
+            .await
+        {
+            Ok(serialized_properties) => serialized_properties,
+            Err(CustomRedisError::NotFound) => return Ok(None),
+            Err(e) => return Err(redis_error(e)),
+        };
+
+        let properties = serde_json::from_str(&serialized_properties).map_err(|e| {
+            tracing::error!("failed to parse data to group properties: {}", e);
+            FlagError::DataParsingError
+        })?;
+
+        Ok(Some(properties))
+    }
+
+    /// Returns the groups of the request that `flags` are aggregated by, keyed by their group
+    /// type index. Their stored properties are merged with the ones sent in the request, which
+    /// take precedence. Groups that can't be read from the store only get the request's
+    /// properties, so their conditions fall back to not matching missing properties.
+    #[instrument(skip_all)]
+    pub async fn resolve_groups(
+        &self,
+        team_id: i64,
+        flags: &[FeatureFlag],
+        request: &FlagRequest,
+    ) -> HashMap<u8, GroupState> {
+        let mut resolved = HashMap::new();
+
+        let Some(groups) = &request.groups else {
+            return resolved;
+        };
+        if flags
+            .iter()
+            .all(|flag| flag.get_group_type_index().is_none())
+        {
+            return resolved;
+        }
+
+        let group_types = match self.group_types(team_id).await {
+            Ok(group_types) => group_types,
+            Err(e) => {
+                tracing::warn!("failed to read group types: {}", e);
+                return resolved;
+            }
+        };
+
+        for group_type_index in flags.iter().filter_map(FeatureFlag::get_group_type_index) {
+            if resolved.contains_key(&group_type_index) {
+                continue;
+            }
+            let Some(group_type) = group_types.get(&group_type_index) else {
+                continue;
+            };
+            let Some(group_key) = groups.get(group_type).map(group_key_from_value) else {
+                continue;
+            };
+
+            let mut properties = match self.get(team_id, group_type, &group_key).await {
+                Ok(properties) => properties.unwrap_or_default(),
+                Err(e) => {
+                    tracing::warn!("failed to read properties of {} group: {}", group_type, e);
+                    HashMap::new()
+                }
+            };
+            if let Some(Value::Object(overrides)) = request
+                .group_properties
+                .as_ref()
+                .and_then(|group_properties| group_properties.get(group_type))
+            {
+                properties.extend(overrides.clone());
+            }
+
+            resolved.insert(
+                group_type_index,
+                GroupState {
+                    key: group_key,
+                    properties,
+                },
+            );
+        }
+
+        resolved
+    }
+}
+
+/// Group keys are usually strings, but clients may send other JSON values such as numbers.
+fn group_key_from_value(value: &Value) -> String {
+    match value {
+        Value::String(key) => key.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn redis_error(e: CustomRedisError) -> FlagError {
+    match e {
+        CustomRedisError::PickleError(_) => {
+            tracing::error!("failed to fetch data: {}", e);
+            FlagError::DataParsingError
+        }
+        _ => {
+            tracing::error!("Unknown redis error: {}", e);
+            FlagError::RedisUnavailable
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_utils::{
+        create_flag_from_json, insert_group_in_redis, insert_group_types_in_redis,
+        setup_redis_client,
+    };
+
+    fn group_flags(team_id: i64) -> Vec<FeatureFlag> {
+        create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "group-flag",
+                "active": true,
+                "team_id": team_id,
+                "filters": {
+                    "aggregation_group_type_index": 0,
+                    "groups": [{
+                        "properties": [{
+                            "key": "plan",
+                            "value": "enterprise",
+                            "type": "group",
+                            "group_type_index": 0,
+                        }],
+                    }],
+                },
+            }])
+            .to_string(),
+        ))
+    }
+
+    fn request(group_properties: Option<Value>) -> FlagRequest {
+        serde_json::from_value(json!({
+            "distinct_id": "user_1",
+            "groups": {"organization": "org_1"},
+            "group_properties": group_properties,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_stored_group() {
+        let client = setup_redis_client(None);
+        let team_id = i64::from(rand::random::<u32>());
+        insert_group_types_in_redis(client.clone(), team_id, &[("organization", 0)])
+            .await
+            .unwrap();
+        insert_group_in_redis(
+            client.clone(),
+            team_id,
+            "organization",
+            "org_1",
+            json!({"plan": "enterprise", "seats": 10}),
+        )
+        .await
+        .unwrap();
+
+        let groups = GroupPropertyStore::new(client)
+            .resolve_groups(team_id, &group_flags(team_id), &request(None))
+            .await;
+
+        assert_eq!(
+            groups.get(&0),
+            Some(&GroupState {
+                key: "org_1".to_string(),
+                properties: HashMap::from([
+                    ("plan".to_string(), json!("enterprise")),
+                    ("seats".to_string(), json!(10)),
+                ]),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_group_not_stored() {
+        let client = setup_redis_client(None);
+        let team_id = i64::from(rand::random::<u32>());
+        insert_group_types_in_redis(client.clone(), team_id, &[("organization", 0)])
+            .await
+            .unwrap();
+
+        let store = GroupPropertyStore::new(client);
+        assert_eq!(
+            store.get(team_id, "organization", "org_1").await.unwrap(),
+            None
+        );
+
+        let groups = store
+            .resolve_groups(team_id, &group_flags(team_id), &request(None))
+            .await;
+
+        // The group is still known by its key, just without properties to match
+        assert_eq!(
+            groups.get(&0),
+            Some(&GroupState {
+                key: "org_1".to_string(),
+                properties: HashMap::new(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_group_properties_override_stored_ones() {
+        let client = setup_redis_client(None);
+        let team_id = i64::from(rand::random::<u32>());
+        insert_group_types_in_redis(client.clone(), team_id, &[("organization", 0)])
+            .await
+            .unwrap();
+        insert_group_in_redis(
+            client.clone(),
+            team_id,
+            "organization",
+            "org_1",
+            json!({"plan": "free", "seats": 10}),
+        )
+        .await
+        .unwrap();
+
+        let groups = GroupPropertyStore::new(client)
+            .resolve_groups(
+                team_id,
+                &group_flags(team_id),
+                &request(Some(json!({"organization": {"plan": "enterprise"}}))),
+            )
+            .await;
+
+        assert_eq!(
+            groups[&0].properties,
+            HashMap::from([
+                ("plan".to_string(), json!("enterprise")),
+                ("seats".to_string(), json!(10)),
+            ])
+        );
+    }
+}
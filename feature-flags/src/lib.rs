@@ -1,7 +1,12 @@
 pub mod api;
+pub mod billing_limiter;
 pub mod config;
+pub mod file_store;
 pub mod flag_definitions;
+pub mod flag_events;
 pub mod flag_matching;
+pub mod flag_overrides;
+pub mod group_properties;
 pub mod property_matching;
 pub mod redis;
 pub mod router;
@@ -25,6 +25,10 @@ async fn shutdown() {
 #[tokio::main]
 async fn main() {
     let config = Config::init_from_env().expect("Invalid configuration:");
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     // Basic logging for now:
     //   - stdout with a level configured by the RUST_LOG envvar (default=ERROR)
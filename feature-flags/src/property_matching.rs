@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use crate::flag_definitions::{OperatorType, PropertyFilter};
 use regex::Regex;
@@ -29,25 +30,80 @@ pub fn to_f64_representation(value: &Value) -> Option<f64> {
     to_string_representation(value).parse::<f64>().ok()
 }
 
+/// Look up the value of a property, resolving dot-separated keys like `address.country` into
+/// nested objects when there's no property with that exact key. Absent nested paths, including
+/// paths through values that aren't objects, are absent properties.
+pub fn get_property_value<'a>(
+    matching_property_values: &'a HashMap<String, Value>,
+    key: &str,
+) -> Option<&'a Value> {
+    if let Some(value) = matching_property_values.get(key) {
+        return Some(value);
+    }
+
+    let mut path = key.split('.');
+    let root = matching_property_values.get(path.next()?)?;
+    path.try_fold(root, |value, segment| value.as_object()?.get(segment))
+}
+
+/// How the exact and is_not operators compare a flag value with a property value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValueCoercion {
+    /// Compare string representations of both values, like the Python implementation does.
+    #[default]
+    Legacy,
+    /// Compare bools with bools, numbers with numbers and strings with strings, parsing
+    /// strings when the other side is a bool or a number. Falls back to `Legacy` for any
+    /// other combination of types.
+    TypeAware,
+}
+
+impl FromStr for ValueCoercion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "legacy" => Ok(ValueCoercion::Legacy),
+            "type_aware" => Ok(ValueCoercion::TypeAware),
+            _ => Err(format!("unknown value coercion: {}", s)),
+        }
+    }
+}
+
 pub fn match_property(
     property: &PropertyFilter,
     matching_property_values: &HashMap<String, Value>,
     partial_props: bool,
+) -> Result<bool, FlagMatchingError> {
+    match_property_with_coercion(
+        property,
+        matching_property_values,
+        partial_props,
+        ValueCoercion::Legacy,
+    )
+}
+
+pub fn match_property_with_coercion(
+    property: &PropertyFilter,
+    matching_property_values: &HashMap<String, Value>,
+    partial_props: bool,
+    coercion: ValueCoercion,
 ) -> Result<bool, FlagMatchingError> {
     // only looks for matches where key exists in override_property_values
     // doesn't support operator is_not_set with partial_props
 
-    if partial_props && !matching_property_values.contains_key(&property.key) {
+    let key = &property.key;
+    let match_value = get_property_value(matching_property_values, key);
+
+    if partial_props && match_value.is_none() {
         return Err(FlagMatchingError::MissingProperty(format!(
             "can't match properties without a value. Missing property: {}",
-            property.key
+            key
         )));
     }
 
-    let key = &property.key;
     let operator = property.operator.clone().unwrap_or(OperatorType::Exact);
     let value = &property.value;
-    let match_value = matching_property_values.get(key);
 
     match operator {
         OperatorType::Exact | OperatorType::IsNot => {
@@ -72,26 +128,38 @@ pub fn match_property(
                     == to_string_representation(override_value).to_lowercase()
             };
 
+            let compute_match = |value: &Value, override_value: &Value| -> bool {
+                match (coercion, value) {
+                    (ValueCoercion::Legacy, _) => compute_exact_match(value, override_value),
+                    (ValueCoercion::TypeAware, Value::Array(values)) => values.iter().any(|v| {
+                        compare_type_aware(v, override_value)
+                            .unwrap_or_else(|| compute_exact_match(v, override_value))
+                    }),
+                    (ValueCoercion::TypeAware, _) => compare_type_aware(value, override_value)
+                        .unwrap_or_else(|| compute_exact_match(value, override_value)),
+                }
+            };
+
             if let Some(match_value) = match_value {
                 if operator == OperatorType::Exact {
-                    Ok(compute_exact_match(value, match_value))
+                    Ok(compute_match(value, match_value))
                 } else {
-                    Ok(!compute_exact_match(value, match_value))
+                    Ok(!compute_match(value, match_value))
                 }
             } else {
                 Ok(false)
             }
         }
-        OperatorType::IsSet => Ok(matching_property_values.contains_key(key)),
+        OperatorType::IsSet => Ok(match_value.is_some()),
         OperatorType::IsNotSet => {
             if partial_props {
-                if matching_property_values.contains_key(key) {
+                if match_value.is_some() {
                     Ok(false)
                 } else {
                     Err(FlagMatchingError::InconclusiveOperatorMatch)
                 }
             } else {
-                Ok(!matching_property_values.contains_key(key))
+                Ok(match_value.is_none())
             }
         }
         OperatorType::Icontains | OperatorType::NotIcontains => {
@@ -194,6 +262,52 @@ pub fn match_property(
     }
 }
 
+/// Lenient version of `match_property` where any property missing from `matching_property_values`
+/// is a non-match, instead of a `MissingProperty` error. This allows evaluating against a partial
+/// set of property overrides, e.g. for previews, while the strict `match_property` is kept for the
+/// live decide path.
+pub fn match_property_lenient(
+    property: &PropertyFilter,
+    matching_property_values: &HashMap<String, Value>,
+) -> Result<bool, FlagMatchingError> {
+    match_property_lenient_with_coercion(property, matching_property_values, ValueCoercion::Legacy)
+}
+
+pub fn match_property_lenient_with_coercion(
+    property: &PropertyFilter,
+    matching_property_values: &HashMap<String, Value>,
+    coercion: ValueCoercion,
+) -> Result<bool, FlagMatchingError> {
+    if get_property_value(matching_property_values, &property.key).is_none() {
+        return Ok(false);
+    }
+
+    match_property_with_coercion(property, matching_property_values, true, coercion)
+}
+
+/// Compare two scalar values according to their types, returning `None` when the
+/// combination of types can't be compared without falling back to string representations.
+fn compare_type_aware(value: &Value, override_value: &Value) -> Option<bool> {
+    match (value, override_value) {
+        (Value::Bool(a), Value::Bool(b)) => Some(a == b),
+        (Value::Number(a), Value::Number(b)) => Some(a.as_f64() == b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.to_lowercase() == b.to_lowercase()),
+        (Value::Bool(b), Value::String(s)) | (Value::String(s), Value::Bool(b)) => {
+            match s.to_lowercase().as_str() {
+                "true" => Some(*b),
+                "false" => Some(!*b),
+                _ => Some(false),
+            }
+        }
+        (Value::Number(n), Value::String(s)) | (Value::String(s), Value::Number(n)) => s
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .map(|parsed| Some(parsed) == n.as_f64()),
+        _ => None,
+    }
+}
+
 fn is_truthy_or_falsy_property_value(value: &Value) -> bool {
     if value.is_boolean() {
         return true;
@@ -409,6 +523,60 @@ mod test_match_properties {
         );
     }
 
+    #[test]
+    fn test_match_properties_strict_vs_lenient_on_missing_property() {
+        let property_a = PropertyFilter {
+            key: "key".to_string(),
+            value: json!("value"),
+            operator: None,
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        };
+        let missing_property_values = HashMap::from([("key2".to_string(), json!("value"))]);
+
+        assert_eq!(
+            match_property(&property_a, &missing_property_values, true)
+                .err()
+                .expect("expected strict match to fail"),
+            FlagMatchingError::MissingProperty(
+                "can't match properties without a value. Missing property: key".to_string()
+            )
+        );
+        assert_eq!(
+            match_property_lenient(&property_a, &missing_property_values)
+                .expect("expected lenient match to exist"),
+            false
+        );
+
+        let property_b = PropertyFilter {
+            key: "key".to_string(),
+            value: json!(true),
+            operator: Some(OperatorType::IsNotSet),
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        };
+
+        assert!(match_property(&property_b, &missing_property_values, true).is_err());
+        assert_eq!(
+            match_property_lenient(&property_b, &missing_property_values)
+                .expect("expected lenient match to exist"),
+            false
+        );
+
+        // Lenient matching is identical to strict matching when the property is present.
+        let matching_property_values = HashMap::from([("key".to_string(), json!("value"))]);
+        assert_eq!(
+            match_property_lenient(&property_a, &matching_property_values)
+                .expect("expected lenient match to exist"),
+            true
+        );
+        assert_eq!(
+            match_property_lenient(&property_b, &matching_property_values)
+                .expect("expected lenient match to exist"),
+            false
+        );
+    }
+
     #[test]
     fn test_match_properties_is_not() {
         let property_a = PropertyFilter {
@@ -1644,4 +1812,253 @@ mod test_match_properties {
             false
         );
     }
+
+    fn exact_filter(value: Value) -> PropertyFilter {
+        PropertyFilter {
+            key: "key".to_string(),
+            value,
+            operator: Some(OperatorType::Exact),
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        }
+    }
+
+    fn matches(filter: &PropertyFilter, property_value: Value, coercion: ValueCoercion) -> bool {
+        match_property_with_coercion(
+            filter,
+            &HashMap::from([("key".to_string(), property_value)]),
+            true,
+            coercion,
+        )
+        .expect("expected match to exist")
+    }
+
+    #[test]
+    fn test_type_aware_coercion_string_vs_number() {
+        let string_filter = exact_filter(json!("42"));
+        let number_filter = exact_filter(json!(42));
+
+        for coercion in [ValueCoercion::Legacy, ValueCoercion::TypeAware] {
+            assert!(matches(&string_filter, json!(42), coercion));
+            assert!(matches(&number_filter, json!("42"), coercion));
+            assert!(!matches(&number_filter, json!("43"), coercion));
+        }
+
+        // Numbers are compared by value rather than by representation
+        assert!(!matches(&number_filter, json!(42.0), ValueCoercion::Legacy));
+        assert!(matches(
+            &number_filter,
+            json!(42.0),
+            ValueCoercion::TypeAware
+        ));
+        assert!(!matches(
+            &string_filter,
+            json!(" 42"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(matches(
+            &number_filter,
+            json!("42.0"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(!matches(
+            &number_filter,
+            json!("forty-two"),
+            ValueCoercion::TypeAware
+        ));
+    }
+
+    #[test]
+    fn test_type_aware_coercion_bool_vs_string() {
+        let bool_filter = exact_filter(json!(true));
+        let string_filter = exact_filter(json!("true"));
+
+        // Legacy matching compares the quoted string representation, so a "true" string
+        // property doesn't match a "true" flag value
+        assert!(!matches(
+            &string_filter,
+            json!("true"),
+            ValueCoercion::Legacy
+        ));
+        assert!(matches(&string_filter, json!(true), ValueCoercion::Legacy));
+
+        for coercion in [ValueCoercion::Legacy, ValueCoercion::TypeAware] {
+            assert!(matches(&bool_filter, json!(true), coercion));
+            assert!(!matches(&bool_filter, json!(false), coercion));
+        }
+
+        assert!(matches(
+            &bool_filter,
+            json!("true"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(matches(
+            &bool_filter,
+            json!("True"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(!matches(
+            &bool_filter,
+            json!("false"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(!matches(
+            &bool_filter,
+            json!("yes"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(matches(
+            &string_filter,
+            json!("true"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(matches(
+            &string_filter,
+            json!(true),
+            ValueCoercion::TypeAware
+        ));
+        assert!(!matches(
+            &string_filter,
+            json!(false),
+            ValueCoercion::TypeAware
+        ));
+
+        let is_not_filter = PropertyFilter {
+            operator: Some(OperatorType::IsNot),
+            ..exact_filter(json!(true))
+        };
+        assert!(!matches(
+            &is_not_filter,
+            json!("true"),
+            ValueCoercion::TypeAware
+        ));
+        assert!(matches(
+            &is_not_filter,
+            json!("false"),
+            ValueCoercion::TypeAware
+        ));
+    }
+
+    #[test]
+    fn test_type_aware_coercion_array_membership_with_mixed_types() {
+        let filter = exact_filter(json!(["a", 1, true, "2.5"]));
+
+        for coercion in [ValueCoercion::Legacy, ValueCoercion::TypeAware] {
+            assert!(matches(&filter, json!("A"), coercion));
+            assert!(matches(&filter, json!(1), coercion));
+            assert!(matches(&filter, json!("1"), coercion));
+            assert!(!matches(&filter, json!("b"), coercion));
+        }
+
+        assert!(matches(&filter, json!(1.0), ValueCoercion::TypeAware));
+        assert!(matches(&filter, json!(2.5), ValueCoercion::TypeAware));
+        assert!(matches(&filter, json!("true"), ValueCoercion::TypeAware));
+        assert!(!matches(&filter, json!(false), ValueCoercion::TypeAware));
+        assert!(!matches(&filter, json!(2), ValueCoercion::TypeAware));
+    }
+
+    #[test]
+    fn test_nested_property_path_matches() {
+        let filter = PropertyFilter {
+            key: "address.country".to_string(),
+            value: json!("FR"),
+            operator: None,
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        };
+        let properties = HashMap::from([(
+            "address".to_string(),
+            json!({"country": "FR", "city": "Paris"}),
+        )]);
+
+        assert!(match_property(&filter, &properties, true).expect("expected match to exist"));
+        assert!(match_property(&filter, &properties, false).expect("expected match to exist"));
+
+        let properties = HashMap::from([("address".to_string(), json!({"country": "DE"}))]);
+        assert!(!match_property(&filter, &properties, true).expect("expected match to exist"));
+
+        // A property with the exact dotted key wins over the nested path.
+        let properties = HashMap::from([
+            ("address.country".to_string(), json!("FR")),
+            ("address".to_string(), json!({"country": "DE"})),
+        ]);
+        assert!(match_property(&filter, &properties, true).expect("expected match to exist"));
+
+        let deep_filter = PropertyFilter {
+            key: "company.address.country".to_string(),
+            operator: Some(OperatorType::IsSet),
+            ..filter
+        };
+        let properties =
+            HashMap::from([("company".to_string(), json!({"address": {"country": "FR"}}))]);
+        assert!(match_property(&deep_filter, &properties, true).expect("expected match to exist"));
+    }
+
+    #[test]
+    fn test_nested_property_path_partially_present() {
+        let filter = PropertyFilter {
+            key: "address.country".to_string(),
+            value: json!("FR"),
+            operator: None,
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        };
+
+        // The object exists without the nested key, or isn't an object at all.
+        for address in [json!({"city": "Paris"}), json!("FR"), json!(null)] {
+            let properties = HashMap::from([("address".to_string(), address)]);
+
+            assert!(matches!(
+                match_property(&filter, &properties, true),
+                Err(FlagMatchingError::MissingProperty(_))
+            ));
+            assert!(!match_property(&filter, &properties, false).expect("expected match to exist"));
+            assert!(!match_property_lenient(&filter, &properties).expect("expected match to exist"));
+
+            let is_not_set = PropertyFilter {
+                operator: Some(OperatorType::IsNotSet),
+                ..filter.clone()
+            };
+            assert!(
+                match_property(&is_not_set, &properties, false).expect("expected match to exist")
+            );
+        }
+    }
+
+    #[test]
+    fn test_nested_property_path_absent() {
+        let filter = PropertyFilter {
+            key: "address.country".to_string(),
+            value: json!("FR"),
+            operator: None,
+            prop_type: "person".to_string(),
+            group_type_index: None,
+        };
+        let properties = HashMap::from([("email".to_string(), json!("a@b.com"))]);
+
+        assert!(matches!(
+            match_property(&filter, &properties, true),
+            Err(FlagMatchingError::MissingProperty(_))
+        ));
+        assert!(!match_property(&filter, &properties, false).expect("expected match to exist"));
+
+        let is_set = PropertyFilter {
+            operator: Some(OperatorType::IsSet),
+            ..filter.clone()
+        };
+        assert!(!match_property(&is_set, &properties, false).expect("expected match to exist"));
+
+        let is_not_set = PropertyFilter {
+            operator: Some(OperatorType::IsNotSet),
+            ..filter
+        };
+        assert!(match_property(&is_not_set, &properties, false).expect("expected match to exist"));
+        assert_eq!(
+            match_property(&is_not_set, &properties, true),
+            Err(FlagMatchingError::MissingProperty(
+                "can't match properties without a value. Missing property: address.country"
+                    .to_string()
+            ))
+        );
+    }
 }
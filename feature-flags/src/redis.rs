@@ -6,9 +6,15 @@ use redis::{AsyncCommands, RedisError};
 use thiserror::Error;
 use tokio::time::timeout;
 
+use crate::flag_definitions::TEAM_FLAGS_CACHE_PREFIX;
+use crate::team::TEAM_TOKEN_CACHE_PREFIX;
+
 // average for all commands is <10ms, check grafana
 const REDIS_TIMEOUT_MILLISECS: u64 = 10;
 
+// Cohorts are not read yet, but will be cached under this prefix
+pub const COHORT_CACHE_PREFIX: &str = "posthog:1:cohorts:";
+
 #[derive(Error, Debug)]
 pub enum CustomRedisError {
     #[error("Not found in redis")]
@@ -34,24 +40,63 @@ pub trait Client {
 
     async fn get(&self, k: String) -> Result<String, CustomRedisError>;
     async fn set(&self, k: String, v: String) -> Result<()>;
+    async fn setex(&self, k: String, v: String, seconds: usize) -> Result<()>;
+    async fn zadd(&self, k: String, v: String, score: f64) -> Result<()>;
+}
+
+/// Addresses of the redis databases holding each kind of data, for deployments where they
+/// don't all live in the same database. Kinds of data without an address use the default one.
+#[derive(Clone, Debug, Default)]
+pub struct RedisDatabases {
+    pub teams_url: Option<String>,
+    pub flags_url: Option<String>,
+    pub cohorts_url: Option<String>,
 }
 
 pub struct RedisClient {
     client: redis::Client,
+    /// Clients used instead of `client` for keys starting with the given prefix.
+    prefixed_clients: Vec<(&'static str, redis::Client)>,
 }
 
 impl RedisClient {
     pub fn new(addr: String) -> Result<RedisClient> {
+        RedisClient::with_databases(addr, RedisDatabases::default())
+    }
+
+    pub fn with_databases(addr: String, databases: RedisDatabases) -> Result<RedisClient> {
         let client = redis::Client::open(addr)?;
 
-        Ok(RedisClient { client })
+        let mut prefixed_clients = Vec::new();
+        for (prefix, url) in [
+            (TEAM_TOKEN_CACHE_PREFIX, databases.teams_url),
+            (TEAM_FLAGS_CACHE_PREFIX, databases.flags_url),
+            (COHORT_CACHE_PREFIX, databases.cohorts_url),
+        ] {
+            if let Some(url) = url {
+                prefixed_clients.push((prefix, redis::Client::open(url)?));
+            }
+        }
+
+        Ok(RedisClient {
+            client,
+            prefixed_clients,
+        })
+    }
+
+    /// Returns the client for the database holding `key`.
+    fn client_for(&self, key: &str) -> &redis::Client {
+        self.prefixed_clients
+            .iter()
+            .find(|(prefix, _)| key.starts_with(prefix))
+            .map_or(&self.client, |(_, client)| client)
     }
 }
 
 #[async_trait]
 impl Client for RedisClient {
     async fn zrangebyscore(&self, k: String, min: String, max: String) -> Result<Vec<String>> {
-        let mut conn = self.client.get_async_connection().await?;
+        let mut conn = self.client_for(&k).get_async_connection().await?;
 
         let results = conn.zrangebyscore(k, min, max);
         let fut = timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
@@ -60,7 +105,7 @@ impl Client for RedisClient {
     }
 
     async fn get(&self, k: String) -> Result<String, CustomRedisError> {
-        let mut conn = self.client.get_async_connection().await?;
+        let mut conn = self.client_for(&k).get_async_connection().await?;
 
         let results = conn.get(k);
         let fut: Result<Vec<u8>, RedisError> =
@@ -86,11 +131,104 @@ impl Client for RedisClient {
         // Here we serialize the json string to bytes using serde_pickle.
         let bytes = serde_pickle::to_vec(&v, Default::default())?;
 
-        let mut conn = self.client.get_async_connection().await?;
+        let mut conn = self.client_for(&k).get_async_connection().await?;
 
         let results = conn.set(k, bytes);
         let fut = timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
 
         Ok(fut?)
     }
+
+    async fn setex(&self, k: String, v: String, seconds: usize) -> Result<()> {
+        // TRICKY: Pickled like `set`, so that `get` can read it back.
+        let bytes = serde_pickle::to_vec(&v, Default::default())?;
+
+        let mut conn = self.client_for(&k).get_async_connection().await?;
+
+        let results = conn.set_ex(k, bytes, seconds);
+        let fut = timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
+
+        Ok(fut?)
+    }
+
+    async fn zadd(&self, k: String, v: String, score: f64) -> Result<()> {
+        let mut conn = self.client_for(&k).get_async_connection().await?;
+
+        let results = conn.zadd(k, v, score);
+        let fut: Result<(), RedisError> =
+            timeout(Duration::from_secs(REDIS_TIMEOUT_MILLISECS), results).await?;
+
+        Ok(fut?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::random_string;
+
+    #[tokio::test]
+    async fn test_reads_go_to_the_database_of_each_kind_of_data() {
+        let client = RedisClient::with_databases(
+            "redis://localhost:6379/".to_string(),
+            RedisDatabases {
+                teams_url: Some("redis://localhost:6379/1".to_string()),
+                flags_url: Some("redis://localhost:6379/2".to_string()),
+                cohorts_url: None,
+            },
+        )
+        .expect("failed to create redis client");
+        let default_db = RedisClient::new("redis://localhost:6379/".to_string()).unwrap();
+        let teams_db = RedisClient::new("redis://localhost:6379/1".to_string()).unwrap();
+        let flags_db = RedisClient::new("redis://localhost:6379/2".to_string()).unwrap();
+
+        let team_key = random_string(TEAM_TOKEN_CACHE_PREFIX, 12);
+        let flags_key = random_string(TEAM_FLAGS_CACHE_PREFIX, 12);
+        let cohort_key = random_string(COHORT_CACHE_PREFIX, 12);
+
+        teams_db
+            .set(team_key.clone(), "team".to_string())
+            .await
+            .expect("failed to write team");
+        flags_db
+            .set(flags_key.clone(), "flags".to_string())
+            .await
+            .expect("failed to write flags");
+        default_db
+            .set(cohort_key.clone(), "cohort".to_string())
+            .await
+            .expect("failed to write cohort");
+
+        assert_eq!(client.get(team_key.clone()).await.unwrap(), "team");
+        assert_eq!(client.get(flags_key.clone()).await.unwrap(), "flags");
+        assert_eq!(client.get(cohort_key.clone()).await.unwrap(), "cohort");
+
+        // Nothing was written to the default database for teams and flags
+        assert!(matches!(
+            default_db.get(team_key).await,
+            Err(CustomRedisError::NotFound)
+        ));
+        assert!(matches!(
+            default_db.get(flags_key).await,
+            Err(CustomRedisError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_single_database_by_default() {
+        let client = RedisClient::new("redis://localhost:6379/".to_string()).unwrap();
+        let teams_db = RedisClient::new("redis://localhost:6379/1".to_string()).unwrap();
+
+        let team_key = random_string(TEAM_TOKEN_CACHE_PREFIX, 12);
+        client
+            .set(team_key.clone(), "team".to_string())
+            .await
+            .expect("failed to write team");
+
+        assert_eq!(client.get(team_key.clone()).await.unwrap(), "team");
+        assert!(matches!(
+            teams_db.get(team_key).await,
+            Err(CustomRedisError::NotFound)
+        ));
+    }
 }
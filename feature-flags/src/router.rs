@@ -1,19 +1,47 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{routing::post, Router};
 
-use crate::{redis::Client, v0_endpoint};
+use crate::{
+    billing_limiter::BillingLimiter, flag_events::FlagCalledSink, property_matching::ValueCoercion,
+    redis::Client, v0_endpoint,
+};
+
+// How long the quota limited teams are cached before being fetched from redis again
+const QUOTA_LIMITS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct State {
     pub redis: Arc<dyn Client + Send + Sync>,
+    pub allow_mismatched_tokens: bool,
+    pub flag_called_sink: Option<Arc<dyn FlagCalledSink + Send + Sync>>,
+    pub billing: BillingLimiter,
+    pub value_coercion: ValueCoercion,
     // TODO: Add pgClient when ready
 }
 
-pub fn router<R: Client + Send + Sync + 'static>(redis: Arc<R>) -> Router {
-    let state = State { redis };
+pub fn router<R: Client + Send + Sync + 'static>(
+    redis: Arc<R>,
+    allow_mismatched_tokens: bool,
+    flag_called_sink: Option<Arc<dyn FlagCalledSink + Send + Sync>>,
+    value_coercion: ValueCoercion,
+) -> Router {
+    let billing = BillingLimiter::new(QUOTA_LIMITS_REFRESH_INTERVAL, redis.clone());
+    let state = State {
+        redis,
+        allow_mismatched_tokens,
+        flag_called_sink,
+        billing,
+        value_coercion,
+    };
 
     Router::new()
         .route("/flags", post(v0_endpoint::flags).get(v0_endpoint::flags))
+        .route("/bulk_flags", post(v0_endpoint::bulk_flags))
+        .route(
+            "/flags/:key",
+            post(v0_endpoint::flag).get(v0_endpoint::flag),
+        )
         .with_state(state)
 }
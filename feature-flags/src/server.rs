@@ -1,31 +1,76 @@
 use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::net::TcpListener;
 
 use crate::config::Config;
 
-use crate::redis::RedisClient;
+use crate::file_store::FileDefinitionStore;
+use crate::flag_events::{CaptureSink, FlagCalledSink};
+use crate::redis::{RedisClient, RedisDatabases};
 use crate::router;
 
 pub async fn serve<F>(config: Config, listener: TcpListener, shutdown: F)
 where
     F: Future<Output = ()> + Send + 'static,
 {
-    let redis_client =
-        Arc::new(RedisClient::new(config.redis_url).expect("failed to create redis client"));
+    let flag_called_sink = config.capture_url.as_ref().map(|url| {
+        let sink: Arc<dyn FlagCalledSink + Send + Sync> =
+            Arc::new(CaptureSink::new(url).expect("failed to create capture client"));
+        sink
+    });
 
-    let app = router::router(redis_client);
+    let app = match config.flag_definitions_path {
+        Some(path) => {
+            let store =
+                Arc::new(FileDefinitionStore::load(path).expect("failed to load flag definitions"));
+            store.clone().watch(Duration::from_secs(
+                config.flag_definitions_reload_interval_secs,
+            ));
+            router::router(
+                store,
+                config.allow_mismatched_tokens,
+                flag_called_sink,
+                config.flag_value_coercion,
+            )
+        }
+        None => {
+            let redis_databases = RedisDatabases {
+                teams_url: config.teams_redis_url,
+                flags_url: config.flags_redis_url,
+                cohorts_url: config.cohorts_redis_url,
+            };
+            let redis_client = Arc::new(
+                RedisClient::with_databases(config.redis_url, redis_databases)
+                    .expect("failed to create redis client"),
+            );
+            router::router(
+                redis_client,
+                config.allow_mismatched_tokens,
+                flag_called_sink,
+                config.flag_value_coercion,
+            )
+        }
+    };
 
-    // run our app with hyper
-    // `axum::Server` is a re-export of `hyper::Server`
     tracing::info!("listening on {:?}", listener.local_addr().unwrap());
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown)
-    .await
-    .unwrap()
+    match (config.tls_cert_path, config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            tls::serve_tls(app, listener, cert_path, key_path, shutdown).await
+        }
+        // Config::validate rejects having only one of TLS_CERT_PATH and TLS_KEY_PATH set
+        _ => {
+            // run our app with hyper
+            // `axum::Server` is a re-export of `hyper::Server`
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown)
+            .await
+            .unwrap()
+        }
+    }
 }
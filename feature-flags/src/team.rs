@@ -16,9 +16,27 @@ pub struct Team {
     pub id: i64,
     pub name: String,
     pub api_token: String,
+    /// Web origins allowed to request flags with this team's token. Any origin is allowed if empty.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
 }
 
 impl Team {
+    /// Returns whether a request sent with the given `Origin` header may use this team's token.
+    /// Requests without an `Origin` header are not sent by browsers, so they are always allowed.
+    pub fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        let origin = match origin {
+            None => return true,
+            Some(origin) => normalize_origin(origin),
+        };
+
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed == "*" || normalize_origin(allowed) == origin)
+    }
+
     /// Validates a token, and returns a team if it exists.
 
     #[instrument(skip_all)]
@@ -52,6 +70,11 @@ impl Team {
     }
 }
 
+/// Origins are case-insensitive and sometimes configured with a trailing slash.
+fn normalize_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_lowercase()
+}
+
 #[cfg(test)]
 mod tests {
     use rand::Rng;
@@ -78,6 +101,31 @@ mod tests {
         assert_eq!(team_from_redis.id, team.id);
     }
 
+    #[test]
+    fn test_is_origin_allowed() {
+        let mut team = Team {
+            id: 1,
+            name: "team".to_string(),
+            api_token: "token".to_string(),
+            allowed_origins: vec![],
+        };
+
+        // No restriction configured
+        assert!(team.is_origin_allowed(None));
+        assert!(team.is_origin_allowed(Some("https://evil.com")));
+
+        team.allowed_origins = vec!["https://app.example.com/".to_string()];
+        assert!(team.is_origin_allowed(None));
+        assert!(team.is_origin_allowed(Some("https://app.example.com")));
+        assert!(team.is_origin_allowed(Some("HTTPS://APP.EXAMPLE.COM")));
+        assert!(!team.is_origin_allowed(Some("https://evil.com")));
+        assert!(!team.is_origin_allowed(Some("http://app.example.com")));
+        assert!(!team.is_origin_allowed(Some("null")));
+
+        team.allowed_origins = vec!["*".to_string()];
+        assert!(team.is_origin_allowed(Some("https://evil.com")));
+    }
+
     #[tokio::test]
     async fn test_fetch_invalid_team_from_redis() {
         let client = setup_redis_client(None);
@@ -107,6 +155,7 @@ mod tests {
             id,
             name: "team".to_string(),
             api_token: token,
+            allowed_origins: vec![],
         };
         let serialized_team = serde_json::to_string(&team).expect("Failed to serialise team");
 
@@ -1,9 +1,12 @@
 use anyhow::Error;
 use serde_json::json;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::{
+    billing_limiter::{FEATURE_FLAGS_RESOURCE, QUOTA_LIMITER_CACHE_KEY},
     flag_definitions::{self, FeatureFlag},
+    group_properties::{self, GroupTypeMapping},
     redis::{Client, RedisClient},
     team::{self, Team},
 };
@@ -25,8 +28,13 @@ pub async fn insert_new_team_in_redis(client: Arc<RedisClient>) -> Result<Team,
         id,
         name: "team".to_string(),
         api_token: token,
+        allowed_origins: vec![],
     };
 
+    insert_team_in_redis(client, team).await
+}
+
+pub async fn insert_team_in_redis(client: Arc<RedisClient>, team: Team) -> Result<Team, Error> {
     let serialized_team = serde_json::to_string(&team)?;
     client
         .set(
@@ -83,6 +91,68 @@ pub async fn insert_flags_for_team_in_redis(
     Ok(())
 }
 
+pub async fn insert_group_types_in_redis(
+    client: Arc<RedisClient>,
+    team_id: i64,
+    group_types: &[(&str, u8)],
+) -> Result<(), Error> {
+    let mappings: Vec<GroupTypeMapping> = group_types
+        .iter()
+        .map(|(group_type, group_type_index)| GroupTypeMapping {
+            group_type: group_type.to_string(),
+            group_type_index: *group_type_index,
+        })
+        .collect();
+
+    client
+        .set(
+            format!("{}{}", group_properties::GROUP_TYPES_CACHE_PREFIX, team_id),
+            serde_json::to_string(&mappings)?,
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_group_in_redis(
+    client: Arc<RedisClient>,
+    team_id: i64,
+    group_type: &str,
+    group_key: &str,
+    properties: serde_json::Value,
+) -> Result<(), Error> {
+    client
+        .set(
+            format!(
+                "{}{}:{}:{}",
+                group_properties::GROUP_PROPERTIES_CACHE_PREFIX,
+                team_id,
+                group_type,
+                group_key
+            ),
+            properties.to_string(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Limit the team with this token as over its feature flag requests quota for the next hour.
+pub async fn insert_quota_limited_token_in_redis(
+    client: Arc<RedisClient>,
+    token: &str,
+) -> Result<(), Error> {
+    let limited_until =
+        SystemTime::now().duration_since(UNIX_EPOCH)? + Duration::from_secs(60 * 60);
+    client
+        .zadd(
+            format!("{QUOTA_LIMITER_CACHE_KEY}{FEATURE_FLAGS_RESOURCE}"),
+            token.to_string(),
+            limited_until.as_secs_f64(),
+        )
+        .await
+}
+
 pub fn setup_redis_client(url: Option<String>) -> Arc<RedisClient> {
     let redis_url = match url {
         Some(value) => value,
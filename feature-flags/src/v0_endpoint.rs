@@ -1,17 +1,32 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use axum::body::Body;
+use axum::response::{IntoResponse, Response};
 use axum::{debug_handler, Json};
 use bytes::Bytes;
 // TODO: stream this instead
-use axum::extract::{MatchedPath, Query, State};
-use axum::http::{HeaderMap, Method};
+use axum::extract::{MatchedPath, Path, Query, State};
+use axum::http::{header, HeaderMap, Method};
 use axum_client_ip::InsecureClientIp;
+use futures::{stream, StreamExt};
+use serde_json::Value;
 use tracing::instrument;
 
 use crate::{
-    api::{FlagError, FlagsResponse},
+    api::{BulkFlagsResponse, BulkFlagsResult, FlagError, FlagResponse, FlagsResponse},
+    billing_limiter::FEATURE_FLAGS_RESOURCE,
+    flag_definitions::{FeatureFlag, FeatureFlagList},
+    flag_events::{flag_called_events, report_flags_called, FlagCalledEvent},
+    flag_matching::{
+        FeatureFlagEvaluationReason, FeatureFlagMatch, FeatureFlagMatchType, FeatureFlagMatcher,
+    },
+    flag_overrides::{FlagOverride, FlagOverrideStore},
+    group_properties::GroupPropertyStore,
+    property_matching::to_string_representation,
     router,
-    v0_request::{FlagRequest, FlagsQueryParams},
+    team::Team,
+    v0_request::{BulkFlagRequest, FlagRequest, FlagsQueryParams},
 };
 
 /// Feature flag evaluation endpoint.
@@ -55,25 +70,8 @@ pub async fn flags(
     tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
     tracing::Span::current().record("ip", ip.to_string());
 
-    let request = match headers
-        .get("content-type")
-        .map_or("", |v| v.to_str().unwrap_or(""))
-    {
-        "application/json" => {
-            tracing::Span::current().record("content_type", "application/json");
-            FlagRequest::from_bytes(body)
-        }
-        ct => {
-            return Err(FlagError::RequestDecodingError(format!(
-                "unsupported content type: {}",
-                ct
-            )));
-        }
-    }?;
-
-    let token = request
-        .extract_and_verify_token(state.redis.clone())
-        .await?;
+    let (request, team) = decode_and_verify_request(&state, &headers, body).await?;
+    let token = team.api_token;
 
     let distinct_id = request.extract_distinct_id()?;
 
@@ -82,13 +80,401 @@ pub async fn flags(
 
     tracing::debug!("request: {:?}", request);
 
-    // TODO: Some actual processing for evaluating the feature flag
+    // Teams over their quota get no flags, without spending anything on evaluating them
+    if state.billing.is_limited(&token).await {
+        return Ok(Json(FlagsResponse {
+            error_while_computing_flags: false,
+            feature_flags: HashMap::new(),
+            removed_flags: None,
+            quota_limited: Some(vec![FEATURE_FLAGS_RESOURCE.to_string()]),
+        }));
+    }
+
+    let flag_list = match FeatureFlagList::from_redis(state.redis.clone(), team.id).await {
+        Ok(list) => list,
+        // Nothing is cached for teams without flags
+        Err(FlagError::TokenValidationError) => FeatureFlagList::default(),
+        Err(e) => return Err(e),
+    };
+
+    let overrides = get_overrides(&state, team.id, &distinct_id).await;
+    let groups = GroupPropertyStore::new(state.redis.clone())
+        .resolve_groups(team.id, &flag_list.flags, &request)
+        .await;
+
+    // Flags with corrupt definitions were skipped, so return the others but flag the response
+    // as incomplete, for clients to keep their previous values of the missing flags.
+    let matcher = FeatureFlagMatcher::new(distinct_id.clone())
+        .with_groups(groups)
+        .with_value_coercion(state.value_coercion);
+    let feature_flags = evaluate_flags(&flag_list.flags, &matcher, &overrides);
+
+    if let Some(sink) = &state.flag_called_sink {
+        let events = flag_called_events(&distinct_id, &feature_flags);
+        report_flags_called(sink.clone(), token, events);
+    }
+
+    let error_while_computing_flags = flag_list.invalid_definitions > 0;
+    let (feature_flags, removed_flags) = match &request.known_flags {
+        None => (feature_flags, None),
+        Some(known_flags) => {
+            let (changed_flags, removed_flags) = diff_flags(feature_flags, known_flags);
+            // Flags with corrupt definitions are missing without having been removed
+            match error_while_computing_flags {
+                false => (changed_flags, Some(removed_flags)),
+                true => (changed_flags, Some(vec![])),
+            }
+        }
+    };
 
     Ok(Json(FlagsResponse {
-        error_while_computing_flags: false,
-        feature_flags: HashMap::from([
-            ("beta-feature".to_string(), "variant-1".to_string()),
-            ("rollout-flag".to_string(), true.to_string()),
-        ]),
+        error_while_computing_flags,
+        feature_flags,
+        removed_flags,
+        quota_limited: None,
+    }))
+}
+
+/// Splits evaluated flags against the ones the client knew, returning the flags whose value
+/// changed or that the client didn't know, and the sorted keys of the known flags that weren't
+/// evaluated. Known values are compared as strings, so `true` and `"true"` are the same value.
+fn diff_flags(
+    feature_flags: HashMap<String, String>,
+    known_flags: &HashMap<String, Value>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut removed_flags: Vec<String> = known_flags
+        .keys()
+        .filter(|key| !feature_flags.contains_key(*key))
+        .cloned()
+        .collect();
+    removed_flags.sort();
+
+    let changed_flags = feature_flags
+        .into_iter()
+        .filter(|(key, value)| {
+            known_flags.get(key).map(to_string_representation).as_ref() != Some(value)
+        })
+        .collect();
+
+    (changed_flags, removed_flags)
+}
+
+/// Bulk feature flag evaluation endpoint, evaluating the flags of many distinct_ids at once.
+/// Clients accepting `application/x-ndjson` get one JSON result per line, streamed as each
+/// distinct_id is evaluated, so that memory stays flat regardless of the number of distinct_ids.
+#[instrument(skip_all, fields(path, token, batch_size, content_type, method))]
+#[debug_handler]
+pub async fn bulk_flags(
+    State(state): State<router::State>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Response, FlagError> {
+    tracing::Span::current().record("method", method.as_str());
+    tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
+
+    check_content_type(&headers)?;
+    let bulk_request = BulkFlagRequest::from_bytes(body)?;
+    let team = verify_team(&state, &headers, &bulk_request.request).await?;
+    let distinct_ids = bulk_request.extract_distinct_ids()?;
+
+    tracing::Span::current().record("token", &team.api_token);
+    tracing::Span::current().record("batch_size", distinct_ids.len());
+
+    // Teams over their quota get no flags, without spending anything on evaluating them
+    let quota_limited = state
+        .billing
+        .is_limited(&team.api_token)
+        .await
+        .then(|| vec![FEATURE_FLAGS_RESOURCE.to_string()]);
+
+    let flag_list = match quota_limited {
+        Some(_) => FeatureFlagList::default(),
+        None => match FeatureFlagList::from_redis(state.redis.clone(), team.id).await {
+            Ok(list) => list,
+            // Nothing is cached for teams without flags
+            Err(FlagError::TokenValidationError) => FeatureFlagList::default(),
+            Err(e) => return Err(e),
+        },
+    };
+
+    // The distinct_ids share the groups of the request, so they are only resolved once
+    let groups = GroupPropertyStore::new(state.redis.clone())
+        .resolve_groups(team.id, &flag_list.flags, &bulk_request.request)
+        .await;
+    let error_while_computing_flags = flag_list.invalid_definitions > 0;
+    let flags = Arc::new(flag_list.flags);
+    let team_id = team.id;
+
+    // Lazily evaluated, one distinct_id at a time as results are consumed
+    let results = stream::iter(distinct_ids).then(move |distinct_id| {
+        let state = state.clone();
+        let flags = flags.clone();
+        let groups = groups.clone();
+        let quota_limited = quota_limited.clone();
+        async move {
+            if quota_limited.is_some() {
+                return BulkFlagsResult {
+                    distinct_id,
+                    error_while_computing_flags,
+                    feature_flags: HashMap::new(),
+                    quota_limited,
+                };
+            }
+
+            let overrides = get_overrides(&state, team_id, &distinct_id).await;
+            let matcher = FeatureFlagMatcher::new(distinct_id.clone())
+                .with_groups(groups)
+                .with_value_coercion(state.value_coercion);
+            BulkFlagsResult {
+                feature_flags: evaluate_flags(&flags, &matcher, &overrides),
+                distinct_id,
+                error_while_computing_flags,
+                quota_limited: None,
+            }
+        }
+    });
+
+    if accepts_ndjson(&headers) {
+        let lines = results.map(|result| {
+            serde_json::to_vec(&result).map(|mut line| {
+                line.push(b'\n');
+                line
+            })
+        });
+        return Ok((
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(lines),
+        )
+            .into_response());
+    }
+
+    Ok(Json(BulkFlagsResponse {
+        results: results.collect().await,
+    })
+    .into_response())
+}
+
+/// Returns whether the client asked for newline-delimited JSON results.
+fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/x-ndjson"))
+}
+
+/// Evaluates the enabled flags, or returns their override if any, as "true", "false" or the
+/// matched variant.
+fn evaluate_flags(
+    flags: &[FeatureFlag],
+    matcher: &FeatureFlagMatcher,
+    overrides: &HashMap<String, FlagOverride>,
+) -> HashMap<String, String> {
+    flags
+        .iter()
+        .filter(|flag| !flag.deleted && (flag.active || overrides.contains_key(&flag.key)))
+        .map(|flag| {
+            let flag_match = match overrides.get(&flag.key) {
+                Some(flag_override) => FeatureFlagMatch::from(flag_override),
+                None => matcher.get_match(flag),
+            };
+            let value = match flag_match.variant {
+                Some(variant) if flag_match.matches => variant,
+                _ => flag_match.matches.to_string(),
+            };
+            (flag.key.clone(), value)
+        })
+        .collect()
+}
+
+/// Single feature flag evaluation endpoint, for clients that only need the value of one flag.
+#[instrument(
+    skip_all,
+    fields(path, token, flag_key, distinct_id, content_type, method)
+)]
+#[debug_handler]
+pub async fn flag(
+    state: State<router::State>,
+    Path(key): Path<String>,
+    meta: Query<FlagsQueryParams>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<FlagResponse>, FlagError> {
+    tracing::Span::current().record("method", method.as_str());
+    tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
+    tracing::Span::current().record("flag_key", &key);
+
+    let (request, team) = decode_and_verify_request(&state, &headers, body).await?;
+    let distinct_id = request.extract_distinct_id()?;
+
+    tracing::Span::current().record("token", &team.api_token);
+    tracing::Span::current().record("distinct_id", &distinct_id);
+
+    // Teams over their quota get no flags, without spending anything on evaluating them
+    if state.billing.is_limited(&team.api_token).await {
+        return Ok(Json(FlagResponse {
+            key,
+            enabled: false,
+            variant: None,
+            payload: None,
+            reason: None,
+            quota_limited: Some(vec![FEATURE_FLAGS_RESOURCE.to_string()]),
+        }));
+    }
+
+    let flags = match FeatureFlagList::from_redis(state.redis.clone(), team.id).await {
+        Ok(list) => list.flags,
+        // Nothing is cached for teams without flags
+        Err(FlagError::TokenValidationError) => vec![],
+        Err(e) => return Err(e),
+    };
+    let flag = flags
+        .into_iter()
+        .find(|flag| flag.key == key && !flag.deleted)
+        .ok_or(FlagError::FlagNotFound)?;
+
+    // Overrides are returned as is, even for disabled flags
+    if let Some(flag_override) = get_overrides(&state, team.id, &distinct_id).await.get(&key) {
+        let flag_match = FeatureFlagMatch::from(flag_override);
+        let payload = if flag_match.matches {
+            flag.get_payload(flag_match.variant.as_deref().unwrap_or("true"))
+        } else {
+            None
+        };
+        return Ok(Json(FlagResponse {
+            key,
+            enabled: flag_match.matches,
+            variant: flag_match.variant,
+            payload,
+            reason: meta.explain().then_some(FeatureFlagEvaluationReason {
+                match_type: FeatureFlagMatchType::Override,
+                condition_index: None,
+                rollout_hash: None,
+            }),
+            quota_limited: None,
+        }));
+    }
+
+    if !flag.active {
+        return Ok(Json(FlagResponse {
+            key,
+            enabled: false,
+            variant: None,
+            payload: None,
+            reason: meta.explain().then_some(FeatureFlagEvaluationReason {
+                match_type: FeatureFlagMatchType::Disabled,
+                condition_index: None,
+                rollout_hash: None,
+            }),
+            quota_limited: None,
+        }));
+    }
+
+    let groups = GroupPropertyStore::new(state.redis.clone())
+        .resolve_groups(team.id, std::slice::from_ref(&flag), &request)
+        .await;
+
+    // Only this endpoint can preview flags leniently, /flags always matches strictly
+    let matcher = FeatureFlagMatcher::new(distinct_id.clone())
+        .with_groups(groups)
+        .with_value_coercion(state.value_coercion)
+        .with_lenient_properties(meta.lenient());
+    let (flag_match, reason) = if meta.explain() {
+        let (flag_match, reason) = matcher.get_match_with_reason(&flag);
+        (flag_match, Some(reason))
+    } else {
+        (matcher.get_match(&flag), None)
+    };
+    let payload = if flag_match.matches {
+        flag.get_payload(flag_match.variant.as_deref().unwrap_or("true"))
+    } else {
+        None
+    };
+
+    if let Some(sink) = &state.flag_called_sink {
+        let response = match &flag_match.variant {
+            Some(variant) if flag_match.matches => variant.clone(),
+            _ => flag_match.matches.to_string(),
+        };
+        let events = vec![FlagCalledEvent::new(&distinct_id, &key, &response)];
+        report_flags_called(sink.clone(), team.api_token, events);
+    }
+
+    Ok(Json(FlagResponse {
+        key,
+        enabled: flag_match.matches,
+        variant: flag_match.variant,
+        payload,
+        reason,
+        quota_limited: None,
     }))
 }
+
+/// Returns the flag overrides set for a distinct_id. Failing to read them shouldn't fail the
+/// request, so flags are evaluated normally in that case.
+async fn get_overrides(
+    state: &router::State,
+    team_id: i64,
+    distinct_id: &str,
+) -> HashMap<String, FlagOverride> {
+    FlagOverrideStore::new(state.redis.clone())
+        .get(team_id, distinct_id)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("failed to read flag overrides: {}", e);
+            HashMap::new()
+        })
+}
+
+/// Decodes a flags request body, and checks its token and origin are valid for the team.
+async fn decode_and_verify_request(
+    state: &router::State,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<(FlagRequest, Team), FlagError> {
+    check_content_type(headers)?;
+    let request = FlagRequest::from_bytes(body)?;
+    let team = verify_team(state, headers, &request).await?;
+
+    Ok((request, team))
+}
+
+/// Only JSON request bodies are supported.
+fn check_content_type(headers: &HeaderMap) -> Result<(), FlagError> {
+    match headers
+        .get("content-type")
+        .map_or("", |v| v.to_str().unwrap_or(""))
+    {
+        "application/json" => {
+            tracing::Span::current().record("content_type", "application/json");
+            Ok(())
+        }
+        ct => Err(FlagError::RequestDecodingError(format!(
+            "unsupported content type: {}",
+            ct
+        ))),
+    }
+}
+
+/// Checks the token and origin of a request are valid for its team, and returns the team.
+async fn verify_team(
+    state: &router::State,
+    headers: &HeaderMap,
+    request: &FlagRequest,
+) -> Result<Team, FlagError> {
+    let team = request
+        .extract_and_verify_team(state.redis.clone(), state.allow_mismatched_tokens)
+        .await?;
+
+    let origin = headers
+        .get("origin")
+        .map(|v| v.to_str().unwrap_or_default());
+    if !team.is_origin_allowed(origin) {
+        return Err(FlagError::OriginNotAllowed);
+    }
+
+    Ok(team)
+}
@@ -11,16 +11,29 @@ use crate::{api::FlagError, redis::Client, team::Team};
 pub struct FlagsQueryParams {
     #[serde(alias = "v")]
     pub version: Option<String>,
+    pub explain: Option<String>,
+    pub lenient: Option<String>,
+}
+
+impl FlagsQueryParams {
+    /// Whether the client asked for the reason of each evaluation, which is off by default.
+    pub fn explain(&self) -> bool {
+        matches!(self.explain.as_deref(), Some("1" | "true"))
+    }
+
+    /// Whether the client asked for properties missing from the request to be non-matches
+    /// instead of errors, to preview a flag against partial properties.
+    pub fn lenient(&self) -> bool {
+        matches!(self.lenient.as_deref(), Some("1" | "true"))
+    }
 }
 
 #[derive(Default, Debug, Deserialize, Serialize)]
 pub struct FlagRequest {
-    #[serde(
-        alias = "$token",
-        alias = "api_key",
-        skip_serializing_if = "Option::is_none"
-    )]
+    #[serde(alias = "$token", skip_serializing_if = "Option::is_none")]
     pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
     #[serde(alias = "$distinct_id", skip_serializing_if = "Option::is_none")]
     pub distinct_id: Option<String>,
     pub geoip_disable: Option<bool>,
@@ -33,6 +46,10 @@ pub struct FlagRequest {
     pub group_properties: Option<HashMap<String, Value>>,
     #[serde(alias = "$anon_distinct_id", skip_serializing_if = "Option::is_none")]
     pub anon_distinct_id: Option<String>,
+    /// The flag values the client already has, for the response to only hold the flags whose
+    /// value changed and the ones removed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_flags: Option<HashMap<String, Value>>,
 }
 
 impl FlagRequest {
@@ -51,36 +68,95 @@ impl FlagRequest {
         Ok(serde_json::from_str::<FlagRequest>(&payload)?)
     }
 
+    /// Returns the request's token, which may be sent as `token` or `api_key`.
+    /// Requests with different values in both are rejected like capture does, unless
+    /// `allow_mismatched_tokens` is set, in which case `token` is used.
+    pub fn extract_token(&self, allow_mismatched_tokens: bool) -> Result<String, FlagError> {
+        match (&self.token, &self.api_key) {
+            (None, None) => Err(FlagError::NoTokenError),
+            (Some(token), Some(api_key)) if token != api_key && !allow_mismatched_tokens => {
+                Err(FlagError::MultipleTokensError)
+            }
+            (Some(token), _) | (None, Some(token)) => Ok(token.to_string()),
+        }
+    }
+
     pub async fn extract_and_verify_token(
         &self,
         redis_client: Arc<dyn Client + Send + Sync>,
+        allow_mismatched_tokens: bool,
     ) -> Result<String, FlagError> {
-        let token = match self {
-            FlagRequest {
-                token: Some(token), ..
-            } => token.to_string(),
-            _ => return Err(FlagError::NoTokenError),
-        };
+        Ok(self
+            .extract_and_verify_team(redis_client, allow_mismatched_tokens)
+            .await?
+            .api_token)
+    }
+
+    /// Validates the request's token, and returns the team it belongs to.
+    pub async fn extract_and_verify_team(
+        &self,
+        redis_client: Arc<dyn Client + Send + Sync>,
+        allow_mismatched_tokens: bool,
+    ) -> Result<Team, FlagError> {
+        let token = self.extract_token(allow_mismatched_tokens)?;
 
         // validate token
-        Team::from_redis(redis_client, token.clone()).await?;
+        let team = Team::from_redis(redis_client, token).await?;
 
         // TODO: fallback when token not found in redis
 
-        Ok(token)
+        Ok(team)
     }
 
     pub fn extract_distinct_id(&self) -> Result<String, FlagError> {
-        let distinct_id = match &self.distinct_id {
-            None => return Err(FlagError::MissingDistinctId),
-            Some(id) => id,
-        };
+        match &self.distinct_id {
+            None => Err(FlagError::MissingDistinctId),
+            Some(id) => validate_distinct_id(id),
+        }
+    }
+}
+
+/// A request to evaluate the flags of many distinct_ids at once, which share the token, groups
+/// and properties of the request.
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct BulkFlagRequest {
+    #[serde(flatten)]
+    pub request: FlagRequest,
+    #[serde(default)]
+    pub distinct_ids: Vec<String>,
+}
 
-        match distinct_id.len() {
-            0 => Err(FlagError::EmptyDistinctId),
-            1..=200 => Ok(distinct_id.to_owned()),
-            _ => Ok(distinct_id.chars().take(200).collect()),
+impl BulkFlagRequest {
+    /// Takes a request payload and tries to read it, like `FlagRequest::from_bytes`.
+    #[instrument(skip_all)]
+    pub fn from_bytes(bytes: Bytes) -> Result<BulkFlagRequest, FlagError> {
+        tracing::debug!(len = bytes.len(), "decoding new bulk request");
+        let payload = String::from_utf8(bytes.into()).map_err(|e| {
+            tracing::error!("failed to decode body: {}", e);
+            FlagError::RequestDecodingError(String::from("invalid body encoding"))
+        })?;
+
+        Ok(serde_json::from_str::<BulkFlagRequest>(&payload)?)
+    }
+
+    pub fn extract_distinct_ids(&self) -> Result<Vec<String>, FlagError> {
+        if self.distinct_ids.is_empty() {
+            return Err(FlagError::MissingDistinctId);
         }
+
+        self.distinct_ids
+            .iter()
+            .map(|distinct_id| validate_distinct_id(distinct_id))
+            .collect()
+    }
+}
+
+/// Rejects empty distinct_ids, and truncates the ones over 200 characters.
+fn validate_distinct_id(distinct_id: &str) -> Result<String, FlagError> {
+    match distinct_id.len() {
+        0 => Err(FlagError::EmptyDistinctId),
+        1..=200 => Ok(distinct_id.to_owned()),
+        _ => Ok(distinct_id.chars().take(200).collect()),
     }
 }
 
@@ -120,6 +196,57 @@ mod tests {
         assert_eq!(flag_payload.extract_distinct_id().unwrap().len(), 200);
     }
 
+    #[test]
+    fn consistent_tokens_are_accepted() {
+        for json in [
+            json!({"token": "my_token1"}),
+            json!({"$token": "my_token1"}),
+            json!({"api_key": "my_token1"}),
+            json!({"token": "my_token1", "api_key": "my_token1"}),
+        ] {
+            let flag_payload = FlagRequest::from_bytes(Bytes::from(json.to_string()))
+                .expect("failed to parse request");
+
+            assert_eq!(flag_payload.extract_token(false).unwrap(), "my_token1");
+        }
+
+        let flag_payload = FlagRequest::from_bytes(Bytes::from(json!({}).to_string()))
+            .expect("failed to parse request");
+        assert!(matches!(
+            flag_payload.extract_token(false),
+            Err(FlagError::NoTokenError)
+        ));
+    }
+
+    #[test]
+    fn mismatched_tokens_are_rejected() {
+        let json = json!({
+            "token": "my_token1",
+            "api_key": "my_token2",
+        });
+        let bytes = Bytes::from(json.to_string());
+
+        let flag_payload = FlagRequest::from_bytes(bytes).expect("failed to parse request");
+
+        assert!(matches!(
+            flag_payload.extract_token(false),
+            Err(FlagError::MultipleTokensError)
+        ));
+    }
+
+    #[test]
+    fn mismatched_tokens_are_allowed_when_relaxed() {
+        let json = json!({
+            "token": "my_token1",
+            "api_key": "my_token2",
+        });
+        let bytes = Bytes::from(json.to_string());
+
+        let flag_payload = FlagRequest::from_bytes(bytes).expect("failed to parse request");
+
+        assert_eq!(flag_payload.extract_token(true).unwrap(), "my_token1");
+    }
+
     #[test]
     fn distinct_id_is_returned_correctly() {
         let json = json!({
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use assert_json_diff::assert_json_include;
 
@@ -6,7 +8,12 @@ use serde_json::{json, Value};
 
 use crate::common::*;
 
-use feature_flags::test_utils::{insert_new_team_in_redis, setup_redis_client};
+use feature_flags::flag_overrides::{FlagOverride, FlagOverrideStore};
+use feature_flags::team::Team;
+use feature_flags::test_utils::{
+    insert_flags_for_team_in_redis, insert_new_team_in_redis, insert_quota_limited_token_in_redis,
+    insert_team_in_redis, random_string, setup_redis_client,
+};
 
 pub mod common;
 
@@ -19,6 +26,26 @@ async fn it_sends_flag_request() -> Result<()> {
     let client = setup_redis_client(Some(config.redis_url.clone()));
     let team = insert_new_team_in_redis(client.clone()).await.unwrap();
     let token = team.api_token;
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "beta-feature",
+            "active": true,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+                "multivariate": {"variants": [{"key": "variant-1", "rollout_percentage": 100}]},
+            },
+        },
+        {
+            "id": 2,
+            "key": "rollout-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
 
     let server = ServerHandle::for_config(config).await;
 
@@ -48,6 +75,235 @@ async fn it_sends_flag_request() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn it_evaluates_flags_of_teams_under_quota() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    // Only other teams are over their quota
+    insert_quota_limited_token_in_redis(client.clone(), &random_string("phc_", 12)).await?;
+    let flags = json!([{
+        "id": 1,
+        "key": "rollout-flag",
+        "active": true,
+        "team_id": team.id,
+        "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+    }]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {
+                "rollout-flag": "true",
+            },
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_returns_no_flags_for_teams_over_quota() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    insert_quota_limited_token_in_redis(client.clone(), &team.api_token).await?;
+    insert_flags_for_team_in_redis(client.clone(), team.id, None).await?;
+
+    let server = ServerHandle::for_config(config).await;
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {},
+            "quotaLimited": ["feature_flag_requests"],
+        })
+    );
+
+    // Single and bulk flag requests are limited too
+    let res = server
+        .send_flag_request("rollout-flag", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "key": "rollout-flag",
+            "enabled": false,
+            "variant": null,
+            "payload": null,
+            "quotaLimited": ["feature_flag_requests"],
+        })
+    );
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_ids": ["user_1", "user_2"],
+    });
+    let res = server
+        .send_bulk_flags_request(payload.to_string(), "application/json")
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "results": [
+                {
+                    "distinctId": "user_1",
+                    "errorWhileComputingFlags": false,
+                    "featureFlags": {},
+                    "quotaLimited": ["feature_flag_requests"],
+                },
+                {
+                    "distinctId": "user_2",
+                    "errorWhileComputingFlags": false,
+                    "featureFlags": {},
+                    "quotaLimited": ["feature_flag_requests"],
+                },
+            ]
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_returns_the_flags_changed_from_the_known_ones() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "beta-feature",
+            "active": true,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+                "multivariate": {"variants": [{"key": "variant-1", "rollout_percentage": 100}]},
+            },
+        },
+        {
+            "id": 2,
+            "key": "rollout-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+
+    // beta-feature changed variant, rollout-flag is unchanged and old-flag was removed
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+        "known_flags": {
+            "beta-feature": "variant-0",
+            "rollout-flag": true,
+            "old-flag": "true",
+        },
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {
+                "beta-feature": "variant-1",
+            },
+            "removedFlags": ["old-flag"],
+        })
+    );
+
+    // Without known flags, all of them are returned
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {
+                "beta-feature": "variant-1",
+                "rollout-flag": "true",
+            },
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_returns_partial_results_for_corrupt_definitions() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "good-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+        },
+        {
+            "id": 2,
+            "key": "corrupt-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"rollout_percentage": "all of them"}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "errorWhileComputingFlags": true,
+            "featureFlags": {
+                "good-flag": "true",
+            }
+        })
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn it_rejects_invalid_headers_flag_request() -> Result<()> {
     let config = DEFAULT_CONFIG.clone();
@@ -81,3 +337,464 @@ async fn it_rejects_invalid_headers_flag_request() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_serves_flag_requests_over_tls_until_shutdown() -> Result<()> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    let dir = std::env::temp_dir().join(format!("feature-flags-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, cert.serialize_pem()?)?;
+    std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+
+    let mut config = DEFAULT_CONFIG.clone();
+    config.tls_cert_path = Some(cert_path.to_string_lossy().to_string());
+    config.tls_key_path = Some(key_path.to_string_lossy().to_string());
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let token = team.api_token;
+    let flags = json!([{
+        "id": 1,
+        "key": "rollout-flag",
+        "active": true,
+        "team_id": team.id,
+        "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+    }]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+    let flags_url = format!("https://localhost:{}/flags", server.addr.port());
+    let https_client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+
+    let payload = json!({
+        "token": token,
+        "distinct_id": "user_distinct_id",
+    });
+    let res = https_client
+        .post(&flags_url)
+        .body(payload.to_string())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .send()
+        .await?;
+    assert_eq!(StatusCode::OK, res.status());
+
+    let json_data = res.json::<Value>().await?;
+    assert_json_include!(
+        actual: json_data,
+        expected: json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {
+                "rollout-flag": "true",
+            }
+        })
+    );
+
+    // Dropping the handle sends the shutdown signal
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(https_client
+        .post(&flags_url)
+        .body(payload.to_string())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .send()
+        .await
+        .is_err());
+
+    std::fs::remove_dir_all(&dir)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_validates_request_origin() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_team_in_redis(
+        client.clone(),
+        Team {
+            id: i64::from(rand::random::<u32>()),
+            name: "team".to_string(),
+            api_token: random_string("phc_", 12),
+            allowed_origins: vec!["https://app.example.com".to_string()],
+        },
+    )
+    .await
+    .unwrap();
+
+    let server = ServerHandle::for_config(config).await;
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+
+    // Allowed origin
+    let res = server
+        .send_flags_request_with_origin(payload.to_string(), "https://app.example.com")
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    // Disallowed origin
+    let res = server
+        .send_flags_request_with_origin(payload.to_string(), "https://evil.example.com")
+        .await;
+    assert_eq!(StatusCode::FORBIDDEN, res.status());
+    assert_eq!(res.text().await?, "Origin is not allowed for this API key");
+
+    // No origin, i.e. a server-side request
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_evaluates_a_single_flag() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "enabled-flag",
+            "name": "enabled flag",
+            "active": true,
+            "deleted": false,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+                "payloads": {"true": {"color": "blue"}},
+            },
+        },
+        {
+            "id": 2,
+            "key": "disabled-flag",
+            "name": "disabled flag",
+            "active": false,
+            "deleted": false,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+            },
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+    });
+
+    let res = server
+        .send_flag_request("enabled-flag", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "key": "enabled-flag",
+            "enabled": true,
+            "variant": null,
+            "payload": {"color": "blue"},
+        })
+    );
+
+    let res = server
+        .send_flag_request("disabled-flag", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "key": "disabled-flag",
+            "enabled": false,
+            "variant": null,
+            "payload": null,
+        })
+    );
+
+    let res = server
+        .send_flag_request("missing-flag", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::NOT_FOUND, res.status());
+    assert_eq!(res.text().await?, "Flag not found");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_returns_flag_overrides() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "overridden-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+                "multivariate": {"variants": [
+                    {"key": "control", "rollout_percentage": 100},
+                    {"key": "test", "rollout_percentage": 0},
+                ]},
+                "payloads": {"test": {"color": "red"}},
+            },
+        },
+        {
+            "id": 2,
+            "key": "rollout-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 100}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let distinct_id = random_string("qa_user_", 8);
+    FlagOverrideStore::new(client.clone())
+        .set(
+            team.id,
+            &distinct_id,
+            &[(
+                "overridden-flag".to_string(),
+                FlagOverride::Variant("test".to_string()),
+            )]
+            .into(),
+            std::time::Duration::from_secs(60),
+        )
+        .await?;
+
+    let server = ServerHandle::for_config(config).await;
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": distinct_id,
+    });
+
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_json_include!(
+        actual: res.json::<Value>().await?,
+        expected: json!({
+            "errorWhileComputingFlags": false,
+            "featureFlags": {
+                "overridden-flag": "test",
+                "rollout-flag": "true",
+            }
+        })
+    );
+
+    let res = server
+        .send_flag_request("overridden-flag?explain=1", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "key": "overridden-flag",
+            "enabled": true,
+            "variant": "test",
+            "payload": {"color": "red"},
+            "reason": {"match_type": "override", "condition_index": null, "rollout_hash": null},
+        })
+    );
+
+    // Flags without an override are evaluated normally
+    let res = server
+        .send_flag_request("rollout-flag?explain=1", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.json::<Value>().await?,
+        json!({
+            "key": "rollout-flag",
+            "enabled": true,
+            "variant": null,
+            "payload": null,
+            "reason": {"match_type": "condition_match", "condition_index": 0, "rollout_hash": null},
+        })
+    );
+
+    // Overrides are only returned for the distinct_id they were set for
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "other_user",
+    });
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_json_include!(
+        actual: res.json::<Value>().await?,
+        expected: json!({
+            "featureFlags": {
+                "overridden-flag": "control",
+            }
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_streams_bulk_flag_results_as_ndjson() -> Result<()> {
+    let config = DEFAULT_CONFIG.clone();
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "rollout-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 50}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+    let distinct_ids: Vec<String> = (0..50).map(|i| format!("user_{i}")).collect();
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_ids": distinct_ids,
+    });
+
+    let res = server
+        .send_bulk_flags_request(payload.to_string(), "application/x-ndjson")
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(
+        res.headers()["content-type"].to_str()?,
+        "application/x-ndjson"
+    );
+
+    let body = res.text().await?;
+    let lines: Vec<Value> = body
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(lines.len(), distinct_ids.len());
+    for (line, distinct_id) in lines.iter().zip(&distinct_ids) {
+        assert_eq!(line["distinctId"], json!(distinct_id));
+        assert_eq!(line["errorWhileComputingFlags"], json!(false));
+        assert!(matches!(
+            line["featureFlags"]["rollout-flag"].as_str(),
+            Some("true" | "false")
+        ));
+    }
+
+    // Clients that don't accept ndjson get the same results in a single JSON document
+    let res = server
+        .send_bulk_flags_request(payload.to_string(), "application/json")
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(res.json::<Value>().await?, json!({"results": lines}));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_reports_feature_flag_called_events() -> Result<()> {
+    // Stand in for capture, forwarding every /batch request body
+    let (batches, mut received) = tokio::sync::mpsc::unbounded_channel::<Value>();
+    let capture = axum::Router::new().route(
+        "/batch",
+        axum::routing::post(move |axum::Json(body): axum::Json<Value>| async move {
+            batches.send(body).unwrap();
+        }),
+    );
+    let capture_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let capture_addr = capture_listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(capture_listener, capture).await });
+
+    let mut config = DEFAULT_CONFIG.clone();
+    config.capture_url = Some(format!("http://{}", capture_addr));
+
+    let client = setup_redis_client(Some(config.redis_url.clone()));
+    let team = insert_new_team_in_redis(client.clone()).await.unwrap();
+    let token = team.api_token;
+    let flags = json!([
+        {
+            "id": 1,
+            "key": "beta-feature",
+            "active": true,
+            "team_id": team.id,
+            "filters": {
+                "groups": [{"properties": [], "rollout_percentage": 100}],
+                "multivariate": {"variants": [{"key": "variant-1", "rollout_percentage": 100}]},
+            },
+        },
+        {
+            "id": 2,
+            "key": "rollout-flag",
+            "active": true,
+            "team_id": team.id,
+            "filters": {"groups": [{"properties": [], "rollout_percentage": 0}]},
+        },
+    ]);
+    insert_flags_for_team_in_redis(client.clone(), team.id, Some(flags.to_string())).await?;
+
+    let server = ServerHandle::for_config(config).await;
+    let payload = json!({"token": token, "distinct_id": "user_distinct_id"});
+
+    let res = server.send_flags_request(payload.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    let batch = tokio::time::timeout(std::time::Duration::from_secs(5), received.recv())
+        .await?
+        .expect("no events were reported");
+    assert_eq!(batch["api_key"], json!(token));
+    let mut events = batch["batch"].as_array().expect("batch is a list").clone();
+    events.sort_by_key(|event| event["properties"]["$feature_flag"].to_string());
+    assert_eq!(
+        events,
+        vec![
+            json!({
+                "event": "$feature_flag_called",
+                "distinct_id": "user_distinct_id",
+                "properties": {
+                    "$feature_flag": "beta-feature",
+                    "$feature_flag_response": "variant-1",
+                },
+            }),
+            json!({
+                "event": "$feature_flag_called",
+                "distinct_id": "user_distinct_id",
+                "properties": {
+                    "$feature_flag": "rollout-flag",
+                    "$feature_flag_response": false,
+                },
+            }),
+        ]
+    );
+
+    // The single flag endpoint reports the flag it evaluated
+    let res = server
+        .send_flag_request("beta-feature", payload.to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    let batch = tokio::time::timeout(std::time::Duration::from_secs(5), received.recv())
+        .await?
+        .expect("no events were reported");
+    assert_eq!(
+        batch["batch"],
+        json!([{
+            "event": "$feature_flag_called",
+            "distinct_id": "user_distinct_id",
+            "properties": {
+                "$feature_flag": "beta-feature",
+                "$feature_flag_response": "variant-1",
+            },
+        }])
+    );
+
+    Ok(())
+}
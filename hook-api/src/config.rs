@@ -1,4 +1,5 @@
 use envconfig::Envconfig;
+use hook_common::config::{check_database_url, check_not_zero, ConfigError, KafkaTopics};
 
 #[derive(Envconfig)]
 pub struct Config {
@@ -17,12 +18,113 @@ pub struct Config {
     #[envconfig(default = "100")]
     pub max_pg_connections: u32,
 
+    // Number of processes with a pool of MAX_PG_CONNECTIONS connecting to the database, checked
+    // against its max_connections at startup. Going over only logs a warning, unless strict.
+    #[envconfig(default = "1")]
+    pub pg_connection_consumers: u32,
+
+    #[envconfig(default = "false")]
+    pub pg_connections_strict: bool,
+
     #[envconfig(default = "5000000")]
     pub max_body_size: usize,
+
+    // Should match the settings of the workers, so previews reflect the requests they send
+    #[envconfig(default = "false")]
+    pub allow_internal_ips: bool,
+
+    #[envconfig(default = "false")]
+    pub body_transform_null_as_object: bool,
+
+    // Comma-separated Kafka topics that jobs with a kafka://topic URL may target, jobs targeting
+    // any other topic are rejected. Should match the setting of the workers.
+    #[envconfig(default = "")]
+    pub kafka_webhook_topics: KafkaTopics,
+
+    // Jobs with more headers than MAX_HEADER_COUNT, or whose header names and values add up to
+    // more than MAX_HEADER_BYTES either as sent or once their templates are rendered, are
+    // rejected. Should match the settings of the workers.
+    #[envconfig(default = "100")]
+    pub max_header_count: usize,
+
+    #[envconfig(default = "65536")]
+    pub max_header_bytes: usize,
+
+    // Bearer token required to call the admin routes, which are disabled if unset
+    pub admin_token: Option<String>,
 }
 
 impl Config {
     pub fn bind(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        check_database_url("DATABASE_URL", &self.database_url, &mut problems);
+        if self.queue_name.is_empty() {
+            problems.push("QUEUE_NAME must not be empty".to_owned());
+        }
+        check_not_zero("MAX_PG_CONNECTIONS", self.max_pg_connections, &mut problems);
+        check_not_zero(
+            "PG_CONNECTION_CONSUMERS",
+            self.pg_connection_consumers,
+            &mut problems,
+        );
+        check_not_zero("MAX_BODY_SIZE", self.max_body_size, &mut problems);
+        if self.admin_token.as_deref() == Some("") {
+            problems.push(
+                "ADMIN_TOKEN must not be empty, unset it to disable the admin routes".to_owned(),
+            );
+        }
+
+        ConfigError::check(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::init_from_hashmap(&vars).expect("failed to parse config")
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(config(&[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_config_reports_every_problem() {
+        let error = config(&[
+            ("DATABASE_URL", "mysql://localhost/posthog"),
+            ("QUEUE_NAME", ""),
+            ("MAX_PG_CONNECTIONS", "0"),
+            ("MAX_BODY_SIZE", "0"),
+            ("ADMIN_TOKEN", ""),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+
+        let message = error.to_string();
+        for setting in [
+            "DATABASE_URL",
+            "QUEUE_NAME",
+            "MAX_PG_CONNECTIONS",
+            "MAX_BODY_SIZE",
+            "ADMIN_TOKEN",
+        ] {
+            assert!(message.contains(setting), "{} is not reported", setting);
+        }
+        assert_eq!(error.0.len(), 5);
+    }
 }
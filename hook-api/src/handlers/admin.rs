@@ -0,0 +1,312 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use serde::Serialize;
+use serde_derive::Deserialize;
+use tracing::{error, info};
+
+use hook_common::pgqueue::{PendingJobsFilter, PgQueue};
+use hook_common::webhook::WebhookJobError;
+
+#[derive(Clone)]
+pub struct AdminState {
+    pub pg_queue: PgQueue,
+    pub admin_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FailJobsResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// The body of a request made to fail all the pending jobs of a target host or a team.
+/// Exactly one of `target` and `team_id` must be set.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct FailJobsRequestBody {
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    team_id: Option<u32>,
+    reason: String,
+}
+
+pub async fn fail(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(payload): Json<FailJobsRequestBody>,
+) -> Result<Json<FailJobsResponse>, (StatusCode, Json<FailJobsResponse>)> {
+    if !is_authorized(&headers, &state.admin_token) {
+        return Err(error_response(
+            StatusCode::UNAUTHORIZED,
+            "invalid admin token",
+        ));
+    }
+
+    let filter = match (payload.target, payload.team_id) {
+        (Some(target), None) if !target.is_empty() => PendingJobsFilter::Target(target),
+        (None, Some(team_id)) => PendingJobsFilter::TeamId(team_id),
+        _ => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "exactly one of target or team_id must be set",
+            ))
+        }
+    };
+
+    let failed = state
+        .pg_queue
+        .fail_pending_jobs(&filter, WebhookJobError::new_cancelled(&payload.reason))
+        .await
+        .map_err(|e| {
+            error!("failed to fail pending jobs: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+        })?;
+
+    info!(
+        "failed {} pending jobs matching {:?}: {}",
+        failed, filter, payload.reason
+    );
+
+    Ok(Json(FailJobsResponse {
+        failed: Some(failed),
+        error: None,
+    }))
+}
+
+fn error_response(status: StatusCode, message: &str) -> (StatusCode, Json<FailJobsResponse>) {
+    (
+        status,
+        Json(FailJobsResponse {
+            failed: None,
+            error: Some(message.to_owned()),
+        }),
+    )
+}
+
+/// Check the request carries the admin token as a bearer token.
+pub(super) fn is_authorized(headers: &HeaderMap, admin_token: &str) -> bool {
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) => constant_time_eq(provided.as_bytes(), admin_token.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compare two byte slices without returning early on the first difference, to avoid leaking
+/// how much of the admin token was guessed right through response timings.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{
+        body::Body,
+        http::{self, Request},
+        Router,
+    };
+    use hook_common::pgqueue::NewJob;
+    use hook_common::webhook::{HttpMethod, WebhookJobMetadata, WebhookJobParameters};
+    use http_body_util::BodyExt; // for `collect`
+    use sqlx::PgPool;
+    use std::collections;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::handlers::app::add_routes;
+    use crate::handlers::webhook::EnqueueOptions;
+    use hook_common::preview::PreviewOptions;
+
+    const ADMIN_TOKEN: &str = "admin-token";
+
+    async fn enqueue_job(pg_queue: &PgQueue, target: &str, team_id: u32) {
+        let job = NewJob::new(
+            3,
+            WebhookJobMetadata {
+                team_id,
+                plugin_id: 2,
+                plugin_config_id: 3,
+            },
+            WebhookJobParameters {
+                headers: collections::HashMap::new(),
+                method: HttpMethod::POST,
+                url: format!("http://{}/", target),
+                body: r#"{"a": "b"}"#.to_owned(),
+                body_transform: None,
+            },
+            target,
+        );
+        pg_queue.enqueue(job).await.expect("failed to enqueue job");
+    }
+
+    async fn count_failed(db: &PgPool, target: &str) -> i64 {
+        sqlx::query_scalar(
+            "SELECT count(*) FROM job_queue WHERE status = 'failed'::job_status AND target = $1",
+        )
+        .bind(target)
+        .fetch_one(db)
+        .await
+        .expect("failed to count failed jobs")
+    }
+
+    fn fail_request(token: Option<&str>, body: serde_json::Value) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method(http::Method::POST)
+            .uri("/admin/fail")
+            .header(http::header::CONTENT_TYPE, "application/json");
+        if let Some(token) = token {
+            builder = builder.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn admin_fail_jobs_for_target(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_admin", db.clone()).await;
+        enqueue_job(&pg_queue, "gone.example.com", 1).await;
+        enqueue_job(&pg_queue, "gone.example.com", 2).await;
+        enqueue_job(&pg_queue, "alive.example.com", 1).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            Some(ADMIN_TOKEN.to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .oneshot(fail_request(
+                Some(ADMIN_TOKEN),
+                serde_json::json!({"target": "gone.example.com", "reason": "domain sold"}),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], br#"{"failed":2}"#);
+
+        assert_eq!(count_failed(&db, "gone.example.com").await, 2);
+        assert_eq!(count_failed(&db, "alive.example.com").await, 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn admin_fail_jobs_for_team(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_admin", db.clone()).await;
+        enqueue_job(&pg_queue, "one.example.com", 1).await;
+        enqueue_job(&pg_queue, "two.example.com", 2).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            Some(ADMIN_TOKEN.to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .oneshot(fail_request(
+                Some(ADMIN_TOKEN),
+                serde_json::json!({"team_id": 2, "reason": "team deleted"}),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(count_failed(&db, "one.example.com").await, 0);
+        assert_eq!(count_failed(&db, "two.example.com").await, 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn admin_fail_requires_token(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_admin", db.clone()).await;
+        enqueue_job(&pg_queue, "gone.example.com", 1).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            Some(ADMIN_TOKEN.to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+        let body = serde_json::json!({"target": "gone.example.com", "reason": "domain sold"});
+
+        let response = app
+            .clone()
+            .oneshot(fail_request(None, body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(fail_request(Some("wrong-token"), body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        assert_eq!(count_failed(&db, "gone.example.com").await, 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn admin_fail_rejects_ambiguous_filter(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_admin", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            Some(ADMIN_TOKEN.to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .oneshot(fail_request(
+                Some(ADMIN_TOKEN),
+                serde_json::json!({"target": "gone.example.com", "team_id": 1, "reason": "?"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn admin_route_disabled_without_token(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_admin", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .oneshot(fail_request(
+                Some(ADMIN_TOKEN),
+                serde_json::json!({"target": "gone.example.com", "reason": "domain sold"}),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -2,20 +2,58 @@ use axum::{routing, Router};
 use tower_http::limit::RequestBodyLimitLayer;
 
 use hook_common::pgqueue::PgQueue;
+use hook_common::preview::PreviewOptions;
 
-use super::webhook;
+use super::admin::{self, AdminState};
+use super::webhook::{self, EnqueueOptions, EnqueueState};
 
-pub fn add_routes(router: Router, pg_pool: PgQueue, max_body_size: usize) -> Router {
-    router
+pub fn add_routes(
+    router: Router,
+    pg_pool: PgQueue,
+    max_body_size: usize,
+    admin_token: Option<String>,
+    preview_options: PreviewOptions,
+    enqueue_options: EnqueueOptions,
+) -> Router {
+    let router = router
         .route("/", routing::get(index))
         .route("/_readiness", routing::get(index))
         .route("/_liveness", routing::get(index)) // No async loop for now, just check axum health
         .route(
             "/webhook",
             routing::post(webhook::post)
-                .with_state(pg_pool)
+                .with_state(EnqueueState {
+                    pg_queue: pg_pool.clone(),
+                    options: enqueue_options,
+                })
                 .layer(RequestBodyLimitLayer::new(max_body_size)),
         )
+        .route(
+            "/webhook/preview",
+            routing::post(webhook::preview)
+                .with_state(preview_options)
+                .layer(RequestBodyLimitLayer::new(max_body_size)),
+        );
+
+    // Admin routes, and the jobs they inspect, are only exposed when an admin token is configured
+    match admin_token {
+        Some(admin_token) => {
+            let admin_state = AdminState {
+                pg_queue: pg_pool,
+                admin_token,
+            };
+            router
+                .route(
+                    "/webhook/:id",
+                    routing::get(webhook::get).with_state(admin_state.clone()),
+                )
+                .route(
+                    "/admin/fail",
+                    routing::post(admin::fail).with_state(admin_state),
+                )
+        }
+        None => router,
+    }
 }
 
 pub async fn index() -> &'static str {
@@ -38,7 +76,14 @@ mod tests {
     async fn index(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, 1_000_000);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            1_000_000,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let response = app
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
@@ -1,4 +1,6 @@
+mod admin;
 mod app;
 mod webhook;
 
 pub use app::add_routes;
+pub use webhook::EnqueueOptions;
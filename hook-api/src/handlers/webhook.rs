@@ -1,14 +1,24 @@
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Instant;
 
-use axum::{extract::State, http::StatusCode, Json};
-use hook_common::webhook::{WebhookJobMetadata, WebhookJobParameters};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use hook_common::webhook::{kafka_topic, WebhookJobMetadata, WebhookJobParameters};
 use serde_derive::Deserialize;
 use url::Url;
 
-use hook_common::pgqueue::{NewJob, PgQueue};
+use hook_common::pgqueue::{JobAttempt, JobRecord, JobStatus, NewJob, PgQueue};
+use hook_common::preview::{preview_webhook, PreviewOptions, WebhookPreview};
+use hook_common::request::{parse_headers, HeaderLimits};
 use serde::Serialize;
 use tracing::{debug, error};
 
+use super::admin::{is_authorized, AdminState};
+
 #[derive(Serialize, Deserialize)]
 pub struct WebhookPostResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,12 +39,54 @@ fn default_max_attempts() -> u32 {
     3
 }
 
+/// The checks webhook jobs must pass to be enqueued, which should match the settings of the
+/// workers so that jobs they would fail right away are rejected instead.
+#[derive(Clone, Default)]
+pub struct EnqueueOptions {
+    /// The topics that jobs with a `kafka://topic` URL may target, none unless set.
+    pub kafka_topics: Arc<HashSet<String>>,
+    /// The limits the headers of jobs must be within, both as sent and once their templates are
+    /// rendered against the body.
+    pub header_limits: HeaderLimits,
+}
+
+#[derive(Clone)]
+pub struct EnqueueState {
+    pub pg_queue: PgQueue,
+    pub options: EnqueueOptions,
+}
+
 pub async fn post(
-    State(pg_queue): State<PgQueue>,
+    State(state): State<EnqueueState>,
     Json(payload): Json<WebhookPostRequestBody>,
 ) -> Result<Json<WebhookPostResponse>, (StatusCode, Json<WebhookPostResponse>)> {
     debug!("received payload: {:?}", payload);
 
+    if let Some(topic) = kafka_topic(&payload.parameters.url) {
+        if !state.options.kafka_topics.contains(topic) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(WebhookPostResponse {
+                    error: Some(format!("kafka topic {:?} is not allowed", topic)),
+                }),
+            ));
+        }
+    }
+
+    let header_limits = state.options.header_limits;
+    header_limits
+        .check(&payload.parameters.headers)
+        .and_then(|()| parse_headers(&payload.parameters.headers, &payload.parameters.body))
+        .and_then(|headers| header_limits.check_rendered(&headers))
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(WebhookPostResponse {
+                    error: Some(e.to_string()),
+                }),
+            )
+        })?;
+
     let url_hostname = get_hostname(&payload.parameters.url)?;
     // We could cast to i32, but this ensures we are not wrapping.
     let max_attempts = i32::try_from(payload.max_attempts).map_err(|_| {
@@ -54,7 +106,7 @@ pub async fn post(
 
     let start_time = Instant::now();
 
-    pg_queue.enqueue(job).await.map_err(internal_error)?;
+    state.pg_queue.enqueue(job).await.map_err(internal_error)?;
 
     let elapsed_time = start_time.elapsed().as_secs_f64();
     metrics::histogram!("webhook_api_enqueue").record(elapsed_time);
@@ -62,6 +114,100 @@ pub async fn post(
     Ok(Json(WebhookPostResponse { error: None }))
 }
 
+/// The current state of a webhook job, along with the history of its attempts.
+/// The job parameters are left out, as their headers and body may hold credentials.
+#[derive(Serialize, Debug)]
+pub struct WebhookJobResponse {
+    id: i64,
+    status: JobStatus,
+    queue: String,
+    target: String,
+    metadata: WebhookJobMetadata,
+    attempt: i32,
+    max_attempts: i32,
+    created_at: chrono::DateTime<chrono::offset::Utc>,
+    scheduled_at: chrono::DateTime<chrono::offset::Utc>,
+    last_attempt_finished_at: Option<chrono::DateTime<chrono::offset::Utc>>,
+    attempts: Vec<JobAttempt>,
+}
+
+impl From<JobRecord<WebhookJobParameters, WebhookJobMetadata>> for WebhookJobResponse {
+    fn from(record: JobRecord<WebhookJobParameters, WebhookJobMetadata>) -> Self {
+        Self {
+            id: record.id,
+            status: record.status,
+            queue: record.queue,
+            target: record.target,
+            metadata: record.metadata.0,
+            attempt: record.attempt,
+            max_attempts: record.max_attempts,
+            created_at: record.created_at,
+            scheduled_at: record.scheduled_at,
+            last_attempt_finished_at: record.last_attempt_finished_at,
+            attempts: record
+                .attempts
+                .into_iter()
+                .map(|attempt| attempt.0)
+                .collect(),
+        }
+    }
+}
+
+/// Return the state of a webhook job, to callers carrying the admin token as a bearer token.
+pub async fn get(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<WebhookJobResponse>, (StatusCode, Json<WebhookPostResponse>)> {
+    if !is_authorized(&headers, &state.admin_token) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(WebhookPostResponse {
+                error: Some("invalid admin token".to_owned()),
+            }),
+        ));
+    }
+
+    let record: Option<JobRecord<WebhookJobParameters, WebhookJobMetadata>> =
+        state.pg_queue.get_job(id).await.map_err(internal_error)?;
+
+    match record {
+        Some(record) => Ok(Json(WebhookJobResponse::from(record))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(WebhookPostResponse {
+                error: Some("webhook job not found".to_owned()),
+            }),
+        )),
+    }
+}
+
+/// The body of a request made to preview the HTTP request a webhook job would make.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct WebhookPreviewRequestBody {
+    parameters: WebhookJobParameters,
+}
+
+pub async fn preview(
+    State(options): State<PreviewOptions>,
+    Json(payload): Json<WebhookPreviewRequestBody>,
+) -> Result<Json<WebhookPreview>, (StatusCode, Json<WebhookPostResponse>)> {
+    debug!("received preview payload: {:?}", payload);
+
+    let preview = preview_webhook(&payload.parameters, options)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(WebhookPostResponse {
+                    error: Some(e.to_string()),
+                }),
+            )
+        })?;
+
+    Ok(Json(preview))
+}
+
 fn internal_error<E>(err: E) -> (StatusCode, Json<WebhookPostResponse>)
 where
     E: std::error::Error,
@@ -105,8 +251,8 @@ mod tests {
         http::{self, Request, StatusCode},
         Router,
     };
-    use hook_common::pgqueue::PgQueue;
-    use hook_common::webhook::{HttpMethod, WebhookJobParameters};
+    use hook_common::pgqueue::{PgQueue, PgQueueJob, PgTransactionBatch};
+    use hook_common::webhook::{HttpMethod, WebhookJobError, WebhookJobParameters};
     use http_body_util::BodyExt;
     use sqlx::PgPool; // for `collect`
     use std::collections;
@@ -120,7 +266,14 @@ mod tests {
     async fn webhook_success(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let mut headers = collections::HashMap::new();
         headers.insert("Content-Type".to_owned(), "application/json".to_owned());
@@ -137,6 +290,7 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "http://example.com/".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
+                                body_transform: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -162,7 +316,14 @@ mod tests {
     async fn webhook_bad_url(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let response = app
             .oneshot(
@@ -177,6 +338,7 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "invalid".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
+                                body_transform: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -195,11 +357,138 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_kafka_topic_must_be_allowed(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions {
+                kafka_topics: Arc::new(HashSet::from(["webhooks".to_owned()])),
+                ..EnqueueOptions::default()
+            },
+        );
+
+        for (url, expected_status) in [
+            ("kafka://webhooks", StatusCode::OK),
+            ("kafka://events_plugin_ingestion", StatusCode::BAD_REQUEST),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::POST)
+                        .uri("/webhook")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&WebhookPostRequestBody {
+                                parameters: WebhookJobParameters {
+                                    headers: collections::HashMap::new(),
+                                    method: HttpMethod::POST,
+                                    url: url.to_owned(),
+                                    body: r#"{"a": "b"}"#.to_owned(),
+                                    body_transform: None,
+                                },
+                                metadata: WebhookJobMetadata {
+                                    team_id: 1,
+                                    plugin_id: 2,
+                                    plugin_config_id: 3,
+                                },
+                                max_attempts: 1,
+                            })
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), expected_status, "{}", url);
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_headers_must_be_within_limits(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions {
+                header_limits: HeaderLimits {
+                    max_count: 1,
+                    max_bytes: 64,
+                },
+                ..EnqueueOptions::default()
+            },
+        );
+        let body = format!(r#"{{"event": "{}"}}"#, "a".repeat(64));
+
+        for (headers, expected_status) in [
+            (vec![("X-One", "1")], StatusCode::OK),
+            (
+                vec![("X-One", "1"), ("X-Two", "2")],
+                StatusCode::BAD_REQUEST,
+            ),
+            // Within the limits as sent, but not once the event is rendered into it.
+            (vec![("X-Event", "{{ @ }}")], StatusCode::BAD_REQUEST),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::POST)
+                        .uri("/webhook")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&WebhookPostRequestBody {
+                                parameters: WebhookJobParameters {
+                                    headers: headers
+                                        .iter()
+                                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                                        .collect(),
+                                    method: HttpMethod::POST,
+                                    url: "http://example.com/".to_owned(),
+                                    body: body.clone(),
+                                    body_transform: None,
+                                },
+                                metadata: WebhookJobMetadata {
+                                    team_id: 1,
+                                    plugin_id: 2,
+                                    plugin_config_id: 3,
+                                },
+                                max_attempts: 1,
+                            })
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), expected_status, "{:?}", headers);
+        }
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn webhook_payload_missing_fields(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let response = app
             .oneshot(
@@ -220,7 +509,14 @@ mod tests {
     async fn webhook_payload_not_json(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let response = app
             .oneshot(
@@ -241,7 +537,14 @@ mod tests {
     async fn webhook_payload_body_too_large(db: PgPool) {
         let pg_queue = PgQueue::new_from_pool("test_index", db).await;
 
-        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE);
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
 
         let bytes: Vec<u8> = vec![b'a'; MAX_BODY_SIZE + 1];
         let long_string = String::from_utf8_lossy(&bytes);
@@ -259,6 +562,7 @@ mod tests {
                                 method: HttpMethod::POST,
                                 url: "http://example.com".to_owned(),
                                 body: long_string.to_string(),
+                                body_transform: None,
                             },
                             metadata: WebhookJobMetadata {
                                 team_id: 1,
@@ -276,4 +580,211 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
     }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_get_returns_attempt_history(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let job = NewJob::new(
+            3,
+            WebhookJobMetadata {
+                team_id: 1,
+                plugin_id: 2,
+                plugin_config_id: 3,
+            },
+            WebhookJobParameters {
+                headers: collections::HashMap::new(),
+                method: HttpMethod::POST,
+                url: "http://example.com/".to_owned(),
+                body: r#"{"a": "b"}"#.to_owned(),
+                body_transform: None,
+            },
+            "example.com",
+        );
+        pg_queue.enqueue(job).await.expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, WebhookJobMetadata> = pg_queue
+            .dequeue_tx("worker", 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job = batch.jobs.pop().unwrap();
+        let job_id = job.job.id;
+        job.retry(
+            WebhookJobError::new_http_status(500, "Internal Server Error"),
+            std::time::Duration::from_secs(0),
+            "test_index",
+        )
+        .await
+        .expect("failed to retry job");
+        batch.commit().await.expect("failed to commit transaction");
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            Some("admin-token".to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .clone()
+            .oneshot(get_request(job_id, Some("admin-token")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(job["id"], job_id);
+        assert_eq!(job["status"], "available");
+        assert_eq!(job["attempt"], 1);
+        assert_eq!(job["max_attempts"], 3);
+        assert_eq!(job["target"], "example.com");
+        assert!(job.get("parameters").is_none());
+
+        let attempts = job["attempts"].as_array().unwrap();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0]["attempt"], 1);
+        assert_eq!(attempts[0]["status"], "retried");
+        assert_eq!(
+            attempts[0]["error"],
+            serde_json::to_value(WebhookJobError::new_http_status(
+                500,
+                "Internal Server Error"
+            ))
+            .unwrap()
+        );
+
+        let response = app
+            .oneshot(get_request(job_id + 1, Some("admin-token")))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn get_request(job_id: i64, token: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder().uri(format!("/webhook/{job_id}"));
+        if let Some(token) = token {
+            builder = builder.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_get_requires_admin_token(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue.clone(),
+            MAX_BODY_SIZE,
+            Some("admin-token".to_owned()),
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+        for token in [None, Some("wrong-token")] {
+            let response = app.clone().oneshot(get_request(1, token)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{:?}", token);
+        }
+
+        // Without an admin token jobs can't be read at all
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+        let response = app.oneshot(get_request(1, Some(""))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    fn preview_request(parameters: WebhookJobParameters) -> Request<Body> {
+        Request::builder()
+            .method(http::Method::POST)
+            .uri("/webhook/preview")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_string(&WebhookPreviewRequestBody { parameters }).unwrap(),
+            ))
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_preview(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let options = PreviewOptions {
+            allow_internal_ips: true,
+            body_transform_null_as_object: false,
+        };
+        let app = add_routes(Router::new(), pg_queue, MAX_BODY_SIZE, None, options);
+
+        let mut headers = collections::HashMap::new();
+        headers.insert("Content-Type".to_owned(), "text/plain".to_owned());
+        headers.insert("X-Custom".to_owned(), "value".to_owned());
+        let response = app
+            .oneshot(preview_request(WebhookJobParameters {
+                headers,
+                method: HttpMethod::PUT,
+                url: "http://localhost:18081/echo".to_owned(),
+                body: r#"{"event": {"name": "$pageview"}}"#.to_owned(),
+                body_transform: Some("{name: event.name}".to_owned()),
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let preview: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            preview,
+            serde_json::json!({
+                "method": "PUT",
+                "url": "http://localhost:18081/echo",
+                "headers": {
+                    "content-type": "text/plain",
+                    "user-agent": "PostHog Webhook Worker",
+                    "x-custom": "value",
+                },
+                "body": r#"{"name":"$pageview"}"#,
+            })
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_preview_bad_url(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db).await;
+
+        let app = add_routes(
+            Router::new(),
+            pg_queue,
+            MAX_BODY_SIZE,
+            None,
+            PreviewOptions::default(),
+            EnqueueOptions::default(),
+        );
+
+        let response = app
+            .oneshot(preview_request(WebhookJobParameters {
+                headers: collections::HashMap::new(),
+                method: HttpMethod::POST,
+                url: "invalid".to_owned(),
+                body: r#"{"a": "b"}"#.to_owned(),
+                body_transform: None,
+            }))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], br#"{"error":"error parsing webhook url"}"#);
+    }
 }
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use axum::Router;
 use config::Config;
 use envconfig::Envconfig;
@@ -5,6 +7,8 @@ use eyre::Result;
 
 use hook_common::metrics::setup_metrics_routes;
 use hook_common::pgqueue::PgQueue;
+use hook_common::preview::PreviewOptions;
+use hook_common::request::HeaderLimits;
 
 mod config;
 mod handlers;
@@ -22,6 +26,12 @@ async fn main() {
     tracing_subscriber::fmt::init();
 
     let config = Config::init_from_env().expect("failed to load configuration from env");
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    // Before the settings the routes take ownership of are moved out of the config
+    let bind = config.bind();
 
     let pg_queue = PgQueue::new(
         // TODO: Coupling the queue name to the PgQueue object doesn't seem ideal from the api
@@ -34,10 +44,35 @@ async fn main() {
     .await
     .expect("failed to initialize queue");
 
-    let app = handlers::add_routes(Router::new(), pg_queue, config.max_body_size);
+    pg_queue
+        .check_max_connections(
+            config.max_pg_connections,
+            config.pg_connection_consumers,
+            config.pg_connections_strict,
+        )
+        .await
+        .expect("failed to check database connection limits");
+
+    let app = handlers::add_routes(
+        Router::new(),
+        pg_queue,
+        config.max_body_size,
+        config.admin_token,
+        PreviewOptions {
+            allow_internal_ips: config.allow_internal_ips,
+            body_transform_null_as_object: config.body_transform_null_as_object,
+        },
+        handlers::EnqueueOptions {
+            kafka_topics: Arc::new(config.kafka_webhook_topics.0),
+            header_limits: HeaderLimits {
+                max_count: config.max_header_count,
+                max_bytes: config.max_header_bytes,
+            },
+        },
+    );
     let app = setup_metrics_routes(app);
 
-    match listen(app, config.bind()).await {
+    match listen(app, bind).await {
         Ok(_) => {}
         Err(e) => tracing::error!("failed to start hook-api http server, {}", e),
     }
@@ -0,0 +1,432 @@
+use std::error::Error as StdError;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::{fmt, io};
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hickory_resolver::config::{
+    NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use hickory_resolver::TokioAsyncResolver;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use thiserror::Error;
+use tokio::task::spawn_blocking;
+
+pub struct NoPublicIPv4Error;
+
+impl std::error::Error for NoPublicIPv4Error {}
+impl fmt::Display for NoPublicIPv4Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No public IPv4 found for specified host")
+    }
+}
+impl fmt::Debug for NoPublicIPv4Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No public IPv4 found for specified host")
+    }
+}
+
+/// Internal reqwest type, copied here as part of Resolving
+pub(crate) type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Why a host couldn't be resolved, telling whether resolving it again may work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolutionErrorKind {
+    /// The host doesn't exist (NXDOMAIN) or has no address, retrying won't change that.
+    NotFound,
+    /// The resolver failed to answer, like on a timeout or a SERVFAIL, retrying may work.
+    Transient,
+}
+
+/// A failed lookup of a host, displayed as the resolver's own error.
+#[derive(Debug, Error)]
+#[error("{source}")]
+pub struct ResolutionError {
+    pub kind: ResolutionErrorKind,
+    source: BoxError,
+}
+
+impl ResolutionError {
+    pub fn new(
+        kind: ResolutionErrorKind,
+        source: impl Into<Box<dyn StdError + Send + Sync>>,
+    ) -> Self {
+        Self {
+            kind,
+            source: source.into(),
+        }
+    }
+}
+
+/// Messages of the getaddrinfo errors telling that the host doesn't exist or has no address,
+/// on Linux and macOS. std only exposes these errors through their message, other errors like
+/// EAI_AGAIN or EAI_FAIL are failures of the resolver.
+const SYSTEM_NOT_FOUND_MESSAGES: [&str; 3] = [
+    "Name or service not known",
+    "No address associated with hostname",
+    "nodename nor servname provided, or not known",
+];
+
+fn system_error_kind(error: &io::Error) -> ResolutionErrorKind {
+    let message = error.to_string();
+    if SYSTEM_NOT_FOUND_MESSAGES
+        .iter()
+        .any(|not_found| message.contains(not_found))
+    {
+        ResolutionErrorKind::NotFound
+    } else {
+        ResolutionErrorKind::Transient
+    }
+}
+
+fn nameserver_error_kind(error: &ResolveError) -> ResolutionErrorKind {
+    match error.kind() {
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain | ResponseCode::NoError,
+            ..
+        } => ResolutionErrorKind::NotFound,
+        _ => ResolutionErrorKind::Transient,
+    }
+}
+
+/// Returns [`true`] if the address appears to be a globally reachable IPv4.
+///
+/// Trimmed down version of the unstable IpAddr::is_global, move to it when it's stable.
+fn is_global_ipv4(addr: &SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            !(ip.octets()[0] == 0 // "This network"
+            || ip.is_private()
+            || ip.is_loopback()
+            || ip.is_link_local()
+            || ip.is_broadcast())
+        }
+        IpAddr::V6(_) => false, // Our network does not currently support ipv6, let's ignore for now
+    }
+}
+
+/// Looks up all the addresses of a host, before they are filtered by the `PublicIPv4Resolver`.
+trait Lookup: Send + Sync {
+    fn lookup(&self, name: Name) -> BoxFuture<'static, Result<Vec<SocketAddr>, ResolutionError>>;
+}
+
+/// Looks up hosts with the system's resolver.
+struct SystemLookup;
+
+impl Lookup for SystemLookup {
+    fn lookup(&self, name: Name) -> BoxFuture<'static, Result<Vec<SocketAddr>, ResolutionError>> {
+        // Closure to call the system's resolver (blocking call) through the ToSocketAddrs trait.
+        let resolve_host = move || (name.as_str(), 0).to_socket_addrs();
+
+        // Execute the blocking call in a separate worker thread then process its result asynchronously.
+        // spawn_blocking returns a JoinHandle that implements Future<Result<(closure result), JoinError>>.
+        spawn_blocking(resolve_host)
+            .map(|result| match result {
+                Ok(Ok(all_addrs)) => Ok(all_addrs.collect()),
+                Ok(Err(err)) => {
+                    // Resolution failed, pass error through with its kind
+                    Err(ResolutionError::new(system_error_kind(&err), err))
+                }
+                Err(join_err) => {
+                    // The tokio task failed, pass as io::Error
+                    Err(ResolutionError::new(
+                        ResolutionErrorKind::Transient,
+                        io::Error::from(join_err),
+                    ))
+                }
+            })
+            .boxed()
+    }
+}
+
+/// Looks up hosts by querying a set of nameservers directly, bypassing the system's resolver.
+struct NameserverLookup {
+    resolver: TokioAsyncResolver,
+}
+
+impl Lookup for NameserverLookup {
+    fn lookup(&self, name: Name) -> BoxFuture<'static, Result<Vec<SocketAddr>, ResolutionError>> {
+        let resolver = self.resolver.clone();
+
+        async move {
+            let ips = resolver
+                .lookup_ip(name.as_str())
+                .await
+                .map_err(|err| ResolutionError::new(nameserver_error_kind(&err), err))?;
+            Ok(ips.iter().map(|ip| SocketAddr::new(ip, 0)).collect())
+        }
+        .boxed()
+    }
+}
+
+/// DNS resolver filtering results to only pass public IPv4 results.
+///
+/// Private and broadcast addresses are filtered out, so are IPv6 results for now (as our infra
+/// does not currently support IPv6 routing anyway).
+/// Hosts are looked up with the system's resolver, unless nameservers are configured.
+/// This is adapted from the GaiResolver in hyper and reqwest.
+#[derive(Clone)]
+pub struct PublicIPv4Resolver {
+    lookup: Arc<dyn Lookup>,
+}
+
+impl PublicIPv4Resolver {
+    /// Returns a resolver using the system's resolver.
+    pub fn new() -> Self {
+        PublicIPv4Resolver {
+            lookup: Arc::new(SystemLookup),
+        }
+    }
+
+    /// Returns a resolver querying `nameservers` instead of the system's resolver, or using the
+    /// system's resolver if `nameservers` is empty.
+    pub fn with_nameservers(nameservers: &[SocketAddr]) -> Self {
+        Self::from_nameservers(nameservers, ResolverOpts::default())
+    }
+
+    /// Returns a resolver like `with_nameservers`, that doesn't cache the answers of the
+    /// nameservers so that every lookup sees the current records of a host.
+    pub fn uncached_with_nameservers(nameservers: &[SocketAddr]) -> Self {
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = 0;
+        Self::from_nameservers(nameservers, opts)
+    }
+
+    fn from_nameservers(nameservers: &[SocketAddr], opts: ResolverOpts) -> Self {
+        if nameservers.is_empty() {
+            return Self::new();
+        }
+
+        let mut group = NameServerConfigGroup::with_capacity(nameservers.len() * 2);
+        for nameserver in nameservers {
+            group.push(NameServerConfig::new(*nameserver, Protocol::Udp));
+            group.push(NameServerConfig::new(*nameserver, Protocol::Tcp));
+        }
+        let config = ResolverConfig::from_parts(None, vec![], group);
+
+        PublicIPv4Resolver {
+            lookup: Arc::new(NameserverLookup {
+                resolver: TokioAsyncResolver::tokio(config, opts),
+            }),
+        }
+    }
+}
+
+impl Default for PublicIPv4Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolve for PublicIPv4Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let future_result = self.lookup.lookup(name).map(|result| {
+            // Resolution succeeded, filter the results
+            let filtered_addr: Vec<SocketAddr> =
+                result?.into_iter().filter(is_global_ipv4).collect();
+            if filtered_addr.is_empty() {
+                // No public IPs found, error out with PermissionDenied
+                let err: BoxError = Box::new(NoPublicIPv4Error);
+                Err(err)
+            } else {
+                // Pass remaining IPs in a boxed iterator for request to use.
+                let addrs: Addrs = Box::new(filtered_addr.into_iter());
+                Ok(addrs)
+            }
+        });
+
+        // Box the Future to satisfy the Resolving interface.
+        Box::pin(future_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dns::{
+        system_error_kind, Lookup, NoPublicIPv4Error, PublicIPv4Resolver, ResolutionError,
+        ResolutionErrorKind,
+    };
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use reqwest::dns::{Name, Resolve};
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    /// Returns the same addresses for every host.
+    struct StubLookup(Vec<SocketAddr>);
+
+    impl Lookup for StubLookup {
+        fn lookup(
+            &self,
+            _name: Name,
+        ) -> BoxFuture<'static, Result<Vec<SocketAddr>, ResolutionError>> {
+            let addrs = self.0.clone();
+            async move { Ok(addrs) }.boxed()
+        }
+    }
+
+    /// Fails every lookup with an error of the same kind.
+    struct FailingLookup(ResolutionErrorKind);
+
+    impl Lookup for FailingLookup {
+        fn lookup(
+            &self,
+            _name: Name,
+        ) -> BoxFuture<'static, Result<Vec<SocketAddr>, ResolutionError>> {
+            let error = ResolutionError::new(self.0, "stub lookup failure");
+            async move { Err(error) }.boxed()
+        }
+    }
+
+    fn stub_resolver(addrs: &[&str]) -> PublicIPv4Resolver {
+        PublicIPv4Resolver {
+            lookup: Arc::new(StubLookup(
+                addrs
+                    .iter()
+                    .map(|addr| SocketAddr::from_str(addr).unwrap())
+                    .collect(),
+            )),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_filters_lookup_results() {
+        let resolver = stub_resolver(&[
+            "127.0.0.1:0",
+            "10.0.0.1:0",
+            "1.2.3.4:0",
+            "[2001:db8::1]:0",
+            "5.6.7.8:0",
+        ]);
+        let addrs: Vec<SocketAddr> = resolver
+            .resolve(Name::from_str("example.com").unwrap())
+            .await
+            .expect("lookup has failed")
+            .collect();
+        assert_eq!(
+            addrs,
+            vec![
+                SocketAddr::from_str("1.2.3.4:0").unwrap(),
+                SocketAddr::from_str("5.6.7.8:0").unwrap()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_denies_lookups_without_public_ips() {
+        let resolver = stub_resolver(&["192.168.1.1:0", "169.254.0.1:0"]);
+        match resolver
+            .resolve(Name::from_str("example.com").unwrap())
+            .await
+        {
+            Ok(_) => panic!("should have failed"),
+            Err(err) => assert!(err.is::<NoPublicIPv4Error>()),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_passes_the_kind_of_lookup_errors() {
+        for kind in [
+            ResolutionErrorKind::NotFound,
+            ResolutionErrorKind::Transient,
+        ] {
+            let resolver = PublicIPv4Resolver {
+                lookup: Arc::new(FailingLookup(kind)),
+            };
+            match resolver
+                .resolve(Name::from_str("example.com").unwrap())
+                .await
+            {
+                Ok(_) => panic!("should have failed"),
+                Err(err) => {
+                    let err = err
+                        .downcast_ref::<ResolutionError>()
+                        .expect("not a resolution error");
+                    assert_eq!(err.kind, kind);
+                    assert_eq!(err.to_string(), "stub lookup failure");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_classifies_system_lookup_errors() {
+        let error = |message: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("failed to lookup address information: {}", message),
+            )
+        };
+
+        // NXDOMAIN, on Linux and macOS
+        assert_eq!(
+            system_error_kind(&error("Name or service not known")),
+            ResolutionErrorKind::NotFound
+        );
+        assert_eq!(
+            system_error_kind(&error("nodename nor servname provided, or not known")),
+            ResolutionErrorKind::NotFound
+        );
+        // EAI_AGAIN and EAI_FAIL, like a timeout or a SERVFAIL
+        assert_eq!(
+            system_error_kind(&error("Temporary failure in name resolution")),
+            ResolutionErrorKind::Transient
+        );
+        assert_eq!(
+            system_error_kind(&error("Non-recoverable failure in name resolution")),
+            ResolutionErrorKind::Transient
+        );
+    }
+
+    #[tokio::test]
+    async fn it_resolves_google_com() {
+        let resolver: PublicIPv4Resolver = PublicIPv4Resolver::new();
+        let addrs = resolver
+            .resolve(Name::from_str("google.com").unwrap())
+            .await
+            .expect("lookup has failed");
+        assert!(addrs.count() > 0, "empty address list")
+    }
+
+    #[tokio::test]
+    async fn it_denies_ipv6_google_com() {
+        let resolver: PublicIPv4Resolver = PublicIPv4Resolver::new();
+        match resolver
+            .resolve(Name::from_str("ipv6.google.com").unwrap())
+            .await
+        {
+            Ok(_) => panic!("should have failed"),
+            Err(err) => assert!(err.is::<NoPublicIPv4Error>()),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_denies_localhost() {
+        let resolver: PublicIPv4Resolver = PublicIPv4Resolver::new();
+        match resolver.resolve(Name::from_str("localhost").unwrap()).await {
+            Ok(_) => panic!("should have failed"),
+            Err(err) => assert!(err.is::<NoPublicIPv4Error>()),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_bubbles_up_resolution_error() {
+        let resolver: PublicIPv4Resolver = PublicIPv4Resolver::new();
+        match resolver
+            .resolve(Name::from_str("invalid.domain.unknown").unwrap())
+            .await
+        {
+            Ok(_) => panic!("should have failed"),
+            Err(err) => {
+                assert!(!err.is::<NoPublicIPv4Error>());
+                assert!(err
+                    .to_string()
+                    .contains("failed to lookup address information"))
+            }
+        }
+    }
+}
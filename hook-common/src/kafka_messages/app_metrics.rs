@@ -22,6 +22,8 @@ pub enum ErrorType {
     ConnectionError,
     BadHttpStatus(u16),
     ParseError,
+    CancelledError,
+    ExpiredError,
 }
 
 // NOTE: This is stored in Postgres and deserialized by the cleanup/janitor process, so this
@@ -132,6 +134,8 @@ where
         ErrorType::TimeoutError => "Timeout Error".to_owned(),
         ErrorType::BadHttpStatus(s) => format!("Bad HTTP Status: {}", s),
         ErrorType::ParseError => "Parse Error".to_owned(),
+        ErrorType::CancelledError => "Cancelled Error".to_owned(),
+        ErrorType::ExpiredError => "Expired Error".to_owned(),
     };
     serializer.serialize_str(&error_type)
 }
@@ -151,6 +155,8 @@ where
                     ErrorType::BadHttpStatus(status.parse().map_err(serde::de::Error::custom)?)
                 }
                 "Parse Error" => ErrorType::ParseError,
+                "Cancelled Error" => ErrorType::CancelledError,
+                "Expired Error" => ErrorType::ExpiredError,
                 _ => {
                     return Err(serde::de::Error::unknown_variant(
                         &s,
@@ -159,6 +165,8 @@ where
                             "Timeout Error",
                             "Bad HTTP Status: <status>",
                             "Parse Error",
+                            "Cancelled Error",
+                            "Expired Error",
                         ],
                     ))
                 }
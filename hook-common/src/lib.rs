@@ -1,5 +1,9 @@
+pub mod config;
+pub mod dns;
 pub mod kafka_messages;
 pub mod metrics;
 pub mod pgqueue;
+pub mod preview;
+pub mod request;
 pub mod retry;
 pub mod webhook;
@@ -11,7 +11,7 @@ use sqlx::postgres::any::AnyConnectionBackend;
 use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
 use thiserror::Error;
 use tokio::sync::Mutex;
-use tracing::error;
+use tracing::{error, warn};
 
 /// Enumeration of parsing errors in PgQueue.
 #[derive(Error, Debug)]
@@ -40,6 +40,47 @@ pub enum DatabaseError {
     TransactionAlreadyClosedError,
 }
 
+/// The share of the server's `max_connections` our pools may add up to, leaving room for
+/// superuser, replication and maintenance connections.
+pub const SAFE_CONNECTIONS_FRACTION: f64 = 0.8;
+
+/// Enumeration of errors that can occur when checking the connection pool sizes against the
+/// server's `max_connections`.
+#[derive(Error, Debug)]
+pub enum ConnectionLimitError {
+    #[error(transparent)]
+    DatabaseError(#[from] DatabaseError),
+    #[error("max_connections is not a number: {0}")]
+    InvalidMaxConnections(String),
+    #[error("{consumers} pools of {pool_size} connections may use {requested} connections, over {safe_limit} ({max_connections} max_connections)")]
+    TooManyConnections {
+        consumers: u32,
+        pool_size: u32,
+        requested: u64,
+        safe_limit: u64,
+        max_connections: u32,
+    },
+}
+
+/// Check that `consumers` pools of `pool_size` connections fit in the safe share of the server's
+/// `max_connections`, returning the error to report if they don't.
+pub fn check_connection_budget(
+    max_connections: u32,
+    pool_size: u32,
+    consumers: u32,
+) -> Option<ConnectionLimitError> {
+    let requested = u64::from(pool_size) * u64::from(consumers);
+    let safe_limit = (f64::from(max_connections) * SAFE_CONNECTIONS_FRACTION) as u64;
+
+    (requested > safe_limit).then_some(ConnectionLimitError::TooManyConnections {
+        consumers,
+        pool_size,
+        requested,
+        safe_limit,
+        max_connections,
+    })
+}
+
 /// An error that occurs when a job cannot be retried.
 /// Returns the underlying job so that a client can fail it.
 #[derive(Error, Debug)]
@@ -60,9 +101,10 @@ pub enum RetryError<T> {
 }
 
 /// Enumeration of possible statuses for a Job.
-#[derive(Debug, PartialEq, sqlx::Type)]
+#[derive(Debug, PartialEq, sqlx::Type, serde::Serialize)]
 #[sqlx(type_name = "job_status")]
 #[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum JobStatus {
     /// A job that is waiting in the queue to be picked up by a worker.
     Available,
@@ -74,6 +116,8 @@ pub enum JobStatus {
     Discarded,
     /// A job that was unsuccessfully completed by a worker.
     Failed,
+    /// A job that is being attempted by a worker.
+    Running,
 }
 
 /// Allow casting JobStatus from strings.
@@ -85,11 +129,36 @@ impl FromStr for JobStatus {
             "available" => Ok(JobStatus::Available),
             "completed" => Ok(JobStatus::Completed),
             "failed" => Ok(JobStatus::Failed),
+            "running" => Ok(JobStatus::Running),
             invalid => Err(ParseError::ParseJobStatusError(invalid.to_owned())),
         }
     }
 }
 
+/// The maximum number of attempts kept in the history of a job, older attempts are dropped first.
+pub const MAX_ATTEMPTS_HISTORY: usize = 20;
+
+/// Build the SQL expression appending an attempt with `status` and `error` to the `attempts` of
+/// a job, keeping only the last `MAX_ATTEMPTS_HISTORY` of them.
+fn append_attempt_sql(status: &str, error: &str) -> String {
+    format!(
+        "(array_append(attempts, jsonb_build_object('attempt', attempt, 'status', '{status}', 'finished_at', NOW(), 'error', {error})))[greatest(1, cardinality(attempts) + 2 - {MAX_ATTEMPTS_HISTORY}):]"
+    )
+}
+
+/// A record of a finished attempt of a job, as stored in its history.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct JobAttempt {
+    /// The number of the attempt, starting at 1.
+    pub attempt: i32,
+    /// The status the attempt transitioned the job to: completed, failed or retried.
+    pub status: String,
+    /// A datetime corresponding to when the attempt finished.
+    pub finished_at: chrono::DateTime<chrono::offset::Utc>,
+    /// The error the attempt failed with, if any.
+    pub error: Option<serde_json::Value>,
+}
+
 /// JobParameters are stored and read to and from a JSONB field, so we accept anything that fits `sqlx::types::Json`.
 pub type JobParameters<J> = sqlx::types::Json<J>;
 
@@ -149,20 +218,24 @@ impl<J, M> Job<J, M> {
     where
         E: sqlx::Executor<'c, Database = sqlx::Postgres>,
     {
-        let base_query = r#"
+        let base_query = format!(
+            r#"
 UPDATE
     job_queue
 SET
     last_attempt_finished_at = NOW(),
-    status = 'completed'::job_status
+    status = 'completed'::job_status,
+    attempts = {}
 WHERE
     queue = $1
     AND id = $2
 RETURNING
     job_queue.*
-        "#;
+            "#,
+            append_attempt_sql("completed", "NULL")
+        );
 
-        sqlx::query(base_query)
+        sqlx::query(&base_query)
             .bind(&self.queue)
             .bind(self.id)
             .execute(executor)
@@ -187,21 +260,25 @@ RETURNING
         E: sqlx::Executor<'c, Database = sqlx::Postgres>,
     {
         let json_error = sqlx::types::Json(error);
-        let base_query = r#"
+        let base_query = format!(
+            r#"
 UPDATE
     job_queue
 SET
     last_attempt_finished_at = NOW(),
     status = 'failed'::job_status,
-    errors = array_append(errors, $3)
+    errors = array_append(errors, $3),
+    attempts = {}
 WHERE
     queue = $1
     AND id = $2
 RETURNING
     job_queue.*
-        "#;
+            "#,
+            append_attempt_sql("failed", "$3::jsonb")
+        );
 
-        sqlx::query(base_query)
+        sqlx::query(&base_query)
             .bind(&self.queue)
             .bind(self.id)
             .bind(&json_error)
@@ -214,6 +291,49 @@ RETURNING
             queue: self.queue,
         })
     }
+
+    /// Consume `Job` to defer it, making it available again after `interval` without counting the
+    /// current attempt, nor recording it in the attempt history, as the job wasn't attempted.
+    /// A `DeferredJob` cannot be used further; it is returned for reporting or inspection.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval`: The duration until the `Job` is to be attempted. Used to set `scheduled_at`.
+    /// * `executor`: Any sqlx::Executor that can execute the UPDATE query required to mark this `Job` as available.
+    async fn defer<'c, E>(
+        self,
+        interval: time::Duration,
+        executor: E,
+    ) -> Result<DeferredJob, sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+    {
+        let base_query = r#"
+UPDATE
+    job_queue
+SET
+    status = 'available'::job_status,
+    scheduled_at = NOW() + $3,
+    attempt = attempt - 1
+WHERE
+    queue = $1
+    AND id = $2
+RETURNING
+    job_queue.*
+        "#;
+
+        sqlx::query(base_query)
+            .bind(&self.queue)
+            .bind(self.id)
+            .bind(interval)
+            .execute(executor)
+            .await?;
+
+        Ok(DeferredJob {
+            id: self.id,
+            queue: self.queue,
+        })
+    }
 }
 
 #[async_trait]
@@ -231,6 +351,8 @@ pub trait PgQueueJob {
         retry_interval: time::Duration,
         queue: &str,
     ) -> Result<RetriedJob, RetryError<Box<Self>>>;
+
+    async fn defer(mut self, interval: time::Duration) -> Result<DeferredJob, DatabaseError>;
 }
 
 /// A Job within an open PostgreSQL transaction.
@@ -245,29 +367,64 @@ pub struct PgTransactionJob<'c, J, M> {
     shared_txn: Arc<Mutex<Option<sqlx::Transaction<'c, sqlx::postgres::Postgres>>>>,
 }
 
-// Container struct for a batch of PgTransactionJob. Includes a reference to the shared transaction
-// for committing the work when all of the jobs are finished.
+// Container struct for a batch of PgTransactionJob. Includes a reference to the shared transactions
+// for committing the work when all of the jobs are finished, or per chunk with `into_chunks`.
 pub struct PgTransactionBatch<'c, J, M> {
     pub jobs: Vec<PgTransactionJob<'c, J, M>>,
 
-    /// The open transaction the jobs in the Vec came from. This should be used to commit or
-    /// rollback when all of the work is finished.
-    shared_txn: Arc<Mutex<Option<sqlx::Transaction<'c, sqlx::postgres::Postgres>>>>,
+    /// The open transactions the jobs in the Vec came from, one per dequeued chunk. These should be
+    /// used to commit or rollback when all of the work is finished.
+    shared_txns: Vec<Arc<Mutex<Option<sqlx::Transaction<'c, sqlx::postgres::Postgres>>>>>,
 }
 
-impl<'c, J, M> PgTransactionBatch<'_, J, M> {
+impl<'c, J, M> PgTransactionBatch<'c, J, M> {
+    /// Return the number of transactions (chunks) backing this batch.
+    pub fn chunk_count(&self) -> usize {
+        self.shared_txns.len()
+    }
+
+    /// Split this batch into one batch per transaction, holding the jobs dequeued in it. Each chunk
+    /// can then be committed as soon as its own jobs are done, releasing its locks and connection
+    /// without waiting for the rest of the batch.
+    pub fn into_chunks(self) -> Vec<PgTransactionBatch<'c, J, M>> {
+        let mut chunks: Vec<PgTransactionBatch<'c, J, M>> = self
+            .shared_txns
+            .into_iter()
+            .map(|shared_txn| PgTransactionBatch {
+                jobs: Vec::new(),
+                shared_txns: vec![shared_txn],
+            })
+            .collect();
+
+        for job in self.jobs {
+            if let Some(chunk) = chunks
+                .iter_mut()
+                .find(|chunk| Arc::ptr_eq(&chunk.shared_txns[0], &job.shared_txn))
+            {
+                chunk.jobs.push(job);
+            }
+        }
+
+        chunks
+    }
+
+    /// Commit every chunk in this batch, one transaction at a time.
+    /// If a commit fails, chunks committed before it remain committed, and the jobs in the remaining
+    /// chunks are rolled back (on drop) and become available again.
     pub async fn commit(self) -> PgQueueResult<()> {
-        let mut txn_guard = self.shared_txn.lock().await;
+        for shared_txn in self.shared_txns {
+            let mut txn_guard = shared_txn.lock().await;
 
-        txn_guard
-            .as_deref_mut()
-            .ok_or(DatabaseError::TransactionAlreadyClosedError)?
-            .commit()
-            .await
-            .map_err(|e| DatabaseError::QueryError {
-                command: "COMMIT".to_owned(),
-                error: e,
-            })?;
+            txn_guard
+                .as_deref_mut()
+                .ok_or(DatabaseError::TransactionAlreadyClosedError)?
+                .commit()
+                .await
+                .map_err(|e| DatabaseError::QueryError {
+                    command: "COMMIT".to_owned(),
+                    error: e,
+                })?;
+        }
 
         Ok(())
     }
@@ -350,6 +507,25 @@ impl<'c, J: std::marker::Send, M: std::marker::Send> PgQueueJob for PgTransactio
 
         Ok(retried_job)
     }
+
+    async fn defer(mut self, interval: time::Duration) -> Result<DeferredJob, DatabaseError> {
+        let mut txn_guard = self.shared_txn.lock().await;
+
+        let txn_ref = txn_guard
+            .as_deref_mut()
+            .ok_or(DatabaseError::TransactionAlreadyClosedError)?;
+
+        let deferred_job =
+            self.job
+                .defer(interval, txn_ref)
+                .await
+                .map_err(|error| DatabaseError::QueryError {
+                    command: "UPDATE".to_owned(),
+                    error,
+                })?;
+
+        Ok(deferred_job)
+    }
 }
 
 /// A Job that has failed but can still be enqueued into a PgQueue to be retried at a later point.
@@ -397,7 +573,8 @@ impl RetryableJob {
         E: sqlx::Executor<'c, Database = sqlx::Postgres>,
     {
         let json_error = sqlx::types::Json(error);
-        let base_query = r#"
+        let base_query = format!(
+            r#"
 UPDATE
     job_queue
 SET
@@ -405,15 +582,18 @@ SET
     status = 'available'::job_status,
     scheduled_at = NOW() + $3,
     errors = array_append(errors, $4),
+    attempts = {},
     queue = $5
 WHERE
     queue = $1
     AND id = $2
 RETURNING
     job_queue.*
-        "#;
+            "#,
+            append_attempt_sql("retried", "$4::jsonb")
+        );
 
-        sqlx::query(base_query)
+        sqlx::query(&base_query)
             .bind(&self.queue)
             .bind(self.id)
             .bind(retry_interval)
@@ -449,6 +629,15 @@ pub struct RetriedJob {
     pub retry_queue: Option<String>,
 }
 
+/// State a `Job` is transitioned to after being deferred without being attempted.
+#[derive(Debug)]
+pub struct DeferredJob {
+    /// A unique id identifying a job.
+    pub id: i64,
+    /// A unique id identifying a job queue.
+    pub queue: String,
+}
+
 /// State a `Job` is transitioned to after exhausting all of their attempts.
 #[derive(Debug)]
 pub struct FailedJob<J> {
@@ -460,6 +649,35 @@ pub struct FailedJob<J> {
     pub queue: String,
 }
 
+/// The current state of a job, along with the history of its attempts.
+#[derive(sqlx::FromRow, Debug)]
+pub struct JobRecord<J, M> {
+    /// A unique id identifying a job.
+    pub id: i64,
+    /// A number corresponding to the current job attempt.
+    pub attempt: i32,
+    /// A datetime corresponding to when the job was created.
+    pub created_at: chrono::DateTime<chrono::offset::Utc>,
+    /// A datetime corresponding to when the job is next available to be dequeued.
+    pub scheduled_at: chrono::DateTime<chrono::offset::Utc>,
+    /// A datetime corresponding to when the last attempt of the job finished, if any.
+    pub last_attempt_finished_at: Option<chrono::DateTime<chrono::offset::Utc>>,
+    /// The current job's number of max attempts.
+    pub max_attempts: i32,
+    /// Arbitrary job metadata stored as JSON.
+    pub metadata: JobMetadata<M>,
+    /// Arbitrary job parameters stored as JSON.
+    pub parameters: JobParameters<J>,
+    /// The queue this job belongs to.
+    pub queue: String,
+    /// The current status of the job.
+    pub status: JobStatus,
+    /// The target of the job. E.g. an endpoint or service we are trying to reach.
+    pub target: String,
+    /// The last `MAX_ATTEMPTS_HISTORY` finished attempts of the job, oldest first.
+    pub attempts: Vec<sqlx::types::Json<JobAttempt>>,
+}
+
 /// This struct represents a new job being created to be enqueued into a `PgQueue`.
 #[derive(Debug)]
 pub struct NewJob<J, M> {
@@ -471,6 +689,8 @@ pub struct NewJob<J, M> {
     pub parameters: JobParameters<J>,
     /// The target of the NewJob. E.g. an endpoint or service we are trying to reach.
     pub target: String,
+    /// A key identifying this NewJob, and how long it prevents enqueuing other jobs with it.
+    pub dedupe: Option<(String, time::Duration)>,
 }
 
 impl<J, M> NewJob<J, M> {
@@ -480,8 +700,110 @@ impl<J, M> NewJob<J, M> {
             metadata: sqlx::types::Json(metadata),
             parameters: sqlx::types::Json(parameters),
             target: target.to_owned(),
+            dedupe: None,
         }
     }
+
+    /// Skip enqueuing this NewJob if a job with the same `dedupe_key` was enqueued in the same
+    /// queue less than `ttl` ago, so that enqueues can be safely retried.
+    ///
+    /// Jobs only dedupe while their row exists: once the janitor deletes a completed job, its key
+    /// is free again, even within `ttl`.
+    pub fn with_dedupe_key(mut self, dedupe_key: &str, ttl: time::Duration) -> Self {
+        self.dedupe = Some((dedupe_key.to_owned(), ttl));
+        self
+    }
+}
+
+/// The outcome of `PgQueue::enqueue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueOutcome {
+    /// The job was inserted.
+    Inserted,
+    /// The job was skipped, as a job with the same dedupe key was enqueued within its TTL.
+    Duplicate,
+}
+
+/// Selects the pending jobs to fail with `PgQueue::fail_pending_jobs`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PendingJobsFilter {
+    /// Jobs with this target.
+    Target(String),
+    /// Jobs with this `team_id` in their metadata.
+    TeamId(u32),
+}
+
+/// The order in which a PgQueue hands out the jobs of each attempt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DequeueOrder {
+    /// Oldest scheduled jobs first.
+    #[default]
+    Fifo,
+    /// Newest scheduled jobs first, for queues where old jobs are stale, like alerts. Old jobs
+    /// may never be dequeued under load, and should be left to expire.
+    Lifo,
+}
+
+impl DequeueOrder {
+    fn scheduled_at_clause(&self) -> &'static str {
+        match self {
+            DequeueOrder::Fifo => "scheduled_at",
+            DequeueOrder::Lifo => "scheduled_at DESC",
+        }
+    }
+}
+
+impl FromStr for DequeueOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fifo" => Ok(DequeueOrder::Fifo),
+            "lifo" => Ok(DequeueOrder::Lifo),
+            _ => Err(format!("unknown dequeue order: {}", s)),
+        }
+    }
+}
+
+/// One of `count` partitions of a queue, made of the jobs whose target hashes to `index`. Workers
+/// of different shards never contend for the same rows when dequeuing, at the cost of the jobs
+/// of a shard waiting if none of its workers are running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueShard {
+    index: u32,
+    count: u32,
+}
+
+impl QueueShard {
+    pub fn new(index: u32, count: u32) -> Result<Self, String> {
+        if index >= count {
+            return Err(format!(
+                "shard index {} is not lower than the shard count {}",
+                index, count
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl FromStr for QueueShard {
+    type Err = String;
+
+    /// Parse a shard from `index/count`, like `2/8` for the third of eight shards.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((index, count)) = s.split_once('/') else {
+            return Err(format!("shard must be index/count, got: {}", s));
+        };
+        let index = index
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid shard index: {}", index))?;
+        let count = count
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid shard count: {}", count))?;
+        QueueShard::new(index, count)
+    }
 }
 
 /// A queue implemented on top of a PostgreSQL table.
@@ -491,6 +813,10 @@ pub struct PgQueue {
     name: String,
     /// A connection pool used to connect to the PostgreSQL database.
     pool: PgPool,
+    /// The order in which jobs are dequeued.
+    order: DequeueOrder,
+    /// The partition of the queue that jobs are dequeued from, if not the whole queue.
+    shard: Option<QueueShard>,
 }
 
 pub type PgQueueResult<T> = std::result::Result<T, DatabaseError>;
@@ -516,7 +842,12 @@ impl PgQueue {
             .max_connections(max_connections)
             .connect_lazy_with(options);
 
-        Ok(Self { name, pool })
+        Ok(Self {
+            name,
+            pool,
+            order: DequeueOrder::default(),
+            shard: None,
+        })
     }
 
     /// Initialize a new PgQueue backed by table in PostgreSQL from a provided connection pool.
@@ -528,7 +859,24 @@ impl PgQueue {
     pub async fn new_from_pool(queue_name: &str, pool: PgPool) -> PgQueue {
         let name = queue_name.to_owned();
 
-        Self { name, pool }
+        Self {
+            name,
+            pool,
+            order: DequeueOrder::default(),
+            shard: None,
+        }
+    }
+
+    /// Dequeue jobs in `order` instead of oldest first.
+    pub fn with_dequeue_order(mut self, order: DequeueOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only dequeue the jobs of `shard`, leaving other jobs to the workers of other shards.
+    pub fn with_shard(mut self, shard: QueueShard) -> Self {
+        self.shard = Some(shard);
+        self
     }
 
     /// Dequeue up to `limit` `Job`s from this `PgQueue` and hold the transaction.
@@ -544,6 +892,74 @@ impl PgQueue {
         attempted_by: &str,
         limit: u32,
     ) -> PgQueueResult<Option<PgTransactionBatch<'a, J, M>>> {
+        self.dequeue_tx_chunked(attempted_by, limit, limit).await
+    }
+
+    /// Dequeue up to `limit` `Job`s from this `PgQueue`, spread across transactions holding up to
+    /// `chunk_size` `Job`s each. Keeping transactions small means each commit holds fewer locks, and a
+    /// failure committing one chunk does not roll back the chunks committed before it.
+    /// Every transaction holds a connection of the pool until committed, so a batch needs up to
+    /// `ceil(limit / chunk_size)` of them; use `PgTransactionBatch::into_chunks` to commit
+    /// each chunk as soon as its jobs are done.
+    pub async fn dequeue_tx_chunked<
+        'a,
+        J: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+        M: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+    >(
+        &self,
+        attempted_by: &str,
+        limit: u32,
+        chunk_size: u32,
+    ) -> PgQueueResult<Option<PgTransactionBatch<'a, J, M>>> {
+        let chunk_size = std::cmp::max(chunk_size, 1);
+        let mut jobs = Vec::new();
+        let mut shared_txns = Vec::new();
+        let mut remaining = limit;
+
+        while remaining > 0 {
+            let chunk_limit = std::cmp::min(remaining, chunk_size);
+
+            let Some((chunk_jobs, shared_txn)) =
+                self.dequeue_tx_chunk(attempted_by, chunk_limit).await?
+            else {
+                break;
+            };
+
+            let chunk_len = chunk_jobs.len() as u32;
+            jobs.extend(chunk_jobs);
+            shared_txns.push(shared_txn);
+
+            if chunk_len < chunk_limit {
+                // The queue has been drained, no point in opening another transaction.
+                break;
+            }
+            remaining -= chunk_len;
+        }
+
+        if jobs.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(PgTransactionBatch { jobs, shared_txns }))
+    }
+
+    /// Dequeue up to `limit` `Job`s in a single transaction, returning them along with the
+    /// transaction they share.
+    #[allow(clippy::type_complexity)]
+    async fn dequeue_tx_chunk<
+        'a,
+        J: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+        M: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+    >(
+        &self,
+        attempted_by: &str,
+        limit: u32,
+    ) -> PgQueueResult<
+        Option<(
+            Vec<PgTransactionJob<'a, J, M>>,
+            Arc<Mutex<Option<sqlx::Transaction<'a, sqlx::postgres::Postgres>>>>,
+        )>,
+    > {
         let mut tx = self
             .pool
             .begin()
@@ -552,7 +968,13 @@ impl PgQueue {
 
         // The query that follows uses a FOR UPDATE SKIP LOCKED clause.
         // For more details on this see: 2ndquadrant.com/en/blog/what-is-select-skip-locked-for-in-postgresql-9-5.
-        let base_query = r#"
+        // hashtext may be negative, so it's cast to not overflow abs.
+        let shard_clause = match self.shard {
+            Some(_) => "AND abs(hashtext(target)::bigint % $4) = $5",
+            None => "",
+        };
+        let base_query = format!(
+            r#"
 WITH available_in_queue AS (
     SELECT
         id
@@ -562,9 +984,11 @@ WITH available_in_queue AS (
         status = 'available'
         AND scheduled_at <= NOW()
         AND queue = $1
+        AND deleted_at IS NULL
+        {}
     ORDER BY
         attempt,
-        scheduled_at
+        {}
     LIMIT $2
     FOR UPDATE SKIP LOCKED
 )
@@ -580,14 +1004,22 @@ WHERE
     job_queue.id = available_in_queue.id
 RETURNING
     job_queue.*
-        "#;
+        "#,
+            shard_clause,
+            self.order.scheduled_at_clause()
+        );
 
-        let query_result: Result<Vec<Job<J, M>>, sqlx::Error> = sqlx::query_as(base_query)
+        let query = sqlx::query_as(&base_query)
             .bind(&self.name)
             .bind(limit as i64)
-            .bind(attempted_by)
-            .fetch_all(&mut *tx)
-            .await;
+            .bind(attempted_by);
+        let query = match self.shard {
+            Some(shard) => query
+                .bind(i64::from(shard.count))
+                .bind(i64::from(shard.index)),
+            None => query,
+        };
+        let query_result: Result<Vec<Job<J, M>>, sqlx::Error> = query.fetch_all(&mut *tx).await;
 
         match query_result {
             Ok(jobs) => {
@@ -605,10 +1037,7 @@ RETURNING
                     })
                     .collect();
 
-                Ok(Some(PgTransactionBatch {
-                    jobs: pg_jobs,
-                    shared_txn: shared_txn.clone(),
-                }))
+                Ok(Some((pg_jobs, shared_txn)))
             }
 
             // Transaction is rolled back on drop.
@@ -620,6 +1049,147 @@ RETURNING
         }
     }
 
+    /// Fail all the jobs of this PgQueue matching `filter` that are still waiting to be attempted
+    /// (including jobs scheduled for a retry), returning the number of jobs failed.
+    /// Jobs currently held by a worker are skipped, as they will be transitioned by the worker.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter`: Selects the jobs to fail, by target or team.
+    /// * `error`: Any JSON-serializable value to be stored as the error of each failed job.
+    pub async fn fail_pending_jobs<S: serde::Serialize + std::marker::Sync>(
+        &self,
+        filter: &PendingJobsFilter,
+        error: S,
+    ) -> PgQueueResult<u64> {
+        let json_error = sqlx::types::Json(error);
+        let filter_clause = match filter {
+            PendingJobsFilter::Target(_) => "target = $2",
+            PendingJobsFilter::TeamId(_) => "(metadata->>'team_id')::bigint = $2",
+        };
+        let base_query = format!(
+            r#"
+UPDATE
+    job_queue
+SET
+    last_attempt_finished_at = NOW(),
+    status = 'failed'::job_status,
+    errors = array_append(errors, $3)
+WHERE
+    id IN (
+        SELECT
+            id
+        FROM
+            job_queue
+        WHERE
+            queue = $1
+            AND status = 'available'::job_status
+            AND {filter_clause}
+        FOR UPDATE SKIP LOCKED
+    )
+        "#
+        );
+
+        let query = sqlx::query(&base_query).bind(&self.name);
+        let query = match filter {
+            PendingJobsFilter::Target(target) => query.bind(target),
+            PendingJobsFilter::TeamId(team_id) => query.bind(i64::from(*team_id)),
+        };
+
+        let result = query
+            .bind(&json_error)
+            .execute(&self.pool)
+            .await
+            .map_err(|error| DatabaseError::QueryError {
+                command: "UPDATE".to_owned(),
+                error,
+            })?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Check that the connection pools of all the processes sharing this database are not
+    /// configured to use more than the server's `max_connections` allow, as running out of
+    /// connections only fails at runtime, with errors that don't point to the configuration.
+    /// Only logs a warning when over the limit, or when the limit can't be read, for instance
+    /// because the database is unreachable, unless `strict` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool_size`: The maximum number of connections of each process' pool.
+    /// * `consumers`: The number of processes with such a pool connecting to the database.
+    /// * `strict`: Whether to return an error instead of a warning when over the limit.
+    pub async fn check_max_connections(
+        &self,
+        pool_size: u32,
+        consumers: u32,
+        strict: bool,
+    ) -> Result<(), ConnectionLimitError> {
+        let max_connections = match self.max_connections().await {
+            Ok(max_connections) => max_connections,
+            Err(error) if strict => return Err(error),
+            Err(error) => {
+                warn!("failed to check the database's connection limit: {}", error);
+                return Ok(());
+            }
+        };
+
+        match check_connection_budget(max_connections, pool_size, consumers) {
+            None => Ok(()),
+            Some(error) if strict => Err(error),
+            Some(error) => {
+                warn!(
+                    "connection pools may exhaust the database's connections: {}",
+                    error
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn max_connections(&self) -> Result<u32, ConnectionLimitError> {
+        let max_connections: String = sqlx::query_scalar("SHOW max_connections")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|error| DatabaseError::QueryError {
+                command: "SHOW".to_owned(),
+                error,
+            })?;
+
+        max_connections
+            .parse()
+            .map_err(|_| ConnectionLimitError::InvalidMaxConnections(max_connections))
+    }
+
+    /// Fetch the current state and attempt history of the job with `id`.
+    /// Jobs are looked up across queues, as retries may move a job to another queue.
+    pub async fn get_job<
+        J: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+        M: for<'d> serde::Deserialize<'d> + std::marker::Send + std::marker::Unpin + 'static,
+    >(
+        &self,
+        id: i64,
+    ) -> PgQueueResult<Option<JobRecord<J, M>>> {
+        let base_query = r#"
+SELECT
+    id, attempt, created_at, scheduled_at, last_attempt_finished_at, max_attempts, metadata,
+    parameters, queue, status, target, attempts
+FROM
+    job_queue
+WHERE
+    id = $1
+        "#;
+
+        sqlx::query_as(base_query)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|error| DatabaseError::QueryError {
+                command: "SELECT".to_owned(),
+                error,
+            })
+    }
+
     /// Enqueue a `NewJob` into this PgQueue.
     /// We take ownership of `NewJob` to enforce a specific `NewJob` is only enqueued once.
     pub async fn enqueue<
@@ -628,29 +1198,78 @@ RETURNING
     >(
         &self,
         job: NewJob<J, M>,
-    ) -> PgQueueResult<()> {
+    ) -> PgQueueResult<EnqueueOutcome> {
         // TODO: Escaping. I think sqlx doesn't support identifiers.
         let base_query = r#"
 INSERT INTO job_queue
-    (attempt, created_at, scheduled_at, max_attempts, metadata, parameters, queue, status, target)
+    (attempt, created_at, scheduled_at, max_attempts, metadata, parameters, queue, status, target, dedupe_key)
 VALUES
-    (0, NOW(), NOW(), $1, $2, $3, $4, 'available'::job_status, $5)
+    (0, NOW(), NOW(), $1, $2, $3, $4, 'available'::job_status, $5, $6)
+ON CONFLICT (queue, dedupe_key) WHERE dedupe_key IS NOT NULL DO NOTHING
         "#;
 
-        sqlx::query(base_query)
+        let insert = sqlx::query(base_query)
             .bind(job.max_attempts)
             .bind(&job.metadata)
             .bind(&job.parameters)
             .bind(&self.name)
             .bind(&job.target)
-            .execute(&self.pool)
-            .await
-            .map_err(|error| DatabaseError::QueryError {
-                command: "INSERT".to_owned(),
-                error,
-            })?;
+            .bind(job.dedupe.as_ref().map(|(dedupe_key, _)| dedupe_key));
+        let insert_error = |error: sqlx::Error| DatabaseError::QueryError {
+            command: "INSERT".to_owned(),
+            error,
+        };
+
+        let result = match &job.dedupe {
+            // Without a key to release the insert is on its own, and needs no transaction
+            None => insert.execute(&self.pool).await.map_err(insert_error)?,
+            Some((dedupe_key, ttl)) => {
+                let mut tx = self
+                    .pool
+                    .begin()
+                    .await
+                    .map_err(|error| DatabaseError::ConnectionError { error })?;
+
+                // Release the key from jobs enqueued before the TTL, so it only dedupes within it.
+                let expire_query = r#"
+UPDATE
+    job_queue
+SET
+    dedupe_key = NULL
+WHERE
+    queue = $1
+    AND dedupe_key = $2
+    AND created_at <= NOW() - make_interval(secs => $3)
+                "#;
+
+                sqlx::query(expire_query)
+                    .bind(&self.name)
+                    .bind(dedupe_key)
+                    .bind(ttl.as_secs_f64())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|error| DatabaseError::QueryError {
+                        command: "UPDATE".to_owned(),
+                        error,
+                    })?;
+
+                let result = insert.execute(&mut *tx).await.map_err(insert_error)?;
+
+                tx.commit()
+                    .await
+                    .map_err(|error| DatabaseError::TransactionError {
+                        command: "COMMIT".to_owned(),
+                        error,
+                    })?;
+                result
+            }
+        };
 
-        Ok(())
+        if result.rows_affected() == 0 {
+            Ok(EnqueueOutcome::Duplicate)
+        } else {
+            Ok(EnqueueOutcome::Inserted)
+        }
     }
 }
 
@@ -703,6 +1322,135 @@ mod tests {
         "https://myhost/endpoint".to_owned()
     }
 
+    #[test]
+    fn test_check_connection_budget() {
+        // A server reporting a low max_connections can't fit our default pool size
+        let error = check_connection_budget(20, 100, 1).expect("budget should be exceeded");
+        assert!(matches!(
+            error,
+            ConnectionLimitError::TooManyConnections {
+                requested: 100,
+                safe_limit: 16,
+                ..
+            }
+        ));
+
+        assert!(check_connection_budget(100, 40, 2).is_none());
+        assert!(check_connection_budget(100, 41, 2).is_some());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_check_max_connections(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_check_max_connections", db.clone()).await;
+
+        queue
+            .check_max_connections(1, 1, true)
+            .await
+            .expect("a single connection should fit");
+
+        // Over the limit only warns, unless strict
+        queue
+            .check_max_connections(u32::MAX, 1, false)
+            .await
+            .expect("exceeding the limit should only warn");
+        let error = queue
+            .check_max_connections(u32::MAX, 1, true)
+            .await
+            .expect_err("exceeding the limit should fail in strict mode");
+        assert!(matches!(
+            error,
+            ConnectionLimitError::TooManyConnections { .. }
+        ));
+
+        // Failing to read the limit only warns too, unless strict
+        db.close().await;
+        queue
+            .check_max_connections(1, 1, false)
+            .await
+            .expect("an unreachable database should only warn");
+        let error = queue
+            .check_max_connections(1, 1, true)
+            .await
+            .expect_err("an unreachable database should fail in strict mode");
+        assert!(matches!(error, ConnectionLimitError::DatabaseError(_)));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_enqueue_with_dedupe_key(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_enqueue_with_dedupe_key", db.clone()).await;
+        let ttl = time::Duration::from_secs(3600);
+        let new_job = || {
+            NewJob::new(
+                1,
+                JobMetadata::default(),
+                JobParameters::default(),
+                "target",
+            )
+            .with_dedupe_key("key", ttl)
+        };
+
+        let outcome = queue
+            .enqueue(new_job())
+            .await
+            .expect("failed to enqueue job");
+        assert_eq!(outcome, EnqueueOutcome::Inserted);
+
+        let outcome = queue
+            .enqueue(new_job())
+            .await
+            .expect("failed to enqueue job");
+        assert_eq!(outcome, EnqueueOutcome::Duplicate);
+
+        // Jobs without a dedupe key are always inserted
+        let job = NewJob::new(
+            1,
+            JobMetadata::default(),
+            JobParameters::default(),
+            "target",
+        );
+        let outcome = queue.enqueue(job).await.expect("failed to enqueue job");
+        assert_eq!(outcome, EnqueueOutcome::Inserted);
+
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM job_queue WHERE queue = $1")
+            .bind("test_enqueue_with_dedupe_key")
+            .fetch_one(&db)
+            .await
+            .expect("failed to count jobs");
+        assert_eq!(count, 2);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_enqueue_with_expired_dedupe_key(db: PgPool) {
+        let queue =
+            PgQueue::new_from_pool("test_enqueue_with_expired_dedupe_key", db.clone()).await;
+        let new_job = || {
+            NewJob::new(
+                1,
+                JobMetadata::default(),
+                JobParameters::default(),
+                "target",
+            )
+            .with_dedupe_key("key", time::Duration::from_secs(60))
+        };
+
+        let outcome = queue
+            .enqueue(new_job())
+            .await
+            .expect("failed to enqueue job");
+        assert_eq!(outcome, EnqueueOutcome::Inserted);
+
+        sqlx::query("UPDATE job_queue SET created_at = NOW() - INTERVAL '2 minutes'")
+            .execute(&db)
+            .await
+            .expect("failed to age job");
+
+        let outcome = queue
+            .enqueue(new_job())
+            .await
+            .expect("failed to enqueue job");
+        assert_eq!(outcome, EnqueueOutcome::Inserted);
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_can_dequeue_tx_job(db: PgPool) {
         let job_target = job_target();
@@ -789,6 +1537,225 @@ mod tests {
         batch.commit().await.expect("failed to commit transaction");
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_can_dequeue_tx_jobs_in_chunks(db: PgPool) {
+        let job_target = job_target();
+        let job_metadata = JobMetadata::default();
+        let job_parameters = JobParameters::default();
+        let worker_id = worker_id();
+
+        let queue = PgQueue::new_from_pool("test_can_dequeue_tx_jobs_in_chunks", db).await;
+
+        for _ in 0..10 {
+            queue
+                .enqueue(NewJob::new(
+                    1,
+                    job_metadata.clone(),
+                    job_parameters.clone(),
+                    &job_target,
+                ))
+                .await
+                .expect("failed to enqueue job");
+        }
+
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx_chunked(&worker_id, 10, 3)
+            .await
+            .expect("failed to dequeue jobs")
+            .expect("didn't find any jobs to dequeue");
+
+        assert_eq!(batch.jobs.len(), 10);
+        // 10 jobs in chunks of up to 3 jobs: 3 + 3 + 3 + 1.
+        assert_eq!(batch.chunk_count(), 4);
+
+        for job in std::mem::take(&mut batch.jobs) {
+            job.complete().await.expect("failed to complete job");
+        }
+        batch.commit().await.expect("failed to commit transactions");
+
+        let batch: Option<PgTransactionBatch<'_, JobParameters, JobMetadata>> = queue
+            .dequeue_tx_chunked(&worker_id, 10, 3)
+            .await
+            .expect("failed to dequeue jobs");
+
+        assert!(batch.is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_can_commit_tx_chunks_separately(db: PgPool) {
+        let job_target = job_target();
+        let job_metadata = JobMetadata::default();
+        let job_parameters = JobParameters::default();
+        let worker_id = worker_id();
+
+        let queue = PgQueue::new_from_pool("test_can_commit_tx_chunks_separately", db).await;
+
+        for _ in 0..10 {
+            queue
+                .enqueue(NewJob::new(
+                    1,
+                    job_metadata.clone(),
+                    job_parameters.clone(),
+                    &job_target,
+                ))
+                .await
+                .expect("failed to enqueue job");
+        }
+
+        let batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx_chunked(&worker_id, 10, 3)
+            .await
+            .expect("failed to dequeue jobs")
+            .expect("didn't find any jobs to dequeue");
+
+        let mut chunks = batch.into_chunks();
+        let chunk_sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.jobs.len()).collect();
+        assert_eq!(chunk_sizes, vec![3, 3, 3, 1]);
+
+        // The first chunk is committed while the others are still open
+        let mut first = chunks.remove(0);
+        let mut first_ids = Vec::new();
+        for job in std::mem::take(&mut first.jobs) {
+            first_ids.push(job.job.id);
+            job.complete().await.expect("failed to complete job");
+        }
+        first.commit().await.expect("failed to commit transaction");
+
+        for id in first_ids {
+            let record: JobRecord<JobParameters, JobMetadata> = queue
+                .get_job(id)
+                .await
+                .expect("failed to get job")
+                .expect("job not found");
+            assert_eq!(record.status, JobStatus::Completed);
+        }
+
+        for mut chunk in chunks {
+            for job in std::mem::take(&mut chunk.jobs) {
+                job.complete().await.expect("failed to complete job");
+            }
+            chunk.commit().await.expect("failed to commit transaction");
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_can_dequeue_tx_jobs_in_lifo_order(db: PgPool) {
+        let job_target = job_target();
+        let job_metadata = JobMetadata::default();
+        let job_parameters = JobParameters::default();
+        let worker_id = worker_id();
+
+        let queue = PgQueue::new_from_pool("test_can_dequeue_tx_jobs_in_lifo_order", db)
+            .await
+            .with_dequeue_order(DequeueOrder::Lifo);
+
+        for _ in 0..5 {
+            queue
+                .enqueue(NewJob::new(
+                    1,
+                    job_metadata.clone(),
+                    job_parameters.clone(),
+                    &job_target,
+                ))
+                .await
+                .expect("failed to enqueue job");
+        }
+
+        // Dequeue one job at a time, as the order of jobs within a batch is not guaranteed
+        let mut dequeued = Vec::new();
+        for _ in 0..5 {
+            let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+                .dequeue_tx(&worker_id, 1)
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            for job in std::mem::take(&mut batch.jobs) {
+                dequeued.push(job.job.id);
+                job.complete().await.expect("failed to complete job");
+            }
+            batch.commit().await.expect("failed to commit transaction");
+        }
+
+        // Jobs are scheduled as they are enqueued, so the last enqueued (highest id) come first
+        let mut newest_first = dequeued.clone();
+        newest_first.sort_unstable_by(|a, b| b.cmp(a));
+        assert_eq!(dequeued.len(), 5);
+        assert_eq!(dequeued, newest_first);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_sharded_queues_partition_jobs(db: PgPool) {
+        let queue_name = "test_sharded_queues_partition_jobs";
+        let job_metadata = JobMetadata::default();
+        let job_parameters = JobParameters::default();
+        let worker_id = worker_id();
+
+        let queue = PgQueue::new_from_pool(queue_name, db.clone()).await;
+        let mut enqueued = Vec::new();
+        for i in 0..30 {
+            let target = format!("https://host-{}.example.com/", i);
+            queue
+                .enqueue(NewJob::new(
+                    1,
+                    job_metadata.clone(),
+                    job_parameters.clone(),
+                    &target,
+                ))
+                .await
+                .expect("failed to enqueue job");
+            enqueued.push(target);
+        }
+
+        // Each worker holds its batch while the others dequeue, as they would when running
+        let mut batches: Vec<PgTransactionBatch<'_, JobParameters, JobMetadata>> = Vec::new();
+        for index in 0..3 {
+            let shard = QueueShard::new(index, 3).expect("invalid shard");
+            let sharded_queue = PgQueue::new_from_pool(queue_name, db.clone())
+                .await
+                .with_shard(shard);
+            if let Some(batch) = sharded_queue
+                .dequeue_tx(&worker_id, 100)
+                .await
+                .expect("failed to dequeue jobs")
+            {
+                batches.push(batch);
+            }
+        }
+
+        let mut dequeued: Vec<String> = batches
+            .iter()
+            .flat_map(|batch| batch.jobs.iter().map(|job| job.job.target.clone()))
+            .collect();
+        assert!(batches.len() > 1, "jobs were not spread across shards");
+        dequeued.sort_unstable();
+        enqueued.sort_unstable();
+        assert_eq!(dequeued, enqueued);
+
+        for mut batch in batches {
+            for job in std::mem::take(&mut batch.jobs) {
+                job.complete().await.expect("failed to complete job");
+            }
+            batch.commit().await.expect("failed to commit transaction");
+        }
+    }
+
+    #[test]
+    fn test_parse_queue_shard() {
+        assert_eq!("2/8".parse(), QueueShard::new(2, 8));
+        assert_eq!(" 0 / 1 ".parse(), QueueShard::new(0, 1));
+        assert!("8/8".parse::<QueueShard>().is_err());
+        assert!("0/0".parse::<QueueShard>().is_err());
+        assert!("2".parse::<QueueShard>().is_err());
+        assert!("a/8".parse::<QueueShard>().is_err());
+    }
+
+    #[test]
+    fn test_parse_dequeue_order() {
+        assert_eq!("fifo".parse(), Ok(DequeueOrder::Fifo));
+        assert_eq!(" LIFO ".parse(), Ok(DequeueOrder::Lifo));
+        assert!("random".parse::<DequeueOrder>().is_err());
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_dequeue_tx_returns_none_on_no_jobs(db: PgPool) {
         let worker_id = worker_id();
@@ -802,6 +1769,33 @@ mod tests {
         assert!(batch.is_none());
     }
 
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_dequeue_tx_skips_tombstoned_jobs(db: PgPool) {
+        let worker_id = worker_id();
+        let new_job = NewJob::new(
+            1,
+            JobMetadata::default(),
+            JobParameters::default(),
+            &job_target(),
+        );
+        let queue =
+            PgQueue::new_from_pool("test_dequeue_tx_skips_tombstoned_jobs", db.clone()).await;
+
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+        sqlx::query("UPDATE job_queue SET deleted_at = NOW() WHERE queue = $1")
+            .bind("test_dequeue_tx_skips_tombstoned_jobs")
+            .execute(&db)
+            .await
+            .expect("failed to tombstone job");
+
+        let batch: Option<PgTransactionBatch<'_, JobParameters, JobMetadata>> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job");
+
+        assert!(batch.is_none());
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_can_retry_job_with_remaining_attempts(db: PgPool) {
         let job_target = job_target();
@@ -826,7 +1820,9 @@ mod tests {
         let job = batch.jobs.pop().unwrap();
 
         let retry_interval = retry_policy.retry_interval(job.job.attempt as u32, None);
-        let retry_queue = retry_policy.retry_queue(&job.job.queue).to_owned();
+        let retry_queue = retry_policy
+            .retry_queue(job.job.attempt as u32, &job.job.queue)
+            .to_owned();
         drop(
             job.retry(
                 "a very reasonable failure reason",
@@ -883,7 +1879,9 @@ mod tests {
         let job = batch.jobs.pop().unwrap();
 
         let retry_interval = retry_policy.retry_interval(job.job.attempt as u32, None);
-        let retry_queue = retry_policy.retry_queue(&job.job.queue).to_owned();
+        let retry_queue = retry_policy
+            .retry_queue(job.job.attempt as u32, &job.job.queue)
+            .to_owned();
         drop(
             job.retry(
                 "a very reasonable failure reason",
@@ -954,4 +1952,142 @@ mod tests {
             .await
             .expect("failed to retry job");
     }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_deferred_jobs_keep_their_attempts(db: PgPool) {
+        let worker_id = worker_id();
+        let new_job = NewJob::new(
+            1,
+            JobMetadata::default(),
+            JobParameters::default(),
+            &job_target(),
+        );
+        let queue = PgQueue::new_from_pool("test_deferred_jobs_keep_their_attempts", db).await;
+
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job = batch.jobs.pop().unwrap();
+        let job_id = job.job.id;
+        assert!(job.job.is_gte_max_attempts());
+
+        job.defer(time::Duration::from_secs(0))
+            .await
+            .expect("failed to defer job");
+        batch.commit().await.expect("failed to commit transaction");
+
+        let record: JobRecord<JobParameters, JobMetadata> = queue
+            .get_job(job_id)
+            .await
+            .expect("failed to get job")
+            .expect("job not found");
+        assert_eq!(record.status, JobStatus::Available);
+        assert_eq!(record.attempt, 0);
+        assert!(record.attempts.is_empty());
+
+        // The job is attempted again as if it had never been dequeued
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job = batch.jobs.pop().unwrap();
+        assert_eq!(job.job.attempt, 1);
+        job.complete().await.expect("failed to complete job");
+        batch.commit().await.expect("failed to commit transaction");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_job_history_grows_per_attempt(db: PgPool) {
+        let worker_id = worker_id();
+        let max_attempts = MAX_ATTEMPTS_HISTORY as i32 + 2;
+        let new_job = NewJob::new(
+            max_attempts,
+            JobMetadata::default(),
+            JobParameters::default(),
+            &job_target(),
+        );
+        let queue = PgQueue::new_from_pool("test_job_history_grows_per_attempt", db).await;
+
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+
+        let mut job_id = None;
+        for attempt in 1..max_attempts {
+            let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+                .dequeue_tx(&worker_id, 1)
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            let job = batch.jobs.pop().unwrap();
+            job_id = Some(job.job.id);
+
+            job.retry(
+                format!("failure {attempt}"),
+                time::Duration::from_secs(0),
+                "test_job_history_grows_per_attempt",
+            )
+            .await
+            .expect("failed to retry job");
+            batch.commit().await.expect("failed to commit transaction");
+
+            let record: JobRecord<JobParameters, JobMetadata> = queue
+                .get_job(job_id.unwrap())
+                .await
+                .expect("failed to get job")
+                .expect("job not found");
+
+            // The history grows with every retry, up to its bound.
+            let expected_len = (attempt as usize).min(MAX_ATTEMPTS_HISTORY);
+            assert_eq!(record.attempts.len(), expected_len);
+            let last = record.attempts.last().unwrap();
+            assert_eq!(last.attempt, attempt);
+            assert_eq!(last.status, "retried");
+            assert_eq!(
+                last.error,
+                Some(serde_json::Value::String(format!("failure {attempt}")))
+            );
+        }
+
+        let mut batch: PgTransactionBatch<'_, JobParameters, JobMetadata> = queue
+            .dequeue_tx(&worker_id, 1)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        batch
+            .jobs
+            .pop()
+            .unwrap()
+            .fail("final failure")
+            .await
+            .expect("failed to fail job");
+        batch.commit().await.expect("failed to commit transaction");
+
+        let record: JobRecord<JobParameters, JobMetadata> = queue
+            .get_job(job_id.unwrap())
+            .await
+            .expect("failed to get job")
+            .expect("job not found");
+
+        // Older attempts are dropped first once the history is full.
+        assert_eq!(record.status, JobStatus::Failed);
+        assert_eq!(record.attempts.len(), MAX_ATTEMPTS_HISTORY);
+        assert_eq!(record.attempts[0].attempt, 3);
+        let last = record.attempts.last().unwrap();
+        assert_eq!(last.attempt, max_attempts);
+        assert_eq!(last.status, "failed");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_get_job_returns_none_on_unknown_id(db: PgPool) {
+        let queue = PgQueue::new_from_pool("test_get_job_returns_none_on_unknown_id", db).await;
+
+        let record: Option<JobRecord<JobParameters, JobMetadata>> =
+            queue.get_job(1234).await.expect("failed to get job");
+
+        assert!(record.is_none());
+    }
 }
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use reqwest::dns::{Name, Resolve};
+use serde::Serialize;
+use thiserror::Error;
+use url::Host;
+
+use crate::dns::PublicIPv4Resolver;
+use crate::request::{
+    default_headers, parse_headers, parse_url, transform_body, WebhookParseError,
+};
+use crate::webhook::WebhookJobParameters;
+
+/// Enumeration of errors that can occur while previewing the request for a webhook job.
+#[derive(Error, Debug)]
+pub enum WebhookPreviewError {
+    #[error(transparent)]
+    Parse(#[from] WebhookParseError),
+    #[error("webhook url has no host")]
+    MissingHost,
+    #[error("error resolving webhook host: {0}")]
+    ResolveError(String),
+}
+
+/// The worker settings that change the request sent for a webhook job.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PreviewOptions {
+    /// Skip checking that the target host resolves to a public IPv4.
+    pub allow_internal_ips: bool,
+    /// Whether to send `{}` instead of an empty body when a body transform yields null.
+    pub body_transform_null_as_object: bool,
+}
+
+/// The request a `WebhookWorker` would send for a webhook job.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct WebhookPreview {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+/// Go through all the steps a `WebhookWorker` takes before sending the request for a webhook job,
+/// and return the request instead of sending it.
+///
+/// # Arguments
+///
+/// * `parameters`: The parameters of the webhook job to preview.
+/// * `options`: The worker settings to apply when building the request.
+pub async fn preview_webhook(
+    parameters: &WebhookJobParameters,
+    options: PreviewOptions,
+) -> Result<WebhookPreview, WebhookPreviewError> {
+    let body = match &parameters.body_transform {
+        Some(expression) => transform_body(
+            &parameters.body,
+            expression,
+            options.body_transform_null_as_object,
+        )?,
+        None => parameters.body.clone(),
+    };
+    let method: http::Method = (&parameters.method).into();
+    let url = parse_url(&parameters.url)?;
+
+    // Headers set on the job replace the client's default headers, as they do when sending.
+    let mut headers = default_headers();
+    headers.extend(parse_headers(&parameters.headers, &parameters.body)?);
+
+    match url.host() {
+        None => return Err(WebhookPreviewError::MissingHost),
+        // Like the HTTP client, only resolve domains: IP addresses are connected to directly.
+        Some(Host::Domain(domain)) if !options.allow_internal_ips => {
+            let name = Name::from_str(domain)
+                .map_err(|e| WebhookPreviewError::ResolveError(e.to_string()))?;
+            PublicIPv4Resolver::new()
+                .resolve(name)
+                .await
+                .map_err(|e| WebhookPreviewError::ResolveError(e.to_string()))?;
+        }
+        Some(_) => {}
+    }
+
+    Ok(WebhookPreview {
+        method: method.to_string(),
+        url: url.to_string(),
+        headers: headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect(),
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::webhook::HttpMethod;
+
+    fn parameters(url: &str) -> WebhookJobParameters {
+        WebhookJobParameters {
+            headers: HashMap::new(),
+            method: HttpMethod::POST,
+            url: url.to_owned(),
+            body: r#"{"event": {"name": "$pageview"}, "person": null}"#.to_owned(),
+            body_transform: None,
+        }
+    }
+
+    fn allow_internal_ips() -> PreviewOptions {
+        PreviewOptions {
+            allow_internal_ips: true,
+            body_transform_null_as_object: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preview_merges_headers_and_transforms_body() {
+        let mut parameters = parameters("http://localhost:18081/echo?a=b");
+        parameters.headers = HashMap::from([
+            ("Content-Type".to_owned(), "text/plain".to_owned()),
+            ("X-Custom".to_owned(), "value".to_owned()),
+        ]);
+        parameters.body_transform = Some("event.name".to_owned());
+
+        let preview = preview_webhook(&parameters, allow_internal_ips())
+            .await
+            .expect("failed to preview webhook");
+
+        assert_eq!(
+            preview,
+            WebhookPreview {
+                method: "POST".to_owned(),
+                url: "http://localhost:18081/echo?a=b".to_owned(),
+                headers: BTreeMap::from([
+                    ("content-type".to_owned(), "text/plain".to_owned()),
+                    ("user-agent".to_owned(), "PostHog Webhook Worker".to_owned()),
+                    ("x-custom".to_owned(), "value".to_owned()),
+                ]),
+                body: r#""$pageview""#.to_owned(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_preview_null_body_transform() {
+        let mut parameters = parameters("http://localhost:18081/echo");
+        parameters.body_transform = Some("person".to_owned());
+
+        let preview = preview_webhook(&parameters, allow_internal_ips())
+            .await
+            .expect("failed to preview webhook");
+        assert_eq!(preview.body, "");
+
+        let options = PreviewOptions {
+            body_transform_null_as_object: true,
+            ..allow_internal_ips()
+        };
+        let preview = preview_webhook(&parameters, options)
+            .await
+            .expect("failed to preview webhook");
+        assert_eq!(preview.body, "{}");
+    }
+
+    #[tokio::test]
+    async fn test_preview_invalid_url() {
+        let err = preview_webhook(&parameters("not a url"), allow_internal_ips())
+            .await
+            .expect_err("preview should have failed");
+
+        assert!(matches!(
+            err,
+            WebhookPreviewError::Parse(WebhookParseError::ParseUrlError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_preview_private_ips_denied() {
+        let err = preview_webhook(
+            &parameters("http://localhost:18081/echo"),
+            PreviewOptions::default(),
+        )
+        .await
+        .expect_err("preview should have failed");
+
+        assert!(matches!(err, WebhookPreviewError::ResolveError(_)));
+        assert!(err
+            .to_string()
+            .contains("No public IPv4 found for specified host"));
+    }
+}
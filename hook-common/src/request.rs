@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+
+use reqwest::header;
+use thiserror::Error;
+
+/// Enumeration of parsing errors that can occur as a webhook job is turned into a request.
+#[derive(Error, Debug)]
+pub enum WebhookParseError {
+    #[error("{0} is not a valid HttpMethod")]
+    ParseHttpMethodError(String),
+    #[error("error parsing webhook headers")]
+    ParseHeadersError(http::Error),
+    #[error("error parsing webhook url")]
+    ParseUrlError(url::ParseError),
+    #[error("error applying webhook body transform: {0}")]
+    ParseBodyTransformError(String),
+    #[error("error rendering webhook header template: {0}")]
+    ParseHeaderTemplateError(String),
+    #[error("webhook headers exceed limits: {0}")]
+    HeaderLimitsError(String),
+}
+
+/// The headers sent with every webhook request, unless overridden by the job's own headers.
+pub fn default_headers() -> header::HeaderMap {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    headers.insert(
+        header::USER_AGENT,
+        header::HeaderValue::from_static("PostHog Webhook Worker"),
+    );
+    headers
+}
+
+/// Bounds the number of headers of a webhook job, and their total size counting both names and
+/// values, so that jobs can't bloat requests with thousands of headers or huge values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderLimits {
+    pub max_count: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self {
+            max_count: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+impl HeaderLimits {
+    /// Check the headers of a webhook job as they are stored, before any templates in them are
+    /// rendered, so that jobs with too many headers are failed without parsing any of them.
+    pub fn check(&self, headers: &HashMap<String, String>) -> Result<(), WebhookParseError> {
+        let bytes = headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        self.check_size(headers.len(), bytes)
+    }
+
+    /// Check the headers of a webhook job once their templates are rendered, as rendering event
+    /// data into them can make them much larger than they are stored.
+    pub fn check_rendered(&self, headers: &header::HeaderMap) -> Result<(), WebhookParseError> {
+        let bytes = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        self.check_size(headers.len(), bytes)
+    }
+
+    fn check_size(&self, count: usize, bytes: usize) -> Result<(), WebhookParseError> {
+        if count > self.max_count {
+            return Err(WebhookParseError::HeaderLimitsError(format!(
+                "{} headers, at most {} are allowed",
+                count, self.max_count
+            )));
+        }
+
+        if bytes > self.max_bytes {
+            return Err(WebhookParseError::HeaderLimitsError(format!(
+                "{} bytes of headers, at most {} are allowed",
+                bytes, self.max_bytes
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the URL of a webhook job.
+pub fn parse_url(url: &str) -> Result<reqwest::Url, WebhookParseError> {
+    url.parse().map_err(WebhookParseError::ParseUrlError)
+}
+
+/// Parse the headers of a webhook job into a `HeaderMap`, rendering any templated values.
+///
+/// # Arguments
+///
+/// * `headers`: The headers of the webhook job. Values may contain `{{ expression }}` templates.
+/// * `event`: The JSON body of the webhook job, which template expressions are applied to.
+pub fn parse_headers(
+    headers: &HashMap<String, String>,
+    event: &str,
+) -> Result<header::HeaderMap, WebhookParseError> {
+    // Only parse the event if there is a template to render.
+    let data: Option<serde_json::Value> = if headers
+        .values()
+        .any(|value| value.contains(HEADER_TEMPLATE_START))
+    {
+        Some(
+            serde_json::from_str(event)
+                .map_err(|e| WebhookParseError::ParseHeaderTemplateError(e.to_string()))?,
+        )
+    } else {
+        None
+    };
+    let mut header_map = header::HeaderMap::with_capacity(headers.len());
+
+    for (name, value) in headers {
+        let name = header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| WebhookParseError::ParseHeadersError(e.into()))?;
+
+        let value = match &data {
+            Some(data) if value.contains(HEADER_TEMPLATE_START) => {
+                render_header_template(value, data)?
+            }
+            _ => value.to_owned(),
+        };
+        let value = header::HeaderValue::from_str(&value)
+            .map_err(|e| WebhookParseError::ParseHeadersError(e.into()))?;
+
+        header_map.insert(name, value);
+    }
+
+    Ok(header_map)
+}
+
+const HEADER_TEMPLATE_START: &str = "{{";
+const HEADER_TEMPLATE_END: &str = "}}";
+
+/// Replace every `{{ expression }}` in a header `template` with the result of applying the JMESPath
+/// `expression` to `data`. Strings are inserted as they are, null as nothing, and any other value
+/// as JSON. Rendered values are escaped so they can't break out of the header.
+fn render_header_template(
+    template: &str,
+    data: &serde_json::Value,
+) -> Result<String, WebhookParseError> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(HEADER_TEMPLATE_START) {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start + HEADER_TEMPLATE_START.len()..];
+
+        let end = rest.find(HEADER_TEMPLATE_END).ok_or_else(|| {
+            WebhookParseError::ParseHeaderTemplateError(format!(
+                "unclosed template in {}",
+                template
+            ))
+        })?;
+        let expression = jmespath::compile(rest[..end].trim())
+            .map_err(|e| WebhookParseError::ParseHeaderTemplateError(e.to_string()))?;
+        let result = expression
+            .search(data)
+            .map_err(|e| WebhookParseError::ParseHeaderTemplateError(e.to_string()))?;
+
+        let value = match result.as_string() {
+            Some(value) => value.to_owned(),
+            None if result.is_null() => String::new(),
+            None => serde_json::to_string(&*result)
+                .map_err(|e| WebhookParseError::ParseHeaderTemplateError(e.to_string()))?,
+        };
+        escape_header_value(&value, &mut rendered);
+
+        rest = &rest[end + HEADER_TEMPLATE_END.len()..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// Percent-encode the bytes of `value` that are not visible ASCII characters or spaces, so that
+/// event data can't inject line breaks or other headers.
+fn escape_header_value(value: &str, escaped: &mut String) {
+    for byte in value.bytes() {
+        if byte == b' ' || byte.is_ascii_graphic() {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+}
+
+/// Apply a JMESPath `expression` to a JSON `body`, returning the serialized result to use as the new body.
+/// Expressions that evaluate to null yield an empty body, or an empty JSON object if `null_as_object` is set.
+///
+/// # Arguments
+///
+/// * `body`: The original body of the webhook job, which must be valid JSON.
+/// * `expression`: The JMESPath expression to apply to `body`.
+/// * `null_as_object`: Whether to return `{}` instead of an empty body when the expression yields null.
+pub fn transform_body(
+    body: &str,
+    expression: &str,
+    null_as_object: bool,
+) -> Result<String, WebhookParseError> {
+    let expression = jmespath::compile(expression)
+        .map_err(|e| WebhookParseError::ParseBodyTransformError(e.to_string()))?;
+    let data: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| WebhookParseError::ParseBodyTransformError(e.to_string()))?;
+    let result = expression
+        .search(data)
+        .map_err(|e| WebhookParseError::ParseBodyTransformError(e.to_string()))?;
+
+    if result.is_null() {
+        return Ok(if null_as_object {
+            "{}".to_owned()
+        } else {
+            String::new()
+        });
+    }
+
+    serde_json::to_string(&*result)
+        .map_err(|e| WebhookParseError::ParseBodyTransformError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_body_with_projection() {
+        let body =
+            r#"{"event": "$pageview", "properties": {"$browser": "Firefox", "$os": "Linux"}}"#;
+
+        let transformed = transform_body(
+            body,
+            "{name: event, browser: properties.\"$browser\"}",
+            false,
+        )
+        .expect("failed to transform body");
+
+        assert_eq!(transformed, r#"{"browser":"Firefox","name":"$pageview"}"#);
+    }
+
+    #[test]
+    fn test_transform_body_with_filter() {
+        let body = r#"{"people": [{"name": "a", "age": 10}, {"name": "b", "age": 30}]}"#;
+
+        let transformed = transform_body(body, "people[?age > `20`].name", false)
+            .expect("failed to transform body");
+
+        assert_eq!(transformed, r#"["b"]"#);
+    }
+
+    #[test]
+    fn test_transform_body_null_result() {
+        let body = r#"{"event": "$pageview"}"#;
+
+        let transformed = transform_body(body, "missing", false).expect("failed to transform body");
+        assert_eq!(transformed, "");
+
+        let transformed = transform_body(body, "missing", true).expect("failed to transform body");
+        assert_eq!(transformed, "{}");
+    }
+
+    #[test]
+    fn test_transform_body_with_invalid_expression() {
+        let body = r#"{"event": "$pageview"}"#;
+
+        let err = transform_body(body, "people[?", false)
+            .err()
+            .expect("transform didn't fail when it should have failed");
+
+        assert!(matches!(
+            err,
+            WebhookParseError::ParseBodyTransformError(..)
+        ));
+    }
+
+    #[test]
+    fn test_parse_headers_with_template() {
+        let body = r#"{"event": "$pageview", "properties": {"count": 3}}"#;
+        let headers = HashMap::from([
+            ("X-Event-Name".to_owned(), "{{ event }}".to_owned()),
+            (
+                "X-Count".to_owned(),
+                "count={{properties.count}}".to_owned(),
+            ),
+            ("X-Missing".to_owned(), "{{ missing }}".to_owned()),
+            ("X-Static".to_owned(), "static".to_owned()),
+        ]);
+
+        let headers = parse_headers(&headers, body).expect("failed to parse headers");
+
+        assert_eq!(headers["x-event-name"], "$pageview");
+        assert_eq!(headers["x-count"], "count=3");
+        assert_eq!(headers["x-missing"], "");
+        assert_eq!(headers["x-static"], "static");
+    }
+
+    #[test]
+    fn test_parse_headers_with_invalid_name() {
+        let headers = HashMap::from([("X Event Name".to_owned(), "{{ event }}".to_owned())]);
+
+        let err = parse_headers(&headers, r#"{"event": "$pageview"}"#)
+            .err()
+            .expect("parsing didn't fail when it should have failed");
+
+        assert!(matches!(err, WebhookParseError::ParseHeadersError(..)));
+    }
+
+    #[test]
+    fn test_parse_headers_escapes_illegal_characters() {
+        let body = r#"{"event": "$pageview\r\nX-Injected: true", "name": "caf\u00e9"}"#;
+        let headers = HashMap::from([
+            ("X-Event-Name".to_owned(), "{{ event }}".to_owned()),
+            ("X-Name".to_owned(), "{{ name }}".to_owned()),
+        ]);
+
+        let headers = parse_headers(&headers, body).expect("failed to parse headers");
+
+        assert_eq!(headers["x-event-name"], "$pageview%0D%0AX-Injected: true");
+        assert_eq!(headers["x-name"], "caf%C3%A9");
+        assert!(!headers.contains_key("x-injected"));
+
+        // Values that aren't rendered from the event are not escaped, and fail to parse.
+        let headers = HashMap::from([("X-Static".to_owned(), "a\r\nb".to_owned())]);
+        let err = parse_headers(&headers, body)
+            .err()
+            .expect("parsing didn't fail when it should have failed");
+        assert!(matches!(err, WebhookParseError::ParseHeadersError(..)));
+    }
+
+    #[test]
+    fn test_parse_headers_with_unrenderable_template() {
+        let body = r#"{"event": "$pageview"}"#;
+
+        for template in ["{{ event", "{{ people[? }}"] {
+            let headers = HashMap::from([("X-Event-Name".to_owned(), template.to_owned())]);
+
+            let err = parse_headers(&headers, body)
+                .err()
+                .expect("parsing didn't fail when it should have failed");
+            assert!(matches!(
+                err,
+                WebhookParseError::ParseHeaderTemplateError(..)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let limits = HeaderLimits {
+            max_count: 2,
+            max_bytes: 20,
+        };
+
+        // Exactly at both limits: 2 headers of 10 bytes each
+        let headers = HashMap::from([
+            ("X-One".to_owned(), "12345".to_owned()),
+            ("X-Two".to_owned(), "12345".to_owned()),
+        ]);
+        assert!(limits.check(&headers).is_ok());
+
+        let too_many = HashMap::from([
+            ("X-1".to_owned(), "1".to_owned()),
+            ("X-2".to_owned(), "2".to_owned()),
+            ("X-3".to_owned(), "3".to_owned()),
+        ]);
+        let err = limits
+            .check(&too_many)
+            .expect_err("3 headers are over the limit");
+        assert_eq!(
+            err.to_string(),
+            "webhook headers exceed limits: 3 headers, at most 2 are allowed"
+        );
+
+        let too_large = HashMap::from([
+            ("X-One".to_owned(), "12345".to_owned()),
+            ("X-Two".to_owned(), "123456".to_owned()),
+        ]);
+        let err = limits
+            .check(&too_large)
+            .expect_err("21 bytes are over the limit");
+        assert_eq!(
+            err.to_string(),
+            "webhook headers exceed limits: 21 bytes of headers, at most 20 are allowed"
+        );
+
+        assert!(HeaderLimits::default().check(&too_many).is_ok());
+    }
+
+    #[test]
+    fn test_header_limits_rendered() {
+        let limits = HeaderLimits {
+            max_count: 2,
+            max_bytes: 32,
+        };
+        let body = r#"{"event": "$pageview", "properties": {"$browser": "Firefox"}}"#;
+
+        // The template is within the limits, but the whole event it renders is not.
+        let headers = HashMap::from([("X-Event".to_owned(), "{{ @ }}".to_owned())]);
+        assert!(limits.check(&headers).is_ok());
+
+        let rendered = parse_headers(&headers, body).expect("failed to parse headers");
+        let err = limits
+            .check_rendered(&rendered)
+            .expect_err("the rendered header is over the limit");
+        assert!(matches!(err, WebhookParseError::HeaderLimitsError(..)));
+
+        let headers = HashMap::from([("X-Event".to_owned(), "{{ event }}".to_owned())]);
+        let rendered = parse_headers(&headers, body).expect("failed to parse headers");
+        assert!(limits.check_rendered(&rendered).is_ok());
+    }
+}
@@ -1,6 +1,7 @@
 //! # Retry
 //!
 //! Module providing a `RetryPolicy` struct to configure job retrying.
+use std::collections;
 use std::time;
 
 #[derive(Clone, Debug)]
@@ -12,8 +13,31 @@ pub struct RetryPolicy {
     pub initial_interval: time::Duration,
     /// The maximum possible backoff between retries.
     pub maximum_interval: Option<time::Duration>,
+    /// The maximum preferred retry interval we will accept, e.g. from a Retry-After header.
+    pub maximum_retry_after: Option<time::Duration>,
     /// An optional queue to send WebhookJob retries to.
     pub queue: Option<String>,
+    /// Queues to send WebhookJob retries to from a given attempt on, sorted by attempt. These
+    /// take precedence over `queue` once a job reaches the attempt of a tier.
+    pub queue_tiers: Vec<(u32, String)>,
+}
+
+/// Where the interval returned by `RetryPolicy::retry_interval` comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryIntervalSource {
+    /// The preferred retry interval, e.g. from a Retry-After header.
+    RetryAfter,
+    /// Our own backoff.
+    Backoff,
+}
+
+impl RetryIntervalSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetryIntervalSource::RetryAfter => "retry_after",
+            RetryIntervalSource::Backoff => "backoff",
+        }
+    }
 }
 
 impl RetryPolicy {
@@ -24,35 +48,69 @@ impl RetryPolicy {
 
     /// Determine interval for retrying at a given attempt number.
     /// If not `None`, this method will respect `preferred_retry_interval` as long as it falls within `candidate_interval <= preferred_retry_interval <= maximum_interval`.
+    /// A `preferred_retry_interval` is first clamped to `maximum_retry_after`, if set. A zero
+    /// `preferred_retry_interval` (e.g. a Retry-After date in the past) falls back to `candidate_interval`.
     pub fn retry_interval(
         &self,
         attempt: u32,
         preferred_retry_interval: Option<time::Duration>,
     ) -> time::Duration {
+        self.retry_interval_with_source(attempt, preferred_retry_interval)
+            .0
+    }
+
+    /// Like `retry_interval`, but also return whether the interval is the `preferred_retry_interval`
+    /// or our own backoff.
+    pub fn retry_interval_with_source(
+        &self,
+        attempt: u32,
+        preferred_retry_interval: Option<time::Duration>,
+    ) -> (time::Duration, RetryIntervalSource) {
         let candidate_interval =
             self.initial_interval * self.backoff_coefficient.pow(attempt.saturating_sub(1));
 
+        let preferred_retry_interval = match (preferred_retry_interval, self.maximum_retry_after) {
+            (Some(duration), Some(max_retry_after)) => {
+                Some(std::cmp::min(duration, max_retry_after))
+            }
+            (preferred, _) => preferred,
+        };
+
         match (preferred_retry_interval, self.maximum_interval) {
             (Some(duration), Some(max_interval)) => {
                 let min_interval_allowed = std::cmp::min(candidate_interval, max_interval);
 
                 if min_interval_allowed <= duration && duration <= max_interval {
-                    duration
+                    (duration, RetryIntervalSource::RetryAfter)
                 } else {
-                    min_interval_allowed
+                    (min_interval_allowed, RetryIntervalSource::Backoff)
                 }
             }
-            (Some(duration), None) => std::cmp::max(candidate_interval, duration),
-            (None, Some(max_interval)) => std::cmp::min(candidate_interval, max_interval),
-            (None, None) => candidate_interval,
+            (Some(duration), None) if duration >= candidate_interval => {
+                (duration, RetryIntervalSource::RetryAfter)
+            }
+            (Some(_), None) | (None, None) => (candidate_interval, RetryIntervalSource::Backoff),
+            (None, Some(max_interval)) => (
+                std::cmp::min(candidate_interval, max_interval),
+                RetryIntervalSource::Backoff,
+            ),
         }
     }
 
-    /// Determine the queue to be used for retrying.
-    /// Only whether a queue is configured in this RetryPolicy is used to determine which queue to use for retrying.
-    /// This may be extended in the future to support more decision parameters.
-    pub fn retry_queue<'s>(&'s self, current_queue: &'s str) -> &'s str {
-        if let Some(new_queue) = &self.queue {
+    /// Determine the queue to be used for retrying after a given attempt number.
+    /// The next attempt goes to the queue of the last tier it has reached, if any, otherwise to the
+    /// queue configured in this RetryPolicy, falling back to `current_queue`.
+    pub fn retry_queue<'s>(&'s self, attempt: u32, current_queue: &'s str) -> &'s str {
+        let next_attempt = attempt.saturating_add(1);
+
+        if let Some((_, tier_queue)) = self
+            .queue_tiers
+            .iter()
+            .rev()
+            .find(|(from_attempt, _)| *from_attempt <= next_attempt)
+        {
+            tier_queue
+        } else if let Some(new_queue) = &self.queue {
             new_queue
         } else {
             current_queue
@@ -66,6 +124,41 @@ impl Default for RetryPolicy {
     }
 }
 
+/// A set of `RetryPolicy`s keyed by queue name, with a default `RetryPolicy` for any other queue.
+#[derive(Clone, Debug, Default)]
+pub struct RetryPolicies {
+    /// The `RetryPolicy` used for queues without a specific `RetryPolicy`.
+    default: RetryPolicy,
+    /// `RetryPolicy`s for specific queues.
+    queues: collections::HashMap<String, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    pub fn new(default: RetryPolicy) -> Self {
+        Self {
+            default,
+            queues: collections::HashMap::new(),
+        }
+    }
+
+    /// Set the `RetryPolicy` to use for jobs in `queue`.
+    pub fn queue(mut self, queue: &str, retry_policy: RetryPolicy) -> Self {
+        self.queues.insert(queue.to_owned(), retry_policy);
+        self
+    }
+
+    /// Return the `RetryPolicy` for `queue`, falling back to the default `RetryPolicy`.
+    pub fn get(&self, queue: &str) -> &RetryPolicy {
+        self.queues.get(queue).unwrap_or(&self.default)
+    }
+}
+
+impl From<RetryPolicy> for RetryPolicies {
+    fn from(default: RetryPolicy) -> Self {
+        RetryPolicies::new(default)
+    }
+}
+
 /// Builder pattern struct to provide a `RetryPolicy`.
 pub struct RetryPolicyBuilder {
     /// Coefficient to multiply initial_interval with for every past attempt.
@@ -74,8 +167,12 @@ pub struct RetryPolicyBuilder {
     pub initial_interval: time::Duration,
     /// The maximum possible backoff between retries.
     pub maximum_interval: Option<time::Duration>,
+    /// The maximum preferred retry interval we will accept, e.g. from a Retry-After header.
+    pub maximum_retry_after: Option<time::Duration>,
     /// An optional queue to send WebhookJob retries to.
     pub queue: Option<String>,
+    /// Queues to send WebhookJob retries to from a given attempt on.
+    pub queue_tiers: Vec<(u32, String)>,
 }
 
 impl Default for RetryPolicyBuilder {
@@ -84,7 +181,9 @@ impl Default for RetryPolicyBuilder {
             backoff_coefficient: 2,
             initial_interval: time::Duration::from_secs(1),
             maximum_interval: None,
+            maximum_retry_after: None,
             queue: None,
+            queue_tiers: Vec::new(),
         }
     }
 }
@@ -103,18 +202,36 @@ impl RetryPolicyBuilder {
         self
     }
 
+    pub fn maximum_retry_after(mut self, interval: time::Duration) -> RetryPolicyBuilder {
+        self.maximum_retry_after = Some(interval);
+        self
+    }
+
     pub fn queue(mut self, queue: &str) -> RetryPolicyBuilder {
         self.queue = Some(queue.to_owned());
         self
     }
 
+    /// Send retries to `queue` once a job reaches attempt `from_attempt`, so that jobs that keep
+    /// failing escalate through increasingly degraded queues.
+    pub fn queue_tier(mut self, from_attempt: u32, queue: &str) -> RetryPolicyBuilder {
+        self.queue_tiers
+            .retain(|(tier_attempt, _)| *tier_attempt != from_attempt);
+        self.queue_tiers.push((from_attempt, queue.to_owned()));
+        self.queue_tiers
+            .sort_by_key(|(tier_attempt, _)| *tier_attempt);
+        self
+    }
+
     /// Provide a `RetryPolicy` according to build parameters provided thus far.
     pub fn provide(&self) -> RetryPolicy {
         RetryPolicy {
             backoff_coefficient: self.backoff_coefficient,
             initial_interval: self.initial_interval,
             maximum_interval: self.maximum_interval,
+            maximum_retry_after: self.maximum_retry_after,
             queue: self.queue.clone(),
+            queue_tiers: self.queue_tiers.clone(),
         }
     }
 }
@@ -204,6 +321,61 @@ mod tests {
         assert_eq!(third_interval, time::Duration::from_secs(4));
     }
 
+    #[test]
+    fn test_retry_interval_clamps_preferred_to_maximum_retry_after() {
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(2))
+            .maximum_retry_after(time::Duration::from_secs(60))
+            .provide();
+        let preferred = time::Duration::from_secs(31536000);
+        let first_interval = retry_policy.retry_interval(1, Some(preferred));
+        let second_interval = retry_policy.retry_interval(2, Some(preferred));
+
+        assert_eq!(first_interval, time::Duration::from_secs(60));
+        assert_eq!(second_interval, time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_retry_interval_source() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(2))
+            .maximum_interval(time::Duration::from_secs(60))
+            .provide();
+
+        assert_eq!(
+            retry_policy.retry_interval_with_source(1, Some(time::Duration::from_secs(30))),
+            (
+                time::Duration::from_secs(30),
+                RetryIntervalSource::RetryAfter
+            )
+        );
+        assert_eq!(
+            retry_policy.retry_interval_with_source(1, Some(time::Duration::from_secs(120))),
+            (time::Duration::from_secs(2), RetryIntervalSource::Backoff)
+        );
+        assert_eq!(
+            retry_policy.retry_interval_with_source(2, None),
+            (time::Duration::from_secs(4), RetryIntervalSource::Backoff)
+        );
+    }
+
+    #[test]
+    fn test_retry_policies_per_queue() {
+        let realtime_policy = RetryPolicy::build(1, time::Duration::from_secs(1)).provide();
+        let bulk_policy = RetryPolicy::build(3, time::Duration::from_secs(10))
+            .maximum_interval(time::Duration::from_secs(60))
+            .provide();
+        let retry_policies = RetryPolicies::new(RetryPolicy::default())
+            .queue("realtime", realtime_policy)
+            .queue("bulk", bulk_policy);
+
+        let realtime_interval = retry_policies.get("realtime").retry_interval(2, None);
+        let bulk_interval = retry_policies.get("bulk").retry_interval(2, None);
+        let default_interval = retry_policies.get("other").retry_interval(2, None);
+
+        assert_eq!(realtime_interval, time::Duration::from_secs(1));
+        assert_eq!(bulk_interval, time::Duration::from_secs(30));
+        assert_eq!(default_interval, time::Duration::from_secs(2));
+    }
+
     #[test]
     fn test_returns_retry_queue_if_set() {
         let retry_queue_name = "retry_queue".to_owned();
@@ -212,7 +384,10 @@ mod tests {
             .provide();
         let current_queue = "queue".to_owned();
 
-        assert_eq!(retry_policy.retry_queue(&current_queue), retry_queue_name);
+        assert_eq!(
+            retry_policy.retry_queue(1, &current_queue),
+            retry_queue_name
+        );
     }
 
     #[test]
@@ -220,6 +395,38 @@ mod tests {
         let retry_policy = RetryPolicy::build(0, time::Duration::from_secs(0)).provide();
         let current_queue = "queue".to_owned();
 
-        assert_eq!(retry_policy.retry_queue(&current_queue), current_queue);
+        assert_eq!(retry_policy.retry_queue(1, &current_queue), current_queue);
+    }
+
+    #[test]
+    fn test_retry_queue_escalates_through_tiers() {
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(1))
+            .queue_tier(5, "webhooks-cold")
+            .queue_tier(3, "webhooks-slow")
+            .provide();
+        let current_queue = "webhooks";
+
+        // Attempts 1 and 2 stay on the current queue, 3 and 4 go to the slow queue, 5 and later to
+        // the cold one. The attempt passed is the one that failed.
+        assert_eq!(retry_policy.retry_queue(1, current_queue), "webhooks");
+        assert_eq!(retry_policy.retry_queue(2, current_queue), "webhooks-slow");
+        assert_eq!(retry_policy.retry_queue(3, current_queue), "webhooks-slow");
+        assert_eq!(retry_policy.retry_queue(4, current_queue), "webhooks-cold");
+        assert_eq!(retry_policy.retry_queue(5, current_queue), "webhooks-cold");
+        assert_eq!(
+            retry_policy.retry_queue(100, current_queue),
+            "webhooks-cold"
+        );
+    }
+
+    #[test]
+    fn test_retry_queue_tiers_take_precedence_over_retry_queue() {
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(1))
+            .queue("webhooks-retries")
+            .queue_tier(3, "webhooks-slow")
+            .provide();
+
+        assert_eq!(retry_policy.retry_queue(1, "webhooks"), "webhooks-retries");
+        assert_eq!(retry_policy.retry_queue(2, "webhooks"), "webhooks-slow");
     }
 }
@@ -126,6 +126,15 @@ pub struct WebhookJobParameters {
     pub headers: collections::HashMap<String, String>,
     pub method: HttpMethod,
     pub url: String,
+    /// An optional JMESPath expression applied to the JSON `body` to reshape it before sending.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_transform: Option<String>,
+}
+
+/// Returns the topic targeted by a `kafka://topic` webhook URL, or `None` for any other URL.
+pub fn kafka_topic(url: &str) -> Option<&str> {
+    url.strip_prefix("kafka://")
+        .map(|topic| topic.trim_end_matches('/'))
 }
 
 /// `JobMetadata` required for the `WebhookWorker` to execute a webhook.
@@ -222,4 +231,44 @@ impl WebhookJobError {
             },
         }
     }
+
+    pub fn new_cancelled(message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Cancelled Error".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::CancelledError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+        }
+    }
+
+    pub fn new_expired(message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Expired Error".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::ExpiredError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kafka_topic() {
+        assert_eq!(kafka_topic("kafka://webhooks"), Some("webhooks"));
+        assert_eq!(kafka_topic("kafka://webhooks/"), Some("webhooks"));
+        assert_eq!(kafka_topic("https://example.com/kafka://webhooks"), None);
+    }
 }
@@ -1,4 +1,12 @@
+use std::str::FromStr;
+
 use envconfig::Envconfig;
+use hook_common::config::{
+    check_database_url, check_kafka_compression_codec, check_kafka_hosts, check_not_zero,
+    ConfigError,
+};
+
+use crate::cleanup::CleanerModeName;
 
 #[derive(Envconfig)]
 pub struct Config {
@@ -14,12 +22,21 @@ pub struct Config {
     #[envconfig(default = "30")]
     pub cleanup_interval_secs: u64,
 
+    // Size of the buckets job results are aggregated in before being sent as app_metrics. The
+    // default matches the hourly granularity of app_metrics in PostHog.
+    #[envconfig(default = "3600")]
+    pub app_metrics_bucket_secs: u64,
+
     // The cleanup task needs to have special knowledge of the queue it's cleaning up. This is so it
     // can do things like flush the proper app_metrics or plugin_log_entries, and so it knows what
     // to expect in the job's payload JSONB column.
     #[envconfig(default = "webhooks")]
     pub mode: String,
 
+    // When set, processed jobs are soft-deleted with a tombstone instead of being deleted, and only
+    // purged once they have been soft-deleted for this many seconds.
+    pub soft_delete_retention_secs: Option<u64>,
+
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 }
@@ -54,4 +71,84 @@ impl Config {
     pub fn bind(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        check_database_url("DATABASE_URL", &self.database_url, &mut problems);
+        check_not_zero(
+            "CLEANUP_INTERVAL_SECS",
+            self.cleanup_interval_secs,
+            &mut problems,
+        );
+        check_not_zero(
+            "APP_METRICS_BUCKET_SECS",
+            self.app_metrics_bucket_secs,
+            &mut problems,
+        );
+        if let Some(retention) = self.soft_delete_retention_secs {
+            check_not_zero("SOFT_DELETE_RETENTION_SECS", retention, &mut problems);
+        }
+        if CleanerModeName::from_str(&self.mode).is_err() {
+            problems.push(format!("MODE must be webhooks, got {:?}", self.mode));
+        }
+        check_kafka_hosts("KAFKA_HOSTS", &self.kafka.kafka_hosts, &mut problems);
+        check_kafka_compression_codec(
+            "KAFKA_COMPRESSION_CODEC",
+            &self.kafka.kafka_compression_codec,
+            &mut problems,
+        );
+
+        ConfigError::check(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::init_from_hashmap(&vars).expect("failed to parse config")
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert_eq!(config(&[("KAFKA_HOSTS", "kafka:9092")]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_config_reports_every_problem() {
+        let error = config(&[
+            ("DATABASE_URL", "localhost:15432"),
+            ("CLEANUP_INTERVAL_SECS", "0"),
+            ("APP_METRICS_BUCKET_SECS", "0"),
+            ("SOFT_DELETE_RETENTION_SECS", "0"),
+            ("MODE", "jobs"),
+            ("KAFKA_HOSTS", "kafka:port"),
+            ("KAFKA_COMPRESSION_CODEC", "brotli"),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+
+        let message = error.to_string();
+        for setting in [
+            "DATABASE_URL",
+            "CLEANUP_INTERVAL_SECS",
+            "APP_METRICS_BUCKET_SECS",
+            "SOFT_DELETE_RETENTION_SECS",
+            "MODE",
+            "KAFKA_HOSTS",
+            "KAFKA_COMPRESSION_CODEC",
+        ] {
+            assert!(message.contains(setting), "{} is not reported", setting);
+        }
+        assert_eq!(error.0.len(), 7);
+    }
 }
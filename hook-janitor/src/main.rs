@@ -44,6 +44,10 @@ async fn main() {
     tracing_subscriber::fmt::init();
 
     let config = Config::init_from_env().expect("failed to load configuration from env");
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     let mode_name = CleanerModeName::from_str(&config.mode)
         .unwrap_or_else(|_| panic!("invalid cleaner mode: {}", config.mode));
@@ -59,14 +63,20 @@ async fn main() {
                 .await
                 .expect("failed to create kafka producer");
 
-            Box::new(
-                WebhookCleaner::new(
-                    &config.database_url,
-                    kafka_producer,
-                    config.kafka.app_metrics_topic.to_owned(),
-                )
-                .expect("unable to create webhook cleaner"),
+            let cleaner = WebhookCleaner::new(
+                &config.database_url,
+                kafka_producer,
+                config.kafka.app_metrics_topic.to_owned(),
+                Duration::from_secs(config.app_metrics_bucket_secs),
             )
+            .expect("unable to create webhook cleaner");
+
+            let cleaner = match config.soft_delete_retention_secs {
+                None => cleaner,
+                Some(retention) => cleaner.with_soft_delete(Duration::from_secs(retention)),
+            };
+
+            Box::new(cleaner)
         }
     };
 
@@ -46,6 +46,8 @@ pub enum WebhookCleanerError {
     DeleteRowsError { error: sqlx::Error },
     #[error("attempted to delete a different number of rows than expected")]
     DeleteConsistencyError,
+    #[error("failed to purge soft-deleted rows: {error}")]
+    PurgeRowsError { error: sqlx::Error },
     #[error("failed to rollback txn: {error}")]
     RollbackTxnError { error: sqlx::Error },
     #[error("failed to commit txn: {error}")]
@@ -58,13 +60,17 @@ pub struct WebhookCleaner {
     pg_pool: PgPool,
     kafka_producer: FutureProducer<KafkaContext>,
     app_metrics_topic: String,
+    app_metrics_bucket: Duration,
+    // When set, processed rows are tombstoned instead of deleted, and only purged once they have
+    // been soft-deleted for this long.
+    soft_delete_retention: Option<Duration>,
 }
 
 #[derive(sqlx::FromRow, Debug)]
 struct CompletedRow {
     // App Metrics truncates/aggregates rows on the hour, so we take advantage of that to GROUP BY
-    // and aggregate to select fewer rows.
-    hour: DateTime<Utc>,
+    // and aggregate to select fewer rows. The bucket size is configurable, but defaults to an hour.
+    bucket: DateTime<Utc>,
     // A note about the `try_from`s: Postgres returns all of those types as `bigint` (i64), but
     // we know their true sizes, and so we can convert them to the correct types here. If this
     // ever fails then something has gone wrong.
@@ -79,7 +85,7 @@ struct CompletedRow {
 impl From<CompletedRow> for AppMetric {
     fn from(row: CompletedRow) -> Self {
         AppMetric {
-            timestamp: row.hour,
+            timestamp: row.bucket,
             team_id: row.team_id,
             plugin_config_id: row.plugin_config_id,
             job_id: None,
@@ -97,8 +103,8 @@ impl From<CompletedRow> for AppMetric {
 #[derive(sqlx::FromRow, Debug)]
 struct FailedRow {
     // App Metrics truncates/aggregates rows on the hour, so we take advantage of that to GROUP BY
-    // and aggregate to select fewer rows.
-    hour: DateTime<Utc>,
+    // and aggregate to select fewer rows. The bucket size is configurable, but defaults to an hour.
+    bucket: DateTime<Utc>,
     // A note about the `try_from`s: Postgres returns all of those types as `bigint` (i64), but
     // we know their true sizes, and so we can convert them to the correct types here. If this
     // ever fails then something has gone wrong.
@@ -123,7 +129,7 @@ struct QueueDepth {
 impl From<FailedRow> for AppMetric {
     fn from(row: FailedRow) -> Self {
         AppMetric {
-            timestamp: row.hour,
+            timestamp: row.bucket,
             team_id: row.team_id,
             plugin_config_id: row.plugin_config_id,
             job_id: None,
@@ -148,6 +154,7 @@ struct CleanupStats {
     completed_agg_row_count: u64,
     failed_row_count: u64,
     failed_agg_row_count: u64,
+    rows_purged: u64,
 }
 
 impl WebhookCleaner {
@@ -155,6 +162,7 @@ impl WebhookCleaner {
         database_url: &str,
         kafka_producer: FutureProducer<KafkaContext>,
         app_metrics_topic: String,
+        app_metrics_bucket: Duration,
     ) -> Result<Self> {
         let options = PgConnectOptions::from_str(database_url)
             .map_err(|error| WebhookCleanerError::PoolCreationError { error })?
@@ -167,6 +175,8 @@ impl WebhookCleaner {
             pg_pool,
             kafka_producer,
             app_metrics_topic,
+            app_metrics_bucket,
+            soft_delete_retention: None,
         })
     }
 
@@ -175,14 +185,24 @@ impl WebhookCleaner {
         pg_pool: PgPool,
         kafka_producer: FutureProducer<KafkaContext>,
         app_metrics_topic: String,
+        app_metrics_bucket: Duration,
     ) -> Result<Self> {
         Ok(Self {
             pg_pool,
             kafka_producer,
             app_metrics_topic,
+            app_metrics_bucket,
+            soft_delete_retention: None,
         })
     }
 
+    /// Soft-delete processed rows by setting their `deleted_at` tombstone instead of deleting
+    /// them, keeping them around for inspection. Tombstoned rows are purged after `retention`.
+    pub fn with_soft_delete(mut self, retention: Duration) -> Self {
+        self.soft_delete_retention = Some(retention);
+        self
+    }
+
     async fn get_queue_depth(&self) -> Result<QueueDepth> {
         let mut conn = self
             .pg_pool
@@ -236,7 +256,8 @@ impl WebhookCleaner {
     ) -> Result<u64> {
         let base_query = r#"
             SELECT count(*) FROM job_queue
-            WHERE status = $1::job_status;
+            WHERE status = $1::job_status
+            AND deleted_at IS NULL;
             "#;
 
         let count: i64 = sqlx::query(base_query)
@@ -249,22 +270,30 @@ impl WebhookCleaner {
         Ok(count as u64)
     }
 
+    /// The size of the buckets app_metrics are aggregated in, as bound into the aggregation
+    /// queries. Buckets are aligned on the Unix epoch, so hourly buckets start on the hour.
+    fn app_metrics_bucket_secs(&self) -> f64 {
+        self.app_metrics_bucket.as_secs_f64()
+    }
+
     async fn get_completed_agg_rows(
         &self,
         tx: &mut SerializableTxn<'_>,
     ) -> Result<Vec<CompletedRow>> {
         let base_query = r#"
-            SELECT DATE_TRUNC('hour', last_attempt_finished_at) AS hour,
+            SELECT to_timestamp(floor(extract(epoch FROM last_attempt_finished_at)::double precision / $1) * $1) AS bucket,
                 (metadata->>'team_id')::bigint AS team_id,
                 (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
                 count(*) as successes
             FROM job_queue
             WHERE status = 'completed'
-            GROUP BY hour, team_id, plugin_config_id
-            ORDER BY hour, team_id, plugin_config_id;
+            AND deleted_at IS NULL
+            GROUP BY bucket, team_id, plugin_config_id
+            ORDER BY bucket, team_id, plugin_config_id;
         "#;
 
         let rows = sqlx::query_as::<_, CompletedRow>(base_query)
+            .bind(self.app_metrics_bucket_secs())
             .fetch_all(&mut *tx.0)
             .await
             .map_err(|e| WebhookCleanerError::GetCompletedRowsError { error: e })?;
@@ -274,18 +303,20 @@ impl WebhookCleaner {
 
     async fn get_failed_agg_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<Vec<FailedRow>> {
         let base_query = r#"
-            SELECT DATE_TRUNC('hour', last_attempt_finished_at) AS hour,
+            SELECT to_timestamp(floor(extract(epoch FROM last_attempt_finished_at)::double precision / $1) * $1) AS bucket,
                    (metadata->>'team_id')::bigint AS team_id,
                    (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
                    errors[array_upper(errors, 1)] AS last_error,
                    count(*) as failures
             FROM job_queue
             WHERE status = 'failed'
-            GROUP BY hour, team_id, plugin_config_id, last_error
-            ORDER BY hour, team_id, plugin_config_id, last_error;
+            AND deleted_at IS NULL
+            GROUP BY bucket, team_id, plugin_config_id, last_error
+            ORDER BY bucket, team_id, plugin_config_id, last_error;
         "#;
 
         let rows = sqlx::query_as::<_, FailedRow>(base_query)
+            .bind(self.app_metrics_bucket_secs())
             .fetch_all(&mut *tx.0)
             .await
             .map_err(|e| WebhookCleanerError::GetFailedRowsError { error: e })?;
@@ -337,12 +368,23 @@ impl WebhookCleaner {
     }
 
     async fn delete_observed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<u64> {
-        // This DELETE is only safe because we are in serializable isolation mode, see the note
-        // in `start_serializable_txn`.
-        let base_query = r#"
+        // This DELETE (or UPDATE, when soft-deleting) is only safe because we are in serializable
+        // isolation mode, see the note in `start_serializable_txn`.
+        let delete_query = r#"
             DELETE FROM job_queue
             WHERE status IN ('failed', 'completed')
+            AND deleted_at IS NULL
         "#;
+        let soft_delete_query = r#"
+            UPDATE job_queue
+            SET deleted_at = NOW()
+            WHERE status IN ('failed', 'completed')
+            AND deleted_at IS NULL
+        "#;
+        let base_query = match self.soft_delete_retention {
+            None => delete_query,
+            Some(_) => soft_delete_query,
+        };
 
         let result = sqlx::query(base_query)
             .execute(&mut *tx.0)
@@ -352,6 +394,24 @@ impl WebhookCleaner {
         Ok(result.rows_affected())
     }
 
+    /// Hard delete the rows that were soft-deleted longer than the retention ago. Without soft
+    /// deletes, this purges any tombstone left behind from when they were enabled.
+    async fn purge_soft_deleted_rows(&self) -> Result<u64> {
+        let base_query = r#"
+            DELETE FROM job_queue
+            WHERE deleted_at <= NOW() - make_interval(secs => $1)
+        "#;
+
+        let retention = self.soft_delete_retention.unwrap_or(Duration::ZERO);
+        let result = sqlx::query(base_query)
+            .bind(retention.as_secs_f64())
+            .execute(&self.pg_pool)
+            .await
+            .map_err(|e| WebhookCleanerError::PurgeRowsError { error: e })?;
+
+        Ok(result.rows_affected())
+    }
+
     async fn rollback_txn(&self, tx: SerializableTxn<'_>) -> Result<()> {
         tx.0.rollback()
             .await
@@ -373,11 +433,11 @@ impl WebhookCleaner {
 
         // Note that we select all completed and failed rows without any pagination at the moment.
         // We aggregrate as much as possible with GROUP BY, truncating the timestamp down to the
-        // hour just like App Metrics does. A completed row is 24 bytes (and aggregates an entire
-        // hour per `plugin_config_id`), and a failed row is 104 bytes + the error message length
-        // (and aggregates an entire hour per `plugin_config_id` per `error`), so we can fit a lot
-        // of rows in memory. It seems unlikely we'll need to paginate, but that can be added in the
-        // future if necessary.
+        // configured bucket size, which defaults to the hour just like App Metrics does. A
+        // completed row is 24 bytes (and aggregates a bucket per `plugin_config_id`), and a failed
+        // row is 104 bytes + the error message length (and aggregates a bucket per
+        // `plugin_config_id` per `error`), so we can fit a lot of rows in memory. It seems
+        // unlikely we'll need to paginate, but that can be added in the future if necessary.
 
         let untried_status = [("status", "untried")];
         let retries_status = [("status", "retries")];
@@ -434,12 +494,15 @@ impl WebhookCleaner {
             self.commit_txn(tx).await?;
         }
 
+        let rows_purged = self.purge_soft_deleted_rows().await?;
+
         Ok(CleanupStats {
             rows_processed: rows_deleted,
             completed_row_count,
             completed_agg_row_count,
             failed_row_count,
             failed_agg_row_count,
+            rows_purged,
         })
     }
 }
@@ -481,6 +544,14 @@ impl Cleaner for WebhookCleaner {
                 } else {
                     debug!("WebhookCleaner finished cleanup, there were no rows to process");
                 }
+
+                if stats.rows_purged > 0 {
+                    metrics::counter!("webhook_cleanup_rows_purged",).increment(stats.rows_purged);
+                    info!(
+                        rows_purged = stats.rows_purged,
+                        "WebhookCleaner::cleanup purged soft-deleted rows"
+                    );
+                }
             }
             Err(error) => {
                 metrics::counter!("webhook_cleanup_failures",).increment(1);
@@ -512,6 +583,7 @@ mod tests {
     use std::str::FromStr;
 
     const APP_METRICS_TOPIC: &str = "app_metrics";
+    const APP_METRICS_BUCKET: Duration = Duration::from_secs(60 * 60);
 
     async fn create_mock_kafka() -> (
         MockCluster<'static, DefaultProducerContext>,
@@ -569,9 +641,13 @@ mod tests {
             .expect("failed to create mock consumer");
         consumer.subscribe(&[APP_METRICS_TOPIC]).unwrap();
 
-        let webhook_cleaner =
-            WebhookCleaner::new_from_pool(db, mock_producer, APP_METRICS_TOPIC.to_owned())
-                .expect("unable to create webhook cleaner");
+        let webhook_cleaner = WebhookCleaner::new_from_pool(
+            db,
+            mock_producer,
+            APP_METRICS_TOPIC.to_owned(),
+            APP_METRICS_BUCKET,
+        )
+        .expect("unable to create webhook cleaner");
 
         let cleanup_stats = webhook_cleaner
             .cleanup_impl()
@@ -742,6 +818,61 @@ mod tests {
         check_app_metric_vector_equality(&expected_app_metrics, &received_app_metrics);
     }
 
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_completed_agg_rows_bucket_size(db: PgPool) {
+        async fn successes_for(
+            db: &PgPool,
+            bucket: Duration,
+            team_id: u32,
+            plugin_config_id: i32,
+        ) -> Vec<(DateTime<Utc>, u32)> {
+            let (_, mock_producer) = create_mock_kafka().await;
+            let webhook_cleaner = WebhookCleaner::new_from_pool(
+                db.clone(),
+                mock_producer,
+                APP_METRICS_TOPIC.to_owned(),
+                bucket,
+            )
+            .expect("unable to create webhook cleaner");
+
+            let mut tx = webhook_cleaner.start_serializable_txn().await.unwrap();
+            let rows = webhook_cleaner
+                .get_completed_agg_rows(&mut tx)
+                .await
+                .unwrap();
+            webhook_cleaner.rollback_txn(tx).await.unwrap();
+
+            rows.into_iter()
+                .filter(|row| row.team_id == team_id && row.plugin_config_id == plugin_config_id)
+                .map(|row| (row.bucket, row.successes))
+                .collect()
+        }
+
+        // The fixtures complete 3 jobs in hour 20 and 1 job in hour 21 for team 1, plugin_config 2.
+        assert_eq!(
+            successes_for(&db, APP_METRICS_BUCKET, 1, 2).await,
+            vec![
+                (
+                    DateTime::<Utc>::from_str("2023-12-19T20:00:00Z").unwrap(),
+                    3
+                ),
+                (
+                    DateTime::<Utc>::from_str("2023-12-19T21:00:00Z").unwrap(),
+                    1
+                ),
+            ]
+        );
+
+        // With daily buckets, all of them collapse into a single row.
+        assert_eq!(
+            successes_for(&db, Duration::from_secs(24 * 60 * 60), 1, 2).await,
+            vec![(
+                DateTime::<Utc>::from_str("2023-12-19T00:00:00Z").unwrap(),
+                4
+            )]
+        );
+    }
+
     #[sqlx::test(migrations = "../migrations")]
     async fn test_cleanup_impl_empty_queue(db: PgPool) {
         let (mock_cluster, mock_producer) = create_mock_kafka().await;
@@ -762,9 +893,13 @@ mod tests {
             .expect("failed to create mock consumer");
         consumer.subscribe(&[APP_METRICS_TOPIC]).unwrap();
 
-        let webhook_cleaner =
-            WebhookCleaner::new_from_pool(db, mock_producer, APP_METRICS_TOPIC.to_owned())
-                .expect("unable to create webhook cleaner");
+        let webhook_cleaner = WebhookCleaner::new_from_pool(
+            db,
+            mock_producer,
+            APP_METRICS_TOPIC.to_owned(),
+            APP_METRICS_BUCKET,
+        )
+        .expect("unable to create webhook cleaner");
 
         let cleanup_stats = webhook_cleaner
             .cleanup_impl()
@@ -779,12 +914,84 @@ mod tests {
         assert_eq!(cleanup_stats.failed_agg_row_count, 0);
     }
 
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_cleanup_impl_soft_delete(db: PgPool) {
+        let (mock_cluster, mock_producer) = create_mock_kafka().await;
+        mock_cluster
+            .create_topic(APP_METRICS_TOPIC, 1, 1)
+            .expect("failed to create mock app_metrics topic");
+
+        let webhook_cleaner = WebhookCleaner::new_from_pool(
+            db.clone(),
+            mock_producer,
+            APP_METRICS_TOPIC.to_owned(),
+            APP_METRICS_BUCKET,
+        )
+        .expect("unable to create webhook cleaner")
+        .with_soft_delete(Duration::from_secs(60 * 60));
+
+        async fn count_rows(db: &PgPool, clause: &str) -> i64 {
+            sqlx::query(&format!("SELECT count(*) FROM job_queue WHERE {}", clause))
+                .fetch_one(db)
+                .await
+                .unwrap()
+                .get(0)
+        }
+
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_processed, 13);
+        assert_eq!(cleanup_stats.rows_purged, 0);
+
+        // Processed rows are tombstoned but still present.
+        assert_eq!(count_rows(&db, "deleted_at IS NOT NULL").await, 13);
+        assert_eq!(count_rows(&db, "true").await, 15);
+
+        // Tombstoned rows aren't processed again.
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_processed, 0);
+        assert_eq!(cleanup_stats.completed_row_count, 0);
+        assert_eq!(cleanup_stats.failed_row_count, 0);
+        assert_eq!(cleanup_stats.rows_purged, 0);
+
+        // Only the available job of the fixtures can be dequeued.
+        let queue = PgQueue::new_from_pool("webhooks", db.clone()).await;
+        let mut batch: PgTransactionBatch<'_, WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue_tx(&"worker_id", 10)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        assert_eq!(batch.jobs.len(), 1);
+        batch.commit().await.expect("failed to commit batch");
+
+        // Tombstones are purged once they are older than the retention.
+        sqlx::query("UPDATE job_queue SET deleted_at = NOW() - interval '2 hours' WHERE deleted_at IS NOT NULL")
+            .execute(&db)
+            .await
+            .unwrap();
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_purged, 13);
+        assert_eq!(count_rows(&db, "true").await, 2);
+    }
+
     #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
     async fn test_serializable_isolation(db: PgPool) {
         let (_, mock_producer) = create_mock_kafka().await;
-        let webhook_cleaner =
-            WebhookCleaner::new_from_pool(db.clone(), mock_producer, APP_METRICS_TOPIC.to_owned())
-                .expect("unable to create webhook cleaner");
+        let webhook_cleaner = WebhookCleaner::new_from_pool(
+            db.clone(),
+            mock_producer,
+            APP_METRICS_TOPIC.to_owned(),
+            APP_METRICS_BUCKET,
+        )
+        .expect("unable to create webhook cleaner");
 
         let queue = PgQueue::new_from_pool("webhooks", db.clone()).await;
 
@@ -836,6 +1043,7 @@ mod tests {
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
                 url: "http://example.com".to_owned(),
+                body_transform: None,
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
@@ -864,6 +1072,7 @@ mod tests {
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
                 url: "http://example.com".to_owned(),
+                body_transform: None,
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::time;
+
+use crate::latency::HostLatencies;
+
+struct Settings {
+    multiplier: f64,
+    min: time::Duration,
+    max: time::Duration,
+}
+
+/// Adapts the timeout of webhook requests to how fast their destination host usually responds,
+/// so that fast hosts get tight timeouts and slow ones get slack.
+///
+/// The timeout of a host is `multiplier` times the p99 of its recent latencies, bounded by
+/// `min` and `max`. Hosts without enough recent latencies use `max`.
+#[derive(Clone, Default)]
+pub struct AdaptiveTimeouts {
+    settings: Option<Arc<Settings>>,
+}
+
+impl AdaptiveTimeouts {
+    pub fn new(multiplier: f64, min: time::Duration, max: time::Duration) -> Self {
+        Self {
+            settings: Some(Arc::new(Settings {
+                multiplier,
+                min,
+                max: max.max(min),
+            })),
+        }
+    }
+
+    /// Requests keep the timeout of the HTTP client.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Whether the latencies of every host are needed to adapt their timeout.
+    pub fn is_enabled(&self) -> bool {
+        self.settings.is_some()
+    }
+
+    /// Return the timeout to use for the next request to `host`, or None if disabled.
+    pub fn timeout(&self, latencies: &HostLatencies, host: &str) -> Option<time::Duration> {
+        let settings = self.settings.as_ref()?;
+
+        let p99 = match latencies.percentile(host, 99.0) {
+            Some(p99) => p99,
+            None => return Some(settings.max),
+        };
+
+        Some(
+            p99.mul_f64(settings.multiplier)
+                .clamp(settings.min, settings.max),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeouts() -> AdaptiveTimeouts {
+        AdaptiveTimeouts::new(
+            3.0,
+            time::Duration::from_millis(100),
+            time::Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn test_disabled_timeouts() {
+        let latencies = HostLatencies::new(100);
+        for _ in 0..100 {
+            latencies.record("example.com", time::Duration::from_millis(50));
+        }
+
+        assert_eq!(
+            AdaptiveTimeouts::disabled().timeout(&latencies, "example.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_timeout_follows_host_p99() {
+        let timeouts = timeouts();
+        let latencies = HostLatencies::new(100);
+
+        // 98 fast requests and 2 slower ones, the p99 is one of the slow ones
+        for _ in 0..98 {
+            latencies.record("fast.example.com", time::Duration::from_millis(50));
+        }
+        for _ in 0..2 {
+            latencies.record("fast.example.com", time::Duration::from_millis(200));
+        }
+
+        let timeout = timeouts
+            .timeout(&latencies, "fast.example.com")
+            .expect("timeouts are enabled");
+        assert!(timeout >= time::Duration::from_millis(450));
+        assert!(timeout <= time::Duration::from_millis(650));
+    }
+
+    #[test]
+    fn test_timeout_is_bounded() {
+        let timeouts = timeouts();
+        let latencies = HostLatencies::new(100);
+
+        for _ in 0..100 {
+            latencies.record("fast.example.com", time::Duration::from_millis(1));
+            latencies.record("slow.example.com", time::Duration::from_secs(8));
+        }
+
+        assert_eq!(
+            timeouts.timeout(&latencies, "fast.example.com"),
+            Some(time::Duration::from_millis(100))
+        );
+        assert_eq!(
+            timeouts.timeout(&latencies, "slow.example.com"),
+            Some(time::Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn test_unknown_hosts_get_max_timeout() {
+        let timeouts = timeouts();
+        let latencies = HostLatencies::new(100);
+
+        latencies.record("new.example.com", time::Duration::from_millis(50));
+
+        assert_eq!(
+            timeouts.timeout(&latencies, "new.example.com"),
+            Some(time::Duration::from_secs(10))
+        );
+        assert_eq!(
+            timeouts.timeout(&latencies, "unknown.example.com"),
+            Some(time::Duration::from_secs(10))
+        );
+    }
+}
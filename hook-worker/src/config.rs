@@ -1,7 +1,16 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
 use std::str::FromStr;
 use std::time;
 
 use envconfig::Envconfig;
+use hook_common::config::{
+    check_database_url, check_kafka_compression_codec, check_kafka_hosts, check_not_zero,
+    ConfigError, KafkaTopics,
+};
+use hook_common::pgqueue::{DequeueOrder, QueueShard};
+use hook_common::retry::{RetryPolicies, RetryPolicy};
 
 #[derive(Envconfig, Clone)]
 pub struct Config {
@@ -17,6 +26,16 @@ pub struct Config {
     #[envconfig(default = "worker")]
     pub worker_name: String,
 
+    // OTLP gRPC endpoint to export traces to, like http://localhost:4317. Traces are only logged
+    // if unset.
+    pub otel_url: Option<String>,
+
+    #[envconfig(default = "1.0")]
+    pub otel_sampling_rate: f64,
+
+    #[envconfig(default = "hook-worker")]
+    pub otel_service_name: String,
+
     #[envconfig(default = "default")]
     pub queue_name: NonEmptyString,
 
@@ -26,20 +45,187 @@ pub struct Config {
     #[envconfig(default = "5000")]
     pub request_timeout: EnvMsDuration,
 
+    #[envconfig(default = "2500")]
+    pub slow_request_threshold: EnvMsDuration,
+
+    // Time out requests after this many times the p99 latency of their target host, bounded by
+    // ADAPTIVE_TIMEOUT_MIN and ADAPTIVE_TIMEOUT_MAX. REQUEST_TIMEOUT is used for all hosts if unset.
+    pub adaptive_timeout_multiplier: Option<f64>,
+
+    #[envconfig(default = "500")]
+    pub adaptive_timeout_min: EnvMsDuration,
+
+    #[envconfig(default = "5000")]
+    pub adaptive_timeout_max: EnvMsDuration,
+
+    // Maximum number of target hosts whose recent latencies are kept for adaptive timeouts and
+    // hedging, the host recorded the longest ago is forgotten for a new one.
+    #[envconfig(default = "10000")]
+    pub max_latency_hosts: usize,
+
+    // Comma-separated hosts that GET, PUT and DELETE requests are hedged to: when a request hasn't
+    // got a response after the HEDGING_PERCENTILE of the recent latencies of its host, bounded by
+    // HEDGING_MIN_DELAY and HEDGING_MAX_DELAY, a second one is sent and the first response kept.
+    #[envconfig(default = "")]
+    pub hedged_hosts: Hosts,
+
+    #[envconfig(default = "95")]
+    pub hedging_percentile: f64,
+
+    #[envconfig(default = "100")]
+    pub hedging_min_delay: EnvMsDuration,
+
+    #[envconfig(default = "1000")]
+    pub hedging_max_delay: EnvMsDuration,
+
+    // Requests per second sent to each target host, bursting up to a second of requests, or to
+    // the hosts in HOST_RATE_LIMIT_OVERRIDES as comma-separated host=rps entries. Jobs waiting
+    // longer than HOST_RATE_LIMIT_MAX_WAIT for their turn are deferred until then instead, without
+    // using up an attempt. Hosts are unlimited if unset.
+    pub host_rate_limit: Option<NonZeroU32>,
+
+    #[envconfig(default = "")]
+    pub host_rate_limit_overrides: HostRateLimitOverrides,
+
+    #[envconfig(default = "1000")]
+    pub host_rate_limit_max_wait: EnvMsDuration,
+
+    #[envconfig(default = "100")]
+    pub max_host_labels: usize,
+
     #[envconfig(default = "1024")]
     pub max_concurrent_jobs: usize,
 
+    // Maximum number of dequeued batches being processed at once, bounding the job bodies held
+    // in memory. Dequeuing pauses while this many batches are in flight. Unbounded if unset.
+    pub max_in_flight_batches: Option<usize>,
+
     #[envconfig(default = "100")]
     pub max_pg_connections: u32,
 
+    // Number of processes with a pool of MAX_PG_CONNECTIONS connecting to the database, checked
+    // against its max_connections at startup. Going over only logs a warning, unless strict.
+    #[envconfig(default = "1")]
+    pub pg_connection_consumers: u32,
+
+    #[envconfig(default = "false")]
+    pub pg_connections_strict: bool,
+
     #[envconfig(nested = true)]
     pub retry_policy: RetryPolicyConfig,
 
     #[envconfig(default = "1")]
     pub dequeue_batch_size: u32,
 
+    // Number of jobs of a batch dequeued, and committed, in the same transaction. Each chunk holds
+    // a connection until its jobs are done, so a batch must not have more than MAX_PG_CONNECTIONS.
+    #[envconfig(default = "100")]
+    pub commit_chunk_size: u32,
+
+    // Dequeue the oldest (fifo) or the newest (lifo) jobs first. lifo suits queues where old
+    // jobs are stale, like alerts, which should then get a short TTL.
+    #[envconfig(default = "fifo")]
+    pub dequeue_order: DequeueOrder,
+
+    // Only dequeue the jobs of one shard of the queue, as index/count like 2/8, to reduce lock
+    // contention between workers over large backlogs. Jobs are sharded by target, so every
+    // shard must have workers. Jobs are dequeued from the whole queue if unset.
+    pub queue_shard: Option<QueueShard>,
+
     #[envconfig(default = "false")]
     pub allow_internal_ips: bool,
+
+    // Comma-separated nameservers, as ip or ip:port, to resolve target hosts with instead of the
+    // system resolver. Results are still filtered unless ALLOW_INTERNAL_IPS is set.
+    #[envconfig(default = "")]
+    pub dns_nameservers: DnsNameservers,
+
+    #[envconfig(default = "false")]
+    pub body_transform_null_as_object: bool,
+
+    // Semicolon-separated host=expression rules, where expression is a JMESPath expression the
+    // JSON body of successful responses from host must evaluate to true against, like
+    // `api.example.com=status == 'ok'`. Failing responses are retried if
+    // RESPONSE_VALIDATION_RETRYABLE, failed right away otherwise.
+    #[envconfig(default = "")]
+    pub response_validations: ResponseValidationRules,
+
+    #[envconfig(default = "false")]
+    pub response_validation_retryable: bool,
+
+    // Semicolon-separated host=pattern rules matched, ignoring case, against the body of failed
+    // responses from host. Retryable failures matching a permanent pattern are failed right away,
+    // like `api.example.com=permanent:`, and non-retryable failures matching a retryable pattern
+    // are retried. A host may have several rules.
+    #[envconfig(default = "")]
+    pub permanent_error_body_patterns: ErrorBodyPatterns,
+
+    #[envconfig(default = "")]
+    pub retryable_error_body_patterns: ErrorBodyPatterns,
+
+    // Semicolon-separated host=codes rules of status codes that host responds with on success on
+    // top of 2xx, like `api.example.com=302,418`. Redirects are followed, so a 3xx status is only
+    // seen when its response can't be followed.
+    #[envconfig(default = "")]
+    pub success_status_codes: SuccessStatusCodes,
+
+    // Send a W3C traceparent header with webhook requests, continuing the trace of the job's own
+    // traceparent header if it has one, so that destinations can correlate them with ours.
+    #[envconfig(default = "false")]
+    pub propagate_trace_context: bool,
+
+    // Send the body of GET jobs too. GET requests are sent without a body by default, as some
+    // servers reject them with one.
+    #[envconfig(default = "false")]
+    pub send_get_body: bool,
+
+    // Jobs created longer ago than this are failed as expired instead of being sent, even with
+    // attempts left, as their data is likely stale. Jobs never expire if unset.
+    pub max_age: Option<EnvMsDuration>,
+
+    // Jobs with more headers than MAX_HEADER_COUNT, or whose header names and values add up to
+    // more than MAX_HEADER_BYTES either as enqueued or once their templates are rendered, are
+    // failed without being sent. Should match the settings of the API.
+    #[envconfig(default = "100")]
+    pub max_header_count: usize,
+
+    #[envconfig(default = "65536")]
+    pub max_header_bytes: usize,
+
+    // Number of retries each target host can get before its jobs are failed instead of retried,
+    // unlimited if unset. One retry is given back every RETRY_BUDGET_REFILL_INTERVAL.
+    pub retry_budget_size: Option<NonZeroU32>,
+
+    #[envconfig(default = "1000")]
+    pub retry_budget_refill_interval: EnvMsDuration,
+
+    // Identical job errors for the same host are logged at most once per window, with a count
+    // of the ones suppressed in between.
+    #[envconfig(default = "60000")]
+    pub error_log_window: EnvMsDuration,
+
+    // Stop the worker after finding no jobs for this long, for it to be scaled to zero until jobs
+    // arrive. Never stops if unset.
+    pub idle_shutdown_timeout: Option<EnvMsDuration>,
+
+    // Report live for this long at startup, before the worker starts polling, so that a slow
+    // warmup doesn't fail liveness probes. Readiness is only reported once the worker dequeued
+    // from the database, whatever this period.
+    #[envconfig(default = "60000")]
+    pub startup_grace_period: EnvMsDuration,
+
+    // Keep serving metrics for this long after the worker stops, for a final scrape to collect
+    // the metrics of its last jobs.
+    #[envconfig(default = "0")]
+    pub metrics_drain: EnvMsDuration,
+
+    // Comma-separated Kafka topics that jobs with a kafka://topic URL may be produced to, jobs
+    // targeting any other topic are failed. Should match the setting of the API.
+    #[envconfig(default = "")]
+    pub kafka_webhook_topics: KafkaTopics,
+
+    #[envconfig(nested = true)]
+    pub kafka: KafkaConfig,
 }
 
 impl Config {
@@ -47,6 +233,133 @@ impl Config {
     pub fn bind(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// Check the settings that parse but can't work, listing every problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        check_database_url("DATABASE_URL", &self.database_url, &mut problems);
+        check_not_zero("REQUEST_TIMEOUT", self.request_timeout.0, &mut problems);
+        check_not_zero("POLL_INTERVAL", self.poll_interval.0, &mut problems);
+        check_not_zero(
+            "MAX_CONCURRENT_JOBS",
+            self.max_concurrent_jobs,
+            &mut problems,
+        );
+        check_not_zero("MAX_PG_CONNECTIONS", self.max_pg_connections, &mut problems);
+        check_not_zero(
+            "PG_CONNECTION_CONSUMERS",
+            self.pg_connection_consumers,
+            &mut problems,
+        );
+        check_not_zero("DEQUEUE_BATCH_SIZE", self.dequeue_batch_size, &mut problems);
+        check_not_zero("COMMIT_CHUNK_SIZE", self.commit_chunk_size, &mut problems);
+        if self.commit_chunk_size > 0 {
+            let chunks = self.dequeue_batch_size.div_ceil(self.commit_chunk_size);
+            if chunks > self.max_pg_connections {
+                problems.push(format!(
+                    "COMMIT_CHUNK_SIZE splits batches of DEQUEUE_BATCH_SIZE jobs into {} transactions, over the {} of MAX_PG_CONNECTIONS",
+                    chunks, self.max_pg_connections
+                ));
+            }
+        }
+        if let Some(max_age) = self.max_age {
+            check_not_zero("MAX_AGE", max_age.0, &mut problems);
+        }
+        if let Some(max_in_flight_batches) = self.max_in_flight_batches {
+            check_not_zero(
+                "MAX_IN_FLIGHT_BATCHES",
+                max_in_flight_batches,
+                &mut problems,
+            );
+        }
+        check_not_zero(
+            "RETRY_BUDGET_REFILL_INTERVAL",
+            self.retry_budget_refill_interval.0,
+            &mut problems,
+        );
+
+        if let Some(multiplier) = self.adaptive_timeout_multiplier {
+            if !(multiplier.is_finite() && multiplier > 0.0) {
+                problems.push(format!(
+                    "ADAPTIVE_TIMEOUT_MULTIPLIER must be a positive number, got {}",
+                    multiplier
+                ));
+            }
+        }
+        if !(self.hedging_percentile > 0.0 && self.hedging_percentile <= 100.0) {
+            problems.push(format!(
+                "HEDGING_PERCENTILE must be greater than 0 and at most 100, got {}",
+                self.hedging_percentile
+            ));
+        }
+        if self.hedging_min_delay.0 > self.hedging_max_delay.0 {
+            problems
+                .push("HEDGING_MIN_DELAY must not be greater than HEDGING_MAX_DELAY".to_owned());
+        }
+        if !(0.0..=1.0).contains(&self.otel_sampling_rate) {
+            problems.push(format!(
+                "OTEL_SAMPLING_RATE must be between 0 and 1, got {}",
+                self.otel_sampling_rate
+            ));
+        }
+        check_not_zero("MAX_LATENCY_HOSTS", self.max_latency_hosts, &mut problems);
+        if self.adaptive_timeout_min.0 > self.adaptive_timeout_max.0 {
+            problems.push(
+                "ADAPTIVE_TIMEOUT_MIN must not be greater than ADAPTIVE_TIMEOUT_MAX".to_owned(),
+            );
+        }
+
+        check_not_zero(
+            "BACKOFF_COEFFICIENT",
+            self.retry_policy.backoff_coefficient,
+            &mut problems,
+        );
+        if self.retry_policy.initial_interval.0 > self.retry_policy.maximum_interval.0 {
+            problems.push("INITIAL_INTERVAL must not be greater than MAXIMUM_INTERVAL".to_owned());
+        }
+        if self.retry_policy.maximum_retry_after.0 > self.retry_policy.maximum_interval.0 {
+            problems
+                .push("MAXIMUM_RETRY_AFTER must not be greater than MAXIMUM_INTERVAL".to_owned());
+        }
+        for queue_policy in &self.retry_policy.queue_retry_policies.0 {
+            if queue_policy.initial_interval.0 > queue_policy.maximum_interval.0 {
+                problems.push(format!(
+                    "the initial interval of the {} queue in QUEUE_RETRY_POLICIES must not be greater than its maximum interval",
+                    queue_policy.queue
+                ));
+            }
+        }
+
+        if let Some(kafka_hosts) = &self.kafka.kafka_hosts {
+            check_kafka_hosts("KAFKA_HOSTS", kafka_hosts, &mut problems);
+        }
+        check_kafka_compression_codec(
+            "KAFKA_COMPRESSION_CODEC",
+            &self.kafka.kafka_compression_codec,
+            &mut problems,
+        );
+
+        ConfigError::check(problems)
+    }
+}
+
+#[derive(Envconfig, Clone)]
+pub struct KafkaConfig {
+    // Brokers to produce the jobs targeting a kafka://topic URL to. These jobs fail if unset.
+    pub kafka_hosts: Option<String>,
+
+    #[envconfig(default = "20")]
+    pub kafka_producer_linger_ms: u32, // Maximum time between producer batches during low traffic
+
+    #[envconfig(default = "20000")]
+    pub kafka_message_timeout_ms: u32, // Time before we stop retrying producing a message: 20 seconds
+
+    #[envconfig(default = "none")]
+    pub kafka_compression_codec: String, // none, gzip, snappy, lz4, zstd
+
+    #[envconfig(default = "false")]
+    pub kafka_tls: bool,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -76,7 +389,336 @@ pub struct RetryPolicyConfig {
     #[envconfig(default = "100000")]
     pub maximum_interval: EnvMsDuration,
 
+    // Retry-After values from responses are clamped to this. Must not be greater than
+    // MAXIMUM_INTERVAL, as any longer Retry-After falls back to the backoff instead.
+    #[envconfig(default = "100000")]
+    pub maximum_retry_after: EnvMsDuration,
+
     pub retry_queue_name: Option<NonEmptyString>,
+
+    // Queues to escalate retries to by attempt, e.g. `3:webhooks-slow,5:webhooks-cold` sends
+    // attempts 3 and 4 to `webhooks-slow` and any later ones to `webhooks-cold`. Tiers are global:
+    // they apply to the jobs of every queue, including those with QUEUE_RETRY_POLICIES.
+    #[envconfig(from = "RETRY_QUEUE_TIERS", default = "")]
+    pub retry_queue_tiers: RetryQueueTiers,
+
+    // Backoff overrides per queue. The retry queue, its tiers and MAXIMUM_RETRY_AFTER are global,
+    // so they still apply to the queues listed here.
+    #[envconfig(from = "QUEUE_RETRY_POLICIES", default = "")]
+    pub queue_retry_policies: QueueRetryPolicyConfigs,
+}
+
+impl RetryPolicyConfig {
+    /// Build the default retry policy, and the policies of the queues in `queue_retry_policies`.
+    pub fn retry_policies(&self) -> RetryPolicies {
+        let default = self.retry_policy(
+            self.backoff_coefficient,
+            self.initial_interval.0,
+            self.maximum_interval.0,
+        );
+
+        self.queue_retry_policies.0.iter().fold(
+            RetryPolicies::new(default),
+            |policies, queue_config| {
+                policies.queue(
+                    &queue_config.queue,
+                    self.retry_policy(
+                        queue_config.backoff_coefficient,
+                        queue_config.initial_interval.0,
+                        queue_config.maximum_interval.0,
+                    ),
+                )
+            },
+        )
+    }
+
+    /// Build a retry policy with the given backoff, and the global retry queue, tiers and maximum
+    /// Retry-After.
+    fn retry_policy(
+        &self,
+        backoff_coefficient: u32,
+        initial_interval: time::Duration,
+        maximum_interval: time::Duration,
+    ) -> RetryPolicy {
+        let builder = RetryPolicy::build(backoff_coefficient, initial_interval)
+            .maximum_interval(maximum_interval)
+            .maximum_retry_after(self.maximum_retry_after.0);
+        let builder = match &self.retry_queue_name {
+            None => builder,
+            Some(queue) => builder.queue(queue.as_str()),
+        };
+
+        self.retry_queue_tiers
+            .0
+            .iter()
+            .fold(builder, |builder, (from_attempt, queue)| {
+                builder.queue_tier(*from_attempt, queue)
+            })
+            .provide()
+    }
+}
+
+/// Retry policy overrides for a single queue.
+#[derive(Debug, Clone)]
+pub struct QueueRetryPolicyConfig {
+    pub queue: String,
+    pub backoff_coefficient: u32,
+    pub initial_interval: EnvMsDuration,
+    pub maximum_interval: EnvMsDuration,
+}
+
+/// Retry policy overrides per queue, parsed from a comma-separated list of
+/// `queue:backoff_coefficient:initial_interval_ms:maximum_interval_ms` entries.
+#[derive(Debug, Clone, Default)]
+pub struct QueueRetryPolicyConfigs(pub Vec<QueueRetryPolicyConfig>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseQueueRetryPolicyConfigsError;
+
+impl FromStr for QueueRetryPolicyConfigs {
+    type Err = ParseQueueRetryPolicyConfigsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut configs = Vec::new();
+
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parts: Vec<&str> = entry.split(':').collect();
+            let &[queue, backoff_coefficient, initial_interval, maximum_interval] =
+                parts.as_slice()
+            else {
+                return Err(ParseQueueRetryPolicyConfigsError);
+            };
+
+            if queue.is_empty() {
+                return Err(ParseQueueRetryPolicyConfigsError);
+            }
+
+            configs.push(QueueRetryPolicyConfig {
+                queue: queue.to_owned(),
+                backoff_coefficient: backoff_coefficient
+                    .parse()
+                    .map_err(|_| ParseQueueRetryPolicyConfigsError)?,
+                initial_interval: initial_interval
+                    .parse()
+                    .map_err(|_| ParseQueueRetryPolicyConfigsError)?,
+                maximum_interval: maximum_interval
+                    .parse()
+                    .map_err(|_| ParseQueueRetryPolicyConfigsError)?,
+            });
+        }
+
+        Ok(QueueRetryPolicyConfigs(configs))
+    }
+}
+
+/// Retry queues by the attempt they apply from, parsed from a comma-separated list of
+/// `from_attempt:queue` entries.
+#[derive(Debug, Clone, Default)]
+pub struct RetryQueueTiers(pub Vec<(u32, String)>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseRetryQueueTiersError;
+
+impl FromStr for RetryQueueTiers {
+    type Err = ParseRetryQueueTiersError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tiers = Vec::new();
+
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((from_attempt, queue)) = entry.split_once(':') else {
+                return Err(ParseRetryQueueTiersError);
+            };
+            let from_attempt = from_attempt
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| ParseRetryQueueTiersError)?;
+            let queue = queue.trim();
+            if from_attempt == 0 || queue.is_empty() {
+                return Err(ParseRetryQueueTiersError);
+            }
+
+            tiers.push((from_attempt, queue.to_owned()));
+        }
+
+        Ok(RetryQueueTiers(tiers))
+    }
+}
+
+/// Nameservers parsed from a comma-separated list of `ip` or `ip:port` entries, using port 53
+/// when it's omitted.
+#[derive(Debug, Clone, Default)]
+pub struct DnsNameservers(pub Vec<SocketAddr>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseDnsNameserversError;
+
+impl FromStr for DnsNameservers {
+    type Err = ParseDnsNameserversError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(|entry| {
+                entry
+                    .parse::<SocketAddr>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| ParseDnsNameserversError)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(DnsNameservers)
+    }
+}
+
+/// Hosts parsed from a comma-separated list.
+#[derive(Debug, Clone, Default)]
+pub struct Hosts(pub HashSet<String>);
+
+impl FromStr for Hosts {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Hosts(
+            s.split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        ))
+    }
+}
+
+/// Response validation rules per host, parsed from a semicolon-separated list of
+/// `host=expression` entries. Semicolons are used as JMESPath expressions may contain commas.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseValidationRules(pub HashMap<String, String>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseResponseValidationRulesError;
+
+impl FromStr for ResponseValidationRules {
+    type Err = ParseResponseValidationRulesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rules = HashMap::new();
+
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, expression)) = entry.split_once('=') else {
+                return Err(ParseResponseValidationRulesError);
+            };
+            let (host, expression) = (host.trim(), expression.trim());
+            if host.is_empty() || jmespath::compile(expression).is_err() {
+                return Err(ParseResponseValidationRulesError);
+            }
+
+            rules.insert(host.to_owned(), expression.to_owned());
+        }
+
+        Ok(ResponseValidationRules(rules))
+    }
+}
+
+/// Error body patterns per host, parsed from a semicolon-separated list of `host=pattern`
+/// entries. Semicolons are used as patterns may contain commas.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorBodyPatterns(pub HashMap<String, Vec<String>>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseErrorBodyPatternsError;
+
+impl FromStr for ErrorBodyPatterns {
+    type Err = ParseErrorBodyPatternsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut patterns: HashMap<String, Vec<String>> = HashMap::new();
+
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, pattern)) = entry.split_once('=') else {
+                return Err(ParseErrorBodyPatternsError);
+            };
+            let (host, pattern) = (host.trim(), pattern.trim());
+            if host.is_empty() || pattern.is_empty() {
+                return Err(ParseErrorBodyPatternsError);
+            }
+
+            patterns
+                .entry(host.to_owned())
+                .or_default()
+                .push(pattern.to_owned());
+        }
+
+        Ok(ErrorBodyPatterns(patterns))
+    }
+}
+
+/// Extra success status codes per host, parsed from a semicolon-separated list of
+/// `host=code,code` entries.
+#[derive(Debug, Clone, Default)]
+pub struct SuccessStatusCodes(pub HashMap<String, HashSet<u16>>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseSuccessStatusCodesError;
+
+impl FromStr for SuccessStatusCodes {
+    type Err = ParseSuccessStatusCodesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut codes: HashMap<String, HashSet<u16>> = HashMap::new();
+
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, host_codes)) = entry.split_once('=') else {
+                return Err(ParseSuccessStatusCodesError);
+            };
+            let host = host.trim();
+            if host.is_empty() {
+                return Err(ParseSuccessStatusCodesError);
+            }
+
+            for code in host_codes.split(',') {
+                let code = match code.trim().parse::<u16>() {
+                    Ok(code) if (100..600).contains(&code) => code,
+                    _ => return Err(ParseSuccessStatusCodesError),
+                };
+                codes.entry(host.to_owned()).or_default().insert(code);
+            }
+        }
+
+        Ok(SuccessStatusCodes(codes))
+    }
+}
+
+/// Requests per second per host, parsed from a comma-separated list of `host=rps` entries.
+#[derive(Debug, Clone, Default)]
+pub struct HostRateLimitOverrides(pub HashMap<String, NonZeroU32>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHostRateLimitOverridesError;
+
+impl FromStr for HostRateLimitOverrides {
+    type Err = ParseHostRateLimitOverridesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = HashMap::new();
+
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((host, rps)) = entry.split_once('=') else {
+                return Err(ParseHostRateLimitOverridesError);
+            };
+            let host = host.trim();
+            if host.is_empty() {
+                return Err(ParseHostRateLimitOverridesError);
+            }
+            let rps = rps
+                .trim()
+                .parse::<NonZeroU32>()
+                .map_err(|_| ParseHostRateLimitOverridesError)?;
+
+            overrides.insert(host.to_owned(), rps);
+        }
+
+        Ok(HostRateLimitOverrides(overrides))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -102,3 +744,205 @@ impl FromStr for NonEmptyString {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::init_from_hashmap(&vars).expect("failed to parse config")
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        assert_eq!(config(&[]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_config_reports_every_problem() {
+        let error = config(&[
+            ("DATABASE_URL", "localhost:15432"),
+            ("REQUEST_TIMEOUT", "0"),
+            ("DEQUEUE_BATCH_SIZE", "0"),
+            ("ADAPTIVE_TIMEOUT_MULTIPLIER", "-1"),
+            ("ADAPTIVE_TIMEOUT_MIN", "10000"),
+            ("HEDGING_PERCENTILE", "0"),
+            ("INITIAL_INTERVAL", "200000"),
+            ("MAXIMUM_RETRY_AFTER", "200000"),
+            ("KAFKA_HOSTS", "kafka"),
+            ("KAFKA_COMPRESSION_CODEC", "brotli"),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+
+        let message = error.to_string();
+        for setting in [
+            "DATABASE_URL",
+            "REQUEST_TIMEOUT",
+            "DEQUEUE_BATCH_SIZE",
+            "ADAPTIVE_TIMEOUT_MULTIPLIER",
+            "ADAPTIVE_TIMEOUT_MIN",
+            "HEDGING_PERCENTILE",
+            "INITIAL_INTERVAL",
+            "MAXIMUM_RETRY_AFTER",
+            "KAFKA_HOSTS",
+            "KAFKA_COMPRESSION_CODEC",
+        ] {
+            assert!(message.contains(setting), "{} is not reported", setting);
+        }
+        assert_eq!(error.0.len(), 10);
+    }
+
+    #[test]
+    fn test_batches_must_fit_in_the_pool() {
+        let fits = config(&[
+            ("DEQUEUE_BATCH_SIZE", "1000"),
+            ("COMMIT_CHUNK_SIZE", "100"),
+            ("MAX_PG_CONNECTIONS", "10"),
+        ]);
+        assert_eq!(fits.validate(), Ok(()));
+
+        let error = config(&[
+            ("DEQUEUE_BATCH_SIZE", "1001"),
+            ("COMMIT_CHUNK_SIZE", "100"),
+            ("MAX_PG_CONNECTIONS", "10"),
+        ])
+        .validate()
+        .expect_err("config should be invalid");
+        assert_eq!(error.0.len(), 1);
+        assert!(error.to_string().contains("COMMIT_CHUNK_SIZE"));
+    }
+
+    #[test]
+    fn test_parse_response_validation_rules() {
+        let rules: ResponseValidationRules =
+            "api.example.com=status == 'ok'; hooks.example.com = contains(result, 'success');"
+                .parse()
+                .expect("failed to parse rules");
+
+        assert_eq!(rules.0.len(), 2);
+        assert_eq!(rules.0["api.example.com"], "status == 'ok'");
+        assert_eq!(rules.0["hooks.example.com"], "contains(result, 'success')");
+
+        assert!("".parse::<ResponseValidationRules>().unwrap().0.is_empty());
+        assert!("api.example.com"
+            .parse::<ResponseValidationRules>()
+            .is_err());
+        assert!("=status == 'ok'"
+            .parse::<ResponseValidationRules>()
+            .is_err());
+        assert!("api.example.com=status =="
+            .parse::<ResponseValidationRules>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_error_body_patterns() {
+        let patterns: ErrorBodyPatterns =
+            "api.example.com=permanent:; api.example.com = invalid API key;hooks.example.com=a=b"
+                .parse()
+                .expect("failed to parse patterns");
+
+        assert_eq!(patterns.0.len(), 2);
+        assert_eq!(
+            patterns.0["api.example.com"],
+            vec!["permanent:", "invalid API key"]
+        );
+        assert_eq!(patterns.0["hooks.example.com"], vec!["a=b"]);
+
+        assert!("".parse::<ErrorBodyPatterns>().unwrap().0.is_empty());
+        assert!("api.example.com".parse::<ErrorBodyPatterns>().is_err());
+        assert!("=permanent".parse::<ErrorBodyPatterns>().is_err());
+        assert!("api.example.com= ".parse::<ErrorBodyPatterns>().is_err());
+    }
+
+    #[test]
+    fn test_parse_success_status_codes() {
+        let codes: SuccessStatusCodes = "api.example.com=302, 418; hooks.example.com = 202;"
+            .parse()
+            .expect("failed to parse status codes");
+
+        assert_eq!(codes.0.len(), 2);
+        assert_eq!(codes.0["api.example.com"], HashSet::from([302, 418]));
+        assert_eq!(codes.0["hooks.example.com"], HashSet::from([202]));
+
+        assert!("".parse::<SuccessStatusCodes>().unwrap().0.is_empty());
+        assert!("api.example.com".parse::<SuccessStatusCodes>().is_err());
+        assert!("=418".parse::<SuccessStatusCodes>().is_err());
+        assert!("api.example.com=".parse::<SuccessStatusCodes>().is_err());
+        assert!("api.example.com=teapot"
+            .parse::<SuccessStatusCodes>()
+            .is_err());
+        assert!("api.example.com=999".parse::<SuccessStatusCodes>().is_err());
+    }
+
+    #[test]
+    fn test_parse_host_rate_limit_overrides() {
+        let overrides: HostRateLimitOverrides = "api.example.com=10, hooks.example.com = 1,"
+            .parse()
+            .expect("failed to parse overrides");
+
+        assert_eq!(overrides.0.len(), 2);
+        assert_eq!(overrides.0["api.example.com"].get(), 10);
+        assert_eq!(overrides.0["hooks.example.com"].get(), 1);
+
+        assert!("".parse::<HostRateLimitOverrides>().unwrap().0.is_empty());
+        assert!("api.example.com".parse::<HostRateLimitOverrides>().is_err());
+        assert!("=10".parse::<HostRateLimitOverrides>().is_err());
+        assert!("api.example.com=0"
+            .parse::<HostRateLimitOverrides>()
+            .is_err());
+        assert!("api.example.com=fast"
+            .parse::<HostRateLimitOverrides>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_retry_queue_tiers() {
+        let tiers: RetryQueueTiers = "3:webhooks-slow, 5 : webhooks-cold,"
+            .parse()
+            .expect("failed to parse tiers");
+
+        assert_eq!(
+            tiers.0,
+            vec![
+                (3, "webhooks-slow".to_owned()),
+                (5, "webhooks-cold".to_owned())
+            ]
+        );
+
+        assert!("".parse::<RetryQueueTiers>().unwrap().0.is_empty());
+        assert!("webhooks-slow".parse::<RetryQueueTiers>().is_err());
+        assert!("0:webhooks-slow".parse::<RetryQueueTiers>().is_err());
+        assert!("3:".parse::<RetryQueueTiers>().is_err());
+        assert!("third:webhooks-slow".parse::<RetryQueueTiers>().is_err());
+    }
+
+    #[test]
+    fn test_queue_retry_policies_keep_the_global_settings() {
+        let policies = config(&[
+            ("RETRY_QUEUE_NAME", "webhooks-retries"),
+            ("RETRY_QUEUE_TIERS", "3:webhooks-slow"),
+            ("QUEUE_RETRY_POLICIES", "alerts:3:5000:60000"),
+        ])
+        .retry_policy
+        .retry_policies();
+
+        let default = policies.get("webhooks");
+        assert_eq!(
+            default.retry_interval(1, None),
+            time::Duration::from_secs(1)
+        );
+        let alerts = policies.get("alerts");
+        assert_eq!(alerts.retry_interval(1, None), time::Duration::from_secs(5));
+
+        for policy in [default, alerts] {
+            assert_eq!(policy.retry_queue(1, "webhooks"), "webhooks-retries");
+            assert_eq!(policy.retry_queue(2, "webhooks"), "webhooks-slow");
+        }
+    }
+}
@@ -2,8 +2,10 @@ use std::error::Error;
 use std::fmt;
 use std::time;
 
-use crate::dns::NoPublicIPv4Error;
+use hook_common::dns::NoPublicIPv4Error;
+use hook_common::request::WebhookParseError;
 use hook_common::{pgqueue, webhook::WebhookJobError};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
 use thiserror::Error;
 
 /// Enumeration of error classes handled by `WebhookWorker`.
@@ -13,17 +15,10 @@ pub enum WebhookError {
     Parse(#[from] WebhookParseError),
     #[error(transparent)]
     Request(#[from] WebhookRequestError),
-}
-
-/// Enumeration of parsing errors that can occur as `WebhookWorker` sets up a webhook.
-#[derive(Error, Debug)]
-pub enum WebhookParseError {
-    #[error("{0} is not a valid HttpMethod")]
-    ParseHttpMethodError(String),
-    #[error("error parsing webhook headers")]
-    ParseHeadersError(http::Error),
-    #[error("error parsing webhook url")]
-    ParseUrlError(url::ParseError),
+    #[error(transparent)]
+    Kafka(#[from] WebhookKafkaError),
+    #[error(transparent)]
+    Response(#[from] WebhookValidationError),
 }
 
 /// Enumeration of request errors that can occur as `WebhookWorker` sends a request.
@@ -40,17 +35,74 @@ pub enum WebhookRequestError {
     },
 }
 
+/// Enumeration of errors that can occur as `WebhookWorker` produces a webhook to a Kafka topic.
+#[derive(Error, Debug)]
+pub enum WebhookKafkaError {
+    #[error("no kafka producer is configured to deliver to kafka:// urls")]
+    NotConfigured,
+    #[error("webhook url has no kafka topic")]
+    MissingTopic,
+    #[error("kafka topic {0} is not allowed for webhooks")]
+    TopicNotAllowed(String),
+    #[error("failed to produce webhook to kafka: {0}")]
+    ProduceError(KafkaError),
+}
+
+impl WebhookKafkaError {
+    /// Produce errors may go away on retry, unless the message is too large for the topic.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            WebhookKafkaError::NotConfigured
+            | WebhookKafkaError::MissingTopic
+            | WebhookKafkaError::TopicNotAllowed(_) => false,
+            WebhookKafkaError::ProduceError(error) => !matches!(
+                error.rdkafka_error_code(),
+                Some(RDKafkaErrorCode::MessageSizeTooLarge)
+            ),
+        }
+    }
+}
+
+impl From<&WebhookKafkaError> for WebhookJobError {
+    fn from(error: &WebhookKafkaError) -> Self {
+        match error {
+            WebhookKafkaError::NotConfigured
+            | WebhookKafkaError::MissingTopic
+            | WebhookKafkaError::TopicNotAllowed(_) => {
+                WebhookJobError::new_parse(&error.to_string())
+            }
+            WebhookKafkaError::ProduceError(_) => {
+                WebhookJobError::new_connection(&error.to_string())
+            }
+        }
+    }
+}
+
+/// A response with a successful status, but a body failing the validation configured for its host.
+#[derive(Error, Debug)]
+#[error("webhook response failed validation: {reason}\n{body}")]
+pub struct WebhookValidationError {
+    pub status: http::StatusCode,
+    pub reason: String,
+    pub body: String,
+    pub retryable: bool,
+}
+
+impl From<&WebhookValidationError> for WebhookJobError {
+    fn from(error: &WebhookValidationError) -> Self {
+        WebhookJobError::new_http_status(error.status.as_u16(), &error.to_string())
+    }
+}
+
 /// Enumeration of errors that can occur while handling a `reqwest::Response`.
 /// Currently, not consumed anywhere. Grouped here to support a common error type for
 /// `utils::first_n_bytes_of_response`.
 #[derive(Error, Debug)]
 pub enum WebhookResponseError {
-    #[error("failed to parse a response as UTF8")]
-    ParseUTF8StringError(#[from] std::str::Utf8Error),
     #[error("error while iterating over response body chunks")]
     StreamIterationError(#[from] reqwest::Error),
-    #[error("attempted to slice a chunk of length {0} with an out of bounds index of {1}")]
-    ChunkOutOfBoundsError(usize, usize),
+    #[error("failed to decompress a response body")]
+    DecompressionError(#[from] std::io::Error),
 }
 
 /// Implement display of `WebhookRequestError` by appending to the underlying `reqwest::Error`
@@ -140,13 +192,17 @@ pub enum WorkerError {
 }
 
 /// Check the error and it's sources (recursively) to return true if an error of the given type is found.
-/// TODO: use Error::sources() when stable
 pub fn is_error_source<T: Error + 'static>(err: &(dyn std::error::Error + 'static)) -> bool {
-    if err.is::<NoPublicIPv4Error>() {
-        return true;
-    }
-    match err.source() {
-        None => false,
-        Some(source) => is_error_source::<T>(source),
+    find_error_source::<T>(err).is_some()
+}
+
+/// Return the first error of the given type among the error and it's sources (recursively).
+/// TODO: use Error::sources() when stable
+pub fn find_error_source<'e, T: Error + 'static>(
+    err: &'e (dyn std::error::Error + 'static),
+) -> Option<&'e T> {
+    match err.downcast_ref::<T>() {
+        Some(err) => Some(err),
+        None => find_error_source::<T>(err.source()?),
     }
 }
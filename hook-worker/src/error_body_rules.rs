@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Overrides whether a failed request is retried based on its response body, for destinations
+/// whose status doesn't tell, like a 500 saying `permanent: invalid API key`.
+///
+/// Rules map a host to patterns, matched as case-insensitive substrings of response bodies:
+/// retryable failures whose body matches a permanent pattern are failed right away, and
+/// non-retryable failures whose body matches a retryable pattern are retried.
+#[derive(Clone, Default)]
+pub struct ErrorBodyRules {
+    permanent: Arc<HashMap<String, Vec<String>>>,
+    retryable: Arc<HashMap<String, Vec<String>>>,
+}
+
+impl ErrorBodyRules {
+    pub fn new(
+        permanent: HashMap<String, Vec<String>>,
+        retryable: HashMap<String, Vec<String>>,
+    ) -> Self {
+        Self {
+            permanent: Arc::new(lowercase_patterns(permanent)),
+            retryable: Arc::new(lowercase_patterns(retryable)),
+        }
+    }
+
+    /// Failures are only classified by status.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Whether to retry a failed request to `host` that its status classified as
+    /// `retryable_status`, given the `body` of its response.
+    pub fn is_retryable(
+        &self,
+        host: Option<&str>,
+        retryable_status: bool,
+        body: Option<&str>,
+    ) -> bool {
+        let (Some(host), Some(body)) = (host, body) else {
+            return retryable_status;
+        };
+        let overrides = match retryable_status {
+            true => &self.permanent,
+            false => &self.retryable,
+        };
+        let Some(patterns) = overrides.get(host) else {
+            return retryable_status;
+        };
+
+        let body = body.to_lowercase();
+        match patterns
+            .iter()
+            .any(|pattern| body.contains(pattern.as_str()))
+        {
+            true => !retryable_status,
+            false => retryable_status,
+        }
+    }
+}
+
+fn lowercase_patterns(rules: HashMap<String, Vec<String>>) -> HashMap<String, Vec<String>> {
+    rules
+        .into_iter()
+        .map(|(host, patterns)| (host, patterns.iter().map(|p| p.to_lowercase()).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> ErrorBodyRules {
+        ErrorBodyRules::new(
+            HashMap::from([(
+                "example.com".to_owned(),
+                vec!["Permanent:".to_owned(), "invalid api key".to_owned()],
+            )]),
+            HashMap::from([("example.com".to_owned(), vec!["try again".to_owned()])]),
+        )
+    }
+
+    #[test]
+    fn test_permanent_patterns() {
+        let rules = rules();
+        let host = Some("example.com");
+
+        assert!(!rules.is_retryable(host, true, Some("permanent: invalid API key")));
+        assert!(!rules.is_retryable(host, true, Some(r#"{"error": "Invalid API Key"}"#)));
+        assert!(rules.is_retryable(host, true, Some("internal server error")));
+        // Only for the hosts they are configured for
+        assert!(rules.is_retryable(Some("other.com"), true, Some("permanent: oops")));
+        assert!(rules.is_retryable(host, true, None));
+    }
+
+    #[test]
+    fn test_retryable_patterns() {
+        let rules = rules();
+        let host = Some("example.com");
+
+        assert!(rules.is_retryable(host, false, Some("Busy, please TRY AGAIN later")));
+        assert!(!rules.is_retryable(host, false, Some("bad request")));
+        assert!(!rules.is_retryable(Some("other.com"), false, Some("try again")));
+        assert!(!rules.is_retryable(None, false, Some("try again")));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let rules = ErrorBodyRules::disabled();
+        assert!(rules.is_retryable(Some("example.com"), true, Some("permanent")));
+        assert!(!rules.is_retryable(Some("example.com"), false, Some("try again")));
+    }
+}
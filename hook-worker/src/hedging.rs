@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time;
+
+use hook_common::webhook::HttpMethod;
+
+use crate::latency::HostLatencies;
+
+struct Settings {
+    hosts: HashSet<String>,
+    percentile: f64,
+    min_delay: time::Duration,
+    max_delay: time::Duration,
+}
+
+/// Sends a second request to destinations that occasionally stall, when the first one hasn't got
+/// a response after the usual latency of their host, and keeps whichever responds first.
+///
+/// Only the opted-in `hosts` are hedged, and only for idempotent methods, as the destination may
+/// get both requests. The delay is the `percentile` of the recent latencies of the host, bounded
+/// by `min_delay` and `max_delay`, hosts without enough recent latencies are hedged after
+/// `max_delay`.
+#[derive(Clone, Default)]
+pub struct RequestHedging {
+    settings: Option<Arc<Settings>>,
+}
+
+impl RequestHedging {
+    pub fn new(
+        hosts: HashSet<String>,
+        percentile: f64,
+        min_delay: time::Duration,
+        max_delay: time::Duration,
+    ) -> Self {
+        Self {
+            settings: Some(Arc::new(Settings {
+                hosts,
+                percentile,
+                min_delay,
+                max_delay: max_delay.max(min_delay),
+            })),
+        }
+    }
+
+    /// No request is hedged.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Whether requests to `host` may be hedged, so its latencies are needed.
+    pub fn is_hedged(&self, host: &str) -> bool {
+        self.settings
+            .as_ref()
+            .is_some_and(|settings| settings.hosts.contains(host))
+    }
+
+    /// Return how long to wait for a response before hedging a `method` request to `host`, or
+    /// None if it must not be hedged.
+    pub fn delay(
+        &self,
+        latencies: &HostLatencies,
+        host: &str,
+        method: &HttpMethod,
+    ) -> Option<time::Duration> {
+        let settings = self.settings.as_ref()?;
+        if !settings.hosts.contains(host) || !is_idempotent(method) {
+            return None;
+        }
+
+        let delay = match latencies.percentile(host, settings.percentile) {
+            Some(delay) => delay,
+            None => return Some(settings.max_delay),
+        };
+
+        Some(delay.clamp(settings.min_delay, settings.max_delay))
+    }
+}
+
+/// Methods that have the same effect whether a request is received once or twice.
+fn is_idempotent(method: &HttpMethod) -> bool {
+    match method {
+        HttpMethod::GET | HttpMethod::PUT | HttpMethod::DELETE => true,
+        HttpMethod::POST | HttpMethod::PATCH => false,
+    }
+}
+
+/// Run `send`, and run it again if it hasn't completed after `delay`, returning the result of
+/// whichever completes first. The other one is dropped, cancelling its request. `send` is only
+/// run once without a delay.
+pub async fn hedge<F, Fut, T>(send: F, delay: Option<time::Duration>) -> T
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let delay = match delay {
+        None => return send().await,
+        Some(delay) => delay,
+    };
+
+    let first = send();
+    tokio::pin!(first);
+    tokio::select! {
+        result = &mut first => return result,
+        _ = tokio::time::sleep(delay) => {}
+    }
+
+    metrics::counter!("webhook_hedged_requests_total").increment(1);
+    let second = send();
+    tokio::pin!(second);
+    tokio::select! {
+        result = &mut first => result,
+        result = &mut second => {
+            metrics::counter!("webhook_hedged_requests_won").increment(1);
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn hedging() -> RequestHedging {
+        RequestHedging::new(
+            HashSet::from(["slow.example.com".to_owned()]),
+            90.0,
+            time::Duration::from_millis(10),
+            time::Duration::from_millis(1000),
+        )
+    }
+
+    #[test]
+    fn test_only_opted_in_hosts_and_idempotent_methods_are_hedged() {
+        let hedging = hedging();
+        let latencies = HostLatencies::new(100);
+
+        for method in [HttpMethod::GET, HttpMethod::PUT, HttpMethod::DELETE] {
+            assert!(hedging
+                .delay(&latencies, "slow.example.com", &method)
+                .is_some());
+        }
+        for method in [HttpMethod::POST, HttpMethod::PATCH] {
+            assert!(hedging
+                .delay(&latencies, "slow.example.com", &method)
+                .is_none());
+        }
+        assert!(hedging
+            .delay(&latencies, "example.com", &HttpMethod::GET)
+            .is_none());
+        assert!(RequestHedging::disabled()
+            .delay(&latencies, "slow.example.com", &HttpMethod::GET)
+            .is_none());
+
+        assert!(hedging.is_hedged("slow.example.com"));
+        assert!(!hedging.is_hedged("example.com"));
+        assert!(!RequestHedging::disabled().is_hedged("slow.example.com"));
+    }
+
+    #[test]
+    fn test_delay_follows_host_percentile() {
+        let hedging = hedging();
+        let latencies = HostLatencies::new(100);
+
+        // Hosts without enough latencies are hedged late
+        assert_eq!(
+            hedging.delay(&latencies, "slow.example.com", &HttpMethod::GET),
+            Some(time::Duration::from_millis(1000))
+        );
+
+        for ms in 1..=100 {
+            latencies.record("slow.example.com", time::Duration::from_millis(ms));
+        }
+        assert_eq!(
+            hedging.delay(&latencies, "slow.example.com", &HttpMethod::GET),
+            Some(time::Duration::from_millis(90))
+        );
+    }
+
+    #[test]
+    fn test_delay_is_bounded() {
+        let hedging = hedging();
+        let latencies = HostLatencies::new(100);
+
+        for _ in 0..100 {
+            latencies.record("slow.example.com", time::Duration::from_millis(1));
+        }
+        assert_eq!(
+            hedging.delay(&latencies, "slow.example.com", &HttpMethod::GET),
+            Some(time::Duration::from_millis(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hedge_takes_the_first_response() {
+        let attempts = AtomicUsize::new(0);
+        let send = || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                // The first request stalls, the hedged one responds right away
+                if attempt == 0 {
+                    tokio::time::sleep(time::Duration::from_secs(60)).await;
+                }
+                attempt
+            }
+        };
+
+        let winner = hedge(send, Some(time::Duration::from_millis(10))).await;
+
+        assert_eq!(winner, 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fast_requests_are_not_hedged() {
+        let attempts = AtomicUsize::new(0);
+        let send = || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move { attempt }
+        };
+
+        assert_eq!(
+            hedge(&send, Some(time::Duration::from_millis(100))).await,
+            0
+        );
+        assert_eq!(hedge(&send, None).await, 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// The label used for all hosts that did not get a label of their own.
+pub const OTHER_HOST_LABEL: &str = "other";
+
+/// Bounds the cardinality of metrics labelled by target host.
+///
+/// Hosts come from user-provided URLs, so labelling metrics with them directly lets anyone grow
+/// the number of exported series without limit. Instead, we keep a label for at most
+/// `max_hosts` hosts and bucket every other host under `other`.
+///
+/// Label slots are given out to the first `max_hosts` hosts seen and are never reassigned, as
+/// that would still grow the number of series over time. Busy destinations are seen early and
+/// often, so in practice the slots go to the top hosts by volume. Request volume is tracked for
+/// labelled hosts and reported by `top_hosts`.
+pub struct HostLabels {
+    max_hosts: usize,
+    volumes: RwLock<HashMap<String, u64>>,
+}
+
+impl HostLabels {
+    pub fn new(max_hosts: usize) -> Self {
+        Self {
+            max_hosts,
+            volumes: RwLock::new(HashMap::with_capacity(max_hosts)),
+        }
+    }
+
+    /// Record a request to `host` and return the label to use for it in metrics.
+    pub fn label(&self, host: &str) -> String {
+        {
+            let volumes = self.volumes.read().expect("host labels lock poisoned");
+            if !volumes.contains_key(host) && volumes.len() >= self.max_hosts {
+                return OTHER_HOST_LABEL.to_owned();
+            }
+        }
+
+        let mut volumes = self.volumes.write().expect("host labels lock poisoned");
+        // Check again, as another task may have taken the last slot since we released the read lock.
+        if let Some(volume) = volumes.get_mut(host) {
+            *volume += 1;
+        } else if volumes.len() < self.max_hosts {
+            volumes.insert(host.to_owned(), 1);
+        } else {
+            return OTHER_HOST_LABEL.to_owned();
+        }
+
+        host.to_owned()
+    }
+
+    /// Return the labelled hosts with their request volume, busiest first.
+    pub fn top_hosts(&self) -> Vec<(String, u64)> {
+        let volumes = self.volumes.read().expect("host labels lock poisoned");
+        let mut top: Vec<(String, u64)> = volumes
+            .iter()
+            .map(|(host, volume)| (host.clone(), *volume))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_label_cardinality_is_bounded() {
+        let host_labels = HostLabels::new(5);
+
+        let mut emitted = HashSet::new();
+        for i in 0..1000 {
+            emitted.insert(host_labels.label(&format!("host-{}.example.com", i % 100)));
+        }
+
+        assert_eq!(emitted.len(), 5 + 1);
+        assert!(emitted.contains(OTHER_HOST_LABEL));
+        for i in 0..5 {
+            assert!(emitted.contains(&format!("host-{}.example.com", i)));
+        }
+    }
+
+    #[test]
+    fn test_labelled_hosts_keep_their_label() {
+        let host_labels = HostLabels::new(1);
+
+        assert_eq!(host_labels.label("busy.example.com"), "busy.example.com");
+        assert_eq!(host_labels.label("quiet.example.com"), OTHER_HOST_LABEL);
+        assert_eq!(host_labels.label("busy.example.com"), "busy.example.com");
+
+        assert_eq!(
+            host_labels.top_hosts(),
+            vec![("busy.example.com".to_owned(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_zero_max_hosts_labels_everything_as_other() {
+        let host_labels = HostLabels::new(0);
+
+        assert_eq!(host_labels.label("example.com"), OTHER_HOST_LABEL);
+        assert!(host_labels.top_hosts().is_empty());
+    }
+}
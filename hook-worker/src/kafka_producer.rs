@@ -0,0 +1,50 @@
+use health::HealthHandle;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::FutureProducer;
+use rdkafka::ClientConfig;
+use tracing::debug;
+
+use crate::config::KafkaConfig;
+
+pub struct KafkaContext {
+    liveness: HealthHandle,
+}
+
+impl rdkafka::ClientContext for KafkaContext {
+    fn stats(&self, _: rdkafka::Statistics) {
+        // Signal liveness, as the main rdkafka loop is running and calling us
+        self.liveness.report_healthy_blocking();
+    }
+}
+
+/// The producer delivering the webhook jobs targeting a `kafka://topic` URL.
+pub type KafkaProducer = FutureProducer<KafkaContext>;
+
+pub fn create_kafka_producer(
+    kafka_hosts: &str,
+    config: &KafkaConfig,
+    liveness: HealthHandle,
+) -> Result<KafkaProducer, KafkaError> {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("bootstrap.servers", kafka_hosts)
+        .set("statistics.interval.ms", "10000")
+        .set("linger.ms", config.kafka_producer_linger_ms.to_string())
+        .set(
+            "message.timeout.ms",
+            config.kafka_message_timeout_ms.to_string(),
+        )
+        .set(
+            "compression.codec",
+            config.kafka_compression_codec.to_owned(),
+        );
+
+    if config.kafka_tls {
+        client_config
+            .set("security.protocol", "ssl")
+            .set("enable.ssl.certificate.verification", "false");
+    };
+
+    debug!("rdkafka configuration: {:?}", client_config);
+    client_config.create_with_context(KafkaContext { liveness })
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time;
+
+/// Number of recent latencies kept per host.
+const LATENCY_WINDOW: usize = 200;
+/// Hosts with fewer latencies than this have no percentile, one over fewer requests is too noisy
+/// to act on.
+const MIN_SAMPLES: usize = 20;
+
+/// The recent latencies of a host, and when the last one was recorded.
+struct HostWindow {
+    latencies: VecDeque<time::Duration>,
+    recorded_at: time::Instant,
+}
+
+/// The recent latencies of the requests to each target host that got a response, which adaptive
+/// timeouts and request hedging derive their per-host durations from.
+///
+/// The latencies of at most `max_hosts` hosts are kept, a new host replaces the one whose latency
+/// was recorded the longest ago.
+#[derive(Clone)]
+pub struct HostLatencies {
+    max_hosts: usize,
+    windows: Arc<Mutex<HashMap<String, HostWindow>>>,
+}
+
+impl Default for HostLatencies {
+    /// Keeps the latencies of as many hosts as the `MAX_LATENCY_HOSTS` default.
+    fn default() -> Self {
+        Self::new(10000)
+    }
+}
+
+impl HostLatencies {
+    pub fn new(max_hosts: usize) -> Self {
+        Self {
+            max_hosts,
+            windows: Arc::default(),
+        }
+    }
+
+    /// Record the latency of a request to `host` that got a response.
+    pub fn record(&self, host: &str, latency: time::Duration) {
+        let mut windows = self.windows.lock().expect("host latencies lock poisoned");
+        if !windows.contains_key(host) && windows.len() >= self.max_hosts {
+            let oldest = windows
+                .iter()
+                .min_by_key(|(_, window)| window.recorded_at)
+                .map(|(host, _)| host.clone());
+            match oldest {
+                Some(oldest) => windows.remove(&oldest),
+                // Keeping no host at all
+                None => return,
+            };
+        }
+
+        let window = windows
+            .entry(host.to_owned())
+            .or_insert_with(|| HostWindow {
+                latencies: VecDeque::with_capacity(LATENCY_WINDOW),
+                recorded_at: time::Instant::now(),
+            });
+        if window.latencies.len() == LATENCY_WINDOW {
+            window.latencies.pop_front();
+        }
+        window.latencies.push_back(latency);
+        window.recorded_at = time::Instant::now();
+    }
+
+    /// Return the `percentile` of the recent latencies of `host`, or None if it has fewer than
+    /// `MIN_SAMPLES` of them.
+    pub fn percentile(&self, host: &str, percentile: f64) -> Option<time::Duration> {
+        let windows = self.windows.lock().expect("host latencies lock poisoned");
+        let window = windows.get(host)?;
+        if window.latencies.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let mut sorted: Vec<time::Duration> = window.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest-rank percentile: the smallest latency at least `percentile`% of the latencies
+        // are under.
+        let rank = (sorted.len() as f64 * percentile / 100.0).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_is_nearest_rank() {
+        let latencies = HostLatencies::new(10);
+
+        for ms in 1..=100 {
+            latencies.record("example.com", time::Duration::from_millis(ms));
+        }
+
+        assert_eq!(
+            latencies.percentile("example.com", 90.0),
+            Some(time::Duration::from_millis(90))
+        );
+        assert_eq!(
+            latencies.percentile("example.com", 99.0),
+            Some(time::Duration::from_millis(99))
+        );
+        assert_eq!(
+            latencies.percentile("example.com", 100.0),
+            Some(time::Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_hosts_without_enough_latencies_have_no_percentile() {
+        let latencies = HostLatencies::new(10);
+
+        for _ in 0..MIN_SAMPLES - 1 {
+            latencies.record("new.example.com", time::Duration::from_millis(50));
+        }
+
+        assert_eq!(latencies.percentile("new.example.com", 99.0), None);
+        assert_eq!(latencies.percentile("unknown.example.com", 99.0), None);
+    }
+
+    #[test]
+    fn test_old_latencies_are_forgotten() {
+        let latencies = HostLatencies::new(10);
+
+        for _ in 0..LATENCY_WINDOW {
+            latencies.record("example.com", time::Duration::from_secs(2));
+        }
+        for _ in 0..LATENCY_WINDOW {
+            latencies.record("example.com", time::Duration::from_millis(100));
+        }
+
+        assert_eq!(
+            latencies.percentile("example.com", 99.0),
+            Some(time::Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn test_least_recently_recorded_host_is_forgotten() {
+        let latencies = HostLatencies::new(2);
+
+        for host in ["a.example.com", "b.example.com"] {
+            for _ in 0..MIN_SAMPLES {
+                latencies.record(host, time::Duration::from_millis(100));
+            }
+        }
+        latencies.record("a.example.com", time::Duration::from_millis(100));
+        for _ in 0..MIN_SAMPLES {
+            latencies.record("c.example.com", time::Duration::from_millis(100));
+        }
+
+        // Each host keeps its own latencies, until it's replaced by a new host
+        for host in ["a.example.com", "c.example.com"] {
+            assert_eq!(
+                latencies.percentile(host, 99.0),
+                Some(time::Duration::from_millis(100))
+            );
+        }
+        assert_eq!(latencies.percentile("b.example.com", 99.0), None);
+    }
+}
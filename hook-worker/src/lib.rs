@@ -1,5 +1,17 @@
+pub mod adaptive_timeout;
 pub mod config;
-pub mod dns;
 pub mod error;
+pub mod error_body_rules;
+pub mod hedging;
+pub mod host_labels;
+pub mod kafka_producer;
+pub mod latency;
+pub mod log_limiter;
+pub mod rate_limits;
+pub mod response_validation;
+pub mod retry_budget;
+pub mod success_statuses;
+pub mod telemetry;
+pub mod trace_context;
 pub mod util;
 pub mod worker;
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time;
+
+use tokio::time::Instant;
+
+/// Collapses repeated logs of the same kind of error for the same host, so that a destination
+/// failing thousands of jobs doesn't flood our logs.
+///
+/// The first error of each kind for a host is logged in full, then identical errors are only
+/// counted until `window` has passed. The next error after that is logged in full again, along
+/// with a summary of how many were suppressed in between.
+///
+/// Hosts are expected to come from `HostLabels`, which bounds how many entries are kept.
+pub struct LogLimiter {
+    window: time::Duration,
+    entries: Mutex<HashMap<(String, &'static str), LogWindow>>,
+}
+
+struct LogWindow {
+    started_at: Instant,
+    suppressed: u64,
+}
+
+/// Whether to log an error, as decided by `LogLimiter::check`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LogDecision {
+    /// Log the error, after summarizing the `suppressed` similar errors since the last one logged.
+    Log { suppressed: u64 },
+    /// Don't log the error, a similar one was logged recently.
+    Suppress,
+}
+
+impl LogLimiter {
+    pub fn new(window: time::Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count an error of `kind` for `host`, returning whether to log it.
+    pub fn check(&self, host: &str, kind: &'static str) -> LogDecision {
+        self.check_at(host, kind, Instant::now())
+    }
+
+    fn check_at(&self, host: &str, kind: &'static str, now: Instant) -> LogDecision {
+        let mut entries = self.entries.lock().expect("log limiter lock poisoned");
+
+        match entries.get_mut(&(host.to_owned(), kind)) {
+            Some(entry) if now.duration_since(entry.started_at) < self.window => {
+                entry.suppressed += 1;
+                LogDecision::Suppress
+            }
+            Some(entry) => {
+                let suppressed = entry.suppressed;
+                entry.started_at = now;
+                entry.suppressed = 0;
+                LogDecision::Log { suppressed }
+            }
+            None => {
+                entries.insert(
+                    (host.to_owned(), kind),
+                    LogWindow {
+                        started_at: now,
+                        suppressed: 0,
+                    },
+                );
+                LogDecision::Log { suppressed: 0 }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_errors_are_throttled() {
+        let limiter = LogLimiter::new(time::Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(
+            limiter.check_at("example.com", "timeout", start),
+            LogDecision::Log { suppressed: 0 }
+        );
+        let logged = (1..1000)
+            .map(|i| {
+                limiter.check_at(
+                    "example.com",
+                    "timeout",
+                    start + time::Duration::from_millis(i),
+                )
+            })
+            .filter(|decision| *decision != LogDecision::Suppress)
+            .count();
+        assert_eq!(logged, 0);
+
+        // The next error after the window is logged, with a summary of the suppressed ones
+        assert_eq!(
+            limiter.check_at(
+                "example.com",
+                "timeout",
+                start + time::Duration::from_secs(61)
+            ),
+            LogDecision::Log { suppressed: 999 }
+        );
+        assert_eq!(
+            limiter.check_at(
+                "example.com",
+                "timeout",
+                start + time::Duration::from_secs(62)
+            ),
+            LogDecision::Suppress
+        );
+    }
+
+    #[test]
+    fn test_errors_are_throttled_per_host_and_kind() {
+        let limiter = LogLimiter::new(time::Duration::from_secs(60));
+
+        assert_eq!(
+            limiter.check("example.com", "timeout"),
+            LogDecision::Log { suppressed: 0 }
+        );
+        assert_eq!(
+            limiter.check("example.com", "connection"),
+            LogDecision::Log { suppressed: 0 }
+        );
+        assert_eq!(
+            limiter.check("other.example.com", "timeout"),
+            LogDecision::Log { suppressed: 0 }
+        );
+        assert_eq!(
+            limiter.check("example.com", "timeout"),
+            LogDecision::Suppress
+        );
+    }
+}
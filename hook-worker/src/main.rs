@@ -3,37 +3,79 @@ use axum::routing::get;
 use axum::Router;
 use envconfig::Envconfig;
 use std::future::ready;
+use tokio::signal;
+use tracing::level_filters::LevelFilter;
+use tracing::Level;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
-use health::HealthRegistry;
+use health::{ComponentStatus, HealthRegistry};
 use hook_common::{
-    metrics::serve, metrics::setup_metrics_routes, pgqueue::PgQueue, retry::RetryPolicy,
+    metrics::serve, metrics::setup_metrics_routes, pgqueue::PgQueue, request::HeaderLimits,
 };
+use hook_worker::adaptive_timeout::AdaptiveTimeouts;
 use hook_worker::config::Config;
 use hook_worker::error::WorkerError;
+use hook_worker::error_body_rules::ErrorBodyRules;
+use hook_worker::hedging::RequestHedging;
+use hook_worker::kafka_producer::create_kafka_producer;
+use hook_worker::rate_limits::HostRateLimiter;
+use hook_worker::response_validation::ResponseValidations;
+use hook_worker::retry_budget::RetryBudget;
+use hook_worker::success_statuses::SuccessStatuses;
+use hook_worker::telemetry::init_tracer;
 use hook_worker::worker::WebhookWorker;
+use metrics_drain::MetricsDrain;
 
 #[tokio::main]
 async fn main() -> Result<(), WorkerError> {
-    tracing_subscriber::fmt::init();
-
     let config = Config::init_from_env().expect("Invalid configuration:");
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    // Instantiate tracing outputs:
+    //   - stdout with a level configured by the RUST_LOG envvar (default=ERROR)
+    //   - OpenTelemetry if enabled, for levels INFO and higher
+    let log_layer = tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env());
+    let otel_layer = config
+        .otel_url
+        .clone()
+        .map(|url| {
+            OpenTelemetryLayer::new(init_tracer(
+                &url,
+                config.otel_sampling_rate,
+                &config.otel_service_name,
+            ))
+        })
+        .with_filter(LevelFilter::from_level(Level::INFO));
+    tracing_subscriber::registry()
+        .with(log_layer)
+        .with(otel_layer)
+        .init();
 
     let liveness = HealthRegistry::new("liveness");
     let worker_liveness = liveness
         .register("worker".to_string(), time::Duration::seconds(60)) // TODO: compute the value from worker params
         .await;
+    worker_liveness
+        .report_status(ComponentStatus::HealthyUntil(
+            time::OffsetDateTime::now_utc() + config.startup_grace_period.0,
+        ))
+        .await;
 
-    let mut retry_policy_builder = RetryPolicy::build(
-        config.retry_policy.backoff_coefficient,
-        config.retry_policy.initial_interval.0,
-    )
-    .maximum_interval(config.retry_policy.maximum_interval.0);
+    // Not ready until the worker reaches the database, distinct from liveness.
+    let readiness = HealthRegistry::new("readiness");
+    let worker_readiness = readiness
+        .register("worker".to_string(), time::Duration::seconds(60))
+        .await;
 
-    retry_policy_builder = if let Some(retry_queue_name) = &config.retry_policy.retry_queue_name {
-        retry_policy_builder.queue(retry_queue_name.as_str())
-    } else {
-        retry_policy_builder
-    };
+    let retry_policies = config.retry_policy.retry_policies();
+    // Before the settings the worker takes ownership of are moved out of the config
+    let bind = config.bind();
 
     let queue = PgQueue::new(
         config.queue_name.as_str(),
@@ -42,37 +84,171 @@ async fn main() -> Result<(), WorkerError> {
         "hook-worker",
     )
     .await
-    .expect("failed to initialize queue");
+    .expect("failed to initialize queue")
+    .with_dequeue_order(config.dequeue_order);
+    let queue = match config.queue_shard {
+        None => queue,
+        Some(shard) => queue.with_shard(shard),
+    };
+
+    queue
+        .check_max_connections(
+            config.max_pg_connections,
+            config.pg_connection_consumers,
+            config.pg_connections_strict,
+        )
+        .await
+        .expect("failed to check database connection limits");
 
     let worker = WebhookWorker::new(
         &config.worker_name,
         &queue,
         config.dequeue_batch_size,
+        config.commit_chunk_size,
         config.poll_interval.0,
         config.request_timeout.0,
+        config.slow_request_threshold.0,
+        config.max_host_labels,
         config.max_concurrent_jobs,
-        retry_policy_builder.provide(),
+        retry_policies,
         config.allow_internal_ips,
+        &config.dns_nameservers.0,
+        config.body_transform_null_as_object,
         worker_liveness,
     );
+    let worker = worker
+        .with_readiness(worker_readiness)
+        .with_error_log_window(config.error_log_window.0)
+        .with_header_limits(HeaderLimits {
+            max_count: config.max_header_count,
+            max_bytes: config.max_header_bytes,
+        });
+    let worker = match config.idle_shutdown_timeout {
+        None => worker,
+        Some(idle_timeout) => worker.with_idle_timeout(idle_timeout.0),
+    };
+    let worker = match config.retry_budget_size {
+        None => worker,
+        Some(size) => worker.with_retry_budget(RetryBudget::new(
+            size,
+            config.retry_budget_refill_interval.0,
+        )),
+    };
+    let worker = match config.response_validations.0.is_empty() {
+        true => worker,
+        false => worker.with_response_validations(ResponseValidations::new(
+            config.response_validations.0,
+            config.response_validation_retryable,
+        )),
+    };
+    let worker = match (
+        config.permanent_error_body_patterns.0.is_empty(),
+        config.retryable_error_body_patterns.0.is_empty(),
+    ) {
+        (true, true) => worker,
+        _ => worker.with_error_body_rules(ErrorBodyRules::new(
+            config.permanent_error_body_patterns.0,
+            config.retryable_error_body_patterns.0,
+        )),
+    };
+    let worker = match config.success_status_codes.0.is_empty() {
+        true => worker,
+        false => worker.with_success_statuses(SuccessStatuses::new(config.success_status_codes.0)),
+    };
+    let worker = match config.max_in_flight_batches {
+        None => worker,
+        Some(max_in_flight_batches) => worker.with_max_in_flight_batches(max_in_flight_batches),
+    };
+    let worker = match config.max_age {
+        None => worker,
+        Some(max_age) => worker.with_max_age(max_age.0),
+    };
+    let worker = match config.propagate_trace_context {
+        false => worker,
+        true => worker.with_trace_context(),
+    };
+    let worker = match config.send_get_body {
+        false => worker,
+        true => worker.with_get_body(),
+    };
+    let worker = match config.adaptive_timeout_multiplier {
+        None => worker,
+        Some(multiplier) => worker.with_adaptive_timeouts(AdaptiveTimeouts::new(
+            multiplier,
+            config.adaptive_timeout_min.0,
+            config.adaptive_timeout_max.0,
+        )),
+    };
+    let worker = match config.hedged_hosts.0.is_empty() {
+        true => worker,
+        false => worker.with_request_hedging(RequestHedging::new(
+            config.hedged_hosts.0,
+            config.hedging_percentile,
+            config.hedging_min_delay.0,
+            config.hedging_max_delay.0,
+        )),
+    };
+    let worker = worker.with_max_latency_hosts(config.max_latency_hosts);
+    let worker = match (
+        config.host_rate_limit,
+        config.host_rate_limit_overrides.0.is_empty(),
+    ) {
+        (None, true) => worker,
+        _ => worker.with_host_rate_limits(HostRateLimiter::new(
+            config.host_rate_limit,
+            config.host_rate_limit_overrides.0,
+            config.host_rate_limit_max_wait.0,
+        )),
+    };
+    let worker = match &config.kafka.kafka_hosts {
+        None => worker,
+        Some(kafka_hosts) => {
+            let kafka_liveness = liveness
+                .register("rdkafka".to_string(), time::Duration::seconds(30))
+                .await;
+            let kafka_producer = create_kafka_producer(kafka_hosts, &config.kafka, kafka_liveness)
+                .expect("failed to create kafka producer");
+            worker.with_kafka_producer(kafka_producer, config.kafka_webhook_topics.0)
+        }
+    };
 
     let router = Router::new()
         .route("/", get(index))
-        .route("/_readiness", get(index))
+        .route("/_readiness", get(move || ready(readiness.get_status())))
         .route("/_liveness", get(move || ready(liveness.get_status())));
     let router = setup_metrics_routes(router);
-    let bind = config.bind();
     tokio::task::spawn(async move {
         serve(router, &bind)
             .await
             .expect("failed to start serving metrics");
     });
 
-    worker.run().await;
+    tokio::select! {
+        _ = worker.run() => {},
+        _ = shutdown() => {},
+    };
+
+    // The metrics server is still up, for the metrics of the last jobs to be scraped
+    MetricsDrain::new(config.metrics_drain.0).run().await;
 
     Ok(())
 }
 
+async fn shutdown() {
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .expect("failed to register SIGTERM handler");
+
+    let mut interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt())
+        .expect("failed to register SIGINT handler");
+
+    tokio::select! {
+        _ = term.recv() => {},
+        _ = interrupt.recv() => {},
+    };
+
+    tracing::info!("Shutting down gracefully...");
+}
+
 pub async fn index() -> &'static str {
     "rusty-hook worker"
 }
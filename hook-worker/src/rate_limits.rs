@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time;
+
+use governor::clock::{Clock, DefaultClock};
+use governor::state::keyed::DefaultKeyedStateStore;
+use governor::{Quota, RateLimiter};
+
+type KeyedRateLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>;
+
+/// Paces the requests sent to each destination host to a number of requests per second, so that
+/// destinations with strict rate limits aren't sent requests they would respond to with a 429.
+///
+/// Every host gets `per_second` requests per second unless it has an override, bursting up to one
+/// second of requests. Requests over the rate wait for up to `max_wait` to be sent, after which
+/// their jobs are deferred instead of holding a slot of the worker.
+#[derive(Clone, Default)]
+pub struct HostRateLimiter {
+    /// Hosts without an override are unlimited when unset.
+    default: Option<Arc<KeyedRateLimiter>>,
+    overrides: Arc<HashMap<String, KeyedRateLimiter>>,
+    max_wait: time::Duration,
+    clock: DefaultClock,
+}
+
+impl HostRateLimiter {
+    pub fn new(
+        per_second: Option<NonZeroU32>,
+        overrides: HashMap<String, NonZeroU32>,
+        max_wait: time::Duration,
+    ) -> Self {
+        let default = per_second
+            .map(|per_second| Arc::new(RateLimiter::dashmap(Quota::per_second(per_second))));
+        let overrides = overrides
+            .into_iter()
+            .map(|(host, per_second)| (host, RateLimiter::dashmap(Quota::per_second(per_second))))
+            .collect();
+
+        Self {
+            default,
+            overrides: Arc::new(overrides),
+            max_wait,
+            clock: DefaultClock::default(),
+        }
+    }
+
+    /// No host is rate limited.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    fn limiter(&self, host: &str) -> Option<&KeyedRateLimiter> {
+        match self.overrides.get(host) {
+            Some(limiter) => Some(limiter),
+            None => self.default.as_deref(),
+        }
+    }
+
+    /// Wait until a request can be sent to `host`. If that takes longer than `max_wait`, return how
+    /// long until it could be sent instead of waiting.
+    pub async fn pace(&self, host: &str) -> Result<(), time::Duration> {
+        let Some(limiter) = self.limiter(host) else {
+            return Ok(());
+        };
+
+        let key = host.to_owned();
+        loop {
+            let wait = match limiter.check_key(&key) {
+                Ok(()) => return Ok(()),
+                Err(not_until) => not_until.wait_time_from(self.clock.now()),
+            };
+            if wait > self.max_wait {
+                return Err(wait);
+            }
+
+            // Other requests to the host may take the next slot first, so check again after.
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Forget the hosts that are back to a full burst, once per minute, so we don't keep state for
+    /// every host we ever sent a request to. Needs to be spawned in a separate task.
+    pub async fn clean_state(&self) {
+        if self.default.is_none() && self.overrides.is_empty() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+
+            for limiter in self
+                .default
+                .iter()
+                .map(Arc::as_ref)
+                .chain(self.overrides.values())
+            {
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rps(per_second: u32) -> NonZeroU32 {
+        NonZeroU32::new(per_second).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_hosts_are_not_paced() {
+        let limiter = HostRateLimiter::unlimited();
+
+        for _ in 0..1000 {
+            assert_eq!(limiter.pace("example.com").await, Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_paced_to_the_host_rate() {
+        let limiter =
+            HostRateLimiter::new(Some(rps(10)), HashMap::new(), time::Duration::from_secs(5));
+
+        // A burst of a second of requests goes through right away, the next ones are sent at 10/s
+        let start = tokio::time::Instant::now();
+        for _ in 0..15 {
+            limiter.pace("example.com").await.unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= time::Duration::from_millis(450), "{:?}", elapsed);
+        assert!(elapsed < time::Duration::from_millis(900), "{:?}", elapsed);
+
+        // Other hosts have their own rate
+        let start = tokio::time::Instant::now();
+        limiter.pace("other.example.com").await.unwrap();
+        assert!(start.elapsed() < time::Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_overrides_apply_to_their_host() {
+        let limiter = HostRateLimiter::new(
+            None,
+            HashMap::from([("strict.example.com".to_owned(), rps(1))]),
+            time::Duration::ZERO,
+        );
+
+        assert_eq!(limiter.pace("strict.example.com").await, Ok(()));
+        let retry_after = limiter
+            .pace("strict.example.com")
+            .await
+            .expect_err("second request should be deferred");
+        assert!(retry_after <= time::Duration::from_secs(1));
+
+        // Hosts without an override are unlimited without a default rate
+        for _ in 0..100 {
+            assert_eq!(limiter.pace("example.com").await, Ok(()));
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::WebhookValidationError;
+use crate::util::first_n_bytes_of_response;
+
+/// Response bodies larger than this are cut short, and fail validation unless the part read is
+/// valid JSON on its own.
+const MAX_VALIDATED_BODY_BYTES: usize = 1024 * 1024;
+/// Number of bytes of a failing response body kept in the job error.
+const MAX_ERROR_BODY_BYTES: usize = 10 * 1024;
+
+/// Checks the body of responses from destinations that report failures with a successful
+/// status, like a 200 with `{"status": "error"}`.
+///
+/// Each rule maps a host to a JMESPath expression that must evaluate to `true` against the JSON
+/// body of its responses, like `status == 'ok'`. Responses from hosts without a rule are only
+/// checked by status.
+#[derive(Clone, Default)]
+pub struct ResponseValidations {
+    rules: Arc<HashMap<String, String>>,
+    retryable: bool,
+}
+
+impl ResponseValidations {
+    /// Responses failing validation are retried if `retryable`, failed right away otherwise.
+    pub fn new(rules: HashMap<String, String>, retryable: bool) -> Self {
+        Self {
+            rules: Arc::new(rules),
+            retryable,
+        }
+    }
+
+    /// Responses are only checked by status.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Check the body of a successful response against the rule of its host, if any, returning
+    /// its status.
+    pub async fn check(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<reqwest::StatusCode, WebhookValidationError> {
+        let status = response.status();
+        let Some(expression) = response
+            .url()
+            .host_str()
+            .and_then(|host| self.rules.get(host))
+        else {
+            return Ok(status);
+        };
+
+        let body = match first_n_bytes_of_response(response, MAX_VALIDATED_BODY_BYTES).await {
+            Ok(body) => body,
+            Err(e) => return Err(self.error(status, e.to_string(), String::new())),
+        };
+
+        match evaluate(expression, &body) {
+            Ok(()) => Ok(status),
+            Err(reason) => Err(self.error(status, reason, body)),
+        }
+    }
+
+    fn error(
+        &self,
+        status: reqwest::StatusCode,
+        reason: String,
+        mut body: String,
+    ) -> WebhookValidationError {
+        if body.len() > MAX_ERROR_BODY_BYTES {
+            let mut end = MAX_ERROR_BODY_BYTES;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            body.truncate(end);
+        }
+
+        WebhookValidationError {
+            status,
+            reason,
+            body,
+            retryable: self.retryable,
+        }
+    }
+}
+
+/// Evaluate `expression` against the JSON `body`, returning why it failed if it didn't yield
+/// `true`.
+fn evaluate(expression: &str, body: &str) -> Result<(), String> {
+    let expression = jmespath::compile(expression).map_err(|e| e.to_string())?;
+    let data: serde_json::Value =
+        serde_json::from_str(body).map_err(|e| format!("response body is not JSON: {}", e))?;
+    let result = expression.search(data).map_err(|e| e.to_string())?;
+
+    match result.as_boolean() {
+        Some(true) => Ok(()),
+        _ => Err(format!(
+            "expected {} to be true, got {}",
+            expression.as_str(),
+            serde_json::to_string(&*result).unwrap_or_default()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("status == 'ok'", r#"{"status": "ok"}"#), Ok(()));
+        assert_eq!(
+            evaluate("status == 'ok'", r#"{"status": "error"}"#),
+            Err("expected status == 'ok' to be true, got false".to_owned())
+        );
+        assert_eq!(
+            evaluate("ok", r#"{"ok": "yes"}"#),
+            Err(r#"expected ok to be true, got "yes""#.to_owned())
+        );
+
+        let err = evaluate("status == 'ok'", "OK").expect_err("body is not JSON");
+        assert!(err.starts_with("response body is not JSON"), "{}", err);
+    }
+}
@@ -0,0 +1,94 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time;
+
+use governor::{clock, state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+
+/// Bounds how many retries each destination host can get, so that a flapping destination can't
+/// use up a disproportionate share of the worker's throughput with retries.
+///
+/// Every host has a bucket of `size` retries, refilled by one retry every `refill_interval`.
+/// Jobs failing while their host's bucket is empty are failed instead of retried.
+#[derive(Clone, Default)]
+pub struct RetryBudget {
+    limiter: Option<Arc<RateLimiter<String, DefaultKeyedStateStore<String>, clock::DefaultClock>>>,
+}
+
+impl RetryBudget {
+    pub fn new(size: NonZeroU32, refill_interval: time::Duration) -> Self {
+        let quota = Quota::with_period(refill_interval)
+            .expect("retry budget refill interval must not be zero")
+            .allow_burst(size);
+
+        Self {
+            limiter: Some(Arc::new(RateLimiter::dashmap(quota))),
+        }
+    }
+
+    /// A budget that never runs out.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Take a retry from the budget of `host`, returning whether there was any left.
+    pub fn try_acquire(&self, host: &str) -> bool {
+        match &self.limiter {
+            None => true,
+            Some(limiter) => limiter.check_key(&host.to_owned()).is_ok(),
+        }
+    }
+
+    /// Forget the hosts whose budget is full again, once per minute, so we don't keep state for
+    /// every host we ever retried. Needs to be spawned in a separate task.
+    pub async fn clean_state(&self) {
+        let Some(limiter) = &self.limiter else {
+            return;
+        };
+
+        let mut interval = tokio::time::interval(time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+
+            limiter.retain_recent();
+            limiter.shrink_to_fit();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_budget() {
+        let budget = RetryBudget::unlimited();
+
+        for _ in 0..1000 {
+            assert!(budget.try_acquire("example.com"));
+        }
+    }
+
+    #[test]
+    fn test_budget_is_per_host() {
+        let budget = RetryBudget::new(NonZeroU32::new(3).unwrap(), time::Duration::from_secs(3600));
+
+        for _ in 0..3 {
+            assert!(budget.try_acquire("flapping.example.com"));
+        }
+        assert!(!budget.try_acquire("flapping.example.com"));
+
+        // Other hosts keep their own budget
+        assert!(budget.try_acquire("healthy.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_budget_refills() {
+        let budget = RetryBudget::new(NonZeroU32::new(1).unwrap(), time::Duration::from_millis(50));
+
+        assert!(budget.try_acquire("example.com"));
+        assert!(!budget.try_acquire("example.com"));
+
+        tokio::time::sleep(time::Duration::from_millis(60)).await;
+        assert!(budget.try_acquire("example.com"));
+    }
+}
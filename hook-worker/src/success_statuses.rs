@@ -0,0 +1,52 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use http::StatusCode;
+
+/// Status codes that some destinations use to signal success on top of 2xx, like a custom 418.
+///
+/// Redirects are followed by the client, so a 3xx only reaches us, and is accepted, when the
+/// client doesn't follow it, like a 302 without a `Location` header.
+#[derive(Clone, Default)]
+pub struct SuccessStatuses {
+    rules: Arc<HashMap<String, HashSet<u16>>>,
+}
+
+impl SuccessStatuses {
+    pub fn new(rules: HashMap<String, HashSet<u16>>) -> Self {
+        Self {
+            rules: Arc::new(rules),
+        }
+    }
+
+    /// Only 2xx statuses, and 3xx ones that aren't followed, are a success.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Whether `status` is a success for `host` on top of the default ones.
+    pub fn accepts(&self, host: Option<&str>, status: StatusCode) -> bool {
+        host.and_then(|host| self.rules.get(host))
+            .is_some_and(|statuses| statuses.contains(&status.as_u16()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        let statuses = SuccessStatuses::new(HashMap::from([(
+            "example.com".to_owned(),
+            HashSet::from([302, 418]),
+        )]));
+
+        assert!(statuses.accepts(Some("example.com"), StatusCode::IM_A_TEAPOT));
+        assert!(statuses.accepts(Some("example.com"), StatusCode::FOUND));
+        assert!(!statuses.accepts(Some("example.com"), StatusCode::BAD_REQUEST));
+        assert!(!statuses.accepts(Some("other.com"), StatusCode::IM_A_TEAPOT));
+        assert!(!statuses.accepts(None, StatusCode::IM_A_TEAPOT));
+        assert!(!SuccessStatuses::disabled().accepts(Some("example.com"), StatusCode::IM_A_TEAPOT));
+    }
+}
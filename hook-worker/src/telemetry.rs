@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{BatchConfig, RandomIdGenerator, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+
+/// The trace config of exported spans, sampling `sampling_rate` of the traces that don't have a
+/// sampled parent.
+fn trace_config(sampling_rate: f64, service_name: &str) -> opentelemetry_sdk::trace::Config {
+    opentelemetry_sdk::trace::Config::default()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            sampling_rate,
+        ))))
+        .with_id_generator(RandomIdGenerator::default())
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            Value::from(service_name.to_string()),
+        )]))
+}
+
+/// Build a tracer exporting spans in batches to the OTLP gRPC collector at `sink_url`.
+pub fn init_tracer(sink_url: &str, sampling_rate: f64, service_name: &str) -> Tracer {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_trace_config(trace_config(sampling_rate, service_name))
+        .with_batch_config(BatchConfig::default())
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(sink_url)
+                .with_timeout(Duration::from_secs(3)),
+        )
+        .install_batch(runtime::Tokio)
+        .expect("failed to install the OpenTelemetry tracer")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_spans_are_exported_with_their_fields() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_config(trace_config(1.0, "hook-worker"))
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(OpenTelemetryLayer::new(provider.tracer("hook-worker")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("process_webhook_job", queue = "webhooks", attempt = 2)
+                .entered();
+        });
+
+        let spans = exporter.get_finished_spans().expect("failed to get spans");
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "process_webhook_job");
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("queue", "webhooks")));
+        assert!(span.attributes.contains(&KeyValue::new("attempt", 2_i64)));
+        assert!(span
+            .resource
+            .iter()
+            .any(|(key, value)| key.as_str() == "service.name" && value.as_str() == "hook-worker"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use reqwest::header::{HeaderMap, HeaderValue};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// A W3C trace context, sent to webhook destinations in a `traceparent` header so that they can
+/// correlate the requests they receive with our processing of the job.
+///
+/// See: https://www.w3.org/TR/trace-context/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Continue the trace of the job's own `traceparent` header, if it holds a valid one, or start
+    /// a new trace. Either way, the request gets a new span id.
+    ///
+    /// A `tracestate` header of the job is sent as is, as it belongs to the trace of the job.
+    pub fn for_job(headers: &HashMap<String, String>) -> Self {
+        let trace_id = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(TRACEPARENT_HEADER))
+            .and_then(|(_, value)| parse_trace_id(value))
+            .unwrap_or_else(|| non_zero(rand::random));
+
+        TraceContext {
+            trace_id,
+            span_id: non_zero(rand::random),
+        }
+    }
+
+    /// The `traceparent` header value, always sampled as the request is being sent.
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+
+    /// Set the `traceparent` header, replacing the job's own.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let value =
+            HeaderValue::from_str(&self.traceparent()).expect("traceparent is a valid header");
+        headers.insert(TRACEPARENT_HEADER, value);
+    }
+}
+
+/// All-zero trace and span ids are invalid.
+fn non_zero<T: Default + PartialEq>(random: impl Fn() -> T) -> T {
+    loop {
+        let id = random();
+        if id != T::default() {
+            return id;
+        }
+    }
+}
+
+/// Parse the trace id of a version 00 `traceparent` header value.
+fn parse_trace_id(traceparent: &str) -> Option<u128> {
+    let parts: Vec<&str> = traceparent.trim().split('-').collect();
+    let [version, trace_id, span_id, flags] = parts.as_slice() else {
+        return None;
+    };
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    if *version != "00" || !is_hex(trace_id, 32) || !is_hex(span_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+
+    u128::from_str_radix(trace_id, 16)
+        .ok()
+        .filter(|trace_id| *trace_id != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check `traceparent` is a well-formed, sampled, version 00 value.
+    fn assert_valid_traceparent(traceparent: &str) {
+        assert!(
+            parse_trace_id(traceparent).is_some(),
+            "invalid traceparent: {}",
+            traceparent
+        );
+        let span_id = &traceparent[36..52];
+        assert_ne!(span_id, "0000000000000000", "{}", traceparent);
+        assert!(traceparent.ends_with("-01"), "{}", traceparent);
+    }
+
+    #[test]
+    fn test_new_trace() {
+        let context = TraceContext::for_job(&HashMap::new());
+        assert_valid_traceparent(&context.traceparent());
+
+        // Each job without a trace starts its own
+        assert_ne!(
+            context.trace_id,
+            TraceContext::for_job(&HashMap::new()).trace_id
+        );
+    }
+
+    #[test]
+    fn test_continue_job_trace() {
+        let headers = HashMap::from([(
+            "Traceparent".to_owned(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_owned(),
+        )]);
+
+        let context = TraceContext::for_job(&headers);
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_ne!(context.span_id, 0x00f067aa0ba902b7);
+        assert!(context
+            .traceparent()
+            .starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
+
+    #[test]
+    fn test_parse_trace_id() {
+        assert_eq!(
+            parse_trace_id("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"),
+            Some(0x4bf92f3577b34da6a3ce929d0e0e4736)
+        );
+        for invalid in [
+            "",
+            "not-a-trace-parent",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(parse_trace_id(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -1,35 +1,163 @@
+use std::io::Write;
+
 use crate::error::WebhookResponseError;
+use flate2::write::{GzDecoder, ZlibDecoder};
 use futures::StreamExt;
 use reqwest::Response;
 
+/// A streaming decoder for response bodies, writing decoded bytes into a buffer.
+enum ResponseDecoder {
+    Identity(Vec<u8>),
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl ResponseDecoder {
+    /// Return a decoder for a Content-Encoding header value, bodies with an unsupported or no
+    /// encoding are read as is.
+    fn from_content_encoding(content_encoding: Option<&str>) -> Self {
+        let content_encoding = content_encoding.map(|value| value.trim().to_ascii_lowercase());
+        match content_encoding.as_deref() {
+            Some("gzip" | "x-gzip") => ResponseDecoder::Gzip(GzDecoder::new(Vec::new())),
+            // HTTP's deflate is the zlib format, not raw deflate.
+            Some("deflate") => ResponseDecoder::Deflate(ZlibDecoder::new(Vec::new())),
+            _ => ResponseDecoder::Identity(Vec::new()),
+        }
+    }
+
+    /// Decode a chunk of bytes, flushing any decoded bytes into the buffer.
+    fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        match self {
+            ResponseDecoder::Identity(buffer) => {
+                buffer.extend_from_slice(chunk);
+                Ok(())
+            }
+            ResponseDecoder::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.flush()
+            }
+            ResponseDecoder::Deflate(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.flush()
+            }
+        }
+    }
+
+    /// The bytes decoded so far.
+    fn decoded(&self) -> &[u8] {
+        match self {
+            ResponseDecoder::Identity(buffer) => buffer,
+            ResponseDecoder::Gzip(decoder) => decoder.get_ref(),
+            ResponseDecoder::Deflate(decoder) => decoder.get_ref(),
+        }
+    }
+}
+
+/// Read up to the first `n` bytes of a response body as a string.
+/// Responses with a gzip or deflate Content-Encoding are decompressed first, with `n` applying to
+/// the decompressed bytes.
+///
+/// Bytes are only decoded as UTF-8 once read, as chunks may end in the middle of a character.
+/// Characters cut off by `n` and invalid bytes are replaced with `U+FFFD`.
 pub async fn first_n_bytes_of_response(
     response: Response,
     n: usize,
 ) -> Result<String, WebhookResponseError> {
+    let mut decoder = ResponseDecoder::from_content_encoding(
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok()),
+    );
     let mut body = response.bytes_stream();
-    let mut buffer = String::with_capacity(n);
 
     while let Some(chunk) = body.next().await {
-        if buffer.len() >= n {
+        if decoder.decoded().len() >= n {
             break;
         }
 
         let chunk = chunk?;
-        let chunk_str = std::str::from_utf8(&chunk)?;
-        let upper_bound = std::cmp::min(n - buffer.len(), chunk_str.len());
-
-        if let Some(partial_chunk_str) = chunk_str.get(0..upper_bound) {
-            buffer.push_str(partial_chunk_str);
-        } else {
-            // For whatever reason we are out of bounds. We should never land here
-            // given the `std::cmp::min` usage, but I am being extra careful by not
-            // using a slice index that would panic instead.
-            return Err(WebhookResponseError::ChunkOutOfBoundsError(
-                chunk_str.len(),
-                upper_bound,
-            ));
+        decoder.write_chunk(&chunk)?;
+    }
+
+    // Lossy, so that a character cut off by the limit, or invalid bytes, don't lose the body.
+    let decoded = decoder.decoded();
+    Ok(String::from_utf8_lossy(&decoded[..std::cmp::min(n, decoded.len())]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+
+    /// A response streaming `chunks` as its body.
+    fn chunked_response(chunks: Vec<Vec<u8>>, content_encoding: Option<&str>) -> Response {
+        let stream = futures::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+        let mut response = http::Response::builder();
+        if let Some(content_encoding) = content_encoding {
+            response = response.header(reqwest::header::CONTENT_ENCODING, content_encoding);
         }
+        Response::from(
+            response
+                .body(reqwest::Body::wrap_stream(stream))
+                .expect("failed to build response"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_character_split_across_two_chunks() {
+        let body = "€".as_bytes();
+        let chunks = vec![body[..1].to_vec(), body[1..].to_vec()];
+
+        let decoded = first_n_bytes_of_response(chunked_response(chunks, None), 1024)
+            .await
+            .expect("failed to read response");
+        assert_eq!(decoded, "€");
+    }
+
+    #[tokio::test]
+    async fn test_character_split_across_chunks() {
+        let body = "zoë 🦔".as_bytes();
+        // Split both in the middle of ë and of the hedgehog
+        let chunks = vec![body[..3].to_vec(), body[3..7].to_vec(), body[7..].to_vec()];
+
+        let decoded = first_n_bytes_of_response(chunked_response(chunks, None), 1024)
+            .await
+            .expect("failed to read response");
+        assert_eq!(decoded, "zoë 🦔");
     }
 
-    Ok(buffer)
+    #[tokio::test]
+    async fn test_character_split_by_the_limit() {
+        let body = "zoë".as_bytes().to_vec();
+
+        // The limit falls in the middle of ë, which is replaced
+        let decoded = first_n_bytes_of_response(chunked_response(vec![body], None), 3)
+            .await
+            .expect("failed to read response");
+        assert_eq!(decoded, "zo\u{FFFD}");
+    }
+
+    #[tokio::test]
+    async fn test_gzip_body_split_across_chunks() {
+        let body = "a compressed error message, in a réponse".repeat(10);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let chunks = compressed.chunks(7).map(<[u8]>::to_vec).collect();
+
+        let decoded = first_n_bytes_of_response(chunked_response(chunks, Some("gzip")), 1024)
+            .await
+            .expect("failed to read response");
+        assert_eq!(decoded, body);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8() {
+        let response = chunked_response(vec![vec![b'a', 0xff, b'b']], None);
+        let decoded = first_n_bytes_of_response(response, 1024)
+            .await
+            .expect("failed to read response");
+        assert_eq!(decoded, "a\u{FFFD}b");
+    }
 }
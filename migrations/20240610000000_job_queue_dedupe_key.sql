@@ -0,0 +1,13 @@
+ALTER TABLE job_queue ADD COLUMN dedupe_key TEXT DEFAULT NULL;
+
+/*
+Unique index making enqueues with a dedupe key idempotent.
+
+Only jobs with a dedupe key are indexed. Enqueues clear the key of the jobs older than their dedupe
+TTL before inserting, so a key only dedupes jobs within that window.
+
+The key is only held by an existing row: once the janitor deletes a completed job, the key is free
+again. Keys therefore dedupe for the shorter of their TTL and the time until the janitor deletes the
+job, TTLs longer than the janitor interval are not honored.
+*/
+CREATE UNIQUE INDEX idx_queue_dedupe_key ON job_queue(queue, dedupe_key) WHERE dedupe_key IS NOT NULL;
@@ -0,0 +1,6 @@
+/*
+History of the attempts of a job, appended to when an attempt completes, fails or is retried.
+
+Only the most recent attempts are kept, see `MAX_ATTEMPTS_HISTORY` in hook-common's PgQueue.
+*/
+ALTER TABLE job_queue ADD COLUMN attempts JSONB [] NOT NULL DEFAULT ARRAY [] :: JSONB [];
@@ -0,0 +1,8 @@
+/*
+Tombstone of the jobs the janitor soft-deleted, instead of deleting them right away.
+
+Tombstoned jobs are never dequeued, and are purged by the janitor after its soft delete retention.
+*/
+ALTER TABLE job_queue ADD COLUMN deleted_at TIMESTAMPTZ DEFAULT NULL;
+
+CREATE INDEX idx_queue_deleted_at ON job_queue(deleted_at) WHERE deleted_at IS NOT NULL;